@@ -0,0 +1,60 @@
+use tokio::sync::mpsc;
+use tracing::{debug_span, info, Instrument};
+
+use ryzenmon_rust::aggregator::Aggregator;
+use ryzenmon_rust::config::Config;
+use ryzenmon_rust::error::RyzenmonError;
+#[cfg(unix)]
+use ryzenmon_rust::privileges;
+use ryzenmon_rust::sink::SinkRegistry;
+use ryzenmon_rust::systemd;
+
+use crate::signals::{Signal, Signals};
+
+// Samples waiting to be written, across all senders.
+const RECEIVE_QUEUE: usize = 4096;
+
+// `ryzenmon aggregator`: write the samples other hosts forward to the
+// configured sinks, without sampling this machine, until SIGTERM or SIGINT.
+pub async fn run(config: &Config, mut sinks: SinkRegistry) -> Result<(), RyzenmonError> {
+    let Some(aggregator) = &config.aggregator else {
+        return Err(RyzenmonError::Config("the aggregator needs an [aggregator] section".to_string()));
+    };
+    let (sender, mut samples) = mpsc::channel(RECEIVE_QUEUE);
+    let listener = Aggregator::bind(aggregator, sender)?;
+
+    #[cfg(unix)]
+    if let Some(user) = &config.privileges.user {
+        privileges::drop_privileges(&config.privileges)?;
+        info!("Dropped privileges, running as {}", user);
+    }
+
+    let mut signals = Signals::new()?;
+    systemd::notify_ready();
+    loop {
+        let metrics = tokio::select! {
+            metrics = samples.recv() => match metrics {
+                Some(metrics) => metrics,
+                None => break,
+            },
+            signal = signals.recv() => match signal {
+                Signal::Stop(name) => {
+                    info!("Received {}, shutting down", name);
+                    break;
+                }
+                // Samples are written as they arrive, and the config isn't reloaded.
+                Signal::Reload | Signal::Flush => continue,
+            },
+        };
+        sinks.write_all(&metrics).instrument(debug_span!("upload")).await;
+    }
+
+    let _ = systemd::notify("STOPPING=1");
+    drop(listener);
+    // Samples accepted before the listener stopped are still written.
+    while let Ok(metrics) = samples.try_recv() {
+        sinks.write_all(&metrics).await;
+    }
+    sinks.shutdown().await;
+    Ok(())
+}
@@ -0,0 +1,224 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::stream::{self, BoxStream};
+use futures::StreamExt;
+use hyper::body::HttpBody;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use subtle::ConstantTimeEq;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tonic::Status;
+use tracing::{debug, error, info};
+
+use crate::config::AggregatorConfig;
+use crate::error::{RyzenmonError, Result};
+use crate::proto::aggregator_server::{Aggregator as AggregatorService, AggregatorServer};
+use crate::proto::{Ack, Sample};
+use crate::rapl::PowerMetrics;
+
+// Larger requests are refused; a sender that was cut off for a while sends
+// everything it buffered at once.
+const MAX_REQUEST_BYTES: usize = 64 * 1024 * 1024;
+
+// Pings on agent streams, so a vanished agent's connection is closed.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+// Receives samples POSTed by [forward] sinks on other hosts to /v1/samples,
+// a JSON array of samples, and streamed by agents over gRPC, and hands them
+// to `samples`. Requests wait while the channel is full, so senders keep
+// their samples buffered rather than the aggregator dropping them.
+pub struct Aggregator {
+    server: JoinHandle<()>,
+    grpc: JoinHandle<()>,
+}
+
+impl Aggregator {
+    pub fn bind(config: &AggregatorConfig, samples: mpsc::Sender<PowerMetrics>) -> Result<Self> {
+        let addr: SocketAddr = config
+            .bind
+            .parse()
+            .map_err(|e| RyzenmonError::Config(format!("aggregator.bind {:?}: {}", config.bind, e)))?;
+        let grpc_addr: SocketAddr = config
+            .grpc_bind
+            .parse()
+            .map_err(|e| RyzenmonError::Config(format!("aggregator.grpc_bind {:?}: {}", config.grpc_bind, e)))?;
+        let token = Arc::new(config.token.clone());
+
+        let grpc_service = GrpcService {
+            samples: samples.clone(),
+            token: token.clone(),
+        };
+        let make_svc = make_service_fn(move |_conn| {
+            let samples = samples.clone();
+            let token = token.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let samples = samples.clone();
+                    let token = token.clone();
+                    async move { Ok::<_, Infallible>(handle(req, token.as_deref(), &samples).await) }
+                }))
+            }
+        });
+
+        let server = Server::try_bind(&addr)
+            .map_err(|e| RyzenmonError::Config(format!("cannot listen on aggregator.bind {}: {}", addr, e)))?
+            .serve(make_svc);
+        info!("Receiving samples on http://{}/v1/samples", addr);
+
+        // Bound here rather than by tonic so a taken port fails startup.
+        let listener = std::net::TcpListener::bind(grpc_addr)
+            .and_then(|listener| {
+                listener.set_nonblocking(true)?;
+                tokio::net::TcpListener::from_std(listener)
+            })
+            .map_err(|e| RyzenmonError::Config(format!("cannot listen on aggregator.grpc_bind {}: {}", grpc_addr, e)))?;
+        let incoming = stream::unfold(listener, |listener| async move {
+            let connection = listener.accept().await.map(|(stream, _)| stream);
+            Some((connection, listener))
+        });
+        let grpc = tonic::transport::Server::builder()
+            .http2_keepalive_interval(Some(KEEPALIVE_INTERVAL))
+            .add_service(AggregatorServer::new(grpc_service))
+            .serve_with_incoming(incoming);
+        info!("Receiving agent streams on {}", grpc_addr);
+
+        Ok(Aggregator {
+            server: tokio::spawn(async move {
+                if let Err(e) = server.await {
+                    error!("Aggregator failed: {}", e);
+                }
+            }),
+            grpc: tokio::spawn(async move {
+                if let Err(e) = grpc.await {
+                    error!("Aggregator gRPC server failed: {}", e);
+                }
+            }),
+        })
+    }
+}
+
+impl Drop for Aggregator {
+    fn drop(&mut self) {
+        self.server.abort();
+        self.grpc.abort();
+    }
+}
+
+// Without its host's tags a sample would pass as the aggregator's own.
+fn check_tags(metrics: &PowerMetrics) -> std::result::Result<(), String> {
+    match metrics.tags.contains_key("host") {
+        true => Ok(()),
+        false => Err("every sample needs a host tag".to_string()),
+    }
+}
+
+// Compared in constant time, so how long a refusal takes doesn't give away
+// how much of a guessed token was right.
+pub(crate) fn authorized(token: Option<&str>, authorization: Option<&str>) -> bool {
+    token.is_none_or(|token| {
+        authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|given| given.as_bytes().ct_eq(token.as_bytes()).into())
+    })
+}
+
+// Serves ryzenmon.Aggregator/Stream: every sample streamed in is handed on,
+// then acknowledged.
+struct GrpcService {
+    samples: mpsc::Sender<PowerMetrics>,
+    token: Arc<Option<String>>,
+}
+
+#[tonic::async_trait]
+impl AggregatorService for GrpcService {
+    type StreamStream = BoxStream<'static, std::result::Result<Ack, Status>>;
+
+    async fn stream(
+        &self,
+        request: tonic::Request<tonic::Streaming<Sample>>,
+    ) -> std::result::Result<tonic::Response<Self::StreamStream>, Status> {
+        let authorization = request.metadata().get("authorization").and_then(|value| value.to_str().ok());
+        if !authorized(self.token.as_deref(), authorization) {
+            return Err(Status::unauthenticated("missing or wrong bearer token"));
+        }
+        let samples = self.samples.clone();
+        let acks = request.into_inner().then(move |sample| {
+            let samples = samples.clone();
+            async move {
+                let sample = sample?;
+                let metrics = sample.metrics.ok_or_else(|| Status::invalid_argument("sample without metrics"))?;
+                let metrics = PowerMetrics::try_from(metrics)
+                    .map_err(|e| Status::invalid_argument(format!("not a sample: {}", e)))?;
+                check_tags(&metrics).map_err(Status::invalid_argument)?;
+                samples.send(metrics).await.map_err(|_| Status::unavailable("shutting down"))?;
+                Ok(Ack { sequence: sample.sequence })
+            }
+        });
+        Ok(tonic::Response::new(acks.boxed()))
+    }
+}
+
+async fn handle(req: Request<Body>, token: Option<&str>, samples: &mpsc::Sender<PowerMetrics>) -> Response<Body> {
+    if req.uri().path() != "/v1/samples" {
+        return error_response(StatusCode::NOT_FOUND, "not found");
+    }
+    if req.method() != Method::POST {
+        return error_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+    }
+    let authorization = req.headers().get(hyper::header::AUTHORIZATION).and_then(|value| value.to_str().ok());
+    if !authorized(token, authorization) {
+        return error_response(StatusCode::UNAUTHORIZED, "missing or wrong bearer token");
+    }
+
+    let mut request = req.into_body();
+    let mut body = Vec::new();
+    while let Some(chunk) = request.data().await {
+        match chunk {
+            Ok(chunk) if body.len() + chunk.len() <= MAX_REQUEST_BYTES => body.extend_from_slice(&chunk),
+            Ok(_) => return error_response(StatusCode::PAYLOAD_TOO_LARGE, "request too large"),
+            Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+        }
+    }
+    let received: Vec<PowerMetrics> = match serde_json::from_slice(&body) {
+        Ok(received) => received,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &format!("not a JSON array of samples: {}", e)),
+    };
+    if let Err(message) = received.iter().try_for_each(check_tags) {
+        return error_response(StatusCode::BAD_REQUEST, &message);
+    }
+
+    debug!("Received {} samples", received.len());
+    for metrics in received {
+        if samples.send(metrics).await.is_err() {
+            return error_response(StatusCode::SERVICE_UNAVAILABLE, "shutting down");
+        }
+    }
+    Response::builder().status(StatusCode::NO_CONTENT).body(Body::empty()).unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::json!({ "error": message }).to_string()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_the_bearer_token() {
+        assert!(authorized(None, None));
+        assert!(authorized(Some("secret"), Some("Bearer secret")));
+        assert!(!authorized(Some("secret"), None));
+        assert!(!authorized(Some("secret"), Some("Bearer secreT")));
+        assert!(!authorized(Some("secret"), Some("Bearer secret2")));
+        assert!(!authorized(Some("secret"), Some("secret")));
+    }
+}
@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use serde::Serialize;
+use tracing::{info, warn};
+use zbus::zvariant::Value;
+
+use crate::config::{hostname, AlertsConfig, EmailConfig};
+use crate::error::{RyzenmonError, Result};
+use crate::rapl::PowerMetrics;
+use crate::smtp;
+use crate::smu::{Limit, SmuLimits};
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+// Slow relays greylist or do DNS checks before accepting a message
+const EMAIL_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Above,
+    AtLeast,
+    Below,
+    AtMost,
+}
+
+impl Comparison {
+    fn breached(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Comparison::Above => value > threshold,
+            Comparison::AtLeast => value >= threshold,
+            Comparison::Below => value < threshold,
+            Comparison::AtMost => value <= threshold,
+        }
+    }
+
+    // Whether a firing alert may resolve: the value has to come back past the
+    // threshold by `hysteresis` so a reading hovering around it does not flap.
+    fn cleared(self, value: f64, threshold: f64, hysteresis: f64) -> bool {
+        match self {
+            Comparison::Above | Comparison::AtLeast => value < threshold - hysteresis,
+            Comparison::Below | Comparison::AtMost => value > threshold + hysteresis,
+        }
+    }
+}
+
+// `<metric> <op> <threshold> [for <duration>]`, e.g. "package_watts > 180 for 60s".
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub metric: String,
+    pub comparison: Comparison,
+    pub threshold: f64,
+    // How long the condition has to hold before the alert fires
+    pub duration: Duration,
+}
+
+impl Condition {
+    pub fn parse(condition: &str) -> Result<Self> {
+        let invalid = |reason: &str| RyzenmonError::Config(format!("invalid alert condition {:?}: {}", condition, reason));
+
+        let tokens: Vec<&str> = condition.split_whitespace().collect();
+        let (metric, comparison, threshold, duration) = match tokens.as_slice() {
+            [metric, comparison, threshold] => (metric, comparison, threshold, None),
+            [metric, comparison, threshold, "for", duration] => (metric, comparison, threshold, Some(duration)),
+            _ => return Err(invalid("expected <metric> <op> <threshold> [for <duration>]")),
+        };
+
+        let comparison = match *comparison {
+            ">" => Comparison::Above,
+            ">=" => Comparison::AtLeast,
+            "<" => Comparison::Below,
+            "<=" => Comparison::AtMost,
+            _ => return Err(invalid("operator must be one of >, >=, <, <=")),
+        };
+        let threshold = threshold.parse().map_err(|_| invalid("threshold is not a number"))?;
+        let duration = match duration {
+            Some(duration) => humantime::parse_duration(duration).map_err(|e| invalid(&e.to_string()))?,
+            None => Duration::ZERO,
+        };
+
+        Ok(Condition {
+            metric: metric.to_string(),
+            comparison,
+            threshold,
+            duration,
+        })
+    }
+}
+
+// Current value of a metric an alert can refer to: one of the fixed names
+// below, or the label of a temperature (tctl, tccd1, ...) or [[hwmon]] sensor.
+pub fn metric_value(metrics: &PowerMetrics, name: &str) -> Option<f64> {
+    let limit = |pick: fn(&SmuLimits) -> Limit| metrics.limits.as_ref().map(|l| pick(l).value);
+    match name {
+        "package_watts" => Some(metrics.package_watts),
+        "core_watts" => Some(metrics.core_sum),
+        "uncore_watts" => Some(metrics.package_watts - metrics.core_sum),
+        "dram_watts" => metrics.dram_watts,
+        "frequency_mhz" => metrics.average_mhz,
+        "utilization" => metrics.utilization,
+        "ppt" => limit(|l| l.ppt),
+        "tdc" => limit(|l| l.tdc),
+        "edc" => limit(|l| l.edc),
+        "throttled" => metrics.throttle.as_ref().map(|t| f64::from(u8::from(t.is_throttled()))),
+        _ => metrics
+            .temperatures
+            .iter()
+            .find(|t| t.label.eq_ignore_ascii_case(name))
+            .map(|t| t.celsius)
+            .or_else(|| metrics.sensors.iter().find(|s| s.label == name).map(|s| s.value)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    Firing,
+    Resolved,
+}
+
+impl fmt::Display for AlertState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlertState::Firing => write!(f, "firing"),
+            AlertState::Resolved => write!(f, "resolved"),
+        }
+    }
+}
+
+// A rule changing state, sent to the webhook as JSON.
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertEvent {
+    pub host: String,
+    pub alert: String,
+    pub state: AlertState,
+    pub condition: String,
+    pub metric: String,
+    pub value: f64,
+    pub threshold: f64,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RuleState {
+    Ok,
+    Pending(Instant),
+    Firing,
+}
+
+struct Rule {
+    name: String,
+    text: String,
+    condition: Condition,
+    hysteresis: f64,
+    state: RuleState,
+}
+
+impl Rule {
+    // Advance the state machine and return the new state when the alert
+    // fires or resolves.
+    fn evaluate(&mut self, value: f64, now: Instant) -> Option<AlertState> {
+        let Condition { comparison, threshold, duration, .. } = self.condition;
+        let breached = comparison.breached(value, threshold);
+
+        match self.state {
+            RuleState::Ok if breached && duration.is_zero() => {
+                self.state = RuleState::Firing;
+                Some(AlertState::Firing)
+            }
+            RuleState::Ok if breached => {
+                self.state = RuleState::Pending(now);
+                None
+            }
+            RuleState::Pending(_) if !breached => {
+                self.state = RuleState::Ok;
+                None
+            }
+            RuleState::Pending(since) if now.duration_since(since) >= duration => {
+                self.state = RuleState::Firing;
+                Some(AlertState::Firing)
+            }
+            RuleState::Firing if comparison.cleared(value, threshold, self.hysteresis) => {
+                self.state = RuleState::Ok;
+                Some(AlertState::Resolved)
+            }
+            _ => None,
+        }
+    }
+}
+
+// Evaluates the [alerts] rules against every sample and notifies the webhook,
+// command, desktop and email when one fires or resolves. Independent of the sinks, so alerts
+// still go out while the metrics backend is down.
+pub struct Alerter {
+    rules: Vec<Rule>,
+    webhook: Option<String>,
+    command: Option<String>,
+    desktop: bool,
+    email: Option<EmailConfig>,
+    client: reqwest::Client,
+    host: String,
+}
+
+impl Alerter {
+    pub fn new(config: &AlertsConfig) -> Result<Self> {
+        let rules = config
+            .rules
+            .iter()
+            .map(|rule| {
+                Ok(Rule {
+                    name: rule.name.clone().unwrap_or_else(|| rule.condition.clone()),
+                    text: rule.condition.clone(),
+                    condition: Condition::parse(&rule.condition)?,
+                    hysteresis: rule.hysteresis,
+                    state: RuleState::Ok,
+                })
+            })
+            .collect::<Result<Vec<Rule>>>()?;
+
+        Ok(Alerter {
+            rules,
+            webhook: config.webhook.clone(),
+            command: config.command.clone(),
+            desktop: config.desktop,
+            email: config.email.clone(),
+            client: reqwest::Client::new(),
+            host: hostname(),
+        })
+    }
+
+    // Carry over the state of rules that are unchanged across a config
+    // reload, so firing alerts still resolve and pending ones keep their timer.
+    pub fn keep_state(&mut self, previous: &Alerter) {
+        for rule in &mut self.rules {
+            if let Some(old) = previous.rules.iter().find(|old| old.name == rule.name && old.text == rule.text) {
+                rule.state = old.state;
+            }
+        }
+    }
+
+    pub fn evaluate(&mut self, metrics: &PowerMetrics, now: Instant) -> Vec<AlertEvent> {
+        let timestamp = metrics
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        self.rules
+            .iter_mut()
+            .filter_map(|rule| {
+                // Metrics missing from this sample leave the rule as it is.
+                let value = metric_value(metrics, &rule.condition.metric)?;
+                let state = rule.evaluate(value, now)?;
+                Some(AlertEvent {
+                    host: self.host.clone(),
+                    alert: rule.name.clone(),
+                    state,
+                    condition: rule.text.clone(),
+                    metric: rule.condition.metric.clone(),
+                    value,
+                    threshold: rule.condition.threshold,
+                    timestamp,
+                })
+            })
+            .collect()
+    }
+
+    // Send `events` in the background; failures are logged, never fatal.
+    pub fn notify(&self, events: Vec<AlertEvent>) {
+        for event in events {
+            info!("Alert {} {}: {} = {}", event.alert, event.state, event.metric, event.value);
+
+            if let Some(webhook) = &self.webhook {
+                let request = self
+                    .client
+                    .post(webhook)
+                    .timeout(WEBHOOK_TIMEOUT)
+                    .header("Content-Type", "application/json")
+                    .body(serde_json::to_string(&event).unwrap_or_default());
+                let alert = event.alert.clone();
+                tokio::spawn(async move {
+                    match request.send().await.and_then(|r| r.error_for_status()) {
+                        Ok(_) => {}
+                        Err(e) => warn!("Alert webhook for {} failed: {}", alert, e),
+                    }
+                });
+            }
+
+            if let Some(command) = &self.command {
+                let mut child = tokio::process::Command::new("sh");
+                child
+                    .arg("-c")
+                    .arg(command)
+                    .env("RYZENMON_ALERT", &event.alert)
+                    .env("RYZENMON_ALERT_STATE", event.state.to_string())
+                    .env("RYZENMON_ALERT_CONDITION", &event.condition)
+                    .env("RYZENMON_ALERT_METRIC", &event.metric)
+                    .env("RYZENMON_ALERT_VALUE", event.value.to_string())
+                    .env("RYZENMON_ALERT_THRESHOLD", event.threshold.to_string());
+                let alert = event.alert.clone();
+                tokio::spawn(async move {
+                    match child.status().await {
+                        Ok(status) if status.success() => {}
+                        Ok(status) => warn!("Alert command for {} exited with {}", alert, status),
+                        Err(e) => warn!("Alert command for {} failed to start: {}", alert, e),
+                    }
+                });
+            }
+
+            if self.desktop {
+                let notification = desktop_notification(&event);
+                let alert = event.alert.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = notify_desktop(notification).await {
+                        warn!("Desktop notification for {} failed: {}", alert, e);
+                    }
+                });
+            }
+
+            if let Some(email) = &self.email {
+                let email = email.clone();
+                let (subject, body) = email_message(&event);
+                let host = event.host.clone();
+                let alert = event.alert.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(EMAIL_TIMEOUT, smtp::send(&email, &host, &subject, &body)).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => warn!("Alert email for {} failed: {}", alert, e),
+                        Err(_) => warn!("Alert email for {} timed out", alert),
+                    }
+                });
+            }
+        }
+    }
+}
+
+// Summary, body and urgency of the desktop notification for `event`,
+// critical while firing so it stays up over a fullscreen game.
+fn desktop_notification(event: &AlertEvent) -> (String, String, u8) {
+    let urgency = match event.state {
+        AlertState::Firing => 2,
+        AlertState::Resolved => 1,
+    };
+    (
+        format!("{} {}", event.alert, event.state),
+        format!("{} = {:.1} ({}) on {}", event.metric, event.value, event.condition, event.host),
+        urgency,
+    )
+}
+
+// Subject and body of the email for `event`.
+fn email_message(event: &AlertEvent) -> (String, String) {
+    let subject = format!("[ryzenmon] {} {} on {}", event.alert, event.state, event.host);
+    let body = format!(
+        "{} is {} on {}.\n\nCondition: {}\n{}: {:.1} (threshold {})\n",
+        event.alert, event.state, event.host, event.condition, event.metric, event.value, event.threshold
+    );
+    (subject, body)
+}
+
+async fn notify_desktop((summary, body, urgency): (String, String, u8)) -> Result<()> {
+    let connection = zbus::Connection::session().await?;
+    let hints = HashMap::from([("urgency", Value::from(urgency))]);
+    connection
+        .call_method(
+            Some("org.freedesktop.Notifications"),
+            "/org/freedesktop/Notifications",
+            Some("org.freedesktop.Notifications"),
+            "Notify",
+            &("ryzenmon", 0u32, "", summary, body, Vec::<&str>::new(), hints, -1i32),
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_conditions() {
+        let condition = Condition::parse("package_watts > 180 for 60s").unwrap();
+        assert_eq!(condition.metric, "package_watts");
+        assert_eq!(condition.comparison, Comparison::Above);
+        assert_eq!(condition.threshold, 180.0);
+        assert_eq!(condition.duration, Duration::from_secs(60));
+
+        assert_eq!(Condition::parse("tctl >= 90").unwrap().duration, Duration::ZERO);
+        assert!(Condition::parse("tctl == 90").is_err());
+        assert!(Condition::parse("tctl > hot").is_err());
+        assert!(Condition::parse("tctl > 90 for ever").is_err());
+    }
+
+    #[test]
+    fn fires_after_duration_and_resolves_with_hysteresis() {
+        let mut rule = Rule {
+            name: "hot".to_string(),
+            text: "tctl > 90 for 30s".to_string(),
+            condition: Condition::parse("tctl > 90 for 30s").unwrap(),
+            hysteresis: 5.0,
+            state: RuleState::Ok,
+        };
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        assert_eq!(rule.evaluate(95.0, at(0)), None);
+        assert_eq!(rule.evaluate(95.0, at(20)), None);
+        assert_eq!(rule.evaluate(95.0, at(30)), Some(AlertState::Firing));
+        assert_eq!(rule.evaluate(95.0, at(40)), None);
+        // Below the threshold but within the hysteresis band
+        assert_eq!(rule.evaluate(87.0, at(50)), None);
+        assert_eq!(rule.evaluate(84.0, at(60)), Some(AlertState::Resolved));
+
+        // Dropping below the threshold before the duration restarts the timer.
+        assert_eq!(rule.evaluate(95.0, at(70)), None);
+        assert_eq!(rule.evaluate(80.0, at(80)), None);
+        assert_eq!(rule.evaluate(95.0, at(90)), None);
+        assert_eq!(rule.evaluate(95.0, at(110)), None);
+        assert_eq!(rule.evaluate(95.0, at(120)), Some(AlertState::Firing));
+    }
+
+    fn event() -> AlertEvent {
+        AlertEvent {
+            host: "desktop".to_string(),
+            alert: "hot".to_string(),
+            state: AlertState::Firing,
+            condition: "tctl > 95 for 30s".to_string(),
+            metric: "tctl".to_string(),
+            value: 96.25,
+            threshold: 95.0,
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn builds_desktop_notifications() {
+        let (summary, body, urgency) = desktop_notification(&event());
+        assert_eq!(summary, "hot firing");
+        assert!(body.ends_with("on desktop"), "{}", body);
+        assert_eq!(urgency, 2);
+    }
+
+    #[test]
+    fn builds_alert_emails() {
+        let (subject, body) = email_message(&event());
+        assert_eq!(subject, "[ryzenmon] hot firing on desktop");
+        assert!(body.contains("Condition: tctl > 95 for 30s"));
+        assert!(body.contains("tctl: 96.2 (threshold 95)"));
+    }
+}
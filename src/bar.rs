@@ -0,0 +1,148 @@
+#[cfg(unix)]
+use std::io::Read;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(unix)]
+use std::time::Duration;
+
+#[cfg(unix)]
+use ryzenmon_rust::config::SocketConfig;
+use ryzenmon_rust::config::Config;
+use ryzenmon_rust::PowerMetrics;
+
+// `ryzenmon bar`: one line for a status bar such as Waybar, i3blocks or
+// polybar, from `format` with `{name}` or `{name:.1}` replaced by the
+// sample's values. `{{` and `}}` are literal braces.
+pub fn print(metrics: &PowerMetrics, format: &str, waybar: bool) {
+    let text = render(format, &values(metrics));
+    if !waybar {
+        println!("{}", text);
+        return;
+    }
+    let throttled = metrics.throttle.as_ref().is_some_and(|throttle| throttle.is_throttled());
+    let line = serde_json::json!({
+        "text": text,
+        "tooltip": tooltip(metrics),
+        "class": if throttled { "throttled" } else { "normal" },
+    });
+    println!("{}", line);
+}
+
+// The latest sample of a running daemon, from its [socket] or the socket's
+// default path. None when no daemon serves one.
+#[cfg(unix)]
+pub fn from_daemon(config: &Config) -> Option<PowerMetrics> {
+    let path = match &config.socket {
+        Some(socket) => socket.path.clone(),
+        None => SocketConfig::default().path,
+    };
+    let mut stream = UnixStream::connect(Path::new(&path)).ok()?;
+    stream.set_read_timeout(Some(Duration::from_secs(2))).ok()?;
+    let mut line = String::new();
+    stream.read_to_string(&mut line).ok()?;
+    // `{"error": "no sample yet"}` before the first one.
+    serde_json::from_str(&line).ok()
+}
+
+// [socket] is Unix only, so there is never a daemon's sample to read.
+#[cfg(not(unix))]
+pub fn from_daemon(_config: &Config) -> Option<PowerMetrics> {
+    None
+}
+
+// Every value a format can name: package_w, cores_w, uncore_w, dram_w, soc_w,
+// core<N>_w, ccd<N>_w, gpu<N>_w, util, mhz, effective_mhz, busy and the
+// temperatures by their lower-cased label, e.g. tctl and tccd1.
+fn values(metrics: &PowerMetrics) -> Vec<(String, f64)> {
+    let mut out = vec![
+        ("package_w".to_string(), metrics.package_watts),
+        ("cores_w".to_string(), metrics.core_sum),
+        ("uncore_w".to_string(), metrics.package_watts - metrics.core_sum),
+    ];
+    let mut value = |name: String, value: Option<f64>| {
+        if let Some(value) = value {
+            out.push((name, value));
+        }
+    };
+    value("dram_w".to_string(), metrics.dram_watts);
+    value("soc_w".to_string(), metrics.soc_watts);
+    value("util".to_string(), metrics.utilization);
+    value("mhz".to_string(), metrics.average_mhz);
+    if let Some(activity) = metrics.mean_activity() {
+        value("effective_mhz".to_string(), Some(activity.effective_mhz));
+        value("busy".to_string(), Some(activity.busy_percent));
+    }
+    for (core, watts) in metrics.per_core(&metrics.core_watts) {
+        value(format!("core{}_w", core), Some(*watts));
+    }
+    for ccd in &metrics.ccds {
+        value(format!("ccd{}_w", ccd.ccd), Some(ccd.watts));
+    }
+    for gpu in &metrics.gpus {
+        value(format!("gpu{}_w", gpu.gpu), gpu.watts);
+    }
+    for temperature in &metrics.temperatures {
+        let name = temperature
+            .label
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+            .collect();
+        value(name, Some(temperature.celsius));
+    }
+    out
+}
+
+// Names this sample has no value for come out as `?`, so the bar still shows
+// on machines without, say, k10temp.
+fn render(format: &str, values: &[(String, f64)]) -> String {
+    let mut out = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let placeholder: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                let (name, precision) = match placeholder.split_once(":.") {
+                    Some((name, precision)) => (name, precision.parse().ok()),
+                    None => (placeholder.as_str(), None),
+                };
+                match values.iter().find(|(n, _)| n == name.trim()) {
+                    Some((_, value)) => match precision {
+                        Some(precision) => out.push_str(&format!("{:.*}", precision, value)),
+                        None => out.push_str(&format!("{:.1}", value)),
+                    },
+                    None => out.push('?'),
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn tooltip(metrics: &PowerMetrics) -> String {
+    let mut lines = vec![
+        format!("Package {:.1} W", metrics.package_watts),
+        format!("Cores {:.1} W", metrics.core_sum),
+    ];
+    for temperature in &metrics.temperatures {
+        lines.push(format!("{} {:.1} °C", temperature.label, temperature.celsius));
+    }
+    if let Some(utilization) = metrics.utilization {
+        lines.push(format!("Utilization {:.0}%", utilization));
+    }
+    if let Some(throttle) = metrics.throttle.as_ref().filter(|throttle| throttle.is_throttled()) {
+        let reasons: Vec<&str> = throttle.active.iter().map(|reason| reason.name()).collect();
+        lines.push(format!("Throttled: {}", reasons.join(", ")));
+    }
+    lines.join("\n")
+}
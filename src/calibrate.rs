@@ -0,0 +1,60 @@
+use std::io::{self, BufRead, Write};
+use std::time::Duration;
+
+use ryzenmon_rust::calibration;
+use ryzenmon_rust::error::RyzenmonError;
+use ryzenmon_rust::Sampler;
+
+// `ryzenmon calibrate`: measure uncalibrated package power over `duration`
+// at loads the user sets up, ask for the wall meter reading each time, and
+// print the [calibration] that maps one onto the other.
+pub fn run(sampler: &mut Sampler, duration: Duration) -> Result<(), RyzenmonError> {
+    println!("Put the machine under a steady load and watch the wall meter while ryzenmon measures.");
+    println!("Repeat at a few different loads, e.g. idle and all cores busy, for a scale and an offset.");
+    let mut stdin = io::stdin().lock();
+    let mut points = Vec::new();
+    loop {
+        match prompt(&mut stdin, &format!("\nPress Enter to measure for {:?}, or q to finish: ", duration))? {
+            Some(answer) if answer != "q" => {}
+            _ => break,
+        }
+        let measured = sampler.sample(duration)?.package_watts;
+        println!("Package power: {:.2} W", measured);
+        while let Some(answer) = prompt(&mut stdin, "Wall meter reading in W (empty to skip): ")? {
+            if answer.is_empty() {
+                break;
+            }
+            match answer.parse::<f64>() {
+                Ok(wall) if wall > 0.0 => {
+                    points.push((measured, wall));
+                    break;
+                }
+                _ => println!("{:?} is not a number of watts", answer),
+            }
+        }
+    }
+
+    let Some(fit) = calibration::fit(&points) else {
+        println!("\nNo readings, nothing to suggest.");
+        return Ok(());
+    };
+    println!();
+    println!("Over {} readings: wall = {:.3} x package + {:.1} W", points.len(), fit.scale, fit.offset);
+    for (measured, wall) in &points {
+        println!("  {:>8.2} W measured, {:>8.2} W on the wall, {:>8.2} W calibrated", measured, wall, fit.apply(*measured));
+    }
+    println!("\nAdd to the config:\n\n[calibration]");
+    println!("package_power = {{ scale = {:.3}, offset = {:.1} }}", fit.scale, fit.offset);
+    Ok(())
+}
+
+// The trimmed line answered to `question`, None at end of input.
+fn prompt(stdin: &mut impl BufRead, question: &str) -> io::Result<Option<String>> {
+    print!("{}", question);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    Ok(match stdin.read_line(&mut line)? {
+        0 => None,
+        _ => Some(line.trim().to_string()),
+    })
+}
@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+
+use crate::config::Calibration;
+use crate::rapl::PowerMetrics;
+
+// Metrics [calibration] can correct, named like the StatsD gauges.
+pub const METRICS: [&str; 3] = ["package_power", "core_power", "dram_power"];
+
+impl Calibration {
+    pub fn apply(&self, watts: f64) -> f64 {
+        watts * self.scale + self.offset
+    }
+}
+
+// Correct power readings with [calibration]. Per-package, per-core and
+// per-CCD power move by the same ratio as their total, so they still add up.
+pub fn apply(calibration: &BTreeMap<String, Calibration>, metrics: &mut PowerMetrics) {
+    if let Some(package) = calibration.get("package_power") {
+        let ratio = ratio(metrics.package_watts, package.apply(metrics.package_watts));
+        metrics.package_watts = package.apply(metrics.package_watts);
+        for package in &mut metrics.packages {
+            package.watts *= ratio;
+        }
+    }
+    if let Some(core) = calibration.get("core_power") {
+        let ratio = ratio(metrics.core_sum, core.apply(metrics.core_sum));
+        metrics.core_sum = core.apply(metrics.core_sum);
+        for watts in &mut metrics.core_watts {
+            *watts *= ratio;
+        }
+        for ccd in &mut metrics.ccds {
+            ccd.watts *= ratio;
+        }
+    }
+    if let (Some(dram), Some(watts)) = (calibration.get("dram_power"), &mut metrics.dram_watts) {
+        *watts = dram.apply(*watts);
+    }
+}
+
+// Parts of a total of zero stay as they are.
+fn ratio(raw: f64, calibrated: f64) -> f64 {
+    if raw == 0.0 {
+        1.0
+    } else {
+        calibrated / raw
+    }
+}
+
+// Fit `wall = scale * measured + offset` through (measured, wall) pairs by
+// least squares. A single pair, or pairs at one measured power, only give a
+// scale.
+pub fn fit(points: &[(f64, f64)]) -> Option<Calibration> {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let covariance: f64 = points.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+    let variance: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    if points.is_empty() || mean_x <= 0.0 {
+        return None;
+    }
+    if variance < 1e-9 {
+        return Some(Calibration {
+            scale: mean_y / mean_x,
+            offset: 0.0,
+        });
+    }
+    let scale = covariance / variance;
+    Some(Calibration {
+        scale,
+        offset: mean_y - scale * mean_x,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fits_scale_and_offset() {
+        let single = fit(&[(50.0, 56.0)]).unwrap();
+        assert!((single.scale - 1.12).abs() < 1e-9);
+        assert_eq!(single.offset, 0.0);
+
+        let line = fit(&[(20.0, 65.0), (60.0, 110.0), (100.0, 155.0)]).unwrap();
+        assert!((line.scale - 1.125).abs() < 1e-9);
+        assert!((line.offset - 42.5).abs() < 1e-9);
+
+        assert!(fit(&[]).is_none());
+    }
+
+    #[test]
+    fn calibrates_one_reading() {
+        let calibration = Calibration { scale: 1.12, offset: 2.0 };
+        assert!((calibration.apply(50.0) - 58.0).abs() < 1e-9);
+        assert_eq!(ratio(0.0, 2.0), 1.0);
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+#[cfg(unix)]
+use nix::unistd::{sysconf, SysconfVar};
+use serde::{Deserialize, Serialize};
+
+use crate::hwmon::glob_match;
+
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+// Package power attributed to one cgroup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CgroupPower {
+    // Path below the cgroup v2 root, e.g. qemu.slice/100.scope or lxc/101
+    pub cgroup: String,
+    pub watts: f64,
+}
+
+// Cumulative CPU microseconds of every matched cgroup by path.
+pub type CgroupUsage = BTreeMap<String, u64>;
+
+// Cgroups matching any of `patterns`, relative to the cgroup v2 root. Every
+// path component may contain `*`.
+pub fn expand(patterns: &[String]) -> Vec<String> {
+    let mut cgroups: Vec<String> = patterns.iter().flat_map(|p| expand_pattern(Path::new(CGROUP_ROOT), p)).collect();
+    cgroups.sort();
+    cgroups.dedup();
+    cgroups
+}
+
+fn expand_pattern(root: &Path, pattern: &str) -> Vec<String> {
+    let mut matched = vec![String::new()];
+
+    for component in pattern.split('/').filter(|c| !c.is_empty()) {
+        matched = matched
+            .iter()
+            .flat_map(|parent| {
+                let dir = root.join(parent);
+                let children: Vec<String> = if component.contains('*') {
+                    fs::read_dir(&dir)
+                        .into_iter()
+                        .flatten()
+                        .flatten()
+                        .filter(|e| e.file_type().map(|t| t.is_dir()).unwrap_or(false))
+                        .filter_map(|e| e.file_name().into_string().ok())
+                        .filter(|name| glob_match(component, name))
+                        .collect()
+                } else if dir.join(component).is_dir() {
+                    vec![component.to_string()]
+                } else {
+                    Vec::new()
+                };
+                children.into_iter().map(move |child| {
+                    if parent.is_empty() {
+                        child
+                    } else {
+                        format!("{}/{}", parent, child)
+                    }
+                })
+            })
+            .collect();
+    }
+
+    matched.retain(|path| !path.is_empty());
+    matched
+}
+
+pub fn read_usage(cgroups: &[String]) -> CgroupUsage {
+    cgroups
+        .iter()
+        .filter_map(|cgroup| {
+            let stat = fs::read_to_string(Path::new(CGROUP_ROOT).join(cgroup).join("cpu.stat")).ok()?;
+            Some((cgroup.clone(), parse_usage_usec(&stat)?))
+        })
+        .collect()
+}
+
+pub fn parse_usage_usec(stat: &str) -> Option<u64> {
+    stat.lines()
+        .find_map(|line| line.strip_prefix("usage_usec "))
+        .and_then(|value| value.trim().parse().ok())
+}
+
+// Microseconds per /proc/stat jiffy.
+#[cfg(unix)]
+pub fn usec_per_tick() -> u64 {
+    match sysconf(SysconfVar::CLK_TCK) {
+        Ok(Some(ticks)) if ticks > 0 => 1_000_000 / ticks as u64,
+        _ => 10_000,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn usec_per_tick() -> u64 {
+    10_000
+}
+
+// Splits `watts` between cgroups by their share of `busy_usec` of CPU time
+// over the window. Cgroups that disappeared during the window are dropped.
+pub fn attribute_power(before: &CgroupUsage, after: &CgroupUsage, busy_usec: u64, watts: f64) -> Vec<CgroupPower> {
+    if busy_usec == 0 {
+        return Vec::new();
+    }
+
+    after
+        .iter()
+        .filter_map(|(cgroup, usage)| {
+            let delta = usage.saturating_sub(*before.get(cgroup)?);
+            Some(CgroupPower {
+                cgroup: cgroup.clone(),
+                watts: watts * (delta as f64 / busy_usec as f64).min(1.0),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cpu_stat() {
+        let stat = "usage_usec 123456\nuser_usec 100000\nsystem_usec 23456\nnr_periods 0\n";
+        assert_eq!(parse_usage_usec(stat), Some(123456));
+        assert_eq!(parse_usage_usec("nr_periods 0\n"), None);
+    }
+
+    #[test]
+    fn attributes_power_by_usage() {
+        let usage = |list: &[(&str, u64)]| -> CgroupUsage {
+            list.iter().map(|(cgroup, usec)| (cgroup.to_string(), *usec)).collect()
+        };
+        let before = usage(&[("qemu.slice/100.scope", 1_000_000), ("lxc/101", 0), ("lxc/102", 5)]);
+        let after = usage(&[("qemu.slice/100.scope", 1_300_000), ("lxc/101", 100_000), ("lxc/103", 50)]);
+
+        let cgroups = attribute_power(&before, &after, 500_000, 100.0);
+        assert_eq!(cgroups.len(), 2);
+        assert_eq!(cgroups[0].cgroup, "lxc/101");
+        assert_eq!(cgroups[0].watts, 20.0);
+        assert_eq!(cgroups[1].watts, 60.0);
+    }
+}
@@ -0,0 +1,125 @@
+use std::fmt::Display;
+use std::slice;
+use std::time::Duration;
+
+use ryzenmon_rust::hwmon;
+use ryzenmon_rust::msr::MsrAccess;
+use ryzenmon_rust::platform::Trace;
+use ryzenmon_rust::sink::{InfluxDb1Sink, InfluxDbSink, MetricSink, SinkError};
+use ryzenmon_rust::topology::Topology;
+use ryzenmon_rust::{Sampler, Source};
+
+use crate::cli::Cli;
+use crate::read_config;
+
+// Per connection attempt, so an unreachable host fails the check rather than hanging it.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// One line per check; a single failure fails the whole report.
+#[derive(Default)]
+struct Report {
+    failed: bool,
+}
+
+impl Report {
+    fn ok(&mut self, check: &str, detail: impl Display) {
+        println!("ok    {}: {}", check, detail);
+    }
+
+    fn warn(&mut self, check: &str, detail: impl Display) {
+        println!("warn  {}: {}", check, detail);
+    }
+
+    fn fail(&mut self, check: &str, detail: impl Display) {
+        println!("FAIL  {}: {}", check, detail);
+        self.failed = true;
+    }
+}
+
+// `ryzenmon check-config`: parse and validate the config, then check that
+// the energy counters, hwmon sensors and InfluxDB targets it refers to are
+// usable. Returns whether every check passed.
+pub async fn run(cli: &Cli) -> bool {
+    let mut report = Report::default();
+
+    if !cli.config.exists() {
+        report.fail("config", format!("{} does not exist", cli.config.display()));
+        return false;
+    }
+    let config = match read_config(cli) {
+        Ok(config) => {
+            report.ok("config", format!("{} is valid", cli.config.display()));
+            config
+        }
+        Err(e) => {
+            report.fail("config", e);
+            return false;
+        }
+    };
+
+    let sampler = match &cli.simulate {
+        Some(trace) => Trace::load(trace).and_then(Sampler::simulated),
+        None => Topology::detect().and_then(|topology| Sampler::new(topology, config.sampling.backend)),
+    };
+    match sampler {
+        Ok(sampler) => {
+            match sampler.cpu() {
+                Some(cpu) => report.ok("energy", format!("reading {:?} on {}", sampler.source(), cpu)),
+                None => report.ok("energy", format!("reading {:?}", sampler.source())),
+            }
+            if sampler.source() == Source::Powercap {
+                if let Some(hint) = MsrAccess::detect().hint() {
+                    report.warn("energy", format!("no per-core power without MSRs: {}", hint));
+                }
+            }
+        }
+        Err(e) => report.fail("energy", e),
+    }
+
+    let temperatures = hwmon::read_k10temp();
+    if temperatures.is_empty() {
+        report.warn("hwmon", "no k10temp sensors, CPU temperatures will be missing");
+    } else {
+        let labels: Vec<&str> = temperatures.iter().map(|t| t.label.as_str()).collect();
+        report.ok("hwmon", format!("k10temp {}", labels.join(", ")));
+    }
+    for sensor in &config.hwmon {
+        let check = format!("hwmon {}", sensor.label);
+        match hwmon::read_sensors(slice::from_ref(sensor)).first() {
+            Some(reading) => report.ok(&check, format!("{} {}", reading.value, reading.unit)),
+            None => report.fail(&check, format!("no chip matching {:?} has a readable {}", sensor.chip, sensor.input)),
+        }
+    }
+
+    let tags = config.resolved_tags();
+    for influxdb in &config.influxdb {
+        match InfluxDbSink::new(influxdb.clone(), tags.clone()) {
+            Ok(sink) => check_sink(&mut report, &sink, &influxdb.host, sink.check()).await,
+            Err(e) => report.fail("influxdb", e),
+        }
+    }
+    if let Some(influxdb1) = &config.influxdb1 {
+        match InfluxDb1Sink::new(influxdb1.clone(), tags.clone()) {
+            Ok(sink) => check_sink(&mut report, &sink, &influxdb1.host, sink.check()).await,
+            Err(e) => report.fail("influxdb1", e),
+        }
+    }
+    if config.influxdb.is_empty() && config.influxdb1.is_none() {
+        report.warn("influxdb", "no InfluxDB configured");
+    }
+
+    !report.failed
+}
+
+async fn check_sink(
+    report: &mut Report,
+    sink: &dyn MetricSink,
+    host: &str,
+    check: impl std::future::Future<Output = Result<String, SinkError>>,
+) {
+    match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(Ok(version)) => report.ok(sink.name(), format!("{} is reachable, {}", host, version)),
+        Ok(Err(e)) => report.fail(sink.name(), format!("{}: {}", host, e)),
+        Err(_) => report.fail(sink.name(), format!("{}: no answer within {:?}", host, CHECK_TIMEOUT)),
+    }
+}
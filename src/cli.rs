@@ -0,0 +1,257 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+use ryzenmon_rust::config::{default_config_path, OutputFormat};
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Ryzen power monitor")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Path to the config file. Defaults to $XDG_CONFIG_HOME/ryzenmon/config.toml
+    /// when that exists, /etc/ryzenmon/config.toml otherwise
+    #[arg(short, long, global = true, default_value_os_t = default_config_path())]
+    pub config: PathBuf,
+
+    /// Seconds between samples, overrides sampling.interval_secs
+    #[arg(short, long, global = true)]
+    pub interval: Option<u64>,
+
+    /// Length of the RAPL measurement window in milliseconds, overrides sampling.window_ms
+    #[arg(short, long, global = true)]
+    pub window_ms: Option<u64>,
+
+    /// Replay the MSR values of a recorded trace instead of reading this machine's counters
+    #[arg(long, global = true, value_name = "TRACE")]
+    pub simulate: Option<PathBuf>,
+
+    /// Take a single sample and exit
+    #[arg(long)]
+    pub once: bool,
+
+    /// Sample and print the InfluxDB line protocol that would be written, without sending it
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Print one sample as InfluxDB line protocol and exit, for Telegraf's inputs.exec;
+    /// the same as --once --dry-run
+    #[arg(long, conflicts_with_all = ["no_upload", "agent", "output"])]
+    pub telegraf: bool,
+
+    /// Sample without uploading; the config file is optional
+    #[arg(long)]
+    pub no_upload: bool,
+
+    /// Print every sample
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Stream samples over gRPC to the `aggregator` at this address, e.g. aggregator:9621,
+    /// instead of using the configured sinks; the config file is optional
+    #[arg(long, value_name = "ADDR")]
+    pub agent: Option<String>,
+
+    /// Print samples to stdout in this format instead of using the configured sinks;
+    /// the config file is optional
+    #[arg(short, long, value_enum)]
+    pub output: Option<Output>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Live dashboard with per-core power, averages and sparklines; the config file is optional
+    Tui,
+    /// Write a commented example config, to --path or the --config location
+    Init {
+        /// Where to write the config, defaults to --config
+        #[arg(long)]
+        path: Option<PathBuf>,
+        /// Overwrite an existing file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Validate the config and check MSR, hwmon and InfluxDB access; exits non-zero on any failure
+    CheckConfig,
+    /// Report whether a daemon is running and, with [socket], its latest sample;
+    /// exits 3 when none is. The config file is optional
+    Status,
+    /// Sample the MSRs without uploading and save every value read as a trace
+    /// for `replay` and --simulate; stops after --duration or on Ctrl-C
+    Record {
+        /// Where to write the trace
+        #[arg(long)]
+        out: PathBuf,
+        /// How long to record for, e.g. 10m
+        #[arg(short, long, value_parser = humantime::parse_duration)]
+        duration: Option<Duration>,
+    },
+    /// Run every sample of a recorded trace through the configured sinks, with
+    /// the timestamps it was recorded at, and exit
+    Replay {
+        /// Trace written by `record`
+        trace: PathBuf,
+    },
+    /// Average, minimum and maximum of metrics stored by the [sqlite] sink
+    Query {
+        /// Metric names or GLOB patterns, e.g. package_power or 'core*.power';
+        /// every metric when none are given
+        metrics: Vec<String>,
+        /// Start of the range, as a time ago (e.g. 1h) or an RFC 3339 timestamp
+        #[arg(long, value_parser = parse_time)]
+        since: Option<SystemTime>,
+        /// End of the range, as a time ago or an RFC 3339 timestamp; defaults to now
+        #[arg(long, value_parser = parse_time)]
+        until: Option<SystemTime>,
+        /// Database to read, defaults to sqlite.path from the config
+        #[arg(long)]
+        db: Option<PathBuf>,
+    },
+    /// Receive samples from [forward] sinks on other hosts on aggregator.bind and
+    /// write them to the configured sinks, without sampling this machine
+    Aggregator,
+    /// Run a command, sampling while it runs, and print its energy, average and
+    /// peak power and runtime; exits with the command's exit code
+    Exec {
+        /// Time between samples, e.g. 100ms
+        #[arg(long = "every", default_value = "100ms", value_parser = parse_nonzero_duration)]
+        every: Duration,
+        /// Also write the samples, folded into one with a command tag, to the configured sinks
+        #[arg(long)]
+        upload: bool,
+        /// The command and its arguments, after --
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Set the package power limit (PPT), within the bounds of [control]; needs a
+    /// build with the control feature
+    SetLimit {
+        /// Package power limit in watts
+        #[arg(long)]
+        ppt: f64,
+    },
+    /// Compare package power with wall meter readings you enter at a few loads and
+    /// suggest [calibration] values; the config file is optional
+    Calibrate {
+        /// How long to measure at each load
+        #[arg(short, long, default_value = "30s", value_parser = parse_nonzero_duration)]
+        duration: Duration,
+    },
+    /// Print everything ryzenmon can read on this machine (topology, energy units,
+    /// raw MSR values, hwmon sensors and the config with secrets redacted) for bug reports
+    Snapshot {
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = SnapshotFormat::Json)]
+        format: SnapshotFormat,
+    },
+    /// Print a completion script for the shell, e.g. to
+    /// /usr/share/bash-completion/completions/ryzenmon-rust
+    Completions {
+        /// Shell to complete in
+        shell: Shell,
+    },
+    /// Print the man page in roff, e.g. to /usr/share/man/man1/ryzenmon-rust.1
+    Man,
+    /// Inside a virtual machine, print the host's latest sample from its [vsock] sink;
+    /// the config file is not read
+    Guest {
+        /// Context ID of the host
+        #[arg(long, default_value_t = 2)]
+        cid: u32,
+        /// Port of the host's [vsock] sink
+        #[arg(short, long, default_value_t = 9630)]
+        port: u32,
+        /// Print only this field of the sample, e.g. package_watts
+        #[arg(short, long)]
+        field: Option<String>,
+        /// Keep printing a sample this often, e.g. 5s
+        #[arg(long, value_parser = parse_nonzero_duration)]
+        every: Option<Duration>,
+    },
+    /// Print one formatted line for a status bar and exit, from the running daemon's
+    /// socket or else a sample of its own; the config file is optional
+    Bar {
+        /// The line, with `{name}` or `{name:.0}` for values such as package_w, cores_w,
+        /// util, mhz, core3_w or tctl
+        #[arg(short, long, default_value = "{package_w:.0}W")]
+        format: String,
+        /// Print Waybar's JSON with text, tooltip and class instead of plain text
+        #[arg(long)]
+        waybar: bool,
+        /// How long to measure for when no daemon serves a sample
+        #[arg(short, long, default_value = "500ms", value_parser = parse_nonzero_duration)]
+        duration: Duration,
+    },
+    /// Measure once over the given duration, print a summary table and exit
+    Once {
+        /// How long to measure for, e.g. 5s or 500ms
+        #[arg(short, long, default_value = "5s", value_parser = parse_nonzero_duration)]
+        duration: Duration,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Output {
+    Text,
+    Json,
+}
+
+impl From<Output> for OutputFormat {
+    fn from(output: Output) -> Self {
+        match output {
+            Output::Text => OutputFormat::Text,
+            Output::Json => OutputFormat::Json,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    Json,
+    Yaml,
+}
+
+// A duration to measure over or sample at, which a rate can't be taken over
+// when it is zero.
+fn parse_nonzero_duration(value: &str) -> Result<Duration, String> {
+    match humantime::parse_duration(value) {
+        Ok(duration) if duration.is_zero() => Err("must be longer than 0".to_string()),
+        Ok(duration) => Ok(duration),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// `2h` for two hours ago, or a timestamp such as `2024-01-31T12:00:00Z` or
+// `2024-01-31 12:00:00` (UTC).
+fn parse_time(value: &str) -> Result<SystemTime, String> {
+    if let Ok(ago) = humantime::parse_duration(value) {
+        return SystemTime::now()
+            .checked_sub(ago)
+            .filter(|time| *time >= UNIX_EPOCH)
+            .ok_or_else(|| format!("{:?} ago is before 1970", value));
+    }
+    humantime::parse_rfc3339_weak(value).map_err(|_| format!("{:?} is neither a duration nor a timestamp", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_times() {
+        let two_hours_ago = parse_time("2h").unwrap();
+        assert!(SystemTime::now().duration_since(two_hours_ago).unwrap() >= Duration::from_secs(7200));
+        assert_eq!(parse_time("1970-01-01T00:01:00Z").unwrap(), UNIX_EPOCH + Duration::from_secs(60));
+        assert!(parse_time("100000d").is_err());
+        assert!(parse_time("yesterday").is_err());
+    }
+}
@@ -0,0 +1,222 @@
+use crate::config::{CollectorKind, Config, HwmonSensorConfig};
+use crate::cpufreq;
+use crate::hwmon::{self, GpuReading, RailReading, SensorReading, TemperatureReading};
+use crate::rapl::PowerMetrics;
+use crate::smu::{self, SmuLimits};
+use crate::topology::Topology;
+
+// One reading a collector adds to a sample.
+#[derive(Debug, Clone)]
+pub enum Metric {
+    Temperature(TemperatureReading),
+    Rail(RailReading),
+    Limits(SmuLimits),
+    Gpu(GpuReading),
+    Sensor(SensorReading),
+    // Per core, in the same order as the topology's cores
+    CoreMhz(Vec<f64>),
+}
+
+impl Metric {
+    fn apply(self, metrics: &mut PowerMetrics) {
+        match self {
+            Metric::Temperature(reading) => metrics.temperatures.push(reading),
+            Metric::Rail(reading) => metrics.rails.push(reading),
+            Metric::Limits(limits) => metrics.limits = Some(limits),
+            Metric::Gpu(reading) => metrics.gpus.push(reading),
+            Metric::Sensor(reading) => metrics.sensors.push(reading),
+            Metric::CoreMhz(mhz) => {
+                metrics.average_mhz = cpufreq::average(&mhz);
+                metrics.core_mhz = mhz;
+            }
+        }
+    }
+}
+
+// A source of readings taken once per sample, after the energy counters.
+// Readings that need a window of their own, such as utilization and C-state
+// residency, are taken by the sampler around the counters instead.
+pub trait Collector: Send {
+    fn name(&self) -> &str;
+
+    fn sample(&mut self, topology: &Topology) -> Vec<Metric>;
+}
+
+// Tctl, Tdie and Tccd* from k10temp.
+pub struct K10temp;
+
+impl Collector for K10temp {
+    fn name(&self) -> &str {
+        "k10temp"
+    }
+
+    fn sample(&mut self, _topology: &Topology) -> Vec<Metric> {
+        hwmon::read_k10temp().into_iter().map(Metric::Temperature).collect()
+    }
+}
+
+// SVI2/SVI3 rail voltage and current.
+pub struct Svi;
+
+impl Collector for Svi {
+    fn name(&self) -> &str {
+        "svi"
+    }
+
+    fn sample(&mut self, _topology: &Topology) -> Vec<Metric> {
+        hwmon::read_svi_rails().into_iter().map(Metric::Rail).collect()
+    }
+}
+
+// PPT/TDC/EDC from the ryzen_smu PM table.
+pub struct Smu;
+
+impl Collector for Smu {
+    fn name(&self) -> &str {
+        "smu"
+    }
+
+    fn sample(&mut self, _topology: &Topology) -> Vec<Metric> {
+        smu::read_limits().into_iter().map(Metric::Limits).collect()
+    }
+}
+
+// amdgpu cards.
+pub struct Amdgpu;
+
+impl Collector for Amdgpu {
+    fn name(&self) -> &str {
+        "amdgpu"
+    }
+
+    fn sample(&mut self, _topology: &Topology) -> Vec<Metric> {
+        hwmon::read_amdgpu().into_iter().map(Metric::Gpu).collect()
+    }
+}
+
+// The [[hwmon]] sensors of the config.
+pub struct Hwmon {
+    sensors: Vec<HwmonSensorConfig>,
+}
+
+impl Hwmon {
+    pub fn new(sensors: Vec<HwmonSensorConfig>) -> Self {
+        Hwmon { sensors }
+    }
+}
+
+impl Collector for Hwmon {
+    fn name(&self) -> &str {
+        "hwmon"
+    }
+
+    fn sample(&mut self, _topology: &Topology) -> Vec<Metric> {
+        hwmon::read_sensors(&self.sensors).into_iter().map(Metric::Sensor).collect()
+    }
+}
+
+// Core frequencies from scaling_cur_freq.
+pub struct Cpufreq;
+
+impl Collector for Cpufreq {
+    fn name(&self) -> &str {
+        "cpufreq"
+    }
+
+    fn sample(&mut self, topology: &Topology) -> Vec<Metric> {
+        let mhz = cpufreq::core_mhz(&topology.cores);
+        if mhz.is_empty() {
+            return Vec::new();
+        }
+        vec![Metric::CoreMhz(mhz)]
+    }
+}
+
+// The collectors the config leaves enabled. amdgpu also needs sampling.gpu
+// and hwmon some [[hwmon]] sensors.
+pub fn from_config(config: &Config) -> Vec<Box<dyn Collector>> {
+    let enabled = |kind: CollectorKind| !config.collectors.disabled.contains(&kind);
+    let mut collectors: Vec<Box<dyn Collector>> = Vec::new();
+    if enabled(CollectorKind::K10temp) {
+        collectors.push(Box::new(K10temp));
+    }
+    if enabled(CollectorKind::Svi) {
+        collectors.push(Box::new(Svi));
+    }
+    if enabled(CollectorKind::Smu) {
+        collectors.push(Box::new(Smu));
+    }
+    if enabled(CollectorKind::Amdgpu) && config.sampling.gpu {
+        collectors.push(Box::new(Amdgpu));
+    }
+    if enabled(CollectorKind::Hwmon) && !config.hwmon.is_empty() {
+        collectors.push(Box::new(Hwmon::new(config.hwmon.clone())));
+    }
+    if enabled(CollectorKind::Cpufreq) {
+        collectors.push(Box::new(Cpufreq));
+    }
+    collectors
+}
+
+// What a sampler runs before it is configured: everything that needs no config.
+pub fn defaults() -> Vec<Box<dyn Collector>> {
+    vec![Box::new(K10temp), Box::new(Svi), Box::new(Smu), Box::new(Cpufreq)]
+}
+
+// Run every collector and add what they read to `metrics`.
+pub fn collect(collectors: &mut [Box<dyn Collector>], topology: &Topology, metrics: &mut PowerMetrics) {
+    for collector in collectors {
+        for metric in collector.sample(topology) {
+            metric.apply(metrics);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::fixture;
+    use crate::Sampler;
+    use std::time::Duration;
+
+    struct Fixed;
+
+    impl Collector for Fixed {
+        fn name(&self) -> &str {
+            "fixed"
+        }
+
+        fn sample(&mut self, _topology: &Topology) -> Vec<Metric> {
+            vec![
+                Metric::Sensor(SensorReading {
+                    label: "pump".to_string(),
+                    value: 1200.0,
+                    unit: "rpm".to_string(),
+                }),
+                Metric::CoreMhz(vec![3000.0, 4000.0]),
+            ]
+        }
+    }
+
+    #[test]
+    fn adds_collected_metrics_to_the_sample() {
+        let mut sampler = Sampler::simulated(fixture()).unwrap();
+        let mut metrics = sampler.sample(Duration::from_millis(10)).unwrap();
+        let topology = sampler.topology().clone();
+
+        let mut collectors: Vec<Box<dyn Collector>> = vec![Box::new(Fixed)];
+        collect(&mut collectors, &topology, &mut metrics);
+        assert_eq!(metrics.sensors.len(), 1);
+        assert_eq!(metrics.sensors[0].label, "pump");
+        assert_eq!(metrics.core_mhz, vec![3000.0, 4000.0]);
+        assert_eq!(metrics.average_mhz, Some(3500.0));
+    }
+
+    #[test]
+    fn leaves_out_disabled_collectors() {
+        let config: Config = toml::from_str("[sampling]\ngpu = true\n[collectors]\ndisabled = [\"smu\", \"svi\"]").unwrap();
+        let names: Vec<String> = from_config(&config).iter().map(|c| c.name().to_string()).collect();
+        assert_eq!(names, vec!["k10temp", "amdgpu", "cpufreq"]);
+        assert!(toml::from_str::<Config>("[collectors]\ndisabled = [\"rapl\"]").is_err());
+    }
+}
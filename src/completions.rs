@@ -0,0 +1,19 @@
+use std::io;
+
+use clap::CommandFactory;
+use clap_complete::{generate, shells};
+
+use crate::cli::{Cli, Shell};
+
+// Completion scripts generated from the clap definitions, so they never fall
+// behind the options.
+pub fn print(shell: Shell) {
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+    let mut out = io::stdout();
+    match shell {
+        Shell::Bash => generate(shells::Bash, &mut command, name, &mut out),
+        Shell::Zsh => generate(shells::Zsh, &mut command, name, &mut out),
+        Shell::Fish => generate(shells::Fish, &mut command, name, &mut out),
+    }
+}
@@ -0,0 +1,259 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use tracing::info;
+
+use crate::config::{ControlConfig, PolicyConfig};
+use crate::error::{RyzenmonError, Result};
+use crate::powercap;
+use crate::rapl::PowerMetrics;
+use crate::smu::{self, RYZEN_SMU_ROOT};
+
+// RSMU mailbox message that sets PPT in milliwatts on Matisse and Vermeer.
+// Other generations number their messages differently, so they go through
+// powercap instead.
+const RSMU_SET_PPT_LIMIT: u32 = 0x53;
+const SMU_OK: u32 = 0x01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interface {
+    Smu,
+    Powercap,
+}
+
+impl fmt::Display for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Interface::Smu => write!(f, "the SMU through ryzen_smu"),
+            Interface::Powercap => write!(f, "the RAPL powercap long-term limit"),
+        }
+    }
+}
+
+// Set the package power limit (PPT) to `watts`, within the bounds of
+// [control]. Uses the SMU where ryzen_smu knows how, powercap otherwise.
+pub fn set_ppt(config: &ControlConfig, watts: f64) -> Result<Interface> {
+    if !(config.min_ppt_watts..=config.max_ppt_watts).contains(&watts) {
+        return Err(RyzenmonError::Control(format!(
+            "{} W is outside control.min_ppt_watts..control.max_ppt_watts ({} to {} W)",
+            watts, config.min_ppt_watts, config.max_ppt_watts
+        )));
+    }
+    let interface = match interface() {
+        Interface::Smu => {
+            smu_command(RSMU_SET_PPT_LIMIT, (watts * 1000.0).round() as u32)?;
+            Interface::Smu
+        }
+        Interface::Powercap => match powercap::set_package_limit(watts) {
+            Ok(0) => return Err(no_powercap_limit()),
+            Ok(_) => Interface::Powercap,
+            Err(e) => return Err(powercap_error(e)),
+        },
+    };
+    info!("Set the package power limit to {} W through {}", watts, interface);
+    Ok(interface)
+}
+
+// The package power limit in effect now, from where set_ppt would set it. On
+// a machine with several packages, the limit of each one, the lowest if
+// they differ.
+pub fn read_ppt() -> Result<f64> {
+    match interface() {
+        Interface::Smu => smu::read_limits()
+            .map(|limits| limits.ppt.limit)
+            .ok_or_else(|| RyzenmonError::Control("can't read the PPT limit from the ryzen_smu PM table".to_string())),
+        Interface::Powercap => match powercap::package_limit() {
+            Ok(Some(watts)) => Ok(watts),
+            Ok(None) => Err(no_powercap_limit()),
+            Err(e) => Err(powercap_error(e)),
+        },
+    }
+}
+
+// The SMU where ryzen_smu knows the message, Matisse and Vermeer.
+fn interface() -> Interface {
+    match smu::pm_table_version().map(|version| version >> 16) {
+        Some(0x24 | 0x38) => Interface::Smu,
+        _ => Interface::Powercap,
+    }
+}
+
+fn no_powercap_limit() -> RyzenmonError {
+    RyzenmonError::Control("neither ryzen_smu nor a powercap package zone with a long-term limit is available".to_string())
+}
+
+fn powercap_error(e: io::Error) -> RyzenmonError {
+    match e.kind() {
+        io::ErrorKind::NotFound => {
+            RyzenmonError::Control(format!("no ryzen_smu and no RAPL powercap zones under {}", powercap::POWERCAP_ROOT))
+        }
+        _ => e.into(),
+    }
+}
+
+// The [control.policy] feedback loop. It steps from the limit in effect, as
+// read_ppt has it before every step, so limits set by hand or by firmware are
+// where it carries on from.
+pub struct Policy {
+    control: ControlConfig,
+    policy: PolicyConfig,
+}
+
+impl Policy {
+    // None when [control] has no policy.
+    pub fn new(control: ControlConfig) -> Option<Self> {
+        Some(Policy {
+            policy: control.policy.clone()?,
+            control,
+        })
+    }
+
+    pub fn config(&self) -> &ControlConfig {
+        &self.control
+    }
+
+    // The limit to set after `metrics` when `limit` is in effect, None to
+    // keep it. The limit holds each package, so the busiest one is compared.
+    pub fn adjust(&self, metrics: &PowerMetrics, limit: f64) -> Option<f64> {
+        let tctl = metrics.temperatures.iter().find(|t| t.label == "Tctl").map(|t| t.celsius);
+        let package_watts = metrics
+            .packages
+            .iter()
+            .map(|package| package.watts)
+            .reduce(f64::max)
+            .unwrap_or(metrics.package_watts);
+        self.next_limit(limit, tctl, package_watts)
+    }
+
+    fn next_limit(&self, limit: f64, tctl: Option<f64>, package_watts: f64) -> Option<f64> {
+        let PolicyConfig {
+            target_tctl,
+            max_package_watts,
+            step_watts,
+            tctl_margin,
+        } = self.policy;
+        let too_hot = matches!((tctl, target_tctl), (Some(tctl), Some(target)) if tctl > target);
+        let too_much = max_package_watts.is_some_and(|max| package_watts > max);
+        let cool = match (tctl, target_tctl) {
+            (Some(tctl), Some(target)) => tctl < target - tctl_margin,
+            // Without a reading, only power is held.
+            _ => true,
+        };
+        let headroom = max_package_watts.is_none_or(|max| package_watts < max - step_watts);
+
+        let (min, max) = (self.control.min_ppt_watts, self.control.max_ppt_watts);
+        // A step never goes the wrong way, even from a limit outside the
+        // bounds.
+        let next = if too_hot || too_much {
+            (limit - step_watts).clamp(min, max).min(limit)
+        } else if cool && headroom {
+            (limit + step_watts).clamp(min, max).max(limit)
+        } else {
+            limit
+        };
+        (next != limit).then_some(next)
+    }
+}
+
+// Send one RSMU message with a single argument. The driver waits for the
+// SMU while writing rsmu_cmd, and reading it back gives the reply.
+fn smu_command(command: u32, argument: u32) -> Result<()> {
+    let root = Path::new(RYZEN_SMU_ROOT);
+    let mut args = [0u8; 24];
+    args[..4].copy_from_slice(&argument.to_le_bytes());
+    fs::write(root.join("smu_args"), args)?;
+    fs::write(root.join("rsmu_cmd"), command.to_le_bytes())?;
+
+    let reply = fs::read(root.join("rsmu_cmd"))?;
+    let status = reply
+        .get(..4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u32::from_le_bytes)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "short reply from rsmu_cmd"))?;
+    match status {
+        SMU_OK => Ok(()),
+        status => Err(RyzenmonError::Control(format!("the SMU rejected the limit with status {:#x}", status))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_limits_outside_the_bounds() {
+        let config = ControlConfig {
+            min_ppt_watts: 15.0,
+            max_ppt_watts: 142.0,
+            token: None,
+            policy: None,
+        };
+        assert!(matches!(set_ppt(&config, 200.0), Err(RyzenmonError::Control(_))));
+        assert!(matches!(set_ppt(&config, 5.0), Err(RyzenmonError::Control(_))));
+        assert!(matches!(set_ppt(&config, f64::NAN), Err(RyzenmonError::Control(_))));
+    }
+
+    #[test]
+    fn steps_the_limit_towards_the_targets() {
+        let policy = Policy::new(ControlConfig {
+            min_ppt_watts: 50.0,
+            max_ppt_watts: 100.0,
+            token: None,
+            policy: Some(PolicyConfig {
+                target_tctl: Some(85.0),
+                max_package_watts: Some(90.0),
+                step_watts: 5.0,
+                tctl_margin: 3.0,
+            }),
+        })
+        .unwrap();
+
+        // Already at the maximum
+        assert_eq!(policy.next_limit(100.0, Some(60.0), 70.0), None);
+        assert_eq!(policy.next_limit(100.0, Some(88.0), 70.0), Some(95.0));
+        assert_eq!(policy.next_limit(100.0, Some(60.0), 95.0), Some(95.0));
+        // Within the margin, or close to the power target, the limit holds.
+        assert_eq!(policy.next_limit(95.0, Some(84.0), 70.0), None);
+        assert_eq!(policy.next_limit(95.0, Some(60.0), 88.0), None);
+        assert_eq!(policy.next_limit(95.0, None, 70.0), Some(100.0));
+        assert_eq!(policy.next_limit(50.0, Some(95.0), 70.0), None);
+    }
+
+    #[test]
+    fn steps_from_the_limit_in_effect() {
+        let policy = Policy::new(ControlConfig {
+            min_ppt_watts: 50.0,
+            max_ppt_watts: 100.0,
+            token: None,
+            policy: Some(PolicyConfig {
+                target_tctl: Some(85.0),
+                max_package_watts: Some(90.0),
+                step_watts: 5.0,
+                tctl_margin: 3.0,
+            }),
+        })
+        .unwrap();
+
+        // Left at 65 W by firmware or set-limit, too hot
+        assert_eq!(policy.next_limit(65.0, Some(90.0), 60.0), Some(60.0));
+        // Outside the bounds, it only moves towards them
+        assert_eq!(policy.next_limit(142.0, Some(90.0), 60.0), Some(100.0));
+        assert_eq!(policy.next_limit(142.0, Some(60.0), 60.0), None);
+        assert_eq!(policy.next_limit(30.0, Some(90.0), 60.0), None);
+        assert_eq!(policy.next_limit(30.0, Some(60.0), 20.0), Some(50.0));
+
+        // Two packages at 88 W each are within 90 W per package.
+        let mut metrics = crate::platform::fixture_sample();
+        metrics.temperatures.clear();
+        metrics.packages = vec![
+            crate::rapl::PackagePower { package: 0, watts: 88.0 },
+            crate::rapl::PackagePower { package: 1, watts: 88.0 },
+        ];
+        metrics.package_watts = 176.0;
+        assert_eq!(policy.adjust(&metrics, 80.0), None);
+        metrics.packages[1].watts = 92.0;
+        assert_eq!(policy.adjust(&metrics, 80.0), Some(75.0));
+    }
+}
@@ -0,0 +1,28 @@
+use std::path::Path;
+
+use crate::hwmon::read_value;
+use crate::topology::CPU_SYSFS_ROOT;
+
+// Current frequency of `cpu` in MHz, as last requested by the governor or
+// reported by the hardware (amd-pstate, intel_pstate).
+pub fn scaling_cur_mhz(cpu: usize) -> Option<f64> {
+    let path = format!("{}/cpu{}/cpufreq/scaling_cur_freq", CPU_SYSFS_ROOT, cpu);
+    read_value(Path::new(&path)).map(|khz| khz / 1000.0)
+}
+
+// Frequency of every core in MHz, empty when cpufreq is not available.
+pub fn core_mhz(cores: &[usize]) -> Vec<f64> {
+    cores
+        .iter()
+        .map(|&cpu| scaling_cur_mhz(cpu))
+        .collect::<Option<Vec<f64>>>()
+        .unwrap_or_default()
+}
+
+pub fn average(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.iter().sum::<f64>() / values.len() as f64)
+    }
+}
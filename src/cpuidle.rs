@@ -0,0 +1,103 @@
+use std::fs;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::topology::CPU_SYSFS_ROOT;
+
+// Share of the window spent in one idle state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CStateResidency {
+    // POLL, C1, C2, ... as named by the cpuidle driver
+    pub state: String,
+    // Mean over all cores
+    pub percent: f64,
+    // Per core, in the same order as core_watts
+    pub cores: Vec<f64>,
+}
+
+// Cumulative microseconds per idle state of one CPU, in state order.
+pub type IdleTimes = Vec<(String, u64)>;
+
+pub fn read_idle_times(cpu: usize) -> Option<IdleTimes> {
+    let root = format!("{}/cpu{}/cpuidle", CPU_SYSFS_ROOT, cpu);
+    let mut states: Vec<(usize, String)> = fs::read_dir(&root)
+        .ok()?
+        .flatten()
+        .filter_map(|e| {
+            let name = e.file_name().into_string().ok()?;
+            Some((name.strip_prefix("state")?.parse().ok()?, name))
+        })
+        .collect();
+    states.sort();
+
+    states
+        .into_iter()
+        .map(|(_, dir)| {
+            let name = fs::read_to_string(format!("{}/{}/name", root, dir)).ok()?;
+            let time = fs::read_to_string(format!("{}/{}/time", root, dir)).ok()?;
+            Some((name.trim().to_string(), time.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+// Idle times of every core, None when cpuidle is not available.
+pub fn read_cores(cores: &[usize]) -> Option<Vec<IdleTimes>> {
+    cores.iter().map(|&cpu| read_idle_times(cpu)).collect()
+}
+
+// Residency per state over `elapsed`, from readings taken before and after.
+// The states are the ones of the first core; they are the same on every core.
+pub fn residency(before: &[IdleTimes], after: &[IdleTimes], elapsed: Duration) -> Vec<CStateResidency> {
+    let Some(first) = before.first() else {
+        return Vec::new();
+    };
+    let elapsed_us = elapsed.as_micros() as f64;
+    if elapsed_us == 0.0 {
+        return Vec::new();
+    }
+
+    first
+        .iter()
+        .enumerate()
+        .map(|(index, (state, _))| {
+            let cores: Vec<f64> = before
+                .iter()
+                .zip(after)
+                .map(|(before, after)| match (before.get(index), after.get(index)) {
+                    (Some((_, before)), Some((_, after))) => {
+                        (after.saturating_sub(*before) as f64 / elapsed_us * 100.0).min(100.0)
+                    }
+                    _ => 0.0,
+                })
+                .collect();
+            CStateResidency {
+                state: state.clone(),
+                percent: cores.iter().sum::<f64>() / cores.len() as f64,
+                cores,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn times(values: &[(&str, u64)]) -> IdleTimes {
+        values.iter().map(|(name, time)| (name.to_string(), *time)).collect()
+    }
+
+    #[test]
+    fn residency_per_state_and_core() {
+        let before = vec![times(&[("C1", 1_000), ("C2", 5_000)]), times(&[("C1", 0), ("C2", 0)])];
+        let after = vec![times(&[("C1", 11_000), ("C2", 55_000)]), times(&[("C1", 90_000), ("C2", 0)])];
+
+        let states = residency(&before, &after, Duration::from_millis(100));
+        assert_eq!(states.len(), 2);
+        assert_eq!(states[0].state, "C1");
+        assert_eq!(states[0].cores, vec![10.0, 90.0]);
+        assert_eq!(states[0].percent, 50.0);
+        assert_eq!(states[1].cores, vec![50.0, 0.0]);
+    }
+}
@@ -0,0 +1,245 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use chrono::{Local, Timelike};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::config::EnergyConfig;
+use crate::error::{RyzenmonError, Result};
+
+const JOULES_PER_KWH: f64 = 3_600_000.0;
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+// Package energy since the counter started, in the form sinks write it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EnergyTotals {
+    pub joules: f64,
+    pub kwh: f64,
+    // Unix seconds when counting started, kept across restarts with a state file
+    pub since: u64,
+    // Running cost of that energy, when energy.price_per_kwh is set
+    pub cost: Option<f64>,
+    pub currency: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct State {
+    joules: f64,
+    since: u64,
+    #[serde(default)]
+    cost: f64,
+}
+
+// Electricity price by local time of day: the first rate whose [start, end)
+// contains the time, `price_per_kwh` otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tariff {
+    pub price_per_kwh: f64,
+    // Minutes since midnight; a rate with end <= start wraps past midnight
+    pub rates: Vec<(u32, u32, f64)>,
+    pub currency: String,
+}
+
+impl Tariff {
+    // None when no price is configured.
+    pub fn from_config(config: &EnergyConfig) -> Result<Option<Self>> {
+        let Some(price_per_kwh) = config.price_per_kwh else {
+            if !config.rates.is_empty() {
+                return Err(RyzenmonError::Config("energy.rates needs energy.price_per_kwh as the default price".to_string()));
+            }
+            return Ok(None);
+        };
+        let rates = config
+            .rates
+            .iter()
+            .map(|rate| Ok((parse_time_of_day(&rate.start)?, parse_time_of_day(&rate.end)?, rate.price_per_kwh)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(Tariff {
+            price_per_kwh,
+            rates,
+            currency: config.currency.clone(),
+        }))
+    }
+
+    pub fn price_at(&self, minute: u32) -> f64 {
+        self.rates
+            .iter()
+            .find(|(start, end, _)| {
+                if start < end {
+                    (*start..*end).contains(&minute)
+                } else {
+                    minute >= *start || minute < *end
+                }
+            })
+            .map(|(_, _, price)| *price)
+            .unwrap_or(self.price_per_kwh)
+    }
+}
+
+// "HH:MM" as minutes since midnight.
+fn parse_time_of_day(time: &str) -> Result<u32> {
+    let invalid = || RyzenmonError::Config(format!("invalid time of day {:?}, expected HH:MM", time));
+    let (hours, minutes) = time.split_once(':').ok_or_else(invalid)?;
+    let hours: u32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+    if hours > 24 || minutes > 59 || hours * 60 + minutes > MINUTES_PER_DAY {
+        return Err(invalid());
+    }
+    Ok((hours * 60 + minutes) % MINUTES_PER_DAY)
+}
+
+fn local_minute_of_day() -> u32 {
+    let now = Local::now();
+    now.hour() * 60 + now.minute()
+}
+
+// Integrates package power over the real time between samples, so the total
+// covers the gaps between measurement windows as well.
+pub struct EnergyCounter {
+    joules: f64,
+    cost: f64,
+    since: u64,
+    last: Option<Instant>,
+    path: Option<PathBuf>,
+    tariff: Option<Tariff>,
+}
+
+impl EnergyCounter {
+    // Resumes from `path` when it holds a previous total.
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let mut counter = EnergyCounter {
+            joules: 0.0,
+            cost: 0.0,
+            since: now,
+            last: None,
+            path,
+            tariff: None,
+        };
+
+        if let Some(path) = &counter.path {
+            if let Some(state) = fs::read_to_string(path).ok().and_then(|s| serde_json::from_str::<State>(&s).ok()) {
+                info!("Restored {:.0} J of package energy from {}", state.joules, path.display());
+                counter.joules = state.joules;
+                counter.cost = state.cost;
+                counter.since = state.since;
+            }
+        }
+
+        counter
+    }
+
+    // Price energy from now on; cost already counted keeps its old price.
+    pub fn set_tariff(&mut self, tariff: Option<Tariff>) {
+        self.tariff = tariff;
+    }
+
+    pub fn add(&mut self, watts: f64, now: Instant) {
+        self.add_at(watts, now, local_minute_of_day());
+    }
+
+    // Cost is counted at the price in effect at `minute`, so a time-of-use
+    // tariff is applied to the energy used in each period.
+    fn add_at(&mut self, watts: f64, now: Instant, minute: u32) {
+        if let Some(last) = self.last {
+            let joules = watts * now.duration_since(last).as_secs_f64();
+            self.joules += joules;
+            if let Some(tariff) = &self.tariff {
+                self.cost += joules / JOULES_PER_KWH * tariff.price_at(minute);
+            }
+        }
+        self.last = Some(now);
+    }
+
+    pub fn totals(&self) -> EnergyTotals {
+        EnergyTotals {
+            joules: self.joules,
+            kwh: self.joules / JOULES_PER_KWH,
+            since: self.since,
+            cost: self.tariff.as_ref().map(|_| self.cost),
+            currency: self.tariff.as_ref().map(|t| t.currency.clone()).unwrap_or_default(),
+        }
+    }
+
+    // Write the total to the state file, replacing it atomically.
+    pub fn persist(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let state = State {
+            joules: self.joules,
+            since: self.since,
+            cost: self.cost,
+        };
+        let temp = path.with_extension("tmp");
+        let result = serde_json::to_string(&state)
+            .map_err(io::Error::from)
+            .and_then(|json| fs::write(&temp, json))
+            .and_then(|()| fs::rename(&temp, path));
+        if let Err(e) = result {
+            error!("Failed to persist energy counter to {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn integrates_over_time_between_samples() {
+        let mut counter = EnergyCounter::new(None);
+        let start = Instant::now();
+
+        counter.add(100.0, start);
+        assert_eq!(counter.totals().joules, 0.0);
+        counter.add(100.0, start + Duration::from_secs(10));
+        counter.add(50.0, start + Duration::from_secs(30));
+
+        let totals = counter.totals();
+        assert_eq!(totals.joules, 2000.0);
+        assert!((totals.kwh - 2000.0 / 3_600_000.0).abs() < 1e-12);
+        assert_eq!(totals.cost, None);
+    }
+
+    #[test]
+    fn prices_energy_by_time_of_use() {
+        let config = EnergyConfig {
+            price_per_kwh: Some(0.30),
+            rates: vec![crate::config::EnergyRateConfig {
+                start: "23:00".to_string(),
+                end: "07:00".to_string(),
+                price_per_kwh: 0.10,
+            }],
+            ..EnergyConfig::default()
+        };
+        let tariff = Tariff::from_config(&config).unwrap().unwrap();
+        assert_eq!(tariff.price_at(23 * 60 + 30), 0.10);
+        assert_eq!(tariff.price_at(3 * 60), 0.10);
+        assert_eq!(tariff.price_at(12 * 60), 0.30);
+
+        let mut counter = EnergyCounter::new(None);
+        counter.set_tariff(Some(tariff));
+        let start = Instant::now();
+        // 1 kWh at night, then 1 kWh during the day
+        counter.add_at(3600.0, start, 0);
+        counter.add_at(3600.0, start + Duration::from_secs(1000), 2 * 60);
+        counter.add_at(3600.0, start + Duration::from_secs(2000), 12 * 60);
+        assert!((counter.totals().cost.unwrap() - 0.40).abs() < 1e-9);
+
+        let config = EnergyConfig {
+            price_per_kwh: Some(0.30),
+            rates: vec![crate::config::EnergyRateConfig {
+                start: "7am".to_string(),
+                end: "07:00".to_string(),
+                price_per_kwh: 0.10,
+            }],
+            ..EnergyConfig::default()
+        };
+        assert!(Tariff::from_config(&config).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use std::io;
+
+use thiserror::Error;
+
+use crate::sink::SinkError;
+
+pub type Result<T, E = RyzenmonError> = std::result::Result<T, E>;
+
+#[derive(Debug, Error)]
+pub enum RyzenmonError {
+    #[error("cannot access MSR on CPU {cpu}: {source}")]
+    MsrAccess {
+        cpu: usize,
+        #[source]
+        source: io::Error,
+    },
+    #[error("invalid config: {0}")]
+    Config(String),
+    #[error("failed to detect CPU topology: {0}")]
+    Topology(#[source] io::Error),
+    #[error("upload to {sink} failed: {source}")]
+    Upload {
+        sink: String,
+        #[source]
+        source: SinkError,
+    },
+    #[error("unsupported CPU: {0}")]
+    UnsupportedCpu(String),
+    #[error("no RAPL energy counters available: {0}")]
+    NoEnergySource(String),
+    #[error("SQLite: {0}")]
+    Sqlite(String),
+    #[error("Postgres: {0}")]
+    Postgres(String),
+    #[error("D-Bus: {0}")]
+    Dbus(String),
+    #[error("SMTP: {0}")]
+    Smtp(String),
+    #[error("cannot set the power limit: {0}")]
+    Control(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl RyzenmonError {
+    // Whether retrying later may succeed. Everything else needs the machine
+    // or the config to change, so the daemon gives up instead of looping.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            RyzenmonError::MsrAccess { source, .. } => source.kind() != io::ErrorKind::PermissionDenied,
+            // A locked or full database may free up
+            RyzenmonError::Upload { .. }
+            | RyzenmonError::Sqlite(_)
+            | RyzenmonError::Postgres(_)
+            | RyzenmonError::Dbus(_)
+            | RyzenmonError::Smtp(_)
+            | RyzenmonError::Io(_) => true,
+            RyzenmonError::Config(_)
+            | RyzenmonError::Topology(_)
+            | RyzenmonError::UnsupportedCpu(_)
+            | RyzenmonError::NoEnergySource(_)
+            | RyzenmonError::Control(_) => false,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for RyzenmonError {
+    fn from(e: rusqlite::Error) -> Self {
+        RyzenmonError::Sqlite(e.to_string())
+    }
+}
+
+impl From<tokio_postgres::Error> for RyzenmonError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        RyzenmonError::Postgres(e.to_string())
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for RyzenmonError {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        RyzenmonError::Smtp(e.to_string())
+    }
+}
+
+impl From<zbus::Error> for RyzenmonError {
+    fn from(e: zbus::Error) -> Self {
+        RyzenmonError::Dbus(e.to_string())
+    }
+}
+
+impl From<toml::de::Error> for RyzenmonError {
+    fn from(e: toml::de::Error) -> Self {
+        RyzenmonError::Config(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permission_denied_msr_is_fatal() {
+        let denied = RyzenmonError::MsrAccess {
+            cpu: 0,
+            source: io::Error::from(io::ErrorKind::PermissionDenied),
+        };
+        let gone = RyzenmonError::MsrAccess {
+            cpu: 0,
+            source: io::Error::from(io::ErrorKind::NotFound),
+        };
+        assert!(!denied.is_transient());
+        assert!(gone.is_transient());
+    }
+
+    #[test]
+    fn upload_errors_are_transient() {
+        let upload = RyzenmonError::Upload {
+            sink: "influxdb".to_string(),
+            source: "connection refused".into(),
+        };
+        assert!(upload.is_transient());
+        assert!(!RyzenmonError::UnsupportedCpu("CentaurHauls".to_string()).is_transient());
+    }
+}
@@ -0,0 +1,144 @@
+use std::io;
+#[cfg(unix)]
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::process::Command;
+use tracing::info;
+
+use ryzenmon_rust::config::Config;
+use ryzenmon_rust::energy::{EnergyCounter, Tariff};
+use ryzenmon_rust::error::RyzenmonError;
+use ryzenmon_rust::sink::SinkRegistry;
+use ryzenmon_rust::{stats, PowerMetrics, Sampler};
+
+// `ryzenmon exec -- <command>`: sample back to back every `interval` while
+// the command runs, then print its energy and power to stderr, and write the
+// samples folded into one to `sinks`. Returns the exit code to exit with,
+// the command's own.
+pub async fn run(
+    config: &Config,
+    mut sampler: Sampler,
+    interval: Duration,
+    command: &[String],
+    mut sinks: SinkRegistry,
+) -> Result<i32, RyzenmonError> {
+    let (program, args) = command.split_first().expect("clap requires a command");
+    let started = Instant::now();
+    let mut child = Command::new(program)
+        .args(args)
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot run {}: {}", program, e)))?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let sampling = tokio::task::spawn_blocking({
+        let stop = stop.clone();
+        move || {
+            let mut samples = Vec::new();
+            // At least one sample, however short the command.
+            loop {
+                let metrics = sampler.sample(interval)?;
+                samples.push((Instant::now(), metrics));
+                if stop.load(Ordering::Relaxed) {
+                    return Ok::<_, RyzenmonError>(samples);
+                }
+            }
+        }
+    });
+
+    // Ctrl-C reaches the command too; keep measuring until it has exited.
+    let status = loop {
+        tokio::select! {
+            status = child.wait() => break status?,
+            _ = tokio::signal::ctrl_c() => {}
+        }
+    };
+    let ended = Instant::now();
+    let runtime = ended - started;
+    stop.store(true, Ordering::Relaxed);
+    let samples = sampling.await.expect("sampling panicked")?;
+
+    let code = status.code().unwrap_or_else(|| 128 + signal(&status));
+    let exited = match status.code() {
+        Some(code) => format!("exited with {}", code),
+        None => format!("was killed by signal {}", signal(&status)),
+    };
+    eprintln!();
+    eprintln!(
+        "{} {} after {:.3} s ({} samples of {:?})",
+        command.join(" "),
+        exited,
+        runtime.as_secs_f64(),
+        samples.len(),
+        interval
+    );
+    eprintln!();
+    eprintln!("{:<10} {:>13} {:>11} {:>11}", "", "energy", "average", "peak");
+    let row = |name: &str, watts: &dyn Fn(&PowerMetrics) -> Option<f64>| {
+        if let Some((joules, average, peak)) = integrate(started, ended, &samples, watts) {
+            eprintln!("{:<10} {:>11.3} J {:>9.3} W {:>9.3} W", name, joules, average, peak);
+        }
+    };
+    row("package", &|m| Some(m.package_watts));
+    row("cores", &|m| Some(m.core_sum));
+    row("dram", &|m| m.dram_watts);
+
+    if sinks.is_empty() {
+        return Ok(code);
+    }
+    let mut energy = EnergyCounter::new(None);
+    energy.set_tariff(Tariff::from_config(&config.energy)?);
+    energy.add(0.0, started);
+    for (at, metrics) in &samples {
+        energy.add(metrics.package_watts, (*at).min(ended));
+    }
+    let name = Path::new(program).file_name().map_or(program.clone(), |name| name.to_string_lossy().into_owned());
+    let mut summary = stats::aggregate(samples.into_iter().map(|(_, metrics)| metrics).collect())
+        .expect("at least one sample was taken");
+    summary.energy = Some(energy.totals());
+    summary.tags.insert("command".to_string(), name);
+    sinks.write_all(&summary).await;
+    sinks.shutdown().await;
+    info!("Uploaded the summary of {}", program);
+    Ok(code)
+}
+
+// The signal that killed the command, or 0.
+#[cfg(unix)]
+fn signal(status: &std::process::ExitStatus) -> i32 {
+    status.signal().unwrap_or(0)
+}
+
+// Every exit has a code on Windows, which has no signals.
+#[cfg(not(unix))]
+fn signal(_status: &std::process::ExitStatus) -> i32 {
+    0
+}
+
+// Energy, average and peak of `watts` over the samples, each covering the
+// time since the previous one, up to when the command exited. None when no
+// sample has the reading.
+fn integrate(
+    started: Instant,
+    ended: Instant,
+    samples: &[(Instant, PowerMetrics)],
+    watts: &dyn Fn(&PowerMetrics) -> Option<f64>,
+) -> Option<(f64, f64, f64)> {
+    let mut last = started;
+    let (mut joules, mut secs, mut peak) = (0.0, 0.0, None::<f64>);
+    for (at, metrics) in samples {
+        let at = (*at).min(ended);
+        let elapsed = at.duration_since(last).as_secs_f64();
+        last = at;
+        let Some(watts) = watts(metrics) else {
+            continue;
+        };
+        joules += watts * elapsed;
+        secs += elapsed;
+        peak = Some(peak.map_or(watts, |peak| peak.max(watts)));
+    }
+    Some((joules, joules / secs.max(f64::EPSILON), peak?))
+}
@@ -0,0 +1,39 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::Duration;
+
+use nix::sys::socket::{connect, socket, AddressFamily, SockAddr, SockFlag, SockType};
+
+// `ryzenmon guest`: inside a virtual machine, read the sample served by the
+// host's [vsock] sink and print it, once or every `every`. Needs neither a
+// config file nor access to the host's network.
+pub fn run(cid: u32, port: u32, field: Option<&str>, every: Option<Duration>) -> io::Result<()> {
+    loop {
+        let line = fetch(cid, port)?;
+        match field {
+            Some(field) => {
+                let sample: serde_json::Value = serde_json::from_str(&line)?;
+                match sample.get(field) {
+                    Some(value) => println!("{}", value),
+                    None => return Err(io::Error::other(format!("the host does not serve {:?}", field))),
+                }
+            }
+            None => println!("{}", line.trim_end()),
+        }
+        match every {
+            Some(every) => std::thread::sleep(every),
+            None => return Ok(()),
+        }
+    }
+}
+
+fn fetch(cid: u32, port: u32) -> io::Result<String> {
+    let fd = socket(AddressFamily::Vsock, SockType::Stream, SockFlag::SOCK_CLOEXEC, None)?;
+    let mut stream = File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+    connect(stream.as_raw_fd(), &SockAddr::new_vsock(cid, port))
+        .map_err(|e| io::Error::new(io::Error::from(e).kind(), format!("cannot connect to vsock {}:{}: {}", cid, port, e)))?;
+    let mut line = String::new();
+    stream.read_to_string(&mut line)?;
+    Ok(line)
+}
@@ -0,0 +1,110 @@
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+use serde::{Serialize, Serializer};
+
+// Liveness of the sampling loop and the sinks, for /healthz and /readyz.
+#[derive(Debug, Clone, Serialize)]
+pub struct Health {
+    #[serde(serialize_with = "serialize_optional_unix_seconds")]
+    pub last_sample: Option<SystemTime>,
+    #[serde(serialize_with = "serialize_optional_unix_seconds")]
+    pub last_upload: Option<SystemTime>,
+    // Points held for retry across all sinks
+    pub buffered_points: usize,
+    // Whether any sink is configured; without one there is nothing to upload
+    pub uploading: bool,
+    #[serde(skip)]
+    pub started: SystemTime,
+    // A sample or upload older than this counts as stalled
+    #[serde(skip)]
+    pub stale_after: Duration,
+}
+
+pub static HEALTH: Lazy<Mutex<Health>> = Lazy::new(|| {
+    Mutex::new(Health {
+        last_sample: None,
+        last_upload: None,
+        buffered_points: 0,
+        uploading: false,
+        started: SystemTime::now(),
+        stale_after: Duration::from_secs(30),
+    })
+});
+
+fn serialize_optional_unix_seconds<S: Serializer>(time: &Option<SystemTime>, serializer: S) -> Result<S::Ok, S::Error> {
+    match time.and_then(|t| t.duration_since(UNIX_EPOCH).ok()) {
+        Some(d) => serializer.serialize_some(&d.as_secs_f64()),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn fresh(time: Option<SystemTime>, now: SystemTime, stale_after: Duration) -> bool {
+    time.is_some_and(|t| now.duration_since(t).unwrap_or_default() <= stale_after)
+}
+
+impl Health {
+    // The sampler is making progress; a daemon that has not sampled yet gets
+    // `stale_after` from startup before it counts as wedged.
+    pub fn healthy(&self, now: SystemTime) -> bool {
+        fresh(self.last_sample, now, self.stale_after) || (self.last_sample.is_none() && fresh(Some(self.started), now, self.stale_after))
+    }
+
+    // Samples are coming in and, when sinks are configured, getting out.
+    pub fn ready(&self, now: SystemTime) -> bool {
+        fresh(self.last_sample, now, self.stale_after) && (!self.uploading || fresh(self.last_upload, now, self.stale_after))
+    }
+}
+
+pub fn record_sample() {
+    HEALTH.lock().unwrap().last_sample = Some(SystemTime::now());
+}
+
+// After every write to the sinks: `succeeded` when none of them failed.
+pub fn record_upload(uploading: bool, succeeded: bool, buffered_points: usize) {
+    let mut health = HEALTH.lock().unwrap();
+    health.uploading = uploading;
+    health.buffered_points = buffered_points;
+    if succeeded {
+        health.last_upload = Some(SystemTime::now());
+    }
+}
+
+// Three sample intervals without progress count as stalled.
+pub fn set_interval(interval: Duration) {
+    HEALTH.lock().unwrap().stale_after = interval * 3;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_sample_is_unhealthy_and_failed_upload_not_ready() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let mut health = Health {
+            last_sample: None,
+            last_upload: None,
+            buffered_points: 0,
+            uploading: true,
+            started: start,
+            stale_after: Duration::from_secs(30),
+        };
+
+        assert!(health.healthy(at(10)));
+        assert!(!health.ready(at(10)));
+        assert!(!health.healthy(at(40)));
+
+        health.last_sample = Some(at(40));
+        health.last_upload = Some(at(40));
+        assert!(health.healthy(at(50)));
+        assert!(health.ready(at(50)));
+
+        health.last_sample = Some(at(80));
+        assert!(health.healthy(at(90)));
+        assert!(!health.ready(at(90)));
+        assert!(!health.healthy(at(120)));
+    }
+}
@@ -0,0 +1,185 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::HistogramConfig;
+use crate::rapl::PowerMetrics;
+
+// Samples per bucket and their sum.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Counts {
+    // One per bound plus the bucket above every bound, not cumulative
+    pub buckets: Vec<u64>,
+    pub sum: f64,
+}
+
+impl Counts {
+    fn new(bounds: &[f64]) -> Self {
+        Counts {
+            buckets: vec![0; bounds.len() + 1],
+            sum: 0.0,
+        }
+    }
+
+    fn add(&mut self, bounds: &[f64], watts: f64) {
+        let bucket = bounds.partition_point(|bound| *bound < watts);
+        self.buckets[bucket] += 1;
+        self.sum += watts;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    // Samples at or below each bound, then all of them, as Prometheus has it.
+    pub fn cumulative(&self) -> Vec<u64> {
+        self.buckets
+            .iter()
+            .scan(0, |total, count| {
+                *total += count;
+                Some(*total)
+            })
+            .collect()
+    }
+}
+
+// Power distribution of one series.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Histogram {
+    // Upper, inclusive edges in watts
+    pub bounds: Vec<f64>,
+    // The samples since the previous upload
+    pub interval: Counts,
+    // Every sample since the daemon started, for Prometheus
+    pub total: Counts,
+}
+
+impl Histogram {
+    fn new(bounds: &[f64]) -> Self {
+        Histogram {
+            bounds: bounds.to_vec(),
+            interval: Counts::new(bounds),
+            total: Counts::new(bounds),
+        }
+    }
+
+    fn add(&mut self, watts: f64) {
+        self.interval.add(&self.bounds, watts);
+        self.total.add(&self.bounds, watts);
+    }
+
+    // The `le` label of each bucket, "+Inf" for the last.
+    pub fn labels(&self) -> Vec<String> {
+        self.bounds
+            .iter()
+            .map(|bound| bound.to_string())
+            .chain(std::iter::once("+Inf".to_string()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PowerHistograms {
+    pub package: Histogram,
+    // In the same order as core_watts
+    pub cores: Vec<Histogram>,
+}
+
+impl PowerHistograms {
+    // Adds the interval counts of an earlier upload, when folding uploads
+    // into one. Histograms with other bounds, from before a reload, are left out.
+    pub fn add_interval(&mut self, earlier: &PowerHistograms) {
+        let pairs = std::iter::once((&mut self.package, &earlier.package)).chain(self.cores.iter_mut().zip(&earlier.cores));
+        for (histogram, earlier) in pairs.filter(|(histogram, earlier)| histogram.bounds == earlier.bounds) {
+            for (count, earlier) in histogram.interval.buckets.iter_mut().zip(&earlier.interval.buckets) {
+                *count += earlier;
+            }
+            histogram.interval.sum += earlier.interval.sum;
+        }
+    }
+}
+
+// Counts every sample, including those folded into one upload with
+// sampling.sample_interval_ms, into the buckets of [histogram].
+pub struct HistogramTracker {
+    config: HistogramConfig,
+    histograms: PowerHistograms,
+}
+
+impl HistogramTracker {
+    pub fn new(config: HistogramConfig) -> Self {
+        let histograms = PowerHistograms {
+            package: Histogram::new(&config.package_buckets),
+            cores: Vec::new(),
+        };
+        HistogramTracker { config, histograms }
+    }
+
+    pub fn config(&self) -> &HistogramConfig {
+        &self.config
+    }
+
+    pub fn observe(&mut self, metrics: &PowerMetrics) {
+        self.histograms.package.add(metrics.package_watts);
+        let cores = &mut self.histograms.cores;
+        if cores.len() < metrics.core_watts.len() {
+            cores.resize_with(metrics.core_watts.len(), || Histogram::new(&self.config.core_buckets));
+        }
+        for (histogram, watts) in cores.iter_mut().zip(&metrics.core_watts) {
+            histogram.add(*watts);
+        }
+    }
+
+    // The counts since the previous call into the sample about to be
+    // uploaded, starting the next interval.
+    pub fn finish(&mut self, metrics: &mut PowerMetrics) {
+        let mut histograms = self.histograms.clone();
+        histograms.cores.truncate(metrics.core_watts.len());
+        metrics.histograms = Some(histograms);
+        let histograms = &mut self.histograms;
+        for histogram in std::iter::once(&mut histograms.package).chain(&mut histograms.cores) {
+            histogram.interval = Counts::new(&histogram.bounds);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::platform::fixture_sample;
+
+    #[test]
+    fn counts_samples_into_buckets() {
+        let mut metrics = fixture_sample();
+        let mut tracker = HistogramTracker::new(HistogramConfig {
+            package_buckets: vec![50.0, 100.0],
+            core_buckets: vec![5.0],
+        });
+        for (package, core) in [(20.0, 1.0), (50.0, 5.0), (75.0, 8.0), (150.0, 12.0)] {
+            metrics.package_watts = package;
+            metrics.core_watts = vec![core, 0.5];
+            tracker.observe(&metrics);
+        }
+        tracker.finish(&mut metrics);
+        let histograms = metrics.histograms.clone().unwrap();
+        // Bounds are inclusive.
+        assert_eq!(histograms.package.interval.buckets, vec![2, 1, 1]);
+        assert_eq!(histograms.package.interval.cumulative(), vec![2, 3, 4]);
+        assert_eq!(histograms.package.interval.sum, 295.0);
+        assert_eq!(histograms.package.labels(), vec!["50", "100", "+Inf"]);
+        assert_eq!(histograms.cores[0].interval.buckets, vec![2, 2]);
+        assert_eq!(histograms.cores[1].interval.buckets, vec![4, 0]);
+
+        // Each interval starts over; the totals don't.
+        metrics.package_watts = 120.0;
+        tracker.observe(&metrics);
+        tracker.finish(&mut metrics);
+        let mut later = metrics.histograms.unwrap();
+        assert_eq!(later.package.interval.buckets, vec![0, 0, 1]);
+        assert_eq!(later.package.total.buckets, vec![2, 1, 2]);
+        assert_eq!(later.package.total.count(), 5);
+
+        later.add_interval(&histograms);
+        assert_eq!(later.package.interval.buckets, vec![2, 1, 2]);
+        assert_eq!(later.package.interval.sum, 415.0);
+    }
+}
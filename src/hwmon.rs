@@ -0,0 +1,277 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::HwmonSensorConfig;
+
+pub const HWMON_ROOT: &str = "/sys/class/hwmon";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemperatureReading {
+    pub label: String,
+    pub celsius: f64,
+}
+
+// Voltage and current of one SVI2/SVI3 rail, as reported by the VRM to the SMU.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RailReading {
+    // vddcr_cpu or vddcr_soc
+    pub rail: String,
+    pub volts: Option<f64>,
+    pub amps: Option<f64>,
+}
+
+// One amdgpu card, numbered by PCI address.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GpuReading {
+    pub gpu: usize,
+    pub watts: Option<f64>,
+    // edge, junction and mem where the card has them
+    pub temperatures: Vec<TemperatureReading>,
+    pub fan_rpm: Option<f64>,
+}
+
+// A sensor declared in a [[hwmon]] config table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SensorReading {
+    pub label: String,
+    pub value: f64,
+    pub unit: String,
+}
+
+// All hwmon directories whose `name` matches `chip`.
+pub fn find_chips(chip: &str) -> Vec<PathBuf> {
+    chips_where(|name| name == chip)
+}
+
+// Every temp*_input of a chip, labelled by temp*_label or the sensor file name.
+pub fn read_temperatures(chip: &Path) -> Vec<TemperatureReading> {
+    let mut readings = Vec::new();
+    let Ok(entries) = fs::read_dir(chip) else {
+        return readings;
+    };
+
+    let mut inputs: Vec<String> = entries
+        .flatten()
+        .filter_map(|e| e.file_name().into_string().ok())
+        .filter(|name| name.starts_with("temp") && name.ends_with("_input"))
+        .collect();
+    inputs.sort();
+
+    for input in inputs {
+        let Some(value) = read_value(&chip.join(&input)) else {
+            continue;
+        };
+        let sensor = input.trim_end_matches("_input");
+        let label = fs::read_to_string(chip.join(format!("{}_label", sensor)))
+            .map(|l| l.trim().to_string())
+            .unwrap_or_else(|_| sensor.to_string());
+
+        readings.push(TemperatureReading {
+            label,
+            celsius: value / 1000.0,
+        });
+    }
+
+    readings
+}
+
+// Tctl, Tdie and Tccd* from every k10temp instance.
+pub fn read_k10temp() -> Vec<TemperatureReading> {
+    find_chips("k10temp")
+        .iter()
+        .flat_map(|chip| read_temperatures(chip))
+        .collect()
+}
+
+pub fn read_value(path: &Path) -> Option<f64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+// The SVI rail a sensor belongs to, from its label: zenpower uses SVI2_Core,
+// SVI2_C_SoC and so on, k10temp (Linux 5.6 and 5.7) Vcore, Isoc and so on.
+fn rail_for_label(label: &str) -> Option<&'static str> {
+    let label = label.to_ascii_lowercase();
+    if label.ends_with("soc") {
+        Some("vddcr_soc")
+    } else if label.ends_with("core") {
+        Some("vddcr_cpu")
+    } else {
+        None
+    }
+}
+
+// VDDCR_CPU and VDDCR_SOC voltage and current from zenpower or k10temp, empty
+// when neither driver exposes them.
+pub fn read_svi_rails() -> Vec<RailReading> {
+    let mut rails: Vec<RailReading> = Vec::new();
+
+    for chip in find_chips("zenpower").iter().chain(find_chips("k10temp").iter()) {
+        let Ok(entries) = fs::read_dir(chip) else {
+            continue;
+        };
+        let mut inputs: Vec<String> = entries
+            .flatten()
+            .filter_map(|e| e.file_name().into_string().ok())
+            .filter(|name| (name.starts_with("in") || name.starts_with("curr")) && name.ends_with("_input"))
+            .collect();
+        inputs.sort();
+
+        for input in inputs {
+            let sensor = input.trim_end_matches("_input");
+            let Ok(label) = fs::read_to_string(chip.join(format!("{}_label", sensor))) else {
+                continue;
+            };
+            let Some(rail) = rail_for_label(label.trim()) else {
+                continue;
+            };
+            // Millivolts and milliamps
+            let Some(value) = read_value(&chip.join(&input)).map(|v| v / 1000.0) else {
+                continue;
+            };
+
+            let reading = match rails.iter_mut().find(|r| r.rail == rail) {
+                Some(reading) => reading,
+                None => {
+                    rails.push(RailReading {
+                        rail: rail.to_string(),
+                        volts: None,
+                        amps: None,
+                    });
+                    rails.last_mut().unwrap()
+                }
+            };
+            // The first driver to report a value wins, zenpower is checked first.
+            if sensor.starts_with("curr") {
+                reading.amps.get_or_insert(value);
+            } else {
+                reading.volts.get_or_insert(value);
+            }
+        }
+    }
+
+    rails.sort_by(|a, b| a.rail.cmp(&b.rail));
+    rails
+}
+
+// SoC power as VDDCR_SOC voltage times current, when both are reported.
+pub fn soc_watts(rails: &[RailReading]) -> Option<f64> {
+    let soc = rails.iter().find(|rail| rail.rail == "vddcr_soc")?;
+    Some(soc.volts? * soc.amps?)
+}
+
+// Power, temperatures and fan speed of every amdgpu card.
+pub fn read_amdgpu() -> Vec<GpuReading> {
+    let mut chips = find_chips("amdgpu");
+    // hwmon numbering depends on probe order, the PCI address does not.
+    chips.sort_by_key(|chip| fs::canonicalize(chip.join("device")).ok());
+
+    chips
+        .iter()
+        .enumerate()
+        .map(|(gpu, chip)| GpuReading {
+            gpu,
+            // Microwatts; RDNA3 only has the instantaneous power1_input.
+            watts: read_value(&chip.join("power1_average"))
+                .or_else(|| read_value(&chip.join("power1_input")))
+                .map(|uw| uw / 1_000_000.0),
+            temperatures: read_temperatures(chip),
+            fan_rpm: read_value(&chip.join("fan1_input")),
+        })
+        .collect()
+}
+
+// Whether `name` matches `pattern`, where `*` matches any run of characters.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(name) = name.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=name.len())
+                .filter(|&i| name.is_char_boundary(i))
+                .any(|i| glob_match(rest, &name[i..]))
+        }
+    }
+}
+
+// Every hwmon directory whose `name` matches the glob `pattern`.
+pub fn find_chips_matching(pattern: &str) -> Vec<PathBuf> {
+    chips_where(|name| glob_match(pattern, name))
+}
+
+fn chips_where(matches: impl Fn(&str) -> bool) -> Vec<PathBuf> {
+    let mut chips = Vec::new();
+    let Ok(entries) = fs::read_dir(HWMON_ROOT) else {
+        return chips;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if let Ok(name) = fs::read_to_string(path.join("name")) {
+            if matches(name.trim()) {
+                chips.push(path);
+            }
+        }
+    }
+
+    chips.sort();
+    chips
+}
+
+// Sensors from the config. A pattern matching several chips gives one reading
+// per chip, labelled <label>_0, <label>_1 and so on.
+pub fn read_sensors(sensors: &[HwmonSensorConfig]) -> Vec<SensorReading> {
+    let mut readings = Vec::new();
+
+    for sensor in sensors {
+        let values: Vec<f64> = find_chips_matching(&sensor.chip)
+            .iter()
+            .filter_map(|chip| read_value(&chip.join(&sensor.input)))
+            .collect();
+        let numbered = values.len() > 1;
+        for (index, value) in values.into_iter().enumerate() {
+            readings.push(SensorReading {
+                label: if numbered {
+                    format!("{}_{}", sensor.label, index)
+                } else {
+                    sensor.label.clone()
+                },
+                value: value * sensor.scale,
+                unit: sensor.unit.clone(),
+            });
+        }
+    }
+
+    readings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matches_exact_and_wildcards() {
+        assert!(glob_match("nvme", "nvme"));
+        assert!(!glob_match("nvme", "nvme1"));
+        assert!(glob_match("nct67*", "nct6798"));
+        assert!(glob_match("*temp", "k10temp"));
+        assert!(glob_match("a*c*e", "abcde"));
+        assert!(!glob_match("nct67*", "it8688"));
+    }
+
+    #[test]
+    fn soc_power_needs_voltage_and_current() {
+        let rail = |rail: &str, volts: Option<f64>, amps: Option<f64>| RailReading {
+            rail: rail.to_string(),
+            volts,
+            amps,
+        };
+        let rails = [rail("vddcr_cpu", Some(1.2), Some(40.0)), rail("vddcr_soc", Some(1.1), Some(10.0))];
+        assert_eq!(soc_watts(&rails), Some(1.1 * 10.0));
+        assert_eq!(soc_watts(&[rail("vddcr_soc", Some(1.1), None)]), None);
+        assert_eq!(soc_watts(&[rail("vddcr_cpu", Some(1.2), Some(40.0))]), None);
+    }
+}
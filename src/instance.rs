@@ -0,0 +1,142 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::SystemTime;
+
+use once_cell::sync::OnceCell;
+
+use crate::config::InstanceConfig;
+
+// This process's identity, set at startup with [instance].
+pub static INSTANCE: OnceCell<Instance> = OnceCell::new();
+
+// Tells this installation apart from every other one, whatever its host tag.
+#[derive(Debug, Clone)]
+pub struct Instance {
+    // Random UUID kept in instance.id_path
+    pub id: String,
+    // Changes on every boot, so two running clones of one disk image, which
+    // share the id file, still differ
+    pub boot_id: String,
+    pub started: SystemTime,
+}
+
+impl Instance {
+    pub fn init(config: &InstanceConfig) -> io::Result<&'static Instance> {
+        let instance = Instance {
+            id: load_id(Path::new(&config.id_path))?,
+            boot_id: read_trimmed("/proc/sys/kernel/random/boot_id").unwrap_or_default(),
+            started: SystemTime::now(),
+        };
+        Ok(INSTANCE.get_or_init(|| instance))
+    }
+
+    // Whether `claim` of the same host tag comes from another machine. A
+    // claim by this installation from an earlier boot is left over from
+    // before a reboot, unless it was made after this process started.
+    pub fn conflicts_with(&self, claim: &Claim) -> bool {
+        if claim.instance != self.id {
+            return true;
+        }
+        claim.boot_id != self.boot_id && claim.seen > self.started
+    }
+}
+
+// The latest claim one installation made on a host tag.
+#[derive(Debug, Clone)]
+pub struct Claim {
+    pub instance: String,
+    pub boot_id: String,
+    // The machine's own hostname, to tell the user which one to fix
+    pub hostname: String,
+    pub seen: SystemTime,
+}
+
+// The id in `path`, made and saved on first use. The file also holds the
+// machine-id the id was made on: a machine cloned from an image with the
+// file on it gets a new machine-id on first boot, and with it a new id.
+fn load_id(path: &Path) -> io::Result<String> {
+    let machine_id = read_trimmed("/etc/machine-id").unwrap_or_default();
+    if let Ok(contents) = fs::read_to_string(path) {
+        let mut lines = contents.lines();
+        if let (Some(id), Some(made_on)) = (lines.next(), lines.next()) {
+            if made_on == machine_id && !id.is_empty() {
+                return Ok(id.to_string());
+            }
+        }
+    }
+    let id = new_uuid()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, format!("{}\n{}\n", id, machine_id))
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot write {}: {}", path.display(), e)))?;
+    Ok(id)
+}
+
+// A random (version 4) UUID.
+fn new_uuid() -> io::Result<String> {
+    let mut bytes = [0u8; 16];
+    fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(format_uuid(bytes))
+}
+
+fn format_uuid(mut bytes: [u8; 16]) -> String {
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
+
+fn read_trimmed(path: &str) -> Option<String> {
+    Some(fs::read_to_string(path).ok()?.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn formats_version_4_uuids() {
+        let uuid = format_uuid([0xff; 16]);
+        assert_eq!(uuid, "ffffffff-ffff-4fff-bfff-ffffffffffff");
+        assert_eq!(format_uuid([0; 16]), "00000000-0000-4000-8000-000000000000");
+    }
+
+    #[test]
+    fn keeps_the_id_until_the_machine_changes() {
+        let path = std::env::temp_dir().join(format!("ryzenmon-instance-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let id = load_id(&path).unwrap();
+        assert_eq!(load_id(&path).unwrap(), id);
+
+        fs::write(&path, format!("{}\nsome-other-machine\n", id)).unwrap();
+        assert_ne!(load_id(&path).unwrap(), id);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn tells_clones_from_reboots() {
+        let started = SystemTime::now();
+        let instance = Instance {
+            id: "a".to_string(),
+            boot_id: "boot2".to_string(),
+            started,
+        };
+        let claim = |instance: &str, boot_id: &str, seen| Claim {
+            instance: instance.to_string(),
+            boot_id: boot_id.to_string(),
+            hostname: "web1".to_string(),
+            seen,
+        };
+        let before = started - Duration::from_secs(60);
+        let after = started + Duration::from_secs(60);
+        assert!(instance.conflicts_with(&claim("b", "boot9", before)));
+        // The previous boot's last claim.
+        assert!(!instance.conflicts_with(&claim("a", "boot1", before)));
+        // A clone of this disk running at the same time.
+        assert!(instance.conflicts_with(&claim("a", "boot1", after)));
+        assert!(!instance.conflicts_with(&claim("a", "boot2", after)));
+    }
+}
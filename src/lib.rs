@@ -0,0 +1,491 @@
+pub mod aggregator;
+pub mod alert;
+pub mod calibration;
+pub mod cgroup;
+pub mod collector;
+pub mod config;
+#[cfg(feature = "control")]
+pub mod control;
+pub mod cpufreq;
+pub mod cpuidle;
+pub mod energy;
+pub mod error;
+pub mod health;
+pub mod histogram;
+pub mod hwmon;
+pub mod instance;
+pub mod logging;
+pub mod msr;
+#[cfg(unix)]
+pub mod pidfile;
+pub mod platform;
+pub mod powercap;
+#[cfg(unix)]
+pub mod privileges;
+pub mod procstat;
+pub mod proto;
+pub mod rapl;
+#[cfg(target_os = "linux")]
+pub mod realtime;
+pub mod ring;
+pub mod schedule;
+pub mod sink;
+pub mod smoothing;
+pub mod smtp;
+pub mod smu;
+pub mod stats;
+pub mod suspend;
+pub mod system_info;
+pub mod systemd;
+pub mod telemetry;
+pub mod throttle;
+pub mod topology;
+pub mod transform;
+pub mod units;
+pub mod validation;
+pub mod windows;
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant, SystemTime};
+
+use tracing::{info, warn};
+
+use collector::Collector;
+use config::{Backend, Calibration};
+use error::{RyzenmonError, Result};
+use msr::{detect_cpu, msr_available, CpuId, MsrAccess, MsrMap, Vendor};
+#[cfg(not(windows))]
+use platform::Host;
+use platform::{Platform, Recorder, Simulated, Trace};
+use powercap::powercap_available;
+use suspend::{Resume, SuspendClock};
+use topology::Topology;
+
+pub use error::RyzenmonError as Error;
+pub use rapl::PowerMetrics;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Msr(Vendor),
+    Powercap,
+}
+
+enum Reader {
+    Amd(rapl::AmdRapl),
+    Intel(rapl::IntelRapl),
+    Powercap(powercap::Powercap),
+}
+
+// Picks the energy counter source once and keeps its devices open across samples.
+pub struct Sampler {
+    source: Source,
+    platform: Box<dyn Platform>,
+    // Replaying a trace, so nothing else on this machine is read
+    simulated: bool,
+    msr: Option<(CpuId, MsrMap)>,
+    topology: Topology,
+    // Online CPU list the topology was detected with
+    online: Option<String>,
+    reader: Reader,
+    collectors: Vec<Box<dyn Collector>>,
+    top_processes: usize,
+    cgroups: Vec<String>,
+    calibration: BTreeMap<String, Calibration>,
+    continuous: bool,
+    baseline: Option<Baseline>,
+    // Only the energy counters are read
+    low_overhead: bool,
+    // The sampling thread is pinned to one CPU
+    pinned: bool,
+    suspend: SuspendClock,
+}
+
+// What the previous sample ended with, where a continuous one starts.
+struct Baseline {
+    at: Instant,
+    idle: Option<Vec<cpuidle::IdleTimes>>,
+    stat: Option<procstat::StatTimes>,
+    processes: Option<procstat::ProcessTimes>,
+    cgroups: cgroup::CgroupUsage,
+}
+
+impl Sampler {
+    #[cfg(not(windows))]
+    pub fn new(topology: Topology, backend: Backend) -> Result<Self> {
+        Self::open(Box::new(Host), topology, host_msr(backend)?, false)
+    }
+
+    // Windows has no powercap, so the MSRs are read through WinRing0.
+    #[cfg(windows)]
+    pub fn new(topology: Topology, backend: Backend) -> Result<Self> {
+        if backend == Backend::Powercap {
+            return Err(RyzenmonError::NoEnergySource("powercap is only available on Linux".to_string()));
+        }
+        let cpu = detect_cpu()?;
+        let map = MsrMap::for_cpu(&cpu)?;
+        Self::open(Box::new(windows::WinRing0::load()?), topology, Some((cpu, map)), false)
+    }
+
+    // Read this machine's MSRs and keep every value read, to be saved as a trace.
+    pub fn recording(topology: Topology) -> Result<(Self, Recorder)> {
+        let (cpu, map) = host_msr(Backend::Msr)?.expect("the MSR backend reads MSRs");
+        let recorder = Recorder::new(cpu, topology.clone());
+        let sampler = Self::open(Box::new(recorder.clone()), topology, Some((cpu, map)), false)?;
+        Ok((sampler, recorder))
+    }
+
+    // Replay the MSR values of a recorded trace instead of reading this machine.
+    pub fn simulated(trace: Trace) -> Result<Self> {
+        let platform = Simulated::new(trace)?;
+        let cpu = platform.cpu()?;
+        let map = MsrMap::for_cpu(&cpu)?;
+        let topology = platform.topology()?;
+        Self::open(Box::new(platform), topology, Some((cpu, map)), true)
+    }
+
+    fn open(
+        platform: Box<dyn Platform>,
+        mut topology: Topology,
+        msr: Option<(CpuId, MsrMap)>,
+        simulated: bool,
+    ) -> Result<Self> {
+        // Numbered before any core is skipped, e.g. in a trace without them.
+        if topology.core_ids.is_empty() {
+            topology.core_ids = (0..topology.cores.len()).collect();
+        }
+        let source = msr.map_or(Source::Powercap, |(cpu, _)| Source::Msr(cpu.vendor));
+        if let Some((cpu, _)) = msr {
+            info!("Reading RAPL MSRs of {}", cpu);
+        }
+        let online = platform.online_cpus();
+        let reader = open_reader(&topology, msr, platform.as_ref())?;
+
+        Ok(Sampler {
+            source,
+            platform,
+            simulated,
+            msr,
+            topology,
+            online,
+            reader,
+            collectors: collector::defaults(),
+            top_processes: 0,
+            cgroups: Vec::new(),
+            calibration: BTreeMap::new(),
+            continuous: false,
+            baseline: None,
+            low_overhead: false,
+            pinned: false,
+            suspend: SuspendClock::new(),
+        })
+    }
+
+    pub fn source(&self) -> Source {
+        self.source
+    }
+
+    // Vendor, family and model, when reading MSRs.
+    pub fn cpu(&self) -> Option<CpuId> {
+        self.msr.map(|(cpu, _)| cpu)
+    }
+
+    pub fn topology(&self) -> &Topology {
+        &self.topology
+    }
+
+    // Readers run after the energy counters on every sample, e.g. from
+    // collector::from_config.
+    pub fn set_collectors(&mut self, collectors: Vec<Box<dyn Collector>>) {
+        self.collectors = collectors;
+    }
+
+    // Run a custom collector on every sample, after the configured ones.
+    pub fn add_collector(&mut self, collector: Box<dyn Collector>) {
+        self.collectors.push(collector);
+    }
+
+    // Attribute package power to the `count` busiest processes, 0 disables.
+    pub fn set_top_processes(&mut self, count: usize) {
+        self.top_processes = count;
+    }
+
+    // cgroup patterns to attribute package power to, empty disables.
+    pub fn set_cgroups(&mut self, patterns: Vec<String>) {
+        self.cgroups = patterns;
+    }
+
+    // [calibration] corrections applied to power as soon as it is read, so
+    // everything derived from it is corrected too.
+    pub fn set_calibration(&mut self, calibration: BTreeMap<String, Calibration>) {
+        self.calibration = calibration;
+    }
+
+    // Measure each sample from where the previous one ended instead of over
+    // its own window, so every moment between samples is covered. The first
+    // sample, and the first after CPUs come or go, still takes the window.
+    pub fn set_continuous(&mut self, enabled: bool) {
+        self.continuous = enabled;
+        if !enabled {
+            self.baseline = None;
+        }
+    }
+
+    // Read only the energy counters, leaving out utilization, C-states,
+    // attribution and the collectors, which scan /proc and sysfs on every sample.
+    pub fn set_low_overhead(&mut self, enabled: bool) {
+        self.low_overhead = enabled;
+    }
+
+    // Whether sampling is pinned to one CPU with sampling.cpu, which the
+    // reads of multi-socket machines then stay on.
+    pub fn set_pinned(&mut self, pinned: bool) {
+        self.pinned = pinned;
+    }
+
+    // Re-detect the topology and reopen the MSR devices when CPUs were onlined
+    // or offlined since the last sample. On failure, e.g. while the kernel is
+    // still bringing a CPU up, the old devices are kept and the next sample
+    // tries again.
+    fn rescan(&mut self) {
+        let online = self.platform.online_cpus();
+        if online == self.online {
+            return;
+        }
+        let rescan = self
+            .platform
+            .topology()
+            .and_then(|topology| Ok((open_reader(&topology, self.msr, self.platform.as_ref())?, topology)));
+        let (reader, topology) = match rescan {
+            Ok(rescan) => rescan,
+            Err(e) => {
+                warn!("Online CPUs changed but the topology could not be rescanned: {}", e);
+                return;
+            }
+        };
+        self.reader = reader;
+        self.baseline = None;
+        info!(
+            "Online CPUs changed to {}, sampling {} cores",
+            online.as_deref().unwrap_or("?").trim(),
+            topology.cores.len()
+        );
+        self.topology = topology;
+        self.online = online;
+    }
+
+    // Read the energy counters over `window`, or since the previous sample
+    // when `continuous`. Cores the reader skipped are dropped from the
+    // topology, so every other per-core reading leaves them out too; returns
+    // which of the previous cores were kept.
+    fn read_counters(&mut self, window: Duration, continuous: bool) -> Result<(PowerMetrics, Vec<bool>)> {
+        // A continuous sample starts where the previous check was made.
+        if !continuous {
+            self.suspend.check();
+        }
+        let mut metrics = match &mut self.reader {
+            Reader::Amd(rapl) => {
+                rapl.set_parallel(!self.pinned);
+                rapl.sample(window, continuous)?
+            }
+            Reader::Intel(rapl) => rapl.sample(window, continuous)?,
+            Reader::Powercap(powercap) => powercap.sample(window, continuous)?,
+        };
+        // The counters may have been reset or kept running through the
+        // suspend, either way the reading is meaningless.
+        if let Some(suspended) = self.suspend.check() {
+            metrics.resume = Some(Resume {
+                at: SystemTime::now(),
+                suspended_secs: suspended.as_secs_f64(),
+            });
+        }
+        calibration::apply(&self.calibration, &mut metrics);
+        let kept = self.topology.retain_cores(|cpu| !metrics.skipped_cores.contains(&cpu));
+        Ok((metrics, kept))
+    }
+
+    // Take one sample, with power averaged over `window`, or in continuous
+    // mode over the time since the previous sample.
+    pub fn sample(&mut self, window: Duration) -> Result<PowerMetrics> {
+        self.rescan();
+        // Nothing else on this machine belongs to a recorded CPU.
+        if self.simulated || self.low_overhead {
+            let (mut metrics, _) = self.read_counters(window, self.continuous)?;
+            metrics.ccds = rapl::ccd_power(&metrics.core_watts, &self.topology.ccds, &[]);
+            self.label_cores(&mut metrics);
+            return Ok(metrics);
+        }
+        // Expanded on every sample, as VMs and containers come and go.
+        let cgroups = cgroup::expand(&self.cgroups);
+        // Without a baseline, the first sample and any after a failure or a
+        // rescan, power is measured over `window` like other readings.
+        let baseline = self.baseline.take().filter(|_| self.continuous);
+        let continuous = baseline.is_some();
+        let since = match baseline {
+            Some(baseline) => baseline,
+            None => Baseline {
+                idle: cpuidle::read_cores(&self.topology.cores),
+                stat: procstat::read_stat(),
+                processes: (self.top_processes > 0).then(procstat::read_processes),
+                cgroups: cgroup::read_usage(&cgroups),
+                at: Instant::now(),
+            },
+        };
+        let (mut metrics, kept) = self.read_counters(window, continuous)?;
+        let mut idle_before = since.idle;
+        if let Some(before) = &mut idle_before {
+            topology::retain_by(before, &kept);
+        }
+        let ended = Instant::now();
+        let idle_after = cpuidle::read_cores(&self.topology.cores);
+        if let (Some(before), Some(after)) = (idle_before, &idle_after) {
+            metrics.cstates = cpuidle::residency(&before, after, ended.duration_since(since.at));
+        }
+        let processes_after = (self.top_processes > 0).then(procstat::read_processes);
+        let cgroups_after = cgroup::read_usage(&cgroups);
+        let stat_after = procstat::read_stat();
+        if let (Some(before), Some(after)) = (&since.stat, &stat_after) {
+            (metrics.core_utilization, metrics.utilization) =
+                procstat::core_utilization(before, after, &self.topology.core_threads);
+            if let (Some(start), Some(end)) = (before.get(&None), after.get(&None)) {
+                let busy = end.busy.saturating_sub(start.busy);
+                if let (Some(before), Some(after)) = (&since.processes, &processes_after) {
+                    metrics.processes =
+                        procstat::attribute_power(before, after, busy, metrics.package_watts, self.top_processes);
+                }
+                metrics.cgroups = cgroup::attribute_power(
+                    &since.cgroups,
+                    &cgroups_after,
+                    busy * cgroup::usec_per_tick(),
+                    metrics.package_watts,
+                );
+            }
+        }
+        if self.continuous {
+            self.baseline = Some(Baseline {
+                at: ended,
+                idle: idle_after,
+                stat: stat_after,
+                processes: processes_after,
+                cgroups: cgroups_after,
+            });
+        }
+        collector::collect(&mut self.collectors, &self.topology, &mut metrics);
+        if let Some(limits) = &metrics.limits {
+            metrics.throttle = Some(throttle::Throttle::from_smu(limits));
+        }
+        if metrics.soc_watts.is_none() {
+            metrics.soc_watts = hwmon::soc_watts(&metrics.rails);
+        }
+        metrics.ccds = rapl::ccd_power(&metrics.core_watts, &self.topology.ccds, &metrics.temperatures);
+        self.label_cores(&mut metrics);
+        Ok(metrics)
+    }
+
+    // Which logical CPUs each core has, its NUMA node, L3 and class, and its
+    // number, which skipped cores don't shift.
+    fn label_cores(&self, metrics: &mut PowerMetrics) {
+        if metrics.core_watts.len() != self.topology.cores.len() {
+            return;
+        }
+        if self.topology.core_threads.len() == self.topology.cores.len() {
+            metrics.core_cpus = self.topology.core_threads.clone();
+        }
+        if self.topology.core_ids.len() == self.topology.cores.len() {
+            metrics.core_ids = self.topology.core_ids.clone();
+        }
+        if self.topology.core_nodes.len() == self.topology.cores.len() {
+            metrics.core_nodes = self.topology.core_nodes.clone();
+        }
+        if self.topology.core_l3s.len() == self.topology.cores.len() {
+            metrics.core_l3s = self.topology.core_l3s.clone();
+        }
+        if self.topology.core_classes.len() == self.topology.cores.len() {
+            metrics.core_classes = self.topology.core_classes.clone();
+        }
+    }
+}
+
+// Root gets the system-wide default paths, such as /run/ryzenmon.pid.
+#[cfg(unix)]
+pub fn is_root() -> bool {
+    nix::unistd::geteuid().is_root()
+}
+
+#[cfg(not(unix))]
+pub fn is_root() -> bool {
+    false
+}
+
+// The MSR layout to read with `backend`, None to read powercap instead.
+fn host_msr(backend: Backend) -> Result<Option<(CpuId, MsrMap)>> {
+    // One actionable message instead of a permission error per core.
+    let msr_hint = || MsrAccess::detect().hint();
+    // The MSR layout for this CPU, or why it has none we can read.
+    let msr_map = || -> Result<(CpuId, MsrMap)> {
+        let cpu = detect_cpu()?;
+        Ok((cpu, MsrMap::for_cpu(&cpu)?))
+    };
+    Ok(match backend {
+        Backend::Msr => match msr_hint() {
+            Some(hint) => return Err(RyzenmonError::NoEnergySource(hint)),
+            None => Some(msr_map()?),
+        },
+        Backend::Powercap => None,
+        Backend::Auto if msr_available() => match msr_map() {
+            Ok(msr) => Some(msr),
+            Err(e) if powercap_available() => {
+                info!("Using powercap: {}", e);
+                None
+            }
+            Err(e) => return Err(e),
+        },
+        Backend::Auto if powercap_available() => {
+            if let Some(hint) = msr_hint() {
+                info!("Using powercap, which has no per-core energy: {}", hint);
+            }
+            None
+        }
+        Backend::Auto => {
+            return Err(RyzenmonError::NoEnergySource(format!(
+                "no /sys/class/powercap RAPL zones are readable and {}",
+                msr_hint().unwrap_or_else(|| "/dev/cpu/*/msr is not readable".to_string())
+            )))
+        }
+    })
+}
+
+fn open_reader(topology: &Topology, msr: Option<(CpuId, MsrMap)>, platform: &dyn Platform) -> Result<Reader> {
+    Ok(match msr {
+        Some((cpu, map)) => match cpu.vendor {
+            Vendor::Amd => Reader::Amd(rapl::AmdRapl::open(topology, map, platform)?),
+            Vendor::Intel => Reader::Intel(rapl::IntelRapl::open(topology, map, platform)?),
+        },
+        None => Reader::Powercap(powercap::Powercap::default()),
+    })
+}
+
+// Take one sample across all physical cores, with power averaged over `window`.
+pub fn sample(window: Duration) -> Result<PowerMetrics> {
+    Sampler::new(Topology::detect()?, Backend::Auto)?.sample(window)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::fixture;
+
+    #[test]
+    fn keeps_core_numbers_when_a_core_is_skipped() {
+        let mut trace = fixture();
+        // CPU 5 comes first but has no MSR in the trace.
+        trace.topology.cores.insert(0, 5);
+        trace.topology.ccds.insert(0, 0);
+        trace.topology.core_threads.insert(0, vec![5]);
+        let mut sampler = Sampler::simulated(trace).unwrap();
+
+        let metrics = sampler.sample(Duration::from_millis(10)).unwrap();
+        assert_eq!(metrics.skipped_cores, vec![5]);
+        assert_eq!(metrics.per_core(&metrics.core_watts).map(|(core, _)| core).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(metrics.core_cpu_list(0).as_deref(), Some("0,2"));
+    }
+}
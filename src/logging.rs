@@ -0,0 +1,56 @@
+use std::env;
+use std::io::IsTerminal;
+
+use once_cell::sync::OnceCell;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::config::{LogConfig, LogFormat};
+
+// Kept so a config reload can change the level without restarting.
+static FILTER: OnceCell<reload::Handle<EnvFilter, Registry>> = OnceCell::new();
+
+// Log to stderr, keeping stdout free for samples. RUST_LOG wins over
+// log.level when set; `verbose` turns on debug output for ryzenmon itself.
+pub fn init(config: &LogConfig, verbose: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (filter, handle) = reload::Layer::new(build_filter(config, verbose)?);
+    let registry = tracing_subscriber::registry().with(filter);
+    let writer = std::io::stderr;
+
+    match config.format {
+        LogFormat::Text => registry
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .with_writer(writer)
+                    .with_ansi(std::io::stderr().is_terminal()),
+            )
+            .try_init()?,
+        LogFormat::Json => registry
+            .with(tracing_subscriber::fmt::layer().json().with_writer(writer))
+            .try_init()?,
+    }
+
+    let _ = FILTER.set(handle);
+    Ok(())
+}
+
+// Apply a new log.level; log.format only takes effect on restart.
+pub fn reload(config: &LogConfig, verbose: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let filter = build_filter(config, verbose)?;
+    if let Some(handle) = FILTER.get() {
+        handle.reload(filter)?;
+    }
+    Ok(())
+}
+
+fn build_filter(config: &LogConfig, verbose: bool) -> Result<EnvFilter, Box<dyn std::error::Error + Send + Sync>> {
+    if let Ok(directives) = env::var(EnvFilter::DEFAULT_ENV) {
+        return Ok(EnvFilter::try_new(directives)?);
+    }
+    let mut directives = config.level.clone();
+    if verbose {
+        directives.push_str(",ryzenmon_rust=debug");
+    }
+    EnvFilter::try_new(&directives).map_err(|e| format!("invalid log.level {:?}: {}", config.level, e).into())
+}
@@ -1,221 +1,994 @@
-use std::fs::{self, File, OpenOptions};
-use std::io::{self, Read, Seek, SeekFrom, Write};
+mod aggregate;
+mod bar;
+mod calibrate;
+mod check;
+mod cli;
+mod completions;
+mod exec;
+#[cfg(target_os = "linux")]
+mod guest;
+mod man;
+mod once;
+mod query;
+mod record;
+mod signals;
+mod snapshot;
+#[cfg(unix)]
+mod status;
+mod tui;
+
+#[cfg(unix)]
+use std::io;
+#[cfg(unix)]
 use std::path::Path;
-use std::process::exit;
-use std::thread;
-use std::time::Duration;
-use serde::Deserialize;
-use once_cell::sync::Lazy;
-use std::sync::Mutex;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Local};
+use clap::Parser;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::time::{Interval, MissedTickBehavior};
+use tracing::{debug, debug_span, error, info, warn, Instrument};
+
+use ryzenmon_rust::alert::Alerter;
+#[cfg(feature = "control")]
+use ryzenmon_rust::control::{self, Policy};
+use ryzenmon_rust::config::{load_config, write_example_config, Config, SamplingConfig, SamplingMode, SharedConfig};
+use ryzenmon_rust::energy::{EnergyCounter, Tariff};
+use ryzenmon_rust::error::RyzenmonError;
+use ryzenmon_rust::histogram::HistogramTracker;
+use ryzenmon_rust::instance::Instance;
+#[cfg(unix)]
+use ryzenmon_rust::pidfile::PidFile;
+use ryzenmon_rust::schedule::Schedule;
+use ryzenmon_rust::stats::IdleFloor;
+#[cfg(unix)]
+use ryzenmon_rust::privileges;
+#[cfg(target_os = "linux")]
+use ryzenmon_rust::realtime;
+use ryzenmon_rust::{collector, health, logging, ring, schedule, stats, systemd, telemetry};
+use ryzenmon_rust::platform::Trace;
+use ryzenmon_rust::smoothing::Smoother;
+use ryzenmon_rust::suspend::Resume;
+use ryzenmon_rust::system_info::SYSTEM_INFO;
+use ryzenmon_rust::throttle::ThrottleTracker;
+use ryzenmon_rust::topology::Topology;
+use ryzenmon_rust::transform::Transform;
+use ryzenmon_rust::units::Units;
+use ryzenmon_rust::validation::Validator;
+use ryzenmon_rust::{PowerMetrics, Sampler};
+use ryzenmon_rust::sink::{build_points, to_line_protocol, AgentSink, SinkRegistry, StdoutSink};
+
+use cli::{Cli, Command};
+use signals::{Signal, Signals};
+
+// Samples waiting for the uploader. At 100 Hz this covers ten seconds of
+// stalled uploads; beyond that, samples are dropped rather than delaying
+// the next measurement.
+const SAMPLE_QUEUE: usize = 1024;
+
+// A sample and when it was taken.
+type Sample = (Instant, Result<PowerMetrics, RyzenmonError>);
+
+// State built once at startup and reused by every worker iteration.
+struct Context {
+    // Shared with the sampling task
+    config: SharedConfig,
+    sampler: Arc<Mutex<Sampler>>,
+    sinks: SinkRegistry,
+    alerter: Option<Alerter>,
+    energy: EnergyCounter,
+    idle_floor: Option<IdleFloor>,
+    // Samples since the last upload and when the next one is due, with
+    // sampling.sample_interval_ms
+    samples: Vec<PowerMetrics>,
+    upload_due: Instant,
+    // Upload the next sample right away and flush the sinks, after SIGUSR1
+    forced: bool,
+    smoother: Option<Smoother>,
+    // Throttle residency and events across the samples of an upload
+    throttle: ThrottleTracker,
+    // Power buckets across the samples of an upload, with [histogram]
+    histograms: Option<HistogramTracker>,
+    // The last resume from suspend, until an upload reports it
+    resume: Option<Resume>,
+    // [[schedule]] rules for the upload interval and which sinks are written
+    schedule: Schedule,
+    validator: Option<Validator>,
+    transform: Option<Transform>,
+    // Samples taken before this are discarded, with sampling.warmup_secs
+    warmup_until: Option<Instant>,
+    // Not while simulating, which would set this machine's limit
+    #[cfg(feature = "control")]
+    policy: Option<Policy>,
+}
 
-use influxdb2::models::DataPoint;
-use influxdb2::Client;
-use futures::stream;
+// Take samples on their own schedule, so neither a slow upload nor the
+// measurement window of the next sample holds the other up. Stops once the
+// uploader is gone.
+async fn sample_loop(mut thread: SamplingThread, config: SharedConfig, samples: mpsc::Sender<Sample>) {
+    let mut schedule = Schedule::default();
+    let mut tick = sample_tick(&config.load(), &schedule);
+    let mut ticks = grid_interval(tick);
+    // The first sample is taken right away, off the grid, so startup and
+    // --once don't wait for the next tick.
+    let mut first = true;
+    let mut adaptive = schedule::Adaptive::default();
+    loop {
+        let (window, period) = {
+            let config = config.load();
+            // Validated with the config.
+            if config.schedule != schedule.config() {
+                schedule = Schedule::new(&config.schedule).unwrap_or_default();
+            }
+            let period = match &config.sampling.adaptive {
+                Some(fast) => adaptive.period(fast, sample_tick(&config, &schedule), Instant::now()),
+                None => sample_tick(&config, &schedule),
+            };
+            (Duration::from_millis(config.sampling.window_ms), period)
+        };
+        if period != tick {
+            debug!("Sampling every {:?}", period);
+            ticks = grid_interval(period);
+            tick = period;
+        }
+        let scheduled = if std::mem::take(&mut first) {
+            None
+        } else {
+            ticks.tick().await;
+            Some(schedule::align(SystemTime::now(), period))
+        };
+
+        let started = Instant::now();
+        let result = thread.sample(window).await.map(|mut metrics| {
+            // Stamped with the tick it was scheduled for, so points line up
+            // across samples and hosts.
+            if let Some(scheduled) = scheduled {
+                metrics.timestamp = scheduled;
+            }
+            metrics
+        });
+        telemetry::record_sample_duration(started.elapsed());
+        record_result(&result);
+        if let (Ok(metrics), Some(fast)) = (&result, &config.load().sampling.adaptive) {
+            adaptive.observe(fast, metrics.package_watts, metrics.utilization, Instant::now());
+        }
 
-const AMD_MSR_PWR_UNIT: u64 = 0xC0010299;
-const AMD_MSR_CORE_ENERGY: u64 = 0xC001029A;
-const AMD_MSR_PACKAGE_ENERGY: u64 = 0xC001029B;
-const AMD_ENERGY_UNIT_MASK: u64 = 0x1F00;
+        match samples.try_send((Instant::now(), result)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => debug!("Uploads are falling behind, dropping a sample"),
+            Err(TrySendError::Closed(_)) => return,
+        }
+    }
+}
 
-const MAX_CPUS: usize = 1024;
-const MAX_PACKAGES: usize = 16;
+// Take one sample on the blocking pool: the window is spent sleeping, which
+// must not stall the runtime.
+async fn sample_blocking(sampler: Arc<Mutex<Sampler>>, window: Duration) -> Result<PowerMetrics, RyzenmonError> {
+    tokio::task::spawn_blocking(move || {
+        debug_span!("sample", window_ms = window.as_millis() as u64).in_scope(|| sampler.lock().unwrap().sample(window))
+    })
+    .await
+    .expect("sampling panicked")
+}
 
-const RYZENMON_CONFIG_DIR: &str = "/etc/ryzenmon";
-const RYZENMON_CONFIG_PATH: &str = "/etc/ryzenmon/config.toml";
-// Configuration has: influxdb host, org, token, bucket
+// The thread scheduled samples are taken on, the same one every time, so it
+// can be pinned and given its own priority. Samples are requested one at a
+// time and answered over channels that are reused, so a sample costs no
+// task or thread of its own.
+struct SamplingThread {
+    requests: std::sync::mpsc::SyncSender<Duration>,
+    results: mpsc::Receiver<Result<PowerMetrics, RyzenmonError>>,
+}
 
+impl SamplingThread {
+    // Returns once the thread is pinned and its priority set, which may need
+    // privileges that are dropped later. Stops when the handle is dropped.
+    fn spawn(sampler: Arc<Mutex<Sampler>>, config: &SamplingConfig) -> Result<Self, RyzenmonError> {
+        let (requests, windows) = std::sync::mpsc::sync_channel::<Duration>(1);
+        let (results_sender, results) = mpsc::channel(1);
+        let (applied_sender, applied) = std::sync::mpsc::sync_channel(1);
+        let config = config.clone();
+        std::thread::Builder::new().name("sampler".to_string()).spawn(move || {
+            // Off Linux, config validation refuses sampling.cpu, nice and fifo_priority.
+            #[cfg(target_os = "linux")]
+            let result = realtime::apply_to_current_thread(&config);
+            #[cfg(not(target_os = "linux"))]
+            let result: Result<(), RyzenmonError> = Ok(());
+            sampler.lock().unwrap().set_pinned(config.cpu.is_some());
+            let ok = result.is_ok();
+            let _ = applied_sender.send(result);
+            if !ok {
+                return;
+            }
+            while let Ok(window) = windows.recv() {
+                let result = debug_span!("sample", window_ms = window.as_millis() as u64)
+                    .in_scope(|| sampler.lock().unwrap().sample(window));
+                if results_sender.blocking_send(result).is_err() {
+                    return;
+                }
+            }
+        })?;
+        applied.recv().expect("the sampling thread stopped before starting")?;
+        Ok(SamplingThread { requests, results })
+    }
 
-#[derive(Deserialize, Debug, Default)]
-struct Config {
-    influxdb: InfluxDBConfig,
+    async fn sample(&mut self, window: Duration) -> Result<PowerMetrics, RyzenmonError> {
+        self.requests.send(window).expect("the sampling thread stopped");
+        self.results.recv().await.expect("sampling panicked")
+    }
 }
 
-#[derive(Deserialize, Debug, Default)]
-struct InfluxDBConfig {
-    host: String,
-    org: String,
-    token: String,
-    bucket: String,
+fn record_result(result: &Result<PowerMetrics, RyzenmonError>) {
+    match result {
+        Ok(_) => health::record_sample(),
+        Err(RyzenmonError::MsrAccess { .. }) => telemetry::record_msr_read_error(),
+        Err(_) => {}
+    }
 }
 
-static CONFIG: Lazy<Mutex<Config>> = Lazy::new(|| Mutex::new(Config::default()));
+// Ticks on the grid of `period`, however long each sample takes. After a
+// stall, missed ticks are skipped rather than sampled in a burst.
+fn grid_interval(period: Duration) -> Interval {
+    let start = tokio::time::Instant::now() + schedule::until_next(SystemTime::now(), period);
+    let mut ticks = tokio::time::interval_at(start, period);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    ticks
+}
 
-fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
-    if !Path::new(RYZENMON_CONFIG_PATH).exists() {
-        fs::create_dir_all(RYZENMON_CONFIG_DIR)?;
+// Handle one sample from the sampling task: account its energy, and upload
+// it, or the combination of all samples since the last upload once one is due.
+async fn worker(cli: &Cli, ctx: &mut Context, (sampled_at, result): Sample) -> Result<(), RyzenmonError> {
+    let mut metrics = result?;
+    let local_time = DateTime::<Local>::from(metrics.timestamp);
+    let config = ctx.config.load();
+    let interval = Duration::from_secs(ctx.schedule.interval_secs(&local_time, config.sampling.interval_secs));
+    // The first sample after a resume spans the suspend, so it goes the same
+    // way; the resume itself goes out with the next upload.
+    if let Some(resume) = metrics.resume.take() {
+        warn!(
+            "Discarding the first sample after resuming from suspend ({:.0?} suspended)",
+            Duration::from_secs_f64(resume.suspended_secs)
+        );
+        telemetry::record_resume();
+        ctx.resume = Some(resume);
+        return Ok(());
+    }
+    // Dropped before anything sees it, so neither the energy total nor an
+    // aggregate is thrown off by a counter reset.
+    if let Some(reason) = metrics.implausible(config.sampling.max_watts) {
+        warn!("Discarding a sample: {}", reason);
+        return Ok(());
+    }
+    if let Some(validator) = &mut ctx.validator {
+        if !validator.apply(&mut metrics) {
+            return Ok(());
+        }
+    }
+    if ctx.warmup_until.is_some_and(|until| sampled_at < until) {
+        debug!("Warming up, not uploading the sample");
+        return Ok(());
+    }
+    ctx.energy.add(metrics.package_watts, sampled_at);
+    if let Some(ring) = ring::RING.get() {
+        ring.push(&metrics);
+    }
+    ctx.throttle.observe(&metrics);
+    if let Some(histograms) = &mut ctx.histograms {
+        histograms.observe(&metrics);
+    }
 
-        let example_config = r#"
-[influxdb]
-host = "http://localhost:8086"
-org = "your_org"
-token = "your_token"
-bucket = "your_bucket"
-"#;
-        let mut file = fs::File::create(RYZENMON_CONFIG_PATH)?;
-        file.write_all(example_config.as_bytes())?;
-        println!("Created example config at {}", RYZENMON_CONFIG_PATH);
-        exit(1);
+    if config.sampling.sample_interval_ms.is_some() && !cli.once {
+        ctx.samples.push(metrics);
+        let now = Instant::now();
+        if now < ctx.upload_due && !ctx.forced {
+            return Ok(());
+        }
+        // Skip ahead rather than catching up after a stall, staying on the grid.
+        while ctx.upload_due <= now {
+            ctx.upload_due += interval;
+        }
+        metrics = stats::aggregate(std::mem::take(&mut ctx.samples)).expect("at least one sample was just collected");
+    }
+    ctx.throttle.finish(&mut metrics);
+    if let Some(histograms) = &mut ctx.histograms {
+        histograms.finish(&mut metrics);
+    }
+    if let Some(smoother) = &mut ctx.smoother {
+        smoother.apply(&mut metrics);
+    }
+    if let Some(transform) = &ctx.transform {
+        match transform.apply(metrics) {
+            Some(transformed) => metrics = transformed,
+            None => return Ok(()),
+        }
     }
 
-    let config_content = fs::read_to_string(RYZENMON_CONFIG_PATH)?;
-    let config: Config = toml::from_str(&config_content)?;
-    Ok(config)
-}
+    ctx.energy.persist();
+    metrics.energy = Some(ctx.energy.totals());
+    if let Some(idle_floor) = &mut ctx.idle_floor {
+        metrics.idle_floor_watts = idle_floor.push(metrics.timestamp, metrics.package_watts);
+    }
+    metrics.resume = ctx.resume.take();
+    metrics.self_telemetry = Some(telemetry::snapshot());
 
-#[derive(Debug)]
-pub struct PowerMetrics {
-    core_watts: Vec<f64>,
-    core_sum: f64,
-    package_watts: f64,
-}
+    if cli.no_upload {
+        println!("{:?}", metrics);
+        return Ok(());
+    }
+    debug!("Sampled {:?}", metrics);
+
+    // Exactly what the InfluxDB sinks would send, one point per line.
+    if cli.dry_run {
+        let (per_core, schema) = match (config.influxdb.first(), &config.influxdb1) {
+            (Some(influxdb), _) => (influxdb.per_core, influxdb.schema.clone()),
+            (None, Some(influxdb1)) => (influxdb1.per_core, influxdb1.schema.clone()),
+            (None, None) => Default::default(),
+        };
+        // In the units of [units], as the sinks convert them.
+        Units::new(config.units.clone()).apply(&mut metrics);
+        let lines = build_points(&metrics, per_core, &config.resolved_tags(), &schema)
+            .and_then(|points| to_line_protocol(&points))
+            .map_err(|source| RyzenmonError::Upload {
+                sink: "influxdb".to_string(),
+                source,
+            })?;
+        for line in lines {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
 
-fn detect_packages() -> io::Result<usize> {
-    let mut package_map = vec![-1; MAX_PACKAGES];
-    let mut total_cores = 0;
+    #[cfg(feature = "control")]
+    if let Some(policy) = &ctx.policy {
+        apply_policy(policy, &metrics).await;
+    }
 
-    for i in 0..MAX_CPUS {
-        let filename = format!("/sys/devices/system/cpu/cpu{}/topology/physical_package_id", i);
-        if let Ok(contents) = std::fs::read_to_string(&filename) {
-            let package: i32 = contents.trim().parse().unwrap_or(-1);
-            if package_map[package as usize] == -1 {
-                package_map[package as usize] = i as i32;
-            }
-            total_cores = i + 1;
-        } else {
-            break;
-        }
+    // Alerts go out before the upload so a slow or failing sink cannot hold them up.
+    if let Some(alerter) = &mut ctx.alerter {
+        let events = alerter.evaluate(&metrics, Instant::now());
+        alerter.notify(events);
     }
 
-    Ok(total_cores)
-}
+    // SIGUSR1 asks for every sink, whatever the schedule.
+    let forced = ctx.forced;
+    let schedule = &ctx.schedule;
+    let started = Instant::now();
+    ctx.sinks
+        .write_where(&metrics, |sink| forced || schedule.uploads_to(&local_time, sink))
+        .instrument(debug_span!("upload"))
+        .await;
+    telemetry::record_upload_latency(started.elapsed());
 
-fn open_msr(core: usize) -> io::Result<File> {
-    let msr_filename = format!("/dev/cpu/{}/msr", core);
-    OpenOptions::new()
-        .read(true)
-        .open(&msr_filename)
-        .map_err(|e| {
-            eprintln!("Failed to open MSR for core {}: {}", core, e);
-            e
-        })
+    Ok(())
 }
 
-fn read_msr(file: &mut File, which: u64) -> io::Result<i64> {
-    let mut buffer = [0u8; 8];
-    file.seek(SeekFrom::Start(which))?;
-    file.read_exact(&mut buffer)?;
-    Ok(i64::from_ne_bytes(buffer))
+// Move the power limit as [control.policy] asks after `metrics`, from the
+// limit in effect now.
+#[cfg(feature = "control")]
+async fn apply_policy(policy: &Policy, metrics: &PowerMetrics) {
+    let current = tokio::task::spawn_blocking(control::read_ppt).await.expect("reading the power limit panicked");
+    let current = match current {
+        Ok(current) => current,
+        Err(e) => return warn!("Power limit policy: {}", e),
+    };
+    let Some(limit) = policy.adjust(metrics, current) else {
+        return;
+    };
+    let config = policy.config().clone();
+    if let Err(e) = tokio::task::spawn_blocking(move || control::set_ppt(&config, limit)).await.expect("setting the power limit panicked") {
+        warn!("Power limit policy: {}", e);
+    }
 }
 
-fn rapl_msr_amd_core(total_cores: usize) -> io::Result<PowerMetrics> {
-    let mut core_energy = vec![0.0; total_cores/2];
-    let mut core_energy_delta = vec![0.0; total_cores/2];
-    let mut package = vec![0.0; total_cores/2];
-    let mut package_delta = vec![0.0; total_cores/2];
-    let mut files: Vec<File> = Vec::new();
+// Run every sample of a recorded trace through the worker, back to back,
+// stamped with and integrated over the times they were recorded at.
+async fn replay(cli: &Cli, ctx: &mut Context, trace: &Trace) -> Result<(), RyzenmonError> {
+    let window = Duration::from_millis(
+        trace.window_ms.unwrap_or_else(|| ctx.config.load().sampling.window_ms),
+    );
+    let started = Instant::now();
+    let first = trace.timestamps.first().copied().unwrap_or_default();
+    for &timestamp in &trace.timestamps {
+        let result = sample_blocking(ctx.sampler.clone(), window).await.map(|mut metrics| {
+            metrics.timestamp = UNIX_EPOCH + Duration::from_secs_f64(timestamp);
+            metrics
+        });
+        let sampled_at = started + Duration::from_secs_f64(timestamp - first);
+        worker(cli, ctx, (sampled_at, result)).await?;
+    }
+    info!("Replayed {} samples", trace.timestamps.len());
+    Ok(())
+}
 
-    for i in 0..total_cores/2 {
-        files.push(open_msr(i)?);
+// Time between samples: the upload interval, as [[schedule]] has it now,
+// unless sampling faster.
+fn sample_tick(config: &Config, schedule: &Schedule) -> Duration {
+    match config.sampling.sample_interval_ms {
+        Some(ms) => Duration::from_millis(ms),
+        None => Duration::from_secs(schedule.interval_secs(&Local::now(), config.sampling.interval_secs)),
     }
+}
 
-    let core_energy_units = read_msr(&mut files[0], AMD_MSR_PWR_UNIT)? as u64;
-    let energy_unit = (core_energy_units & AMD_ENERGY_UNIT_MASK) >> 8;
-    let energy_unit_d = 0.5f64.powf(energy_unit as f64);
+// How long a sample may take to arrive before it counts as overdue.
+fn longest_interval(config: &Config) -> Duration {
+    let schedule = Schedule::new(&config.schedule).unwrap_or_default();
+    Duration::from_secs(schedule.longest_interval_secs(config.sampling.interval_secs))
+}
 
-    for i in 0..total_cores/2 {
-        let core_energy_raw = read_msr(&mut files[i], AMD_MSR_CORE_ENERGY)? as f64;
-        let package_raw = read_msr(&mut files[i], AMD_MSR_PACKAGE_ENERGY)? as f64;
-        
-        core_energy[i] = core_energy_raw * energy_unit_d;
-        package[i] = package_raw * energy_unit_d;
+// Load the config file with command line overrides applied on top.
+fn read_config(cli: &Cli) -> Result<Config, RyzenmonError> {
+    let config_optional = cli.no_upload
+        || cli.dry_run
+        || cli.output.is_some()
+        || cli.agent.is_some()
+        || matches!(
+            cli.command,
+            Some(
+                Command::Tui
+                    | Command::Once { .. }
+                    | Command::Bar { .. }
+                    | Command::Record { .. }
+                    | Command::Calibrate { .. }
+                    | Command::Status
+            )
+        )
+        || matches!(cli.command, Some(Command::Exec { upload: false, .. }))
+        || matches!(cli.command, Some(Command::Query { db: Some(_), .. }));
+    let mut config = if config_optional && !cli.config.exists() {
+        Config::default()
+    } else {
+        load_config(&cli.config)?
+    };
+    if let Some(interval) = cli.interval {
+        config.sampling.interval_secs = interval;
     }
+    if let Some(window_ms) = cli.window_ms {
+        config.sampling.window_ms = window_ms;
+    }
+    config.validate()?;
+    Ok(config)
+}
 
-    thread::sleep(Duration::from_micros(100000));
+// Whether the command writes to sinks, unless told not to with --no-upload or --dry-run.
+fn uploads(cli: &Cli) -> bool {
+    matches!(
+        cli.command,
+        None | Some(Command::Replay { .. } | Command::Aggregator | Command::Exec { upload: true, .. })
+    )
+}
 
-    for i in 0..total_cores/2 {
-        let core_energy_raw = read_msr(&mut files[i], AMD_MSR_CORE_ENERGY)? as f64;
-        let package_raw = read_msr(&mut files[i], AMD_MSR_PACKAGE_ENERGY)? as f64;
-        
-        core_energy_delta[i] = core_energy_raw * energy_unit_d;
-        package_delta[i] = package_raw * energy_unit_d;
+fn build_sinks(cli: &Cli, config: &Config) -> Result<SinkRegistry, Box<dyn std::error::Error + Send + Sync>> {
+    if cli.no_upload || cli.dry_run || !uploads(cli) {
+        return Ok(SinkRegistry::default());
+    }
+    if let Some(output) = cli.output {
+        let mut sinks = SinkRegistry::default();
+        sinks.register(Box::new(StdoutSink { format: output.into() }));
+        return Ok(sinks);
+    }
+    if let Some(address) = &cli.agent {
+        let mut sinks = SinkRegistry::default();
+        sinks.register(Box::new(AgentSink::new(address, config.agent.clone(), config.resolved_tags())?));
+        info!("Streaming samples to the aggregator at {}", address);
+        return Ok(sinks);
     }
 
-    let mut core_watts = Vec::with_capacity(total_cores/2);
-    let mut sum = 0.0;
-    let package_watts = (package_delta[0] - package[0]) * 10.0;
+    let sinks = SinkRegistry::from_config(config)?;
+    if sinks.is_empty() {
+        warn!("No sinks configured, samples will be discarded");
+    } else {
+        info!("Enabled sinks: {}", sinks.names().join(", "));
+    }
+    Ok(sinks)
+}
 
-    for i in 0..total_cores/2 {
-        let watts = (core_energy_delta[i] - core_energy[i]) * 10.0;
-        core_watts.push(watts);
-        sum += watts;
+// build_sinks, then prepare_all, releasing the sinks' ports if that fails.
+async fn build_prepared_sinks(cli: &Cli, config: &Config) -> Result<SinkRegistry, Box<dyn std::error::Error + Send + Sync>> {
+    let mut sinks = build_sinks(cli, config)?;
+    if let Err(e) = sinks.prepare_all().await {
+        sinks.close_all().await;
+        return Err(e);
     }
+    Ok(sinks)
+}
 
-    Ok(PowerMetrics {
-        core_watts,
-        core_sum: sum,
-        package_watts,
-    })
+// Swap in `sinks` once the ones they replace have flushed what they can and
+// handed over what they couldn't.
+async fn replace_sinks(ctx: &mut Context, sinks: SinkRegistry) {
+    let mut previous = std::mem::replace(&mut ctx.sinks, sinks);
+    previous.flush_all().await;
+    ctx.sinks.adopt_buffers(&mut previous);
+    previous.close_all().await;
 }
 
-async fn upload(metrics : PowerMetrics) -> Result<(), Box<dyn std::error::Error>> {
-    let config = CONFIG.lock().unwrap();
-    let InfluxDBConfig { host, org, token, bucket } = &config.influxdb;
-    let client = Client::new(host, org, token);
-
-    let points = vec![
-        DataPoint::builder("power")
-            .tag("host", "pvehost")
-            .tag("service", "ryzen-rapl")
-            .field("core-power", metrics.core_sum)
-            .build()?,
-        DataPoint::builder("power")
-            .tag("host", "pvehost")
-            .tag("service", "ryzen-rapl")
-            .field("package-power", metrics.package_watts)
-            .build()?,
-    ];
-
-    client.write(bucket, stream::iter(points)).await?;
-    Ok(())
+fn build_alerter(cli: &Cli, config: &Config) -> Result<Option<Alerter>, RyzenmonError> {
+    if cli.no_upload || cli.dry_run || !matches!(cli.command, None | Some(Command::Replay { .. })) {
+        return Ok(None);
+    }
+    config.alerts.as_ref().map(Alerter::new).transpose()
 }
 
-async fn worker(total_cores: usize) -> Result<(), Box<dyn std::error::Error>> {
-    let metrics = rapl_msr_amd_core(total_cores)?;
+// Re-read the config and swap it in along with freshly built sinks. Only the
+// sinks that listen are closed first, to free their addresses; the others run
+// until their replacements are ready, then flush and hand over what is still
+// held for retry. On any error the running config is kept.
+async fn reload(cli: &Cli, ctx: &mut Context) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let config = read_config(cli)?;
+    let previous = ctx.config.load();
+    if config.sampling.backend != previous.sampling.backend {
+        warn!("Changing sampling.backend requires a restart");
+    }
+    if config.log.format != previous.log.format {
+        warn!("Changing log.format requires a restart");
+    }
+    if config.privileges != previous.privileges {
+        warn!("Changing [privileges] requires a restart");
+    }
+    if config.energy.state_path != previous.energy.state_path {
+        warn!("Changing energy.state_path requires a restart");
+    }
+    if config.daemon != previous.daemon {
+        warn!("Changing [daemon] requires a restart");
+    }
+    if config.instance.as_ref().map(|i| &i.id_path) != previous.instance.as_ref().map(|i| &i.id_path) {
+        warn!("Changing [instance] or its id_path requires a restart");
+    }
+
+    logging::reload(&config.log, cli.verbose)?;
 
-    if let Err(e) = upload(metrics).await {
-        eprintln!("Upload failed: {}", e);
+    ctx.sinks.close_listeners().await;
+    match build_prepared_sinks(cli, &config).await {
+        Ok(sinks) => replace_sinks(ctx, sinks).await,
+        Err(e) => {
+            // Brings the listeners back as they were.
+            match build_prepared_sinks(cli, &previous).await {
+                Ok(sinks) => replace_sinks(ctx, sinks).await,
+                Err(e) => error!("Restoring the sinks failed, running on without those that listen: {}", e),
+            }
+            logging::reload(&previous.log, cli.verbose)?;
+            return Err(e);
+        }
     }
 
+    let mut alerter = build_alerter(cli, &config)?;
+    if let (Some(alerter), Some(previous)) = (&mut alerter, &ctx.alerter) {
+        alerter.keep_state(previous);
+    }
+    ctx.alerter = alerter;
+    ctx.energy.set_tariff(Tariff::from_config(&config.energy)?);
+    ctx.schedule = Schedule::new(&config.schedule)?;
+    // Kept unless the window changed, so a reload doesn't forget the floor.
+    let idle_floor_window = config.idle_floor.as_ref().map(|idle_floor| Duration::from_secs(idle_floor.window_secs));
+    if idle_floor_window != ctx.idle_floor.as_ref().map(IdleFloor::window) {
+        ctx.idle_floor = idle_floor_window.map(IdleFloor::new);
+    }
+    // Kept unless changed, so a reload doesn't restart the averages.
+    if config.smoothing.as_ref() != ctx.smoother.as_ref().map(Smoother::config) {
+        ctx.smoother = config.smoothing.clone().map(Smoother::new);
+    }
+    if config.histogram.as_ref() != ctx.histograms.as_ref().map(HistogramTracker::config) {
+        ctx.histograms = config.histogram.clone().map(HistogramTracker::new);
+    }
+    if config.validation.as_ref() != ctx.validator.as_ref().map(Validator::config) {
+        ctx.validator = config.validation.clone().map(Validator::new);
+    }
+    // Compiled again even when the config is the same, to pick up an edited script.
+    ctx.transform = config.transform.clone().map(Transform::new).transpose()?;
+    #[cfg(feature = "control")]
+    if cli.simulate.is_none() && config.control.as_ref() != ctx.policy.as_ref().map(Policy::config) {
+        ctx.policy = config.control.clone().and_then(Policy::new);
+    }
+
+    {
+        let mut sampler = ctx.sampler.lock().unwrap();
+        sampler.set_collectors(collector::from_config(&config));
+        sampler.set_top_processes(config.sampling.top_processes);
+        sampler.set_cgroups(config.sampling.cgroups.clone());
+        sampler.set_low_overhead(config.sampling.low_overhead);
+        sampler.set_calibration(config.calibration.clone());
+        sampler.set_continuous(config.sampling.mode == SamplingMode::Continuous);
+    }
+    ctx.config.store(config);
     Ok(())
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let config = load_config()?;
-    {
-        let mut global_config = CONFIG.lock().unwrap();
-        *global_config = config;
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut cli = Cli::parse();
+    if cli.telegraf {
+        cli.once = true;
+        cli.dry_run = true;
+    }
+    if let Some(Command::CheckConfig) = cli.command {
+        let passed = check::run(&cli).await;
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+    if let Some(Command::Snapshot { format }) = cli.command {
+        snapshot::run(&cli, format);
+        return Ok(());
+    }
+    if let Some(Command::Completions { shell }) = cli.command {
+        completions::print(shell);
+        return Ok(());
+    }
+    if let Some(Command::Man) = cli.command {
+        man::print();
+        return Ok(());
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(Command::Guest { cid, port, field, every }) = &cli.command {
+        if let Err(e) = guest::run(*cid, *port, field.as_deref(), *every) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    #[cfg(not(target_os = "linux"))]
+    if let Some(Command::Guest { .. }) = cli.command {
+        eprintln!("Error: guest needs vsock, which is only available on Linux");
+        std::process::exit(1);
+    }
+    if let Some(Command::Init { path, force }) = &cli.command {
+        let path = path.as_ref().unwrap_or(&cli.config);
+        write_example_config(path, *force)?;
+        println!("Wrote example config to {}", path.display());
+        return Ok(());
     }
-    println!("Loaded config: {:?}", *CONFIG.lock().unwrap());
-
-    let total_cores = detect_packages();
 
-    let mut cores = 0;
-    match total_cores {
-        Ok(total_cores) => {
-            println!("Detected {} cores", total_cores);
-            cores = total_cores;
-        },
+    // Printed plainly rather than as a Debug dump, since a missing or broken
+    // config is the most common startup failure.
+    let config = match read_config(&cli) {
+        Ok(config) => config,
         Err(e) => {
-            eprintln!("Failed to detect cores: {}", e);
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    #[cfg(unix)]
+    if let Some(Command::Status) = cli.command {
+        std::process::exit(status::run(&config));
+    }
+    #[cfg(not(unix))]
+    if let Some(Command::Status) = cli.command {
+        eprintln!("Error: status needs the PID file lock, which is only available on Unix");
+        std::process::exit(4);
+    }
+    // A daemon's sample costs nothing, where sampling here takes a window
+    // and MSR access.
+    if let Some(Command::Bar { format, waybar, .. }) = &cli.command {
+        if let Some(metrics) = bar::from_daemon(&config) {
+            bar::print(&metrics, format, *waybar);
             return Ok(());
         }
     }
+    logging::init(&config.log, cli.verbose)?;
+    debug!("Loaded config: {:?}", config);
+
+    // Taken before any sink binds its port, so a second daemon says why it
+    // can't start. Only daemons that report count: the subcommands sample
+    // briefly or not at all, and a simulated CPU isn't this machine's.
+    #[cfg(unix)]
+    let reports = cli.command.is_none() && !cli.once && !cli.no_upload && !cli.dry_run && cli.simulate.is_none();
+    #[cfg(unix)]
+    let pid_file = if reports && !config.daemon.pid_file.is_empty() {
+        match PidFile::acquire(Path::new(&config.daemon.pid_file)) {
+            Ok(pid_file) => Some(pid_file),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+            Err(e) => {
+                warn!("Cannot write {}, not checking for another instance: {}", config.daemon.pid_file, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // Before the sinks are built, which tag their points with it.
+    if let Some(instance) = config.instance.as_ref().filter(|_| uploads(&cli)) {
+        match Instance::init(instance) {
+            Ok(instance) => info!("Instance {}", instance.id),
+            Err(e) => {
+                eprintln!("Error: cannot keep the instance id in {}: {}", instance.id_path, e);
+                std::process::exit(1);
+            }
+        }
+    }
 
-    loop {
-        if let Err(e) = worker(cores).await {
-            eprintln!("Worker failed: {}", e);
+    let shared_config = SharedConfig::new(config);
+    let config = shared_config.load();
+    let mut sinks = build_sinks(&cli, &config)?;
+    // Reported once here, rather than as a failure on every write.
+    if let Err(e) = sinks.prepare_all().await {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+    let alerter = build_alerter(&cli, &config)?;
+
+    if let Some(Command::Record { out, duration }) = &cli.command {
+        return Ok(record::run(&config, out, *duration).await?);
+    }
+    if let Some(Command::Query { metrics, since, until, db }) = &cli.command {
+        if let Err(e) = query::run(&config, metrics, *since, *until, db.as_deref()) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(Command::Aggregator) = &cli.command {
+        return Ok(aggregate::run(&config, sinks).await?);
+    }
+    if let Some(Command::SetLimit { ppt }) = &cli.command {
+        let Some(control) = &config.control else {
+            eprintln!("Error: setting the power limit needs [control] in the config");
+            std::process::exit(1);
+        };
+        #[cfg(feature = "control")]
+        {
+            match ryzenmon_rust::control::set_ppt(control, *ppt) {
+                Ok(interface) => println!("Set the package power limit to {} W through {}", ppt, interface),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return Ok(());
         }
-        tokio::time::sleep(Duration::from_secs(10)).await;
+        // Config validation rejects [control] in such builds.
+        #[cfg(not(feature = "control"))]
+        unreachable!("[control] {:?} passed validation without the control feature, asked for {} W", control, ppt);
     }
+
+    let trace = match &cli.command {
+        Some(Command::Replay { trace }) => Some(trace),
+        _ => cli.simulate.as_ref(),
+    };
+    let trace = trace.map(|path| Trace::load(path).map(|trace| (path, trace))).transpose()?;
+    let mut sampler = match &trace {
+        Some((path, trace)) => {
+            info!("Simulating the CPU recorded in {}", path.display());
+            Sampler::simulated(trace.clone())?
+        }
+        None => {
+            let topology = match Topology::detect() {
+                Ok(topology) => {
+                    info!(
+                        "Detected {} packages, {} cores ({} threads)",
+                        topology.packages.len(),
+                        topology.cores.len(),
+                        topology.threads
+                    );
+                    topology
+                },
+                Err(e) => {
+                    error!("{}", e);
+                    return Ok(());
+                }
+            };
+            Sampler::new(topology, config.sampling.backend)?
+        }
+    };
+    sampler.set_collectors(collector::from_config(&config));
+    sampler.set_top_processes(config.sampling.top_processes);
+    sampler.set_cgroups(config.sampling.cgroups.clone());
+    // Calibrating compares the readings as they are.
+    if !matches!(cli.command, Some(Command::Calibrate { .. })) {
+        sampler.set_calibration(config.calibration.clone());
+    }
+    info!("Sampling from {:?}", sampler.source());
+
+    match &cli.command {
+        Some(Command::Tui) => {
+            // The dashboard is for watching live, so it refreshes every second unless told otherwise.
+            let interval = Duration::from_secs(cli.interval.unwrap_or(1));
+            let window = Duration::from_millis(config.sampling.window_ms).min(interval / 2);
+            let history = Duration::from_secs(config.history.clone().unwrap_or_default().minutes * 60);
+            tui::run(&mut sampler, window, interval, history)?;
+            return Ok(());
+        }
+        Some(Command::Once { duration }) => {
+            let metrics = sampler.sample(*duration)?;
+            once::print_summary(&metrics, *duration);
+            return Ok(());
+        }
+        Some(Command::Bar { format, waybar, duration }) => {
+            let metrics = sampler.sample(*duration)?;
+            bar::print(&metrics, format, *waybar);
+            return Ok(());
+        }
+        Some(Command::Calibrate { duration }) => {
+            calibrate::run(&mut sampler, *duration)?;
+            return Ok(());
+        }
+        Some(Command::Exec { every, command, .. }) => {
+            let code = exec::run(&config, sampler, *every, command, sinks).await?;
+            std::process::exit(code);
+        }
+        Some(
+            Command::CheckConfig
+            | Command::Status
+            | Command::Init { .. }
+            | Command::Completions { .. }
+            | Command::Man
+            | Command::Record { .. }
+            | Command::Replay { .. }
+            | Command::Query { .. }
+            | Command::Aggregator
+            | Command::SetLimit { .. }
+            | Command::Snapshot { .. }
+            | Command::Guest { .. },
+        )
+        | None => {}
+    }
+
+    // Traces are replayed one window at a time, and the subcommands above
+    // show everything there is.
+    if cli.command.is_none() {
+        sampler.set_continuous(config.sampling.mode == SamplingMode::Continuous);
+        sampler.set_low_overhead(config.sampling.low_overhead);
+    }
+
+    // Sized for the fastest sampling; changing history.minutes takes a restart.
+    if let Some(history) = &config.history {
+        let tick = match &config.sampling.adaptive {
+            Some(adaptive) => sample_tick(&config, &Schedule::default()).min(Duration::from_millis(adaptive.fast_interval_ms)),
+            None => sample_tick(&config, &Schedule::default()),
+        };
+        let capacity = history.minutes as u128 * 60_000 / tick.as_millis().max(1);
+        let _ = ring::RING.set(ring::Ring::new(capacity as usize));
+    }
+
+    let mut energy = EnergyCounter::new(config.energy.state_path.as_ref().map(PathBuf::from));
+    energy.set_tariff(Tariff::from_config(&config.energy)?);
+    let mut ctx = Context {
+        config: shared_config,
+        sampler: Arc::new(Mutex::new(sampler)),
+        sinks,
+        alerter,
+        energy,
+        idle_floor: config.idle_floor.as_ref().map(|idle_floor| IdleFloor::new(Duration::from_secs(idle_floor.window_secs))),
+        samples: Vec::new(),
+        forced: false,
+        smoother: config.smoothing.clone().map(Smoother::new),
+        throttle: ThrottleTracker::new(),
+        histograms: config.histogram.clone().map(HistogramTracker::new),
+        resume: None,
+        schedule: Schedule::new(&config.schedule)?,
+        validator: config.validation.clone().map(Validator::new),
+        transform: config.transform.clone().map(Transform::new).transpose()?,
+        // A replayed trace was recorded after its own start, and --once has no
+        // time to spare.
+        warmup_until: (config.sampling.warmup_secs > 0 && !matches!(cli.command, Some(Command::Replay { .. })) && !cli.once)
+            .then(|| Instant::now() + Duration::from_secs(config.sampling.warmup_secs)),
+        #[cfg(feature = "control")]
+        policy: config.control.clone().filter(|_| trace.is_none()).and_then(Policy::new),
+        upload_due: Instant::now() + schedule::until_next(SystemTime::now(), Duration::from_secs(config.sampling.interval_secs)),
+    };
+
+    if let (Some(Command::Replay { .. }), Some((_, trace))) = (&cli.command, &trace) {
+        let result = replay(&cli, &mut ctx, trace).await;
+        ctx.sinks.shutdown().await;
+        return Ok(result?);
+    }
+
+    if config.system_info.as_ref().is_some_and(|system_info| system_info.info_point) {
+        ctx.sinks.write_info_all(&SYSTEM_INFO).await;
+    }
+
+    // Pinned and prioritized while still root.
+    let sampling_thread = SamplingThread::spawn(ctx.sampler.clone(), &config.sampling)?;
+    let placement: Vec<String> = [
+        config.sampling.cpu.map(|cpu| format!("pinned to CPU {}", cpu)),
+        config.sampling.nice.map(|nice| format!("nice {}", nice)),
+        config.sampling.fifo_priority.map(|priority| format!("SCHED_FIFO priority {}", priority)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if !placement.is_empty() {
+        info!("Sampling thread {}", placement.join(", "));
+    }
+
+    // Everything that needs root (MSR devices, privileged ports, buffer files)
+    // is open by now; the long-running loop does not need it.
+    #[cfg(unix)]
+    if let Some(user) = &config.privileges.user {
+        privileges::drop_privileges(&config.privileges)?;
+        info!("Dropped privileges, running as {}", user);
+    }
+
+    let interval = longest_interval(&config);
+    health::set_interval(interval);
+    if let Some(timeout) = systemd::watchdog_timeout() {
+        if timeout < interval * 2 {
+            warn!(
+                "WatchdogSec ({:?}) should be at least twice the sample interval ({:?})",
+                timeout, interval
+            );
+        }
+    }
+
+    let mut signals = Signals::new()?;
+
+    let (sender, mut samples) = mpsc::channel(SAMPLE_QUEUE);
+    let sampling = tokio::spawn(sample_loop(sampling_thread, ctx.config.clone(), sender));
+
+    let mut ready = false;
+    let result = loop {
+        let sample = tokio::select! {
+            sample = samples.recv() => match sample {
+                Some(sample) => sample,
+                None => break Err("sampling task stopped".into()),
+            },
+            signal = signals.recv() => match signal {
+                Signal::Stop(name) => {
+                    info!("Received {}, shutting down", name);
+                    break Ok(());
+                }
+                Signal::Reload => {
+                    match reload(&cli, &mut ctx).await {
+                        Ok(()) => {
+                            health::set_interval(longest_interval(&ctx.config.load()));
+                            info!("Reloaded config from {}", cli.config.display());
+                        }
+                        Err(e) => error!("Config reload failed, keeping the previous config: {}", e),
+                    }
+                    continue;
+                }
+                Signal::Flush => {
+                    info!("Received SIGUSR1, sampling and flushing now");
+                    // Out of band: the sampling task keeps its own schedule.
+                    let window = Duration::from_millis(ctx.config.load().sampling.window_ms);
+                    let result = sample_blocking(ctx.sampler.clone(), window).await;
+                    record_result(&result);
+                    ctx.forced = true;
+                    (Instant::now(), result)
+                }
+            },
+        };
+
+        let result = worker(&cli, &mut ctx, sample).await;
+        if std::mem::take(&mut ctx.forced) {
+            ctx.sinks.flush_all().await;
+        }
+        match result {
+            Ok(()) => {
+                // Readiness waits for the first good sample; the watchdog is only
+                // fed while the loop keeps completing.
+                if !ready {
+                    systemd::notify_ready();
+                    ready = true;
+                }
+                systemd::notify_watchdog();
+            }
+            Err(e) => {
+                error!("Worker failed: {}", e);
+                // Transient failures are retried on the next interval; anything
+                // else would fail the same way every time.
+                if cli.once || !e.is_transient() {
+                    break Err(e.into());
+                }
+            }
+        }
+        if cli.once {
+            break Ok(());
+        }
+    };
+
+    let _ = systemd::notify("STOPPING=1");
+    sampling.abort();
+    let _ = sampling.await;
+    ctx.sinks.shutdown().await;
+    // Dropping the context closes the MSR devices.
+    drop(ctx);
+    #[cfg(unix)]
+    if let Some(pid_file) = pid_file {
+        pid_file.remove();
+    }
+
+    result
 }
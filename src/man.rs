@@ -0,0 +1,49 @@
+use std::io::{self, Write};
+
+use clap::CommandFactory;
+use clap_mangen::Man;
+
+use ryzenmon_rust::config::{ENV_PREFIX, RYZENMON_CONFIG_PATH};
+
+use crate::cli::Cli;
+
+// ryzenmon-rust(1) in roff, generated from the clap definitions, with the
+// description, files and environment that clap doesn't know about.
+pub fn print() {
+    // The default config path depends on who generates the page, and the
+    // help already names both candidates.
+    let command = Cli::command().mut_arg("config", |arg| arg.hide_default_value(true));
+    let name = command.get_name().to_string();
+    let man = Man::new(command);
+    if let Err(e) = render(&man, &name, &mut io::stdout().lock()) {
+        eprintln!("Failed to print the man page: {}", e);
+    }
+}
+
+fn render(man: &Man, name: &str, out: &mut dyn Write) -> io::Result<()> {
+    man.render_title(out)?;
+    man.render_name_section(out)?;
+    man.render_synopsis_section(out)?;
+    out.write_all(b".SH DESCRIPTION\nWithout a command, samples the RAPL energy counters every interval and writes the readings to the sinks in the config file, until SIGTERM or SIGINT. SIGHUP reloads the config and SIGUSR1 samples and flushes right away.\n")?;
+    man.render_options_section(out)?;
+    man.render_subcommands_section(out)?;
+    // Not default_config_path(), which depends on who generates the page.
+    write!(
+        out,
+        ".SH FILES\n.TP\n$XDG_CONFIG_HOME/ryzenmon/config.toml\nThe config file when it exists, unless \\fB\\-\\-config\\fR is given.\n.TP\n{}\nThe config file otherwise. \\fB{} init\\fR writes a commented example.\n",
+        escape(RYZENMON_CONFIG_PATH),
+        escape(name),
+    )?;
+    write!(
+        out,
+        ".SH ENVIRONMENT\n.TP\n{}<SECTION>_<KEY>\nOverrides a config value, e.g. {}INFLUXDB_TOKEN.\n.TP\nRUST_LOG\nLog filter, overriding log.level.\n",
+        escape(ENV_PREFIX),
+        escape(ENV_PREFIX),
+    )?;
+    man.render_version_section(out)
+}
+
+// Hyphens are roff syntax.
+fn escape(text: &str) -> String {
+    text.replace('-', "\\-")
+}
@@ -0,0 +1,481 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io;
+#[cfg(not(unix))]
+use std::io::{Read, Seek, SeekFrom};
+#[cfg(unix)]
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+#[cfg(unix)]
+use nix::errno::Errno;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{RyzenmonError, Result};
+
+pub const AMD_MSR_PWR_UNIT: u64 = 0xC0010299;
+pub const AMD_MSR_CORE_ENERGY: u64 = 0xC001029A;
+pub const AMD_MSR_PACKAGE_ENERGY: u64 = 0xC001029B;
+pub const AMD_ENERGY_UNIT_MASK: u64 = 0x1F00;
+
+pub const INTEL_MSR_RAPL_POWER_UNIT: u64 = 0x606;
+pub const INTEL_MSR_PKG_ENERGY_STATUS: u64 = 0x611;
+pub const INTEL_MSR_DRAM_ENERGY_STATUS: u64 = 0x619;
+pub const INTEL_MSR_PP0_ENERGY_STATUS: u64 = 0x639;
+// PROCHOT, thermal and power limit status of the package
+pub const INTEL_MSR_PACKAGE_THERM_STATUS: u64 = 0x1B1;
+pub const INTEL_ENERGY_UNIT_MASK: u64 = 0x1F00;
+
+// Architectural, implemented by AMD as well
+pub const MSR_TSC: u64 = 0x10;
+pub const MSR_MPERF: u64 = 0xE7;
+pub const MSR_APERF: u64 = 0xE8;
+
+// errno of reading an MSR the CPU doesn't implement, which faults
+pub const EIO: i32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Vendor {
+    Amd,
+    Intel,
+}
+
+// First value of a field in /proc/cpuinfo, e.g. "vendor_id".
+fn cpuinfo_field(cpuinfo: &str, field: &str) -> Option<String> {
+    cpuinfo
+        .lines()
+        .find(|line| line.split(':').next().map(str::trim) == Some(field))
+        .and_then(|line| line.split_once(':'))
+        .map(|(_, value)| value.trim().to_string())
+}
+
+pub fn detect_vendor() -> Result<Vendor> {
+    Ok(detect_cpu()?.vendor)
+}
+
+// Windows has no /proc/cpuinfo; only the tests parse one there.
+#[cfg_attr(windows, allow(dead_code))]
+fn parse_vendor(cpuinfo: &str) -> Result<Vendor> {
+    vendor(&cpuinfo_field(cpuinfo, "vendor_id").unwrap_or_default())
+}
+
+fn vendor(vendor_id: &str) -> Result<Vendor> {
+    match vendor_id {
+        "AuthenticAMD" | "HygonGenuine" => Ok(Vendor::Amd),
+        "GenuineIntel" => Ok(Vendor::Intel),
+        other => Err(RyzenmonError::UnsupportedCpu(format!("vendor {:?}", other))),
+    }
+}
+
+// Vendor, family and model as /proc/cpuinfo reports them, with the extended
+// family and model bits of CPUID already folded in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CpuId {
+    pub vendor: Vendor,
+    pub family: u32,
+    pub model: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZenGeneration {
+    Zen,
+    Zen2,
+    Zen3,
+    Zen4,
+    Zen5,
+}
+
+impl CpuId {
+    #[cfg_attr(windows, allow(dead_code))]
+    fn parse(cpuinfo: &str) -> Result<Self> {
+        let vendor = parse_vendor(cpuinfo)?;
+        let number = |field| {
+            cpuinfo_field(cpuinfo, field)
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| RyzenmonError::UnsupportedCpu(format!("/proc/cpuinfo has no {:?}", field)))
+        };
+        Ok(CpuId {
+            vendor,
+            family: number("cpu family")?,
+            model: number("model")?,
+        })
+    }
+
+    // From the vendor string of CPUID leaf 0 and the signature in EAX of leaf 1,
+    // folding in the extended family and model bits the way the kernel does.
+    pub fn from_cpuid(vendor_id: &str, signature: u32) -> Result<Self> {
+        let base_family = (signature >> 8) & 0xF;
+        let mut family = base_family;
+        let mut model = (signature >> 4) & 0xF;
+        if base_family == 0xF {
+            family += (signature >> 20) & 0xFF;
+        }
+        if base_family >= 0x6 {
+            model |= ((signature >> 16) & 0xF) << 4;
+        }
+        Ok(CpuId {
+            vendor: vendor(vendor_id)?,
+            family,
+            model,
+        })
+    }
+
+    // None for Intel and for AMD CPUs before Zen.
+    pub fn zen_generation(&self) -> Option<ZenGeneration> {
+        if self.vendor != Vendor::Amd {
+            return None;
+        }
+        match (self.family, self.model) {
+            // Zen and Zen+, and Hygon Dhyana which is built on Zen
+            (0x17, 0x00..=0x2F) | (0x18, _) => Some(ZenGeneration::Zen),
+            (0x17, _) => Some(ZenGeneration::Zen2),
+            (0x19, 0x10..=0x1F | 0x60..=0x7F | 0xA0..=0xAF) => Some(ZenGeneration::Zen4),
+            (0x19, _) => Some(ZenGeneration::Zen3),
+            (0x1A, _) => Some(ZenGeneration::Zen5),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CpuId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} family {:#x} model {:#x}", self.vendor, self.family, self.model)?;
+        if let Some(generation) = self.zen_generation() {
+            write!(f, " ({:?})", generation)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(windows))]
+pub fn detect_cpu() -> Result<CpuId> {
+    CpuId::parse(&std::fs::read_to_string("/proc/cpuinfo")?)
+}
+
+// Windows has no /proc/cpuinfo, so ask the CPU itself.
+#[cfg(windows)]
+pub fn detect_cpu() -> Result<CpuId> {
+    use std::arch::x86_64::__cpuid;
+
+    #[allow(unused_unsafe)]
+    let (leaf0, leaf1) = unsafe { (__cpuid(0), __cpuid(1)) };
+    let vendor_id: Vec<u8> = [leaf0.ebx, leaf0.edx, leaf0.ecx].iter().flat_map(|r| r.to_le_bytes()).collect();
+    CpuId::from_cpuid(&String::from_utf8_lossy(&vendor_id), leaf1.eax)
+}
+
+// Intel server parts count DRAM energy in fixed 15.3 uJ units rather than the
+// unit in MSR_RAPL_POWER_UNIT (Haswell-EP onwards, see the Linux intel_rapl driver).
+const INTEL_SERVER_DRAM_ENERGY_UNIT: f64 = 15.3e-6;
+const INTEL_SERVER_MODELS: &[u32] = &[0x3F, 0x4F, 0x55, 0x56, 0x57, 0x6A, 0x6C, 0x85, 0x8F, 0xAD, 0xAE, 0xCF];
+
+// Where a CPU keeps its RAPL energy counters. Every Zen generation uses the
+// same 0xC00102xx layout; Intel differs in the DRAM energy unit between
+// client and server parts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MsrMap {
+    pub power_unit: u64,
+    pub energy_unit_mask: u64,
+    pub package_energy: u64,
+    // Per core on AMD, all cores together (PP0) on Intel
+    pub core_energy: u64,
+    pub dram_energy: Option<u64>,
+    // Joules per DRAM counter increment, when it isn't the power unit's
+    pub dram_energy_unit: Option<f64>,
+}
+
+impl MsrMap {
+    pub fn for_cpu(cpu: &CpuId) -> Result<Self> {
+        match cpu.vendor {
+            Vendor::Amd => match cpu.zen_generation() {
+                Some(_) => Ok(MsrMap {
+                    power_unit: AMD_MSR_PWR_UNIT,
+                    energy_unit_mask: AMD_ENERGY_UNIT_MASK,
+                    package_energy: AMD_MSR_PACKAGE_ENERGY,
+                    core_energy: AMD_MSR_CORE_ENERGY,
+                    dram_energy: None,
+                    dram_energy_unit: None,
+                }),
+                None => Err(RyzenmonError::UnsupportedCpu(format!(
+                    "{} predates Zen and has no RAPL energy MSRs, use sampling.backend = \"powercap\" if the kernel supports it",
+                    cpu
+                ))),
+            },
+            // RAPL arrived with Sandy Bridge (model 0x2A); the Westmere parts numbered above it lack it
+            Vendor::Intel if cpu.family == 6 && cpu.model >= 0x2A && !matches!(cpu.model, 0x2C | 0x2E | 0x2F) => {
+                Ok(MsrMap {
+                    power_unit: INTEL_MSR_RAPL_POWER_UNIT,
+                    energy_unit_mask: INTEL_ENERGY_UNIT_MASK,
+                    package_energy: INTEL_MSR_PKG_ENERGY_STATUS,
+                    core_energy: INTEL_MSR_PP0_ENERGY_STATUS,
+                    dram_energy: Some(INTEL_MSR_DRAM_ENERGY_STATUS),
+                    dram_energy_unit: INTEL_SERVER_MODELS
+                        .contains(&cpu.model)
+                        .then_some(INTEL_SERVER_DRAM_ENERGY_UNIT),
+                })
+            }
+            Vendor::Intel => Err(RyzenmonError::UnsupportedCpu(format!("{} has no RAPL energy MSRs", cpu))),
+        }
+    }
+}
+
+// Marketing name, e.g. "AMD Ryzen 9 5950X 16-Core Processor".
+pub fn cpu_model() -> Option<String> {
+    let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").ok()?;
+    cpuinfo_field(&cpuinfo, "model name")
+}
+
+// Microcode revision of the first CPU, e.g. "0xa201016".
+pub fn cpu_microcode() -> Option<String> {
+    let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").ok()?;
+    cpuinfo_field(&cpuinfo, "microcode")
+}
+
+// "cpu family" from /proc/cpuinfo, 0x17 for Zen/Zen 2, 0x19 for Zen 3/Zen 4.
+pub fn cpu_family() -> Option<u32> {
+    let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").ok()?;
+    cpuinfo_field(&cpuinfo, "cpu family")?.parse().ok()
+}
+
+// Whether MSRs can be read at all.
+pub fn msr_available() -> bool {
+    open_msr(0).is_ok()
+}
+
+// The stock msr driver's device, or msr-safe's (https://github.com/LLNL/msr-safe)
+// when that is not readable. msr-safe lets non-root users read the MSRs listed
+// in /dev/cpu/msr_allowlist.
+pub fn open_msr(core: usize) -> io::Result<File> {
+    let open = |path: &str| OpenOptions::new().read(true).open(path);
+    let msr_safe = format!("/dev/cpu/{}/msr_safe", core);
+    match open(&format!("/dev/cpu/{}/msr", core)) {
+        Ok(file) => Ok(file),
+        Err(_) if Path::new(&msr_safe).exists() => open(&msr_safe),
+        Err(e) => Err(e),
+    }
+}
+
+// CAP_SYS_RAWIO, which the stock msr driver requires on top of file permissions
+const CAP_SYS_RAWIO: u32 = 17;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceAccess {
+    Missing,
+    Denied,
+    Readable,
+}
+
+fn device_access(path: &str) -> DeviceAccess {
+    match OpenOptions::new().read(true).open(path) {
+        Ok(_) => DeviceAccess::Readable,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => DeviceAccess::Missing,
+        Err(_) => DeviceAccess::Denied,
+    }
+}
+
+// What stands between this process and the MSRs, checked once at startup.
+#[derive(Debug, Clone, Copy)]
+pub struct MsrAccess {
+    pub msr: DeviceAccess,
+    pub msr_safe: DeviceAccess,
+    pub root: bool,
+    pub raw_io: bool,
+}
+
+impl MsrAccess {
+    pub fn detect() -> Self {
+        let raw_io = std::fs::read_to_string("/proc/self/status")
+            .ok()
+            .and_then(|status| effective_capabilities(&status))
+            .is_some_and(|caps| caps & (1 << CAP_SYS_RAWIO) != 0);
+        MsrAccess {
+            msr: device_access("/dev/cpu/0/msr"),
+            msr_safe: device_access("/dev/cpu/0/msr_safe"),
+            root: crate::is_root(),
+            raw_io,
+        }
+    }
+
+    // What to do about it, None when the MSRs are readable.
+    pub fn hint(&self) -> Option<String> {
+        if self.msr == DeviceAccess::Readable || self.msr_safe == DeviceAccess::Readable {
+            return None;
+        }
+        let hint = match (self.msr, self.msr_safe) {
+            (DeviceAccess::Missing, DeviceAccess::Missing) => {
+                "/dev/cpu/*/msr does not exist: load the msr module with `modprobe msr`, or install msr-safe to run without root"
+            }
+            (_, DeviceAccess::Denied) => {
+                "msr-safe is loaded but /dev/cpu/*/msr_safe is not readable by this user: give the service's user or group read access, e.g. with a udev rule"
+            }
+            _ if self.root => {
+                "/dev/cpu/*/msr is not readable even as root, e.g. because of kernel lockdown or a security module: use msr-safe or sampling.backend = \"powercap\""
+            }
+            _ if self.raw_io => {
+                "CAP_SYS_RAWIO is present but /dev/cpu/*/msr is not readable by this user: give the service's user or group read access, e.g. with a udev rule"
+            }
+            _ => {
+                "/dev/cpu/*/msr needs root, or CAP_SYS_RAWIO (AmbientCapabilities=CAP_SYS_RAWIO in the unit) plus read access to the device nodes; msr-safe needs neither"
+            }
+        };
+        Some(hint.to_string())
+    }
+}
+
+// CapEff from /proc/self/status.
+fn effective_capabilities(status: &str) -> Option<u64> {
+    let caps = status.lines().find_map(|line| line.strip_prefix("CapEff:"))?;
+    u64::from_str_radix(caps.trim(), 16).ok()
+}
+
+// The MSR is the file offset, so each is a single pread, without a seek.
+#[cfg(unix)]
+pub fn read_msr(file: &File, which: u64) -> io::Result<u64> {
+    let mut buffer = [0u8; 8];
+    file.read_exact_at(&mut buffer, which)?;
+    Ok(u64::from_ne_bytes(buffer))
+}
+
+#[cfg(not(unix))]
+pub fn read_msr(mut file: &File, which: u64) -> io::Result<u64> {
+    let mut buffer = [0u8; 8];
+    file.seek(SeekFrom::Start(which))?;
+    file.read_exact(&mut buffer)?;
+    Ok(u64::from_ne_bytes(buffer))
+}
+
+// Read each of `which` into the same place in `values`.
+pub fn read_msrs(file: &File, which: &[u64], values: &mut [u64]) -> io::Result<()> {
+    for (which, value) in which.iter().zip(values) {
+        *value = read_msr(file, *which)?;
+    }
+    Ok(())
+}
+
+// An MSR device kept open across samples. The handle is reopened when the CPU
+// went away and came back (hotplug), which shows up as EBADF/ENOENT/ENXIO.
+#[derive(Debug)]
+pub struct MsrDevice {
+    cpu: usize,
+    file: Option<File>,
+}
+
+impl MsrDevice {
+    pub fn open(cpu: usize) -> Result<Self> {
+        let file = open_msr(cpu).map_err(|source| RyzenmonError::MsrAccess { cpu, source })?;
+        Ok(MsrDevice { cpu, file: Some(file) })
+    }
+
+    pub fn cpu(&self) -> usize {
+        self.cpu
+    }
+
+    pub fn read(&mut self, which: u64) -> Result<u64> {
+        let mut value = [0];
+        self.read_into(&[which], &mut value)?;
+        Ok(value[0])
+    }
+
+    // Read several MSRs in one pass over the open handle.
+    pub fn read_into(&mut self, which: &[u64], values: &mut [u64]) -> Result<()> {
+        self.read_io(which, values)
+            .map_err(|source| RyzenmonError::MsrAccess { cpu: self.cpu, source })
+    }
+
+    fn read_io(&mut self, which: &[u64], values: &mut [u64]) -> io::Result<()> {
+        let file = match self.file.as_ref() {
+            Some(file) => file,
+            None => self.file.insert(open_msr(self.cpu)?),
+        };
+
+        match read_msrs(file, which, values) {
+            Err(e) if is_stale_handle(&e) => {
+                self.file = None;
+                let file = self.file.insert(open_msr(self.cpu)?);
+                read_msrs(file, which, values)
+            }
+            result => result,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_stale_handle(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error().map(Errno::from_i32),
+        Some(Errno::EBADF | Errno::ENOENT | Errno::ENXIO | Errno::ENODEV)
+    )
+}
+
+#[cfg(not(unix))]
+fn is_stale_handle(_: &io::Error) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_effective_capabilities() {
+        let status = "Name:\tryzenmon-rust\nCapInh:\t0000000000000000\nCapEff:\t0000000000020000\n";
+        let caps = effective_capabilities(status).unwrap();
+        assert_ne!(caps & (1 << CAP_SYS_RAWIO), 0);
+    }
+
+    #[test]
+    fn decodes_cpuid_signatures() {
+        // Ryzen 9 5950X and Core i7-12700K
+        let zen3 = CpuId::from_cpuid("AuthenticAMD", 0x00A2_0F10).unwrap();
+        assert_eq!((zen3.family, zen3.model), (0x19, 0x21));
+        assert_eq!(zen3.zen_generation(), Some(ZenGeneration::Zen3));
+        let alder_lake = CpuId::from_cpuid("GenuineIntel", 0x0009_0672).unwrap();
+        assert_eq!((alder_lake.vendor, alder_lake.family, alder_lake.model), (Vendor::Intel, 6, 0x97));
+        assert!(CpuId::from_cpuid("VIA VIA VIA ", 0x06F1).is_err());
+    }
+
+    #[test]
+    fn detects_zen_generation_and_msr_map() {
+        let cpuinfo = |vendor, family, model| {
+            format!("processor\t: 0\nvendor_id\t: {}\ncpu family\t: {}\nmodel\t\t: {}\n", vendor, family, model)
+        };
+        let cpu = CpuId::parse(&cpuinfo("AuthenticAMD", 25, 97)).unwrap();
+        assert_eq!(cpu.zen_generation(), Some(ZenGeneration::Zen4));
+        assert_eq!(cpu.to_string(), "Amd family 0x19 model 0x61 (Zen4)");
+        assert_eq!(MsrMap::for_cpu(&cpu).unwrap().core_energy, AMD_MSR_CORE_ENERGY);
+
+        let zen3 = CpuId::parse(&cpuinfo("AuthenticAMD", 25, 33)).unwrap();
+        assert_eq!(zen3.zen_generation(), Some(ZenGeneration::Zen3));
+        let zen2 = CpuId::parse(&cpuinfo("AuthenticAMD", 23, 113)).unwrap();
+        assert_eq!(zen2.zen_generation(), Some(ZenGeneration::Zen2));
+
+        // Bulldozer-era parts have no RAPL MSRs
+        let piledriver = CpuId::parse(&cpuinfo("AuthenticAMD", 21, 2)).unwrap();
+        assert!(matches!(MsrMap::for_cpu(&piledriver), Err(RyzenmonError::UnsupportedCpu(_))));
+
+        let skylake_x = CpuId::parse(&cpuinfo("GenuineIntel", 6, 85)).unwrap();
+        assert_eq!(MsrMap::for_cpu(&skylake_x).unwrap().dram_energy_unit, Some(INTEL_SERVER_DRAM_ENERGY_UNIT));
+        let westmere = CpuId::parse(&cpuinfo("GenuineIntel", 6, 44)).unwrap();
+        assert!(MsrMap::for_cpu(&westmere).is_err());
+
+        assert!(CpuId::parse(&cpuinfo("CentaurHauls", 6, 15)).is_err());
+    }
+
+    #[test]
+    fn hints_match_the_missing_piece() {
+        let access = |msr, msr_safe, root, raw_io| MsrAccess { msr, msr_safe, root, raw_io };
+
+        assert!(access(DeviceAccess::Denied, DeviceAccess::Readable, false, false).hint().is_none());
+        assert!(access(DeviceAccess::Missing, DeviceAccess::Missing, true, true)
+            .hint()
+            .unwrap()
+            .contains("modprobe msr"));
+        assert!(access(DeviceAccess::Denied, DeviceAccess::Missing, false, false)
+            .hint()
+            .unwrap()
+            .contains("CAP_SYS_RAWIO"));
+        assert!(access(DeviceAccess::Denied, DeviceAccess::Denied, false, false)
+            .hint()
+            .unwrap()
+            .contains("msr_safe"));
+    }
+}
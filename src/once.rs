@@ -0,0 +1,156 @@
+use std::time::Duration;
+
+use ryzenmon_rust::PowerMetrics;
+
+// Human readable table for `ryzenmon once`.
+pub fn print_summary(metrics: &PowerMetrics, duration: Duration) {
+    let secs = duration.as_secs_f64();
+    let row = |name: &str, watts: f64| {
+        println!("{:<14} {:>9.3} W {:>11.3} J", name, watts, watts * secs);
+    };
+
+    println!("Measured over {:.3} s", secs);
+    println!();
+    row("package", metrics.package_watts);
+    if metrics.packages.len() > 1 {
+        for package in &metrics.packages {
+            row(&format!("  package {}", package.package), package.watts);
+        }
+    }
+    row("cores", metrics.core_sum);
+    row("uncore", metrics.package_watts - metrics.core_sum);
+    if let Some(dram_watts) = metrics.dram_watts {
+        row("dram", dram_watts);
+    }
+    if let Some(soc_watts) = metrics.soc_watts {
+        row("soc", soc_watts);
+    }
+
+    if !metrics.ccds.is_empty() {
+        println!();
+        for ccd in &metrics.ccds {
+            row(&format!("ccd {}", ccd.ccd), ccd.watts);
+        }
+    }
+
+    if !metrics.core_watts.is_empty() {
+        println!();
+        if let Some(utilization) = metrics.utilization {
+            println!("{:<14} {:>9.1} %", "utilization", utilization);
+        }
+        for (core, watts) in metrics.core_watts.iter().enumerate() {
+            let label = format!("core {}", metrics.core_id(core));
+            match metrics.core_utilization.get(core) {
+                Some(utilization) => println!(
+                    "{:<14} {:>9.3} W {:>11.3} J {:>7.1} % util",
+                    label,
+                    watts,
+                    watts * secs,
+                    utilization
+                ),
+                None => row(&label, *watts),
+            }
+        }
+    }
+
+    if let Some(mhz) = metrics.average_mhz {
+        println!();
+        println!("{:<14} {:>9.0} MHz", "frequency", mhz);
+        for (core, mhz) in metrics.per_core(&metrics.core_mhz) {
+            println!("{:<14} {:>9.0} MHz", format!("core {}", core), mhz);
+        }
+    }
+
+    if let Some(activity) = metrics.mean_activity() {
+        println!();
+        println!(
+            "{:<14} {:>9.0} MHz {:>9.1} % busy",
+            "effective", activity.effective_mhz, activity.busy_percent
+        );
+        for (core, activity) in metrics.per_core(&metrics.core_activity) {
+            println!(
+                "{:<14} {:>9.0} MHz {:>9.1} % busy",
+                format!("core {}", core),
+                activity.effective_mhz,
+                activity.busy_percent
+            );
+        }
+    }
+
+    if !metrics.cstates.is_empty() {
+        println!();
+        for residency in &metrics.cstates {
+            println!("{:<14} {:>9.1} %", residency.state, residency.percent);
+        }
+    }
+
+    if !metrics.temperatures.is_empty() {
+        println!();
+        for temperature in &metrics.temperatures {
+            println!("{:<14} {:>9.1} °C", temperature.label, temperature.celsius);
+        }
+    }
+
+    for gpu in &metrics.gpus {
+        println!();
+        if let Some(watts) = gpu.watts {
+            row(&format!("gpu {}", gpu.gpu), watts);
+        }
+        for temperature in &gpu.temperatures {
+            println!("{:<14} {:>9.1} °C", format!("  {}", temperature.label), temperature.celsius);
+        }
+        if let Some(rpm) = gpu.fan_rpm {
+            println!("{:<14} {:>9.0} RPM", "  fan", rpm);
+        }
+    }
+
+    if !metrics.processes.is_empty() {
+        println!();
+        for process in &metrics.processes {
+            row(&process.name, process.watts);
+        }
+    }
+
+    if !metrics.cgroups.is_empty() {
+        println!();
+        for cgroup in &metrics.cgroups {
+            row(&cgroup.cgroup, cgroup.watts);
+        }
+    }
+
+    if !metrics.sensors.is_empty() {
+        println!();
+        for sensor in &metrics.sensors {
+            println!("{:<14} {:>9.3} {}", sensor.label, sensor.value, sensor.unit);
+        }
+    }
+
+    if let Some(limits) = &metrics.limits {
+        println!();
+        for (name, limit, unit) in limits.iter() {
+            println!(
+                "{:<14} {:>9.1} {} of {:.1} {} ({:.0}%)",
+                name.to_ascii_uppercase(),
+                limit.value,
+                unit,
+                limit.limit,
+                unit,
+                limit.usage() * 100.0
+            );
+        }
+    }
+    if let Some(throttle) = metrics.throttle.as_ref().filter(|throttle| throttle.is_throttled()) {
+        let reasons: Vec<&str> = throttle.active.iter().map(|reason| reason.name()).collect();
+        println!();
+        println!("Throttled      {}", reasons.join(", "));
+    }
+
+    if !metrics.rails.is_empty() {
+        println!();
+        for rail in &metrics.rails {
+            let volts = rail.volts.map(|v| format!("{:>9.3} V", v)).unwrap_or_default();
+            let amps = rail.amps.map(|a| format!("{:>9.2} A", a)).unwrap_or_default();
+            println!("{:<14} {:>11} {:>11}", rail.rail, volts, amps);
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+
+// The daemon's PID in a file it holds an flock on for as long as it runs, so
+// a second instance doesn't sample the same MSRs and report everything twice.
+// The lock, not the file, says whether a daemon is running: one that was
+// killed leaves the file behind, but the kernel drops its lock.
+pub struct PidFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl PidFile {
+    // Lock `path` and write this process's PID to it. Fails with the PID of
+    // the instance that holds it.
+    pub fn acquire(path: &Path) -> io::Result<Self> {
+        // Not truncated before the lock is ours, or the other instance's PID would be lost.
+        let mut file =
+            OpenOptions::new().read(true).write(true).create(true).truncate(false).mode(0o644).open(path)?;
+        match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+            Ok(()) => {}
+            Err(Errno::EWOULDBLOCK) => {
+                let pid = read_pid(&mut file).map(|pid| format!(" (PID {})", pid)).unwrap_or_default();
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("another instance is running{}, it holds {}", pid, path.display()),
+                ));
+            }
+            Err(e) => return Err(io::Error::from(e)),
+        }
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+        file.sync_all()?;
+        Ok(PidFile {
+            path: path.to_path_buf(),
+            file,
+        })
+    }
+
+    // On a clean shutdown. After dropping privileges the file may not be
+    // ours to remove, which is fine: the lock goes with the process.
+    pub fn remove(self) {
+        let _ = fs::remove_file(&self.path);
+        drop(self.file);
+    }
+}
+
+// PID of the instance holding `path`, None when no instance does.
+pub fn holder(path: &Path) -> io::Result<Option<u32>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    match flock(file.as_raw_fd(), FlockArg::LockSharedNonblock) {
+        // Left behind by an instance that is gone.
+        Ok(()) => Ok(None),
+        Err(Errno::EWOULDBLOCK) => read_pid(&mut file)
+            .map(Some)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{} holds no PID", path.display()))),
+        Err(e) => Err(io::Error::from(e)),
+    }
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut contents = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_the_pid_file_for_one_instance() {
+        let path = std::env::temp_dir().join(format!("ryzenmon-test-{}.pid", std::process::id()));
+        assert_eq!(holder(&path).unwrap(), None);
+
+        let pid_file = PidFile::acquire(&path).unwrap();
+        assert_eq!(holder(&path).unwrap(), Some(std::process::id()));
+        let error = PidFile::acquire(&path).err().unwrap();
+        assert!(error.to_string().contains(&format!("PID {}", std::process::id())));
+
+        // A stale file doesn't count, and is taken over.
+        drop(pid_file);
+        assert_eq!(holder(&path).unwrap(), None);
+        PidFile::acquire(&path).unwrap().remove();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,371 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{RyzenmonError, Result};
+use crate::msr::{detect_cpu, CpuId, MsrDevice, EIO};
+use crate::topology::{self, Topology};
+
+// Where the CPU layout and energy counters come from: this machine, or a
+// recorded trace, so the sampling math can run in tests and with --simulate
+// without root or a Ryzen.
+pub trait Platform: Send + Sync {
+    fn cpu(&self) -> Result<CpuId>;
+    fn topology(&self) -> Result<Topology>;
+    // Changes whenever CPUs are onlined or offlined, None when unknown
+    fn online_cpus(&self) -> Option<String>;
+    fn open_msr(&self, cpu: usize) -> Result<Box<dyn Msr>>;
+    // Whether counters advance with the wall clock. A trace replays the same
+    // deltas however long the sampler sleeps between reads.
+    fn real_time(&self) -> bool {
+        true
+    }
+}
+
+// The MSRs of one logical CPU.
+pub trait Msr: Send {
+    fn cpu(&self) -> usize;
+    fn read(&mut self, which: u64) -> Result<u64>;
+    // Read each of `which` into the same place in `values`.
+    fn read_into(&mut self, which: &[u64], values: &mut [u64]) -> Result<()> {
+        for (which, value) in which.iter().zip(values) {
+            *value = self.read(*which)?;
+        }
+        Ok(())
+    }
+}
+
+impl Msr for MsrDevice {
+    fn cpu(&self) -> usize {
+        MsrDevice::cpu(self)
+    }
+
+    fn read(&mut self, which: u64) -> Result<u64> {
+        MsrDevice::read(self, which)
+    }
+
+    fn read_into(&mut self, which: &[u64], values: &mut [u64]) -> Result<()> {
+        MsrDevice::read_into(self, which, values)
+    }
+}
+
+// Reads the same MSRs from every device it is given, e.g. the clock counters
+// of each core, into one buffer reused across devices.
+pub struct MsrReader<const N: usize> {
+    which: [u64; N],
+    values: [u64; N],
+}
+
+impl<const N: usize> MsrReader<N> {
+    pub fn new(which: [u64; N]) -> Self {
+        MsrReader { which, values: [0; N] }
+    }
+
+    pub fn read(&mut self, device: &mut dyn Msr) -> Result<[u64; N]> {
+        device.read_into(&self.which, &mut self.values)?;
+        Ok(self.values)
+    }
+}
+
+// /proc/cpuinfo, sysfs and /dev/cpu/*/msr of the running machine.
+pub struct Host;
+
+impl Platform for Host {
+    fn cpu(&self) -> Result<CpuId> {
+        detect_cpu()
+    }
+
+    fn topology(&self) -> Result<Topology> {
+        Topology::detect()
+    }
+
+    fn online_cpus(&self) -> Option<String> {
+        topology::online_cpus()
+    }
+
+    fn open_msr(&self, cpu: usize) -> Result<Box<dyn Msr>> {
+        Ok(Box::new(MsrDevice::open(cpu)?))
+    }
+}
+
+// A CPU and the values its MSRs returned, as JSON:
+//
+//   {"cpu": {"vendor": "Amd", "family": 25, "model": 33},
+//    "topology": {"cores": [0, 1], "packages": [{"id": 0, "cpu": 0}], "threads": 2},
+//    "msrs": {"0": {"0xc0010299": [659459], "0xc001029a": [1000, 66536]}}}
+//
+// Values of each CPU and MSR are returned in order. A single value is a
+// constant, such as the power unit; reading past the end of a longer list
+// ends the trace.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trace {
+    pub cpu: CpuId,
+    pub topology: Topology,
+    // CPU -> MSR address in hex -> values
+    pub msrs: BTreeMap<usize, BTreeMap<String, Vec<u64>>>,
+    // Measurement window the trace was recorded with, used again on replay
+    #[serde(default)]
+    pub window_ms: Option<u64>,
+    // End of each recorded sample's window, in Unix seconds
+    #[serde(default)]
+    pub timestamps: Vec<f64>,
+}
+
+impl Trace {
+    pub fn load(path: &Path) -> Result<Self> {
+        let trace = fs::read_to_string(path)?;
+        serde_json::from_str(&trace)
+            .map_err(|e| RyzenmonError::Config(format!("invalid trace {}: {}", path.display(), e)))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut file, self).map_err(io::Error::from)?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+// Reads this machine's MSRs and keeps every value read, for `ryzenmon record`.
+// The topology is fixed at the start, so CPU hotplug is not followed.
+#[derive(Clone)]
+pub struct Recorder {
+    trace: Arc<Mutex<Trace>>,
+}
+
+impl Recorder {
+    pub fn new(cpu: CpuId, topology: Topology) -> Self {
+        let trace = Trace {
+            cpu,
+            topology,
+            msrs: BTreeMap::new(),
+            window_ms: None,
+            timestamps: Vec::new(),
+        };
+        Recorder {
+            trace: Arc::new(Mutex::new(trace)),
+        }
+    }
+
+    // Note the end of a sample's window.
+    pub fn sampled(&self, timestamp: SystemTime) {
+        let seconds = timestamp.duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+        self.trace.lock().unwrap().timestamps.push(seconds);
+    }
+
+    pub fn finish(&self, window: Duration) -> Trace {
+        let mut trace = self.trace.lock().unwrap().clone();
+        trace.window_ms = Some(window.as_millis() as u64);
+        trace
+    }
+}
+
+impl Platform for Recorder {
+    fn cpu(&self) -> Result<CpuId> {
+        Ok(self.trace.lock().unwrap().cpu)
+    }
+
+    fn topology(&self) -> Result<Topology> {
+        Ok(self.trace.lock().unwrap().topology.clone())
+    }
+
+    fn online_cpus(&self) -> Option<String> {
+        None
+    }
+
+    fn open_msr(&self, cpu: usize) -> Result<Box<dyn Msr>> {
+        Ok(Box::new(RecordingMsr {
+            device: MsrDevice::open(cpu)?,
+            trace: Arc::clone(&self.trace),
+        }))
+    }
+}
+
+struct RecordingMsr {
+    device: MsrDevice,
+    trace: Arc<Mutex<Trace>>,
+}
+
+impl Msr for RecordingMsr {
+    fn cpu(&self) -> usize {
+        self.device.cpu()
+    }
+
+    fn read(&mut self, which: u64) -> Result<u64> {
+        let value = self.device.read(which)?;
+        let mut trace = self.trace.lock().unwrap();
+        let msrs = trace.msrs.entry(self.device.cpu()).or_default();
+        msrs.entry(format!("{:#x}", which)).or_default().push(value);
+        Ok(value)
+    }
+}
+
+struct Register {
+    values: VecDeque<u64>,
+    constant: bool,
+}
+
+type Counters = Arc<Mutex<BTreeMap<(usize, u64), Register>>>;
+
+// Replays a trace. Devices opened on the same CPU share its read position,
+// like the registers of a real CPU do.
+pub struct Simulated {
+    cpu: CpuId,
+    topology: Topology,
+    counters: Counters,
+}
+
+impl Simulated {
+    pub fn new(trace: Trace) -> Result<Self> {
+        let mut counters = BTreeMap::new();
+        for (cpu, msrs) in trace.msrs {
+            for (which, values) in msrs {
+                let address = u64::from_str_radix(which.trim_start_matches("0x"), 16)
+                    .map_err(|_| RyzenmonError::Config(format!("invalid MSR address {:?} in trace", which)))?;
+                let register = Register {
+                    constant: values.len() == 1,
+                    values: VecDeque::from(values),
+                };
+                counters.insert((cpu, address), register);
+            }
+        }
+        Ok(Simulated {
+            cpu: trace.cpu,
+            topology: trace.topology,
+            counters: Arc::new(Mutex::new(counters)),
+        })
+    }
+}
+
+impl Platform for Simulated {
+    fn cpu(&self) -> Result<CpuId> {
+        Ok(self.cpu)
+    }
+
+    fn topology(&self) -> Result<Topology> {
+        Ok(self.topology.clone())
+    }
+
+    fn online_cpus(&self) -> Option<String> {
+        None
+    }
+
+    fn real_time(&self) -> bool {
+        false
+    }
+
+    fn open_msr(&self, cpu: usize) -> Result<Box<dyn Msr>> {
+        let counters = self.counters.lock().unwrap();
+        if !counters.keys().any(|&(traced, _)| traced == cpu) {
+            let source = io::Error::new(io::ErrorKind::NotFound, "CPU is not in the trace");
+            return Err(RyzenmonError::MsrAccess { cpu, source });
+        }
+        Ok(Box::new(SimulatedMsr {
+            cpu,
+            counters: Arc::clone(&self.counters),
+        }))
+    }
+}
+
+struct SimulatedMsr {
+    cpu: usize,
+    counters: Counters,
+}
+
+impl Msr for SimulatedMsr {
+    fn cpu(&self) -> usize {
+        self.cpu
+    }
+
+    fn read(&mut self, which: u64) -> Result<u64> {
+        let mut counters = self.counters.lock().unwrap();
+        // An MSR missing from the trace faults like one the CPU doesn't implement.
+        let Some(register) = counters.get_mut(&(self.cpu, which)) else {
+            let source = io::Error::from_raw_os_error(EIO);
+            return Err(RyzenmonError::MsrAccess { cpu: self.cpu, source });
+        };
+        let value = if register.constant {
+            register.values.front().copied()
+        } else {
+            register.values.pop_front()
+        };
+        value.ok_or_else(|| {
+            RyzenmonError::NoEnergySource(format!("the trace has no more values for MSR {:#x} on CPU {}", which, self.cpu))
+        })
+    }
+}
+
+// The Zen 3 trace in fixtures/, for tests.
+#[cfg(test)]
+pub(crate) fn fixture() -> Trace {
+    Trace::load(Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/zen3.json"))).unwrap()
+}
+
+// One sample of the fixture trace, for tests that need realistic metrics.
+#[cfg(test)]
+pub(crate) fn fixture_sample() -> crate::PowerMetrics {
+    crate::Sampler::simulated(fixture()).unwrap().sample(Duration::from_millis(10)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msr::Vendor;
+
+    #[test]
+    fn replays_constants_and_counters() {
+        let trace = Trace {
+            cpu: CpuId {
+                vendor: Vendor::Amd,
+                family: 0x19,
+                model: 0x21,
+            },
+            topology: Topology::default(),
+            msrs: BTreeMap::from([(
+                0,
+                BTreeMap::from([("0xc0010299".to_string(), vec![7]), ("0xc001029b".to_string(), vec![1, 2])]),
+            )]),
+            window_ms: None,
+            timestamps: Vec::new(),
+        };
+        let json = serde_json::to_string(&trace).unwrap();
+        let platform = Simulated::new(serde_json::from_str(&json).unwrap()).unwrap();
+
+        let mut msr = platform.open_msr(0).unwrap();
+        assert_eq!(msr.read(0xc0010299).unwrap(), 7);
+        assert_eq!(msr.read(0xc0010299).unwrap(), 7);
+        assert_eq!(msr.read(0xc001029b).unwrap(), 1);
+        assert_eq!(msr.read(0xc001029b).unwrap(), 2);
+        assert!(matches!(msr.read(0xc001029b), Err(RyzenmonError::NoEnergySource(_))));
+        assert!(matches!(msr.read(0xe8), Err(RyzenmonError::MsrAccess { .. })));
+        assert!(platform.open_msr(1).is_err());
+    }
+
+    #[test]
+    fn reads_several_msrs_per_device() {
+        let registers = |tsc: u64| BTreeMap::from([("0x10".to_string(), vec![tsc]), ("0xe7".to_string(), vec![tsc / 2])]);
+        let trace = Trace {
+            cpu: CpuId {
+                vendor: Vendor::Amd,
+                family: 0x19,
+                model: 0x21,
+            },
+            topology: Topology::default(),
+            msrs: BTreeMap::from([(0, registers(100)), (1, registers(300))]),
+            window_ms: None,
+            timestamps: Vec::new(),
+        };
+        let platform = Simulated::new(trace).unwrap();
+
+        let mut reader = MsrReader::new([0x10, 0xe7]);
+        assert_eq!(reader.read(platform.open_msr(0).unwrap().as_mut()).unwrap(), [100, 50]);
+        assert_eq!(reader.read(platform.open_msr(1).unwrap().as_mut()).unwrap(), [300, 150]);
+        let mut reader = MsrReader::new([0x10, 0xe8]);
+        assert!(reader.read(platform.open_msr(0).unwrap().as_mut()).is_err());
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::error::{RyzenmonError, Result};
+use crate::rapl::{PackagePower, PowerMetrics};
+
+pub const POWERCAP_ROOT: &str = "/sys/class/powercap";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Domain {
+    Package(usize),
+    Core,
+    Dram,
+    Uncore,
+    Other,
+}
+
+#[derive(Debug, Clone)]
+struct Zone {
+    domain: Domain,
+    energy_path: PathBuf,
+    // energy_uj wraps back to 0 after reaching this value
+    max_energy_uj: u64,
+}
+
+// RAPL zones exported by the intel_rapl driver, which also covers AMD since
+// Linux 5.8. Top-level zones are packages, their subzones are core/dram/uncore.
+fn find_zones() -> io::Result<Vec<Zone>> {
+    let mut zones = Vec::new();
+
+    for entry in fs::read_dir(POWERCAP_ROOT)?.flatten() {
+        let file_name = entry.file_name();
+        let Some(name) = file_name.to_str() else {
+            continue;
+        };
+        // intel-rapl:0 is a package, intel-rapl:0:0 a subzone of it.
+        let Some(id) = name.strip_prefix("intel-rapl:") else {
+            continue;
+        };
+
+        let path = entry.path();
+        let domain = match fs::read_to_string(path.join("name")) {
+            Ok(zone_name) if !id.contains(':') && zone_name.starts_with("package") => {
+                // Zone names are "package-<id>"
+                let package = zone_name
+                    .trim()
+                    .strip_prefix("package-")
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(0);
+                Domain::Package(package)
+            }
+            Ok(zone_name) if zone_name.trim() == "core" => Domain::Core,
+            Ok(zone_name) if zone_name.trim() == "dram" => Domain::Dram,
+            Ok(zone_name) if zone_name.trim() == "uncore" => Domain::Uncore,
+            _ => Domain::Other,
+        };
+
+        let max_energy_uj = read_energy_uj(&path.join("max_energy_range_uj")).unwrap_or(u64::MAX);
+        zones.push(Zone {
+            domain,
+            energy_path: path.join("energy_uj"),
+            max_energy_uj,
+        });
+    }
+
+    Ok(zones)
+}
+
+pub fn powercap_available() -> bool {
+    find_zones()
+        .map(|zones| {
+            zones
+                .iter()
+                .any(|zone| matches!(zone.domain, Domain::Package(_)) && read_energy_uj(&zone.energy_path).is_ok())
+        })
+        .unwrap_or(false)
+}
+
+// Set the long-term power limit of every package zone that has one, returns
+// how many were set. AMD zones usually only count energy and have none.
+pub fn set_package_limit(watts: f64) -> io::Result<usize> {
+    let mut set = 0;
+    for limit in package_limit_paths()? {
+        fs::write(limit, format!("{}", (watts * 1_000_000.0).round() as u64))?;
+        set += 1;
+    }
+    Ok(set)
+}
+
+// The lowest long-term power limit of the package zones in watts, None when
+// none has one. Each limit holds one package.
+pub fn package_limit() -> io::Result<Option<f64>> {
+    let mut lowest: Option<f64> = None;
+    for limit in package_limit_paths()? {
+        let watts = read_energy_uj(&limit)? as f64 / 1_000_000.0;
+        lowest = Some(lowest.map_or(watts, |lowest| lowest.min(watts)));
+    }
+    Ok(lowest)
+}
+
+// constraint_<n>_power_limit_uw of the long-term constraint of every package
+// zone that has one.
+fn package_limit_paths() -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for zone in find_zones()? {
+        if !matches!(zone.domain, Domain::Package(_)) {
+            continue;
+        }
+        let zone_path = zone.energy_path.parent().expect("energy_uj is in a zone directory");
+        for constraint in 0.. {
+            let Ok(name) = fs::read_to_string(zone_path.join(format!("constraint_{}_name", constraint))) else {
+                break;
+            };
+            if name.trim() == "long_term" {
+                paths.push(zone_path.join(format!("constraint_{}_power_limit_uw", constraint)));
+                break;
+            }
+        }
+    }
+    Ok(paths)
+}
+
+fn read_energy_uj(path: &Path) -> io::Result<u64> {
+    fs::read_to_string(path)?
+        .trim()
+        .parse::<u64>()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// The energy counter of every zone, and when reading them started.
+fn read_zones(zones: &[Zone]) -> io::Result<(Instant, BTreeMap<PathBuf, u64>)> {
+    let at = Instant::now();
+    let mut energy = BTreeMap::new();
+    for zone in zones {
+        energy.insert(zone.energy_path.clone(), read_energy_uj(&zone.energy_path)?);
+    }
+    Ok((at, energy))
+}
+
+// Microjoules between two reads of a zone counter that wraps at `max_energy_uj`.
+pub fn wrapping_delta_uj(before: u64, after: u64, max_energy_uj: u64) -> u64 {
+    if after >= before {
+        after - before
+    } else {
+        max_energy_uj.saturating_sub(before) + after
+    }
+}
+
+// Reads the zones found on every sample. The powercap interface has no
+// per-core counters, so `core_watts` stays empty.
+#[derive(Default)]
+pub struct Powercap {
+    // The end of the previous sample, where a continuous one starts
+    last: Option<(Instant, BTreeMap<PathBuf, u64>)>,
+}
+
+impl Powercap {
+    // Power over `window`, or with `continuous` since the previous sample
+    // when every zone has a reading from it.
+    pub fn sample(&mut self, window: Duration, continuous: bool) -> Result<PowerMetrics> {
+        let zones: Vec<Zone> = find_zones()?
+            .into_iter()
+            .filter(|zone| zone.domain != Domain::Other)
+            .collect();
+        if !zones.iter().any(|zone| matches!(zone.domain, Domain::Package(_))) {
+            return Err(RyzenmonError::NoEnergySource(format!(
+                "no RAPL package zones under {}",
+                POWERCAP_ROOT
+            )));
+        }
+
+        let (started, before) = match self.last.take() {
+            Some(last) if continuous && zones.iter().all(|zone| last.1.contains_key(&zone.energy_path)) => last,
+            _ => {
+                let before = read_zones(&zones)?;
+                thread::sleep(window);
+                before
+            }
+        };
+        let (ended, after) = read_zones(&zones)?;
+
+        // The sleep can run long, and the reads take time.
+        let window_secs = ended.duration_since(started).as_secs_f64();
+        let mut packages = Vec::new();
+        let mut core_sum = 0.0;
+        let mut dram_watts = None;
+        let mut soc_watts = None;
+
+        for zone in &zones {
+            let (before, after) = (before[&zone.energy_path], after[&zone.energy_path]);
+            let watts = wrapping_delta_uj(before, after, zone.max_energy_uj) as f64 / 1_000_000.0 / window_secs;
+            match zone.domain {
+                Domain::Package(package) => packages.push(PackagePower { package, watts }),
+                Domain::Core => core_sum += watts,
+                Domain::Dram => *dram_watts.get_or_insert(0.0) += watts,
+                Domain::Uncore => *soc_watts.get_or_insert(0.0) += watts,
+                Domain::Other => {}
+            }
+        }
+
+        packages.sort_by_key(|p| p.package);
+        self.last = Some((ended, after));
+
+        Ok(PowerMetrics {
+            core_watts: Vec::new(),
+            core_sum,
+            package_watts: packages.iter().map(|p| p.watts).sum(),
+            packages,
+            dram_watts,
+            soc_watts,
+            temperatures: Vec::new(),
+            rails: Vec::new(),
+            limits: None,
+            throttle: None,
+            ccds: Vec::new(),
+            gpus: Vec::new(),
+            sensors: Vec::new(),
+            core_mhz: Vec::new(),
+            average_mhz: None,
+            core_activity: Vec::new(),
+            cstates: Vec::new(),
+            core_utilization: Vec::new(),
+            utilization: None,
+            processes: Vec::new(),
+            cgroups: Vec::new(),
+            energy: None,
+            idle_floor_watts: None,
+            self_telemetry: None,
+            stats: None,
+            histograms: None,
+            resume: None,
+            core_cpus: Vec::new(),
+            core_ids: Vec::new(),
+            core_nodes: Vec::new(),
+            core_l3s: Vec::new(),
+            core_classes: Vec::new(),
+            skipped_cores: Vec::new(),
+            tags: BTreeMap::new(),
+            timestamp: SystemTime::now(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delta_without_wrap() {
+        assert_eq!(wrapping_delta_uj(100, 350, 1000), 250);
+    }
+
+    #[test]
+    fn delta_across_wrap() {
+        assert_eq!(wrapping_delta_uj(900, 50, 1000), 150);
+    }
+}
@@ -0,0 +1,49 @@
+use std::io;
+
+use nix::unistd::{self, Gid, Group, Uid, User};
+
+use crate::config::PrivilegesConfig;
+use crate::error::{RyzenmonError, Result};
+
+fn os_error(e: nix::Error) -> RyzenmonError {
+    RyzenmonError::Io(io::Error::from(e))
+}
+
+// Switch to the configured user and group once the MSR devices are open.
+// Supplementary groups are cleared, so only `group` (or the user's primary
+// group) stays. The change is process wide and cannot be undone.
+pub fn drop_privileges(config: &PrivilegesConfig) -> Result<()> {
+    let Some(name) = &config.user else {
+        return Ok(());
+    };
+    if !unistd::geteuid().is_root() {
+        return Err(RyzenmonError::Config(format!(
+            "privileges.user = {:?} needs ryzenmon to be started as root",
+            name
+        )));
+    }
+
+    let user = User::from_name(name)
+        .map_err(os_error)?
+        .ok_or_else(|| RyzenmonError::Config(format!("unknown user {:?} in privileges.user", name)))?;
+    let gid = match &config.group {
+        Some(group) => {
+            Group::from_name(group)
+                .map_err(os_error)?
+                .ok_or_else(|| RyzenmonError::Config(format!("unknown group {:?} in privileges.group", group)))?
+                .gid
+        }
+        None => user.gid,
+    };
+
+    // Group first: after setuid there is no permission left to change it.
+    unistd::setgroups(&[gid]).map_err(os_error)?;
+    unistd::setgid(gid).map_err(os_error)?;
+    unistd::setuid(user.uid).map_err(os_error)?;
+
+    let regained = || unistd::setuid(Uid::from_raw(0)).is_ok() || unistd::setgid(Gid::from_raw(0)).is_ok();
+    if !user.uid.is_root() && regained() {
+        return Err(RyzenmonError::Config("failed to drop root privileges".to_string()));
+    }
+    Ok(())
+}
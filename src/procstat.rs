@@ -0,0 +1,204 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+
+// Busy and total jiffies of one CPU from /proc/stat.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuTimes {
+    pub busy: u64,
+    pub total: u64,
+}
+
+impl CpuTimes {
+    fn add(self, other: CpuTimes) -> CpuTimes {
+        CpuTimes {
+            busy: self.busy + other.busy,
+            total: self.total + other.total,
+        }
+    }
+}
+
+// The aggregate "cpu" line as None and every "cpu<n>" line as Some(n).
+pub type StatTimes = BTreeMap<Option<usize>, CpuTimes>;
+
+pub fn read_stat() -> Option<StatTimes> {
+    Some(parse_stat(&fs::read_to_string("/proc/stat").ok()?))
+}
+
+pub fn parse_stat(stat: &str) -> StatTimes {
+    let mut times = BTreeMap::new();
+
+    for line in stat.lines() {
+        let mut fields = line.split_whitespace();
+        let Some(cpu) = fields.next().and_then(|name| name.strip_prefix("cpu")) else {
+            continue;
+        };
+        let cpu = if cpu.is_empty() {
+            None
+        } else {
+            match cpu.parse() {
+                Ok(cpu) => Some(cpu),
+                Err(_) => continue,
+            }
+        };
+
+        // user nice system idle iowait irq softirq steal; guest time is
+        // already counted in user and nice.
+        let values: Vec<u64> = fields.take(8).filter_map(|v| v.parse().ok()).collect();
+        if values.len() < 4 {
+            continue;
+        }
+        let idle = values[3] + values.get(4).copied().unwrap_or(0);
+        let total: u64 = values.iter().sum();
+        times.insert(cpu, CpuTimes { busy: total - idle, total });
+    }
+
+    times
+}
+
+// Busy percentage between two readings.
+pub fn utilization(before: CpuTimes, after: CpuTimes) -> f64 {
+    let total = after.total.saturating_sub(before.total);
+    if total == 0 {
+        return 0.0;
+    }
+    after.busy.saturating_sub(before.busy) as f64 / total as f64 * 100.0
+}
+
+// Utilization of every core, counting all of its SMT threads, and of the whole
+// system. Cores are given as their thread lists.
+pub fn core_utilization(before: &StatTimes, after: &StatTimes, core_threads: &[Vec<usize>]) -> (Vec<f64>, Option<f64>) {
+    let sum = |times: &StatTimes, threads: &[usize]| {
+        threads
+            .iter()
+            .filter_map(|cpu| times.get(&Some(*cpu)))
+            .fold(CpuTimes::default(), |acc, t| acc.add(*t))
+    };
+
+    let cores = core_threads
+        .iter()
+        .map(|threads| utilization(sum(before, threads), sum(after, threads)))
+        .collect();
+    let total = match (before.get(&None), after.get(&None)) {
+        (Some(before), Some(after)) => Some(utilization(*before, *after)),
+        _ => None,
+    };
+    (cores, total)
+}
+
+// Package power attributed to all processes sharing one name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessPower {
+    // comm as shown in /proc/<pid>/stat
+    pub name: String,
+    pub watts: f64,
+}
+
+// Cumulative user + system jiffies of every process by pid, with its name.
+pub type ProcessTimes = HashMap<u32, (String, u64)>;
+
+pub fn read_processes() -> ProcessTimes {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return ProcessTimes::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|e| {
+            let pid = e.file_name().to_str()?.parse().ok()?;
+            let stat = fs::read_to_string(e.path().join("stat")).ok()?;
+            Some((pid, parse_process_stat(&stat)?))
+        })
+        .collect()
+}
+
+// Name and utime + stime of a /proc/<pid>/stat line. The name is in
+// parentheses and may itself contain spaces and parentheses.
+pub fn parse_process_stat(stat: &str) -> Option<(String, u64)> {
+    let (name, rest) = stat.split_once('(').map(|(_, rest)| rest)?.rsplit_once(')')?;
+    // rest starts at field 3 (state); utime and stime are fields 14 and 15.
+    let mut fields = rest.split_whitespace().skip(11);
+    let utime: u64 = fields.next()?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+    Some((name.to_string(), utime + stime))
+}
+
+// Splits `watts` between processes by their share of `busy` jiffies over the
+// window and returns the `top` largest, summed by name. Processes that exited
+// during the window are not counted.
+pub fn attribute_power(
+    before: &ProcessTimes,
+    after: &ProcessTimes,
+    busy: u64,
+    watts: f64,
+    top: usize,
+) -> Vec<ProcessPower> {
+    if busy == 0 {
+        return Vec::new();
+    }
+
+    let mut by_name: HashMap<&str, u64> = HashMap::new();
+    for (pid, (name, time)) in after {
+        let start = before.get(pid).map(|(_, time)| *time).unwrap_or(0);
+        let delta = time.saturating_sub(start);
+        if delta > 0 {
+            *by_name.entry(name.as_str()).or_default() += delta;
+        }
+    }
+
+    let mut processes: Vec<ProcessPower> = by_name
+        .into_iter()
+        .map(|(name, time)| ProcessPower {
+            name: name.to_string(),
+            watts: watts * (time as f64 / busy as f64).min(1.0),
+        })
+        .collect();
+    processes.sort_by(|a, b| b.watts.total_cmp(&a.watts).then_with(|| a.name.cmp(&b.name)));
+    processes.truncate(top);
+    processes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cpu_lines() {
+        let stat = "cpu  100 0 50 800 50 0 0 0 0 0\ncpu0 60 0 20 400 20 0 0 0 0 0\ncpu1 40 0 30 400 30 0 0 0 0 0\nintr 12345\n";
+        let times = parse_stat(stat);
+        assert_eq!(times[&None], CpuTimes { busy: 150, total: 1000 });
+        assert_eq!(times[&Some(0)], CpuTimes { busy: 80, total: 500 });
+        assert_eq!(times.len(), 3);
+    }
+
+    #[test]
+    fn utilization_counts_smt_siblings() {
+        let before = parse_stat("cpu 0 0 0 0\ncpu0 0 0 0 0\ncpu1 0 0 0 0\n");
+        let after = parse_stat("cpu 150 0 0 50\ncpu0 100 0 0 0\ncpu1 50 0 0 50\n");
+        let (cores, total) = core_utilization(&before, &after, &[vec![0, 1]]);
+        assert_eq!(cores, vec![75.0]);
+        assert_eq!(total, Some(75.0));
+    }
+
+    #[test]
+    fn parses_process_name_with_parentheses() {
+        let stat = "1234 (tmux: server) (x) S 1 1234 1234 0 -1 4194560 100 0 0 0 250 40 0 0 20 0 1 0 100 0 0";
+        assert_eq!(parse_process_stat(stat), Some(("tmux: server) (x".to_string(), 290)));
+    }
+
+    #[test]
+    fn attributes_power_by_name() {
+        let times = |list: &[(u32, &str, u64)]| -> ProcessTimes {
+            list.iter().map(|(pid, name, time)| (*pid, (name.to_string(), *time))).collect()
+        };
+        let before = times(&[(1, "make", 100), (2, "cc1", 0), (3, "cc1", 0), (4, "idle", 5)]);
+        let after = times(&[(1, "make", 110), (2, "cc1", 30), (3, "cc1", 50), (4, "idle", 5), (5, "ld", 10)]);
+
+        let processes = attribute_power(&before, &after, 100, 50.0, 2);
+        assert_eq!(processes.len(), 2);
+        assert_eq!(processes[0].name, "cc1");
+        assert_eq!(processes[0].watts, 40.0);
+        assert_eq!(processes[1].name, "ld");
+        assert_eq!(processes[1].watts, 5.0);
+    }
+}
@@ -0,0 +1,456 @@
+// The messages agents stream to an aggregator, generated from
+// proto/ryzenmon.proto, and their conversions from and to PowerMetrics.
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{cgroup, cpuidle, energy, histogram, hwmon, procstat, rapl, smu, stats, suspend, telemetry, throttle, topology};
+
+tonic::include_proto!("ryzenmon");
+
+fn seconds(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0)
+}
+
+fn time(seconds: f64) -> Result<SystemTime, String> {
+    Duration::try_from_secs_f64(seconds)
+        .map(|since_epoch| UNIX_EPOCH + since_epoch)
+        .map_err(|e| format!("bad time {}: {}", seconds, e))
+}
+
+fn ids(ids: &[usize]) -> Vec<u64> {
+    ids.iter().map(|&id| id as u64).collect()
+}
+
+fn indices(ids: Vec<u64>) -> Vec<usize> {
+    ids.into_iter().map(|id| id as usize).collect()
+}
+
+// Message fields are optional in proto3; the ones PowerMetrics always has
+// must be there.
+fn required<T>(field: Option<T>, name: &str) -> Result<T, String> {
+    field.ok_or_else(|| format!("{} is missing", name))
+}
+
+impl From<&rapl::PowerMetrics> for PowerMetrics {
+    fn from(metrics: &rapl::PowerMetrics) -> Self {
+        PowerMetrics {
+            core_watts: metrics.core_watts.clone(),
+            core_sum: metrics.core_sum,
+            package_watts: metrics.package_watts,
+            packages: metrics
+                .packages
+                .iter()
+                .map(|p| PackagePower { package: p.package as u64, watts: p.watts })
+                .collect(),
+            dram_watts: metrics.dram_watts,
+            soc_watts: metrics.soc_watts,
+            temperatures: metrics.temperatures.iter().map(Into::into).collect(),
+            rails: metrics
+                .rails
+                .iter()
+                .map(|r| RailReading { rail: r.rail.clone(), volts: r.volts, amps: r.amps })
+                .collect(),
+            limits: metrics.limits.as_ref().map(|l| SmuLimits {
+                ppt: Some(l.ppt.into()),
+                tdc: Some(l.tdc.into()),
+                edc: Some(l.edc.into()),
+                thm: l.thm.map(Into::into),
+            }),
+            throttle: metrics.throttle.as_ref().map(|t| Throttle {
+                checked: t.checked.iter().map(|&r| ThrottleReason::from(r) as i32).collect(),
+                active: t.active.iter().map(|&r| ThrottleReason::from(r) as i32).collect(),
+                residency: t
+                    .residency
+                    .iter()
+                    .map(|(&reason, &share)| ThrottleResidency { reason: ThrottleReason::from(reason) as i32, share })
+                    .collect(),
+                events: t
+                    .events
+                    .iter()
+                    .map(|e| ThrottleEvent {
+                        reason: ThrottleReason::from(e.reason) as i32,
+                        started: seconds(e.started),
+                        duration_secs: e.duration_secs,
+                    })
+                    .collect(),
+            }),
+            ccds: metrics
+                .ccds
+                .iter()
+                .map(|c| CcdPower { ccd: c.ccd as u64, watts: c.watts, celsius: c.celsius })
+                .collect(),
+            gpus: metrics
+                .gpus
+                .iter()
+                .map(|g| GpuReading {
+                    gpu: g.gpu as u64,
+                    watts: g.watts,
+                    temperatures: g.temperatures.iter().map(Into::into).collect(),
+                    fan_rpm: g.fan_rpm,
+                })
+                .collect(),
+            sensors: metrics
+                .sensors
+                .iter()
+                .map(|s| SensorReading { label: s.label.clone(), value: s.value, unit: s.unit.clone() })
+                .collect(),
+            core_mhz: metrics.core_mhz.clone(),
+            average_mhz: metrics.average_mhz,
+            core_activity: metrics
+                .core_activity
+                .iter()
+                .map(|a| CoreActivity { effective_mhz: a.effective_mhz, busy_percent: a.busy_percent })
+                .collect(),
+            cstates: metrics
+                .cstates
+                .iter()
+                .map(|c| CStateResidency { state: c.state.clone(), percent: c.percent, cores: c.cores.clone() })
+                .collect(),
+            core_utilization: metrics.core_utilization.clone(),
+            utilization: metrics.utilization,
+            processes: metrics
+                .processes
+                .iter()
+                .map(|p| ProcessPower { name: p.name.clone(), watts: p.watts })
+                .collect(),
+            cgroups: metrics
+                .cgroups
+                .iter()
+                .map(|c| CgroupPower { cgroup: c.cgroup.clone(), watts: c.watts })
+                .collect(),
+            energy: metrics.energy.as_ref().map(|e| EnergyTotals {
+                joules: e.joules,
+                kwh: e.kwh,
+                since: e.since,
+                cost: e.cost,
+                currency: e.currency.clone(),
+            }),
+            idle_floor_watts: metrics.idle_floor_watts,
+            self_telemetry: metrics.self_telemetry.map(|t| SelfTelemetry {
+                sample_duration_ms: t.sample_duration_ms,
+                upload_latency_ms: t.upload_latency_ms,
+                msr_read_errors: t.msr_read_errors,
+                upload_retries: t.upload_retries,
+                dropped_points: t.dropped_points,
+                rejected_values: t.rejected_values,
+                open_circuits: t.open_circuits,
+                resumes: t.resumes,
+            }),
+            stats: metrics.stats.as_ref().map(|s| PowerStats {
+                samples: s.samples as u64,
+                core_sum: Some(s.core_sum.into()),
+                package: Some(s.package.into()),
+                dram: s.dram.map(Into::into),
+                cores: s.cores.iter().map(|&c| c.into()).collect(),
+            }),
+            resume: metrics.resume.map(|r| Resume { at: seconds(r.at), suspended_secs: r.suspended_secs }),
+            histograms: metrics.histograms.as_ref().map(|h| PowerHistograms {
+                package: Some((&h.package).into()),
+                cores: h.cores.iter().map(Into::into).collect(),
+            }),
+            core_cpus: metrics.core_cpus.iter().map(|cpus| CoreCpus { cpus: ids(cpus) }).collect(),
+            core_ids: ids(&metrics.core_ids),
+            core_nodes: ids(&metrics.core_nodes),
+            core_l3s: ids(&metrics.core_l3s),
+            core_classes: metrics.core_classes.iter().map(|&c| CoreClass::from(c) as i32).collect(),
+            skipped_cores: ids(&metrics.skipped_cores),
+            tags: metrics.tags.clone().into_iter().collect(),
+            timestamp: seconds(metrics.timestamp),
+        }
+    }
+}
+
+impl TryFrom<PowerMetrics> for rapl::PowerMetrics {
+    type Error = String;
+
+    fn try_from(metrics: PowerMetrics) -> Result<Self, String> {
+        Ok(rapl::PowerMetrics {
+            core_watts: metrics.core_watts,
+            core_sum: metrics.core_sum,
+            package_watts: metrics.package_watts,
+            packages: metrics
+                .packages
+                .into_iter()
+                .map(|p| rapl::PackagePower { package: p.package as usize, watts: p.watts })
+                .collect(),
+            dram_watts: metrics.dram_watts,
+            soc_watts: metrics.soc_watts,
+            temperatures: metrics.temperatures.into_iter().map(Into::into).collect(),
+            rails: metrics
+                .rails
+                .into_iter()
+                .map(|r| hwmon::RailReading { rail: r.rail, volts: r.volts, amps: r.amps })
+                .collect(),
+            limits: metrics
+                .limits
+                .map(|l| -> Result<_, String> {
+                    Ok(smu::SmuLimits {
+                        ppt: required(l.ppt, "limits.ppt")?.into(),
+                        tdc: required(l.tdc, "limits.tdc")?.into(),
+                        edc: required(l.edc, "limits.edc")?.into(),
+                        thm: l.thm.map(Into::into),
+                    })
+                })
+                .transpose()?,
+            throttle: metrics
+                .throttle
+                .map(|t| -> Result<_, String> {
+                    Ok(throttle::Throttle {
+                        checked: t.checked.into_iter().map(reason).collect::<Result<_, _>>()?,
+                        active: t.active.into_iter().map(reason).collect::<Result<_, _>>()?,
+                        residency: t
+                            .residency
+                            .into_iter()
+                            .map(|r| Ok((reason(r.reason)?, r.share)))
+                            .collect::<Result<_, String>>()?,
+                        events: t
+                            .events
+                            .into_iter()
+                            .map(|e| {
+                                Ok(throttle::ThrottleEvent {
+                                    reason: reason(e.reason)?,
+                                    started: time(e.started)?,
+                                    duration_secs: e.duration_secs,
+                                })
+                            })
+                            .collect::<Result<_, String>>()?,
+                    })
+                })
+                .transpose()?,
+            ccds: metrics
+                .ccds
+                .into_iter()
+                .map(|c| rapl::CcdPower { ccd: c.ccd as usize, watts: c.watts, celsius: c.celsius })
+                .collect(),
+            gpus: metrics
+                .gpus
+                .into_iter()
+                .map(|g| hwmon::GpuReading {
+                    gpu: g.gpu as usize,
+                    watts: g.watts,
+                    temperatures: g.temperatures.into_iter().map(Into::into).collect(),
+                    fan_rpm: g.fan_rpm,
+                })
+                .collect(),
+            sensors: metrics
+                .sensors
+                .into_iter()
+                .map(|s| hwmon::SensorReading { label: s.label, value: s.value, unit: s.unit })
+                .collect(),
+            core_mhz: metrics.core_mhz,
+            average_mhz: metrics.average_mhz,
+            core_activity: metrics
+                .core_activity
+                .into_iter()
+                .map(|a| rapl::CoreActivity { effective_mhz: a.effective_mhz, busy_percent: a.busy_percent })
+                .collect(),
+            cstates: metrics
+                .cstates
+                .into_iter()
+                .map(|c| cpuidle::CStateResidency { state: c.state, percent: c.percent, cores: c.cores })
+                .collect(),
+            core_utilization: metrics.core_utilization,
+            utilization: metrics.utilization,
+            processes: metrics
+                .processes
+                .into_iter()
+                .map(|p| procstat::ProcessPower { name: p.name, watts: p.watts })
+                .collect(),
+            cgroups: metrics
+                .cgroups
+                .into_iter()
+                .map(|c| cgroup::CgroupPower { cgroup: c.cgroup, watts: c.watts })
+                .collect(),
+            energy: metrics.energy.map(|e| energy::EnergyTotals {
+                joules: e.joules,
+                kwh: e.kwh,
+                since: e.since,
+                cost: e.cost,
+                currency: e.currency,
+            }),
+            idle_floor_watts: metrics.idle_floor_watts,
+            self_telemetry: metrics.self_telemetry.map(|t| telemetry::SelfTelemetry {
+                sample_duration_ms: t.sample_duration_ms,
+                upload_latency_ms: t.upload_latency_ms,
+                msr_read_errors: t.msr_read_errors,
+                upload_retries: t.upload_retries,
+                dropped_points: t.dropped_points,
+                rejected_values: t.rejected_values,
+                open_circuits: t.open_circuits,
+                resumes: t.resumes,
+            }),
+            stats: metrics
+                .stats
+                .map(|s| -> Result<_, String> {
+                    Ok(stats::PowerStats {
+                        samples: s.samples as usize,
+                        core_sum: required(s.core_sum, "stats.core_sum")?.into(),
+                        package: required(s.package, "stats.package")?.into(),
+                        dram: s.dram.map(Into::into),
+                        cores: s.cores.into_iter().map(Into::into).collect(),
+                    })
+                })
+                .transpose()?,
+            resume: metrics
+                .resume
+                .map(|r| -> Result<_, String> { Ok(suspend::Resume { at: time(r.at)?, suspended_secs: r.suspended_secs }) })
+                .transpose()?,
+            histograms: metrics
+                .histograms
+                .map(|h| -> Result<_, String> {
+                    Ok(histogram::PowerHistograms {
+                        package: required(h.package, "histograms.package")?.try_into()?,
+                        cores: h.cores.into_iter().map(TryInto::try_into).collect::<Result<_, _>>()?,
+                    })
+                })
+                .transpose()?,
+            core_cpus: metrics.core_cpus.into_iter().map(|c| indices(c.cpus)).collect(),
+            core_ids: indices(metrics.core_ids),
+            core_nodes: indices(metrics.core_nodes),
+            core_l3s: indices(metrics.core_l3s),
+            core_classes: metrics
+                .core_classes
+                .into_iter()
+                .map(|class| match CoreClass::try_from(class) {
+                    Ok(CoreClass::Freq) => Ok(topology::CoreClass::Freq),
+                    Ok(CoreClass::Cache) => Ok(topology::CoreClass::Cache),
+                    Ok(CoreClass::Dense) => Ok(topology::CoreClass::Dense),
+                    Err(_) => Err(format!("unknown core class {}", class)),
+                })
+                .collect::<Result<_, _>>()?,
+            skipped_cores: indices(metrics.skipped_cores),
+            tags: metrics.tags.into_iter().collect(),
+            timestamp: time(metrics.timestamp)?,
+        })
+    }
+}
+
+fn reason(reason: i32) -> Result<throttle::ThrottleReason, String> {
+    match ThrottleReason::try_from(reason) {
+        Ok(ThrottleReason::Prochot) => Ok(throttle::ThrottleReason::Prochot),
+        Ok(ThrottleReason::Thermal) => Ok(throttle::ThrottleReason::Thermal),
+        Ok(ThrottleReason::PowerLimit) => Ok(throttle::ThrottleReason::PowerLimit),
+        Ok(ThrottleReason::Ppt) => Ok(throttle::ThrottleReason::Ppt),
+        Ok(ThrottleReason::Tdc) => Ok(throttle::ThrottleReason::Tdc),
+        Ok(ThrottleReason::Edc) => Ok(throttle::ThrottleReason::Edc),
+        Err(_) => Err(format!("unknown throttle reason {}", reason)),
+    }
+}
+
+impl From<throttle::ThrottleReason> for ThrottleReason {
+    fn from(reason: throttle::ThrottleReason) -> Self {
+        match reason {
+            throttle::ThrottleReason::Prochot => ThrottleReason::Prochot,
+            throttle::ThrottleReason::Thermal => ThrottleReason::Thermal,
+            throttle::ThrottleReason::PowerLimit => ThrottleReason::PowerLimit,
+            throttle::ThrottleReason::Ppt => ThrottleReason::Ppt,
+            throttle::ThrottleReason::Tdc => ThrottleReason::Tdc,
+            throttle::ThrottleReason::Edc => ThrottleReason::Edc,
+        }
+    }
+}
+
+impl From<topology::CoreClass> for CoreClass {
+    fn from(class: topology::CoreClass) -> Self {
+        match class {
+            topology::CoreClass::Freq => CoreClass::Freq,
+            topology::CoreClass::Cache => CoreClass::Cache,
+            topology::CoreClass::Dense => CoreClass::Dense,
+        }
+    }
+}
+
+impl From<&hwmon::TemperatureReading> for TemperatureReading {
+    fn from(t: &hwmon::TemperatureReading) -> Self {
+        TemperatureReading { label: t.label.clone(), celsius: t.celsius }
+    }
+}
+
+impl From<TemperatureReading> for hwmon::TemperatureReading {
+    fn from(t: TemperatureReading) -> Self {
+        hwmon::TemperatureReading { label: t.label, celsius: t.celsius }
+    }
+}
+
+impl From<smu::Limit> for Limit {
+    fn from(l: smu::Limit) -> Self {
+        Limit { value: l.value, limit: l.limit }
+    }
+}
+
+impl From<Limit> for smu::Limit {
+    fn from(l: Limit) -> Self {
+        smu::Limit { value: l.value, limit: l.limit }
+    }
+}
+
+impl From<stats::Summary> for Summary {
+    fn from(s: stats::Summary) -> Self {
+        Summary { min: s.min, max: s.max, mean: s.mean, p95: s.p95 }
+    }
+}
+
+impl From<Summary> for stats::Summary {
+    fn from(s: Summary) -> Self {
+        stats::Summary { min: s.min, max: s.max, mean: s.mean, p95: s.p95 }
+    }
+}
+
+impl From<&histogram::Histogram> for Histogram {
+    fn from(h: &histogram::Histogram) -> Self {
+        let counts = |c: &histogram::Counts| Counts { buckets: c.buckets.clone(), sum: c.sum };
+        Histogram {
+            bounds: h.bounds.clone(),
+            interval: Some(counts(&h.interval)),
+            total: Some(counts(&h.total)),
+        }
+    }
+}
+
+impl TryFrom<Histogram> for histogram::Histogram {
+    type Error = String;
+
+    fn try_from(h: Histogram) -> Result<Self, String> {
+        let counts = |c: Counts| histogram::Counts { buckets: c.buckets, sum: c.sum };
+        Ok(histogram::Histogram {
+            bounds: h.bounds,
+            interval: counts(required(h.interval, "histogram.interval")?),
+            total: counts(required(h.total, "histogram.total")?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+    use crate::platform::fixture_sample;
+
+    #[test]
+    fn round_trips_every_field() {
+        let mut metrics = fixture_sample();
+        metrics.tags.insert("host".to_string(), "node1".to_string());
+        metrics.throttle = Some(throttle::Throttle {
+            checked: vec![throttle::ThrottleReason::Thermal, throttle::ThrottleReason::Ppt],
+            active: vec![throttle::ThrottleReason::Ppt],
+            residency: [(throttle::ThrottleReason::Ppt, 0.25)].into(),
+            events: vec![throttle::ThrottleEvent {
+                reason: throttle::ThrottleReason::Ppt,
+                started: metrics.timestamp,
+                duration_secs: 1.5,
+            }],
+        });
+        metrics.core_classes = vec![topology::CoreClass::Cache; metrics.core_watts.len()];
+
+        let encoded = PowerMetrics::from(&metrics).encode_to_vec();
+        let decoded = rapl::PowerMetrics::try_from(PowerMetrics::decode(encoded.as_slice()).unwrap()).unwrap();
+        assert_eq!(serde_json::to_value(&decoded).unwrap(), serde_json::to_value(&metrics).unwrap());
+    }
+
+    #[test]
+    fn refuses_unknown_enum_values() {
+        let mut message = PowerMetrics::from(&fixture_sample());
+        message.core_classes = vec![7];
+        assert!(rapl::PowerMetrics::try_from(message).is_err());
+    }
+}
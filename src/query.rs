@@ -0,0 +1,45 @@
+use std::path::Path;
+use std::time::SystemTime;
+
+use ryzenmon_rust::config::Config;
+use ryzenmon_rust::error::RyzenmonError;
+use ryzenmon_rust::sink::sqlite::{open_read_only, summarize};
+
+// `ryzenmon query`: print the sample count, mean, minimum and maximum of
+// each matching metric between `since` and `until`, one line per metric.
+pub fn run(
+    config: &Config,
+    patterns: &[String],
+    since: Option<SystemTime>,
+    until: Option<SystemTime>,
+    db: Option<&Path>,
+) -> Result<(), RyzenmonError> {
+    let path = match (db, &config.sqlite) {
+        (Some(db), _) => db,
+        (None, Some(sqlite)) => Path::new(&sqlite.path),
+        (None, None) => {
+            return Err(RyzenmonError::Config("no [sqlite] section in the config, pass --db".to_string()));
+        }
+    };
+    let db = open_read_only(path)?;
+
+    let patterns = if patterns.is_empty() { vec!["*".to_string()] } else { patterns.to_vec() };
+    let mut summaries = Vec::new();
+    for pattern in &patterns {
+        summaries.extend(summarize(&db, pattern, since, until)?);
+    }
+    if summaries.is_empty() {
+        println!("No samples of {} in that range", patterns.join(", "));
+        return Ok(());
+    }
+
+    let width = summaries.iter().map(|s| s.metric.len()).max().unwrap_or(0).max("metric".len());
+    println!("{:<width$} {:>8} {:>12} {:>12} {:>12}", "metric", "samples", "avg", "min", "max");
+    for summary in summaries {
+        println!(
+            "{:<width$} {:>8} {:>12.3} {:>12.3} {:>12.3}",
+            summary.metric, summary.samples, summary.mean, summary.min, summary.max
+        );
+    }
+    Ok(())
+}
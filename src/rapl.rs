@@ -0,0 +1,996 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tracing::warn;
+
+use crate::error::{RyzenmonError, Result};
+use crate::cgroup::CgroupPower;
+use crate::cpuidle::CStateResidency;
+use crate::energy::EnergyTotals;
+use crate::hwmon::{GpuReading, RailReading, SensorReading, TemperatureReading};
+use crate::msr::{MsrMap, EIO, INTEL_MSR_PACKAGE_THERM_STATUS, MSR_APERF, MSR_MPERF, MSR_TSC};
+use crate::platform::{Msr, MsrReader, Platform};
+use crate::procstat::ProcessPower;
+use crate::smu::SmuLimits;
+use crate::histogram::PowerHistograms;
+use crate::stats::PowerStats;
+use crate::suspend::Resume;
+use crate::telemetry::SelfTelemetry;
+use crate::throttle::Throttle;
+use crate::topology::{retain_by, CoreClass, Package, Topology};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackagePower {
+    pub package: usize,
+    pub watts: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CcdPower {
+    pub ccd: usize,
+    // Sum of the CCD's core power
+    pub watts: f64,
+    // Tccd<n> from k10temp
+    pub celsius: Option<f64>,
+}
+
+// From APERF/MPERF over the sampling window.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CoreActivity {
+    // Average clock over the whole window, idle time included
+    pub effective_mhz: f64,
+    // Share of the window spent in C0
+    pub busy_percent: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PowerMetrics {
+    pub core_watts: Vec<f64>,
+    pub core_sum: f64,
+    // Sum over all packages
+    pub package_watts: f64,
+    pub packages: Vec<PackagePower>,
+    // Only reported on Intel parts that expose the DRAM domain
+    pub dram_watts: Option<f64>,
+    // SoC power, from the RAPL uncore zone or VDDCR_SOC telemetry
+    #[serde(default)]
+    pub soc_watts: Option<f64>,
+    pub temperatures: Vec<TemperatureReading>,
+    // SVI2/SVI3 rail telemetry from zenpower or k10temp, when available
+    pub rails: Vec<RailReading>,
+    // PPT/TDC/EDC from the ryzen_smu PM table, when the module is loaded
+    pub limits: Option<SmuLimits>,
+    // PROCHOT, thermal and power limit throttling, where the CPU exposes it
+    #[serde(default)]
+    pub throttle: Option<Throttle>,
+    // Only on parts with more than one CCD
+    pub ccds: Vec<CcdPower>,
+    // amdgpu cards, when enabled with sampling.gpu
+    pub gpus: Vec<GpuReading>,
+    // [[hwmon]] sensors from the config
+    pub sensors: Vec<SensorReading>,
+    // Per core from cpufreq, in the same order as core_watts
+    pub core_mhz: Vec<f64>,
+    // Mean over all cores
+    pub average_mhz: Option<f64>,
+    // Per core from APERF/MPERF, MSR backend only
+    pub core_activity: Vec<CoreActivity>,
+    // cpuidle residency per state over the window
+    pub cstates: Vec<CStateResidency>,
+    // Busy percentage per core over the window from /proc/stat, counting all
+    // SMT threads, in the same order as core_watts
+    pub core_utilization: Vec<f64>,
+    // Busy percentage of the whole system
+    pub utilization: Option<f64>,
+    // Top processes by estimated power, when enabled with sampling.top_processes
+    pub processes: Vec<ProcessPower>,
+    // cgroups matching sampling.cgroups, by estimated power
+    pub cgroups: Vec<CgroupPower>,
+    // Package energy since the daemon started, filled in by the daemon loop
+    pub energy: Option<EnergyTotals>,
+    // Lowest package power over [idle_floor] window_secs, filled in by the
+    // daemon loop
+    #[serde(default)]
+    pub idle_floor_watts: Option<f64>,
+    // Counters about ryzenmon itself, filled in by the daemon loop
+    pub self_telemetry: Option<SelfTelemetry>,
+    // Power over the upload interval, when sampling.sample_interval_ms is set
+    pub stats: Option<PowerStats>,
+    // Set on the sample a resume from suspend ended in, which the daemon
+    // discards, then on the next one it uploads
+    #[serde(default)]
+    pub resume: Option<Resume>,
+    // Package and per-core power buckets, with [histogram]
+    #[serde(default)]
+    pub histograms: Option<PowerHistograms>,
+    // Logical CPUs (SMT siblings) of each core, in the same order as core_watts
+    #[serde(default)]
+    pub core_cpus: Vec<Vec<usize>>,
+    // Number each core is reported under, its lscpu CORE number, in the same
+    // order as core_watts; kept when other cores are skipped, so `core=N`
+    // always means the same core. Empty when cores are numbered by position
+    #[serde(default)]
+    pub core_ids: Vec<usize>,
+    // NUMA node and L3 cache id of each core, in the same order as
+    // core_watts; empty when unknown
+    #[serde(default)]
+    pub core_nodes: Vec<usize>,
+    #[serde(default)]
+    pub core_l3s: Vec<usize>,
+    // Class of each core on parts with more than one kind, in the same order
+    // as core_watts; empty otherwise
+    #[serde(default)]
+    pub core_classes: Vec<CoreClass>,
+    // Logical CPUs of cores left out because their MSR device can't be read,
+    // e.g. offlined or outside this process's cpuset
+    pub skipped_cores: Vec<usize>,
+    // Tags of the host that took the sample, set on samples sent to an
+    // aggregator; they take precedence over the aggregator's own tags
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    // Wall clock time at the end of the measurement window
+    #[serde(serialize_with = "serialize_unix_seconds", deserialize_with = "deserialize_unix_seconds")]
+    pub timestamp: SystemTime,
+}
+
+// Seconds since the epoch as a float, the most convenient form for jq and friends.
+pub(crate) fn serialize_unix_seconds<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+    serializer.serialize_f64(secs)
+}
+
+pub(crate) fn deserialize_unix_seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+    let secs = f64::deserialize(deserializer)?;
+    Duration::try_from_secs_f64(secs)
+        .map(|since_epoch| UNIX_EPOCH + since_epoch)
+        .map_err(serde::de::Error::custom)
+}
+
+// Core power summed per CCD, with the matching Tccd temperature. `ccds` holds
+// the CCD of every core; single-CCD parts get no breakdown.
+pub fn ccd_power(core_watts: &[f64], ccds: &[usize], temperatures: &[TemperatureReading]) -> Vec<CcdPower> {
+    if core_watts.len() != ccds.len() {
+        return Vec::new();
+    }
+    let count = ccds.iter().max().map_or(0, |max| max + 1);
+    if count < 2 {
+        return Vec::new();
+    }
+
+    (0..count)
+        .map(|ccd| CcdPower {
+            ccd,
+            watts: core_watts
+                .iter()
+                .zip(ccds)
+                .filter(|(_, &c)| c == ccd)
+                .map(|(watts, _)| watts)
+                .sum(),
+            // k10temp numbers them from 1
+            celsius: temperatures
+                .iter()
+                .find(|t| t.label == format!("Tccd{}", ccd + 1))
+                .map(|t| t.celsius),
+        })
+        .collect()
+}
+
+impl PowerMetrics {
+    // Why this sample can't be right: a power reading that is negative or
+    // above `max_watts`, as after a counter reset. None when it is plausible.
+    pub fn implausible(&self, max_watts: Option<f64>) -> Option<String> {
+        let mut readings = vec![("package power".to_string(), self.package_watts), ("core power".to_string(), self.core_sum)];
+        readings.extend(self.dram_watts.map(|watts| ("DRAM power".to_string(), watts)));
+        readings.extend(self.packages.iter().map(|p| (format!("package {} power", p.package), p.watts)));
+        readings.extend(self.core_watts.iter().enumerate().map(|(core, watts)| (format!("core {} power", self.core_id(core)), *watts)));
+        readings.into_iter().find_map(|(name, watts)| {
+            if watts < 0.0 {
+                Some(format!("{} is negative ({:.1} W)", name, watts))
+            } else if max_watts.is_some_and(|max| watts > max) {
+                Some(format!("{} ({:.1} W) is above sampling.max_watts", name, watts))
+            } else {
+                None
+            }
+        })
+    }
+
+    // The number `core`, a position in core_watts, is reported under.
+    pub fn core_id(&self, core: usize) -> usize {
+        self.core_ids.get(core).copied().unwrap_or(core)
+    }
+
+    // Per core readings with the number of their core.
+    pub fn per_core<'a, T>(&'a self, values: &'a [T]) -> impl Iterator<Item = (usize, &'a T)> + 'a {
+        values.iter().enumerate().map(|(core, value)| (self.core_id(core), value))
+    }
+
+    // The number of `core`, a position in core_watts, then its NUMA node, L3
+    // and class where known, as tags for sinks that can group by them.
+    pub fn core_tags(&self, core: usize) -> Vec<(&'static str, String)> {
+        let mut tags = vec![("core", self.core_id(core).to_string())];
+        if let Some(node) = self.core_nodes.get(core) {
+            tags.push(("numa_node", node.to_string()));
+        }
+        if let Some(l3) = self.core_l3s.get(core) {
+            tags.push(("l3", l3.to_string()));
+        }
+        if let Some(class) = self.core_classes.get(core) {
+            tags.push(("core_class", class.as_str().to_string()));
+        }
+        tags
+    }
+
+    // SMT siblings of `core` as a list, e.g. "3,19", None when unknown.
+    pub fn core_cpu_list(&self, core: usize) -> Option<String> {
+        let cpus = self.core_cpus.get(core)?;
+        Some(cpus.iter().map(|cpu| cpu.to_string()).collect::<Vec<_>>().join(","))
+    }
+
+    // Mean effective clock and busy share over all cores.
+    pub fn mean_activity(&self) -> Option<CoreActivity> {
+        if self.core_activity.is_empty() {
+            return None;
+        }
+        let count = self.core_activity.len() as f64;
+        Some(CoreActivity {
+            effective_mhz: self.core_activity.iter().map(|a| a.effective_mhz).sum::<f64>() / count,
+            busy_percent: self.core_activity.iter().map(|a| a.busy_percent).sum::<f64>() / count,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ClockCounters {
+    tsc: u64,
+    mperf: u64,
+    aperf: u64,
+}
+
+// TSC, MPERF and APERF of every core, None if any of them can't be read (some
+// hypervisors don't expose APERF/MPERF). They are per thread, so each core is
+// represented by the thread its device is opened on.
+fn read_clocks(devices: &mut [Box<dyn Msr>]) -> Option<Vec<ClockCounters>> {
+    let mut reader = clock_reader();
+    devices.iter_mut().map(|device| read_clock(&mut reader, device.as_mut())).collect()
+}
+
+fn clock_reader() -> MsrReader<3> {
+    MsrReader::new([MSR_TSC, MSR_MPERF, MSR_APERF])
+}
+
+fn read_clock(reader: &mut MsrReader<3>, device: &mut dyn Msr) -> Option<ClockCounters> {
+    let [tsc, mperf, aperf] = reader.read(device).ok()?;
+    Some(ClockCounters { tsc, mperf, aperf })
+}
+
+fn clock_activity(before: Option<Vec<ClockCounters>>, after: Option<Vec<ClockCounters>>, window: Duration) -> Vec<CoreActivity> {
+    let (Some(before), Some(after)) = (before, after) else {
+        return Vec::new();
+    };
+    before
+        .iter()
+        .zip(&after)
+        .map(|(before, after)| {
+            core_activity(
+                after.tsc.wrapping_sub(before.tsc),
+                after.mperf.wrapping_sub(before.mperf),
+                after.aperf.wrapping_sub(before.aperf),
+                window,
+            )
+        })
+        .collect()
+}
+
+// MPERF counts at the TSC rate and APERF at the actual clock, both only in C0.
+pub fn core_activity(tsc_delta: u64, mperf_delta: u64, aperf_delta: u64, window: Duration) -> CoreActivity {
+    CoreActivity {
+        effective_mhz: aperf_delta as f64 / window.as_secs_f64() / 1e6,
+        busy_percent: if tsc_delta > 0 {
+            (mperf_delta as f64 / tsc_delta as f64 * 100.0).min(100.0)
+        } else {
+            0.0
+        },
+    }
+}
+
+// RAPL energy status registers are 32 bits wide and wrap around; at 15.3uJ per
+// count that takes around 10 minutes on a busy package.
+pub const ENERGY_COUNTER_MASK: u64 = 0xFFFF_FFFF;
+
+// Joules per counter increment from the power unit MSR.
+pub fn energy_unit_joules(power_unit: u64, mask: u64) -> f64 {
+    0.5f64.powi(((power_unit & mask) >> 8) as i32)
+}
+
+// Counter increments between two reads, correct across a single wraparound.
+pub fn counter_delta(before: u64, after: u64) -> u64 {
+    (after & ENERGY_COUNTER_MASK).wrapping_sub(before & ENERGY_COUNTER_MASK) & ENERGY_COUNTER_MASK
+}
+
+// Joules per energy counter increment. A power unit MSR that faults or holds
+// no energy unit means the CPU doesn't implement RAPL after all, e.g. under a
+// hypervisor, and every reading would be garbage.
+fn read_energy_unit(device: &mut dyn Msr, map: &MsrMap) -> Result<f64> {
+    let unsupported = |why| {
+        RyzenmonError::UnsupportedCpu(format!("the RAPL power unit MSR {:#x} {}", map.power_unit, why))
+    };
+    let power_unit = match device.read(map.power_unit) {
+        Err(RyzenmonError::MsrAccess { source, .. }) if source.raw_os_error() == Some(EIO) => {
+            return Err(unsupported("can't be read"))
+        }
+        result => result?,
+    };
+    if power_unit & map.energy_unit_mask == 0 {
+        return Err(unsupported("reports no energy unit"));
+    }
+    Ok(energy_unit_joules(power_unit, map.energy_unit_mask))
+}
+
+// How long the counters ran between reads started at `before` and at
+// `after`, which is longer than the nominal `window` by however late the
+// sleep woke up and the reads took.
+fn elapsed(before: Instant, after: Instant, window: Duration, real_time: bool) -> Duration {
+    if real_time {
+        after.duration_since(before)
+    } else {
+        window
+    }
+}
+
+pub fn counter_watts(before: u64, after: u64, energy_unit: f64, window: Duration) -> f64 {
+    counter_delta(before, after) as f64 * energy_unit / window.as_secs_f64()
+}
+
+// Devices of the cores that could be opened, and the CPUs of those that couldn't
+type OpenCores = (Vec<Box<dyn Msr>>, Vec<usize>);
+
+// Opens the MSR device of every core that allows it. The rest are returned
+// separately, so one offlined core or a restrictive cpuset doesn't stop the
+// others from being sampled; fails only when no core can be opened.
+fn open_cores(platform: &dyn Platform, cores: &[usize]) -> Result<OpenCores> {
+    let mut devices = Vec::with_capacity(cores.len());
+    let mut skipped = Vec::new();
+    let mut error = None;
+    for &cpu in cores {
+        match platform.open_msr(cpu) {
+            Ok(device) => devices.push(device),
+            Err(e) => {
+                skipped.push(cpu);
+                error.get_or_insert(e);
+            }
+        }
+    }
+    match error {
+        Some(e) if devices.is_empty() => Err(e),
+        Some(e) => {
+            warn!("Skipping the cores on CPUs {:?}: {}", skipped, e);
+            Ok((devices, skipped))
+        }
+        None => Ok((devices, skipped)),
+    }
+}
+
+// Per-core and per-package MSR devices for AMD. Core devices are opened on
+// one logical CPU per physical core; the core energy MSR is per core, so
+// reading it from an SMT sibling would count the core twice.
+pub struct AmdRapl {
+    cores: Vec<Box<dyn Msr>>,
+    packages: Vec<(Package, Box<dyn Msr>)>,
+    // Index into `packages` of each core
+    core_packages: Vec<usize>,
+    map: MsrMap,
+    energy_unit: f64,
+    // Cores that couldn't be read, left out of every sample
+    skipped: Vec<usize>,
+    real_time: bool,
+    // Packages are read on threads of their own
+    parallel: bool,
+    // The end of the previous sample, where a continuous one starts
+    last: Option<AmdReading>,
+}
+
+struct AmdReading {
+    at: Instant,
+    cores: Vec<Option<u64>>,
+    packages: Vec<u64>,
+    clocks: Option<Vec<ClockCounters>>,
+}
+
+impl AmdRapl {
+    pub fn open(topology: &Topology, map: MsrMap, platform: &dyn Platform) -> Result<Self> {
+        if topology.cores.is_empty() || topology.packages.is_empty() {
+            return Err(RyzenmonError::Topology(io::Error::new(io::ErrorKind::NotFound, "no CPU cores to sample")));
+        }
+
+        let (mut cores, skipped) = open_cores(platform, &topology.cores)?;
+        let mut packages = Vec::with_capacity(topology.packages.len());
+        for &package in &topology.packages {
+            packages.push((package, platform.open_msr(package.cpu)?));
+        }
+
+        // Cores of an unknown package are read with the first.
+        let core_packages = cores
+            .iter()
+            .map(|device| {
+                let core = topology.cores.iter().position(|&cpu| cpu == device.cpu());
+                let id = core.and_then(|core| topology.core_packages.get(core));
+                packages.iter().position(|(package, _)| Some(&package.id) == id).unwrap_or(0)
+            })
+            .collect();
+
+        let energy_unit = read_energy_unit(cores[0].as_mut(), &map)?;
+        Ok(AmdRapl {
+            cores,
+            packages,
+            core_packages,
+            map,
+            energy_unit,
+            skipped,
+            real_time: platform.real_time(),
+            parallel: true,
+            last: None,
+        })
+    }
+
+    // Read the packages one after the other, for a sampling thread pinned to
+    // one CPU, where threads of its own would only take turns on that CPU.
+    pub fn set_parallel(&mut self, parallel: bool) {
+        self.parallel = parallel;
+    }
+
+    // Each package is read on its own thread, so on a multi-socket machine
+    // the last core isn't read long after the first.
+    fn read(&mut self) -> Result<AmdReading> {
+        let at = Instant::now();
+        let parallel = self.parallel;
+        let map = &self.map;
+        let mut groups: Vec<PackageDevices> =
+            self.packages.iter_mut().map(|(_, device)| (device, Vec::new())).collect();
+        for ((core, device), &package) in self.cores.iter_mut().enumerate().zip(&self.core_packages) {
+            groups[package].1.push((core, device));
+        }
+        let readings: Vec<PackageReading> = if groups.len() == 1 || !parallel {
+            groups.into_iter().map(|group| read_package(map, group)).collect()
+        } else {
+            thread::scope(|scope| {
+                let threads: Vec<_> =
+                    groups.into_iter().map(|group| scope.spawn(move || read_package(map, group))).collect();
+                threads.into_iter().map(|thread| thread.join().expect("reading MSRs panicked")).collect()
+            })
+        };
+
+        let mut cores = vec![None; self.cores.len()];
+        let mut clocks = vec![None; self.cores.len()];
+        let mut packages = Vec::with_capacity(readings.len());
+        for (package, per_core) in readings {
+            packages.push(package?);
+            for (core, energy, clock) in per_core {
+                cores[core] = energy;
+                clocks[core] = clock;
+            }
+        }
+        Ok(AmdReading {
+            at,
+            cores,
+            packages,
+            clocks: clocks.into_iter().collect(),
+        })
+    }
+
+    // Power over `window`, or with `continuous` since the previous sample
+    // when there is one.
+    pub fn sample(&mut self, window: Duration, continuous: bool) -> Result<PowerMetrics> {
+        let before = match self.last.take() {
+            Some(last) if continuous => last,
+            _ => {
+                let before = self.read()?;
+                thread::sleep(window);
+                before
+            }
+        };
+        let mut after = self.read()?;
+        let window = elapsed(before.at, after.at, window, self.real_time);
+        let (core_before, package_before, clocks_before) = (before.cores, before.packages, before.clocks);
+        let (core_after, package_after, clocks_after) = (after.cores.clone(), after.packages.clone(), after.clocks.clone());
+
+        let energy_unit = self.energy_unit;
+        let readings: Vec<Option<(u64, u64)>> = core_before.into_iter().zip(core_after).map(|(b, a)| b.zip(a)).collect();
+        let core_watts: Vec<f64> = readings
+            .iter()
+            .flatten()
+            .map(|&(before, after)| counter_watts(before, after, energy_unit, window))
+            .collect();
+        let mut core_activity = clock_activity(clocks_before, clocks_after, window);
+        // A core whose MSR went away during the window is left out from now on.
+        if readings.iter().any(Option::is_none) {
+            let keep: Vec<bool> = readings.iter().map(Option::is_some).collect();
+            let lost: Vec<usize> =
+                self.cores.iter().zip(&keep).filter(|(_, keep)| !**keep).map(|(device, _)| device.cpu()).collect();
+            retain_by(&mut self.cores, &keep);
+            retain_by(&mut self.core_packages, &keep);
+            warn!("Skipping the cores on CPUs {:?} from now on, their MSRs can no longer be read", lost);
+            self.skipped.extend(lost);
+            retain_by(&mut core_activity, &keep);
+            retain_by(&mut after.cores, &keep);
+            if let Some(clocks) = &mut after.clocks {
+                retain_by(clocks, &keep);
+            }
+        }
+        self.last = Some(after);
+
+        let packages: Vec<PackagePower> = self
+            .packages
+            .iter()
+            .zip(package_before.iter().zip(&package_after))
+            .map(|((p, _), (&before, &after))| PackagePower {
+                package: p.id,
+                watts: counter_watts(before, after, energy_unit, window),
+            })
+            .collect();
+
+        Ok(PowerMetrics {
+            core_sum: core_watts.iter().sum(),
+            core_watts,
+            package_watts: packages.iter().map(|p| p.watts).sum(),
+            packages,
+            dram_watts: None,
+            soc_watts: None,
+            temperatures: Vec::new(),
+            rails: Vec::new(),
+            limits: None,
+            throttle: None,
+            ccds: Vec::new(),
+            gpus: Vec::new(),
+            sensors: Vec::new(),
+            core_mhz: Vec::new(),
+            average_mhz: None,
+            core_activity,
+            cstates: Vec::new(),
+            core_utilization: Vec::new(),
+            utilization: None,
+            processes: Vec::new(),
+            cgroups: Vec::new(),
+            energy: None,
+            idle_floor_watts: None,
+            self_telemetry: None,
+            stats: None,
+            histograms: None,
+            resume: None,
+            core_cpus: Vec::new(),
+            core_ids: Vec::new(),
+            core_nodes: Vec::new(),
+            core_l3s: Vec::new(),
+            core_classes: Vec::new(),
+            skipped_cores: self.skipped.clone(),
+            tags: BTreeMap::new(),
+            timestamp: SystemTime::now(),
+        })
+    }
+}
+
+// Intel only exposes package-wide counters, so the PP0 (all cores) domain is
+// reported as the core sum and there is no per-core breakdown.
+pub struct IntelRapl {
+    packages: Vec<(Package, Box<dyn Msr>)>,
+    // Only used for APERF/MPERF
+    cores: Vec<Box<dyn Msr>>,
+    map: MsrMap,
+    energy_unit: f64,
+    dram_energy_unit: f64,
+    skipped: Vec<usize>,
+    real_time: bool,
+    last: Option<IntelReading>,
+}
+
+struct IntelReading {
+    at: Instant,
+    // Package, PP0 and DRAM energy of each package
+    counters: Vec<(u64, u64, Option<u64>)>,
+    clocks: Option<Vec<ClockCounters>>,
+}
+
+impl IntelRapl {
+    pub fn open(topology: &Topology, map: MsrMap, platform: &dyn Platform) -> Result<Self> {
+        if topology.packages.is_empty() {
+            return Err(RyzenmonError::Topology(io::Error::new(io::ErrorKind::NotFound, "no CPU packages to sample")));
+        }
+
+        let mut packages = Vec::with_capacity(topology.packages.len());
+        for &package in &topology.packages {
+            packages.push((package, platform.open_msr(package.cpu)?));
+        }
+
+        let energy_unit = read_energy_unit(packages[0].1.as_mut(), &map)?;
+        // Only clocks come from the cores, so without any the packages are
+        // still sampled, and no core counts as skipped.
+        let (cores, skipped) = match open_cores(platform, &topology.cores) {
+            Ok(cores) => cores,
+            Err(e) => {
+                warn!("No core MSR can be opened, so there are no core clocks: {}", e);
+                (Vec::new(), Vec::new())
+            }
+        };
+        Ok(IntelRapl {
+            packages,
+            cores,
+            map,
+            energy_unit,
+            dram_energy_unit: map.dram_energy_unit.unwrap_or(energy_unit),
+            skipped,
+            real_time: platform.real_time(),
+            last: None,
+        })
+    }
+
+    fn read(&mut self) -> Result<IntelReading> {
+        let at = Instant::now();
+        let mut counters = Vec::with_capacity(self.packages.len());
+        for (_, device) in self.packages.iter_mut() {
+            let package = device.read(self.map.package_energy)?;
+            let pp0 = device.read(self.map.core_energy)?;
+            let dram = self.map.dram_energy.and_then(|which| device.read(which).ok());
+            counters.push((package, pp0, dram));
+        }
+        Ok(IntelReading {
+            at,
+            counters,
+            clocks: read_clocks(&mut self.cores),
+        })
+    }
+
+    // Power over `window`, or with `continuous` since the previous sample
+    // when there is one.
+    pub fn sample(&mut self, window: Duration, continuous: bool) -> Result<PowerMetrics> {
+        let before = match self.last.take() {
+            Some(last) if continuous => last,
+            _ => {
+                let before = self.read()?;
+                thread::sleep(window);
+                before
+            }
+        };
+        let after = self.read()?;
+        let window = elapsed(before.at, after.at, window, self.real_time);
+        let core_activity = clock_activity(before.clocks, after.clocks.clone(), window);
+
+        let energy_unit = self.energy_unit;
+        let mut core_sum = 0.0;
+        let mut dram_watts = None;
+        let mut package_power = Vec::with_capacity(self.packages.len());
+
+        for ((p, _), (before, after)) in self.packages.iter().zip(before.counters.iter().zip(&after.counters)) {
+            package_power.push(PackagePower {
+                package: p.id,
+                watts: counter_watts(before.0, after.0, energy_unit, window),
+            });
+            core_sum += counter_watts(before.1, after.1, energy_unit, window);
+            if let (Some(dram_before), Some(dram_after)) = (before.2, after.2) {
+                *dram_watts.get_or_insert(0.0) += counter_watts(dram_before, dram_after, self.dram_energy_unit, window);
+            }
+        }
+        self.last = Some(after);
+        let statuses: Vec<u64> = self
+            .packages
+            .iter_mut()
+            .filter_map(|(_, device)| device.read(INTEL_MSR_PACKAGE_THERM_STATUS).ok())
+            .collect();
+
+        Ok(PowerMetrics {
+            core_watts: Vec::new(),
+            core_sum,
+            package_watts: package_power.iter().map(|p| p.watts).sum(),
+            packages: package_power,
+            dram_watts,
+            soc_watts: None,
+            temperatures: Vec::new(),
+            rails: Vec::new(),
+            limits: None,
+            throttle: Throttle::from_package_therm_status(&statuses),
+            ccds: Vec::new(),
+            gpus: Vec::new(),
+            sensors: Vec::new(),
+            core_mhz: Vec::new(),
+            average_mhz: None,
+            core_activity,
+            cstates: Vec::new(),
+            core_utilization: Vec::new(),
+            utilization: None,
+            processes: Vec::new(),
+            cgroups: Vec::new(),
+            energy: None,
+            idle_floor_watts: None,
+            self_telemetry: None,
+            stats: None,
+            histograms: None,
+            resume: None,
+            core_cpus: Vec::new(),
+            core_ids: Vec::new(),
+            core_nodes: Vec::new(),
+            core_l3s: Vec::new(),
+            core_classes: Vec::new(),
+            skipped_cores: self.skipped.clone(),
+            tags: BTreeMap::new(),
+            timestamp: SystemTime::now(),
+        })
+    }
+}
+
+// None for a core whose MSR can't be read.
+// The package device and the cores of one package, with their index
+type PackageDevices<'a> = (&'a mut Box<dyn Msr>, Vec<(usize, &'a mut Box<dyn Msr>)>);
+// Package energy, and the energy and clocks of each core by index
+type PackageReading = (Result<u64>, Vec<(usize, Option<u64>, Option<ClockCounters>)>);
+
+// The energy of a package's cores, then its own, then the clocks of its cores.
+fn read_package(map: &MsrMap, (package, mut cores): PackageDevices) -> PackageReading {
+    let energy: Vec<Option<u64>> = cores.iter_mut().map(|(_, device)| device.read(map.core_energy).ok()).collect();
+    let package_energy = package.read(map.package_energy);
+    let mut clocks = clock_reader();
+    let per_core = cores
+        .into_iter()
+        .zip(energy)
+        .map(|((core, device), energy)| (core, energy, read_clock(&mut clocks, device.as_mut())))
+        .collect();
+    (package_energy, per_core)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msr::{CpuId, Vendor};
+    use crate::platform::{fixture, Simulated, Trace};
+
+    #[test]
+    fn counter_delta_without_wrap() {
+        assert_eq!(counter_delta(1000, 1500), 500);
+        assert_eq!(counter_delta(0, 0), 0);
+    }
+
+    #[test]
+    fn counter_delta_across_wrap() {
+        assert_eq!(counter_delta(0xFFFF_FF00, 0x0000_0100), 0x200);
+        assert_eq!(counter_delta(ENERGY_COUNTER_MASK, 0), 1);
+    }
+
+    #[test]
+    fn counter_delta_ignores_reserved_high_bits() {
+        assert_eq!(counter_delta(0xABCD_0000_0000_0010, 0x1234_0000_0000_0020), 0x10);
+    }
+
+    #[test]
+    fn energy_unit_from_power_unit_register() {
+        // Zen reports ESU = 16, i.e. 1/65536 J per count
+        assert_eq!(energy_unit_joules(0x000A_1003, crate::msr::AMD_ENERGY_UNIT_MASK), 1.0 / 65536.0);
+    }
+
+    #[test]
+    fn ccd_power_sums_cores_and_matches_tccd() {
+        let temperatures = vec![
+            TemperatureReading { label: "Tctl".to_string(), celsius: 70.0 },
+            TemperatureReading { label: "Tccd2".to_string(), celsius: 61.5 },
+        ];
+        let ccds = ccd_power(&[1.0, 2.0, 3.0, 4.0], &[0, 0, 1, 1], &temperatures);
+
+        assert_eq!(ccds.len(), 2);
+        assert_eq!(ccds[0].watts, 3.0);
+        assert_eq!(ccds[0].celsius, None);
+        assert_eq!(ccds[1].watts, 7.0);
+        assert_eq!(ccds[1].celsius, Some(61.5));
+    }
+
+    #[test]
+    fn single_ccd_has_no_breakdown() {
+        assert!(ccd_power(&[1.0, 2.0], &[0, 0], &[]).is_empty());
+    }
+
+    #[test]
+    fn activity_from_aperf_mperf() {
+        // Half the window in C0 at 4.4 GHz on a 3.4 GHz TSC
+        let window = Duration::from_millis(100);
+        let activity = core_activity(340_000_000, 170_000_000, 220_000_000, window);
+        assert!((activity.effective_mhz - 2200.0).abs() < 1e-6);
+        assert!((activity.busy_percent - 50.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn watts_over_wrapped_window() {
+        let unit = 1.0 / 65536.0;
+        let watts = counter_watts(0xFFFF_0000, 0x0000_0000, unit, Duration::from_millis(100));
+        assert!((watts - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn divides_by_the_measured_window() {
+        let before = Instant::now();
+        let after = before + Duration::from_millis(112);
+        let window = Duration::from_millis(100);
+        assert_eq!(elapsed(before, after, window, true), Duration::from_millis(112));
+        assert_eq!(elapsed(before, after, window, false), window);
+    }
+
+    #[test]
+    fn samples_a_simulated_trace() {
+        let mut trace = fixture();
+        // A core whose MSR can't be opened is skipped
+        trace.topology.cores.push(4);
+        let topology = trace.topology.clone();
+        let platform = Simulated::new(trace).unwrap();
+        let map = MsrMap::for_cpu(&platform.cpu().unwrap()).unwrap();
+        let mut rapl = AmdRapl::open(&topology, map, &platform).unwrap();
+
+        let window = Duration::from_millis(10);
+        let watts = |counts: f64| counts * 0.5f64.powi(16) / window.as_secs_f64();
+        for _ in 0..4 {
+            let metrics = rapl.sample(window, false).unwrap();
+            assert_eq!(metrics.skipped_cores, vec![4]);
+            assert_eq!(metrics.core_watts.len(), 2);
+            assert!((metrics.core_watts[0] - watts(32768.0)).abs() < 1e-9);
+            assert!((metrics.core_watts[1] - watts(16384.0)).abs() < 1e-9);
+            // The third reading wraps around
+            assert!((metrics.package_watts - watts(262144.0)).abs() < 1e-9);
+            assert!(metrics.core_activity.is_empty());
+        }
+        assert!(matches!(rapl.sample(window, false), Err(RyzenmonError::NoEnergySource(_))));
+    }
+
+    #[test]
+    fn reads_each_package_on_its_own_thread() {
+        let registers = |core: [u64; 2], package: [u64; 2]| {
+            BTreeMap::from([
+                ("0xc0010299".to_string(), vec![0x000a_1003]),
+                ("0xc001029a".to_string(), core.to_vec()),
+                ("0xc001029b".to_string(), package.to_vec()),
+            ])
+        };
+        let trace = Trace {
+            cpu: CpuId {
+                vendor: Vendor::Amd,
+                family: 0x19,
+                model: 0x11,
+            },
+            topology: Topology {
+                cores: vec![0, 1],
+                packages: vec![Package { id: 0, cpu: 0 }, Package { id: 1, cpu: 1 }],
+                threads: 2,
+                core_packages: vec![0, 1],
+                ..Topology::default()
+            },
+            msrs: BTreeMap::from([(0, registers([0, 65536], [0, 655360])), (1, registers([0, 32768], [0, 327680]))]),
+            window_ms: None,
+            timestamps: Vec::new(),
+        };
+        let topology = trace.topology.clone();
+        // Pinned sampling reads them one after the other, with the same result.
+        for parallel in [true, false] {
+            let platform = Simulated::new(trace.clone()).unwrap();
+            let map = MsrMap::for_cpu(&platform.cpu().unwrap()).unwrap();
+            let mut rapl = AmdRapl::open(&topology, map, &platform).unwrap();
+            rapl.set_parallel(parallel);
+
+            let window = Duration::from_millis(100);
+            let metrics = rapl.sample(window, false).unwrap();
+            assert_eq!(metrics.core_watts, vec![10.0, 5.0]);
+            assert_eq!(metrics.packages.iter().map(|p| (p.package, p.watts)).collect::<Vec<_>>(), vec![(0, 100.0), (1, 50.0)]);
+        }
+    }
+
+    #[test]
+    fn samples_intel_packages_without_core_msrs() {
+        // Only the package CPU is in the trace, the cores can't be opened.
+        let trace = Trace {
+            cpu: CpuId {
+                vendor: Vendor::Intel,
+                family: 6,
+                model: 0x97,
+            },
+            topology: Topology {
+                cores: vec![2, 4],
+                packages: vec![Package { id: 0, cpu: 0 }],
+                threads: 2,
+                core_packages: vec![0, 0],
+                ..Topology::default()
+            },
+            msrs: BTreeMap::from([(
+                0,
+                BTreeMap::from([
+                    ("0x606".to_string(), vec![0x000a_1003]),
+                    ("0x611".to_string(), vec![0, 655360]),
+                    ("0x639".to_string(), vec![0, 327680]),
+                ]),
+            )]),
+            window_ms: None,
+            timestamps: Vec::new(),
+        };
+        let topology = trace.topology.clone();
+        let platform = Simulated::new(trace).unwrap();
+        let map = MsrMap::for_cpu(&platform.cpu().unwrap()).unwrap();
+        let mut rapl = IntelRapl::open(&topology, map, &platform).unwrap();
+
+        let metrics = rapl.sample(Duration::from_millis(100), false).unwrap();
+        assert_eq!(metrics.package_watts, 100.0);
+        assert_eq!(metrics.core_sum, 50.0);
+        assert!(metrics.core_watts.is_empty());
+        assert!(metrics.skipped_cores.is_empty());
+    }
+
+    #[test]
+    fn samples_continuously_from_the_previous_reading() {
+        let trace = fixture();
+        let topology = trace.topology.clone();
+        let platform = Simulated::new(trace).unwrap();
+        let map = MsrMap::for_cpu(&platform.cpu().unwrap()).unwrap();
+        let mut rapl = AmdRapl::open(&topology, map, &platform).unwrap();
+
+        // Every reading but the first ends one sample and starts the next, so
+        // no energy is left uncounted between samples.
+        let window = Duration::from_millis(10);
+        let mut counts = 0.0;
+        for _ in 0..7 {
+            counts += rapl.sample(window, true).unwrap().core_watts[0] * window.as_secs_f64() / 0.5f64.powi(16);
+        }
+        assert!((counts - (152768.0 - 1000.0)).abs() < 1e-6);
+        assert!(rapl.sample(window, true).is_err());
+    }
+
+    #[test]
+    fn round_trips_json_with_tags() {
+        let trace = fixture();
+        let topology = trace.topology.clone();
+        let platform = Simulated::new(trace).unwrap();
+        let map = MsrMap::for_cpu(&platform.cpu().unwrap()).unwrap();
+        let mut metrics = AmdRapl::open(&topology, map, &platform).unwrap().sample(Duration::from_millis(10), false).unwrap();
+        metrics.tags.insert("host".to_string(), "agent1".to_string());
+        metrics.timestamp = UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+
+        let parsed: PowerMetrics = serde_json::from_str(&serde_json::to_string(&metrics).unwrap()).unwrap();
+        assert_eq!(parsed.core_watts, metrics.core_watts);
+        assert_eq!(parsed.package_watts, metrics.package_watts);
+        assert_eq!(parsed.tags, metrics.tags);
+        assert_eq!(parsed.timestamp, metrics.timestamp);
+    }
+
+    #[test]
+    fn labels_cores_with_their_number_siblings_and_locality() {
+        let trace = fixture();
+        let topology = trace.topology.clone();
+        let platform = Simulated::new(trace).unwrap();
+        let map = MsrMap::for_cpu(&platform.cpu().unwrap()).unwrap();
+        let mut metrics = AmdRapl::open(&topology, map, &platform).unwrap().sample(Duration::from_millis(10), false).unwrap();
+        metrics.core_watts = vec![1.0, 2.0];
+        assert_eq!(metrics.per_core(&metrics.core_watts).map(|(core, _)| core).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(metrics.core_cpu_list(0), None);
+
+        metrics.core_cpus = vec![vec![0, 16], vec![3, 19]];
+        metrics.core_ids = vec![0, 3];
+        assert_eq!(metrics.per_core(&metrics.core_watts).map(|(core, _)| core).collect::<Vec<_>>(), vec![0, 3]);
+        assert_eq!(metrics.core_cpu_list(1).as_deref(), Some("3,19"));
+        assert_eq!(metrics.core_tags(1), vec![("core", "3".to_string())]);
+
+        metrics.core_nodes = vec![0, 1];
+        metrics.core_l3s = vec![0, 8];
+        assert_eq!(
+            metrics.core_tags(1),
+            vec![("core", "3".to_string()), ("numa_node", "1".to_string()), ("l3", "8".to_string())]
+        );
+
+        metrics.core_classes = vec![CoreClass::Cache, CoreClass::Freq];
+        assert_eq!(metrics.core_tags(0).last(), Some(&("core_class", "cache".to_string())));
+    }
+
+    #[test]
+    fn finds_implausible_power() {
+        let trace = fixture();
+        let topology = trace.topology.clone();
+        let platform = Simulated::new(trace).unwrap();
+        let map = MsrMap::for_cpu(&platform.cpu().unwrap()).unwrap();
+        let mut metrics = AmdRapl::open(&topology, map, &platform).unwrap().sample(Duration::from_millis(10), false).unwrap();
+        assert_eq!(metrics.implausible(Some(400.0)), None);
+        assert!(metrics.implausible(Some(1.0)).is_some());
+
+        metrics.core_watts = vec![5.0, -2.0];
+        assert_eq!(metrics.implausible(None).as_deref(), Some("core 1 power is negative (-2.0 W)"));
+    }
+}
@@ -0,0 +1,50 @@
+use std::io;
+
+use nix::libc;
+use nix::sched::{sched_setaffinity, CpuSet};
+use nix::unistd::{gettid, Pid};
+
+use crate::config::SamplingConfig;
+use crate::error::{RyzenmonError, Result};
+
+// Pin the calling thread to sampling.cpu and set its niceness or SCHED_FIFO
+// priority, so it keeps out of the way of latency-sensitive work on the other
+// cores. Linux applies all three per thread; the rest of the process keeps
+// the defaults.
+pub fn apply_to_current_thread(config: &SamplingConfig) -> Result<()> {
+    if let Some(cpu) = config.cpu {
+        let mut set = CpuSet::new();
+        set.set(cpu)
+            .and_then(|()| sched_setaffinity(Pid::from_raw(0), &set))
+            .map_err(|e| {
+                let e = io::Error::from(e);
+                RyzenmonError::Config(format!(
+                    "cannot pin the sampling thread to CPU {}: {} (is it online and in this process's cpuset?)",
+                    cpu, e
+                ))
+            })?;
+    }
+    if let Some(nice) = config.nice {
+        // With a thread ID, PRIO_PROCESS only reaches that thread.
+        let tid = gettid().as_raw() as libc::id_t;
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid, nice) } != 0 {
+            return Err(failed(&format!("sampling.nice = {}", nice), io::Error::last_os_error()));
+        }
+    }
+    if let Some(priority) = config.fifo_priority {
+        let param = libc::sched_param { sched_priority: priority };
+        if unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) } != 0 {
+            return Err(failed(&format!("sampling.fifo_priority = {}", priority), io::Error::last_os_error()));
+        }
+    }
+    Ok(())
+}
+
+fn failed(setting: &str, e: io::Error) -> RyzenmonError {
+    let hint = if e.kind() == io::ErrorKind::PermissionDenied {
+        " (needs root or CAP_SYS_NICE)"
+    } else {
+        ""
+    };
+    RyzenmonError::Config(format!("cannot apply {}: {}{}", setting, e, hint))
+}
@@ -0,0 +1,42 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use tracing::info;
+
+use ryzenmon_rust::config::Config;
+use ryzenmon_rust::error::RyzenmonError;
+use ryzenmon_rust::topology::Topology;
+use ryzenmon_rust::Sampler;
+
+// `ryzenmon record`: sample every interval like the daemon, without uploading,
+// and save every MSR value read to `out` once `duration` is up or on Ctrl-C.
+pub async fn run(config: &Config, out: &Path, duration: Option<Duration>) -> Result<(), RyzenmonError> {
+    let (mut sampler, recorder) = Sampler::recording(Topology::detect()?)?;
+    let window = Duration::from_millis(config.sampling.window_ms);
+    let interval = match config.sampling.sample_interval_ms {
+        Some(ms) => Duration::from_millis(ms),
+        None => Duration::from_secs(config.sampling.interval_secs),
+    };
+    let deadline = duration.map(|duration| Instant::now() + duration);
+    info!("Recording to {}, stop with Ctrl-C", out.display());
+
+    let mut ticks = tokio::time::interval(interval);
+    let mut samples = 0;
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = tokio::signal::ctrl_c() => break,
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            break;
+        }
+        // The window is spent sleeping; nothing else runs on the runtime meanwhile.
+        let metrics = tokio::task::block_in_place(|| sampler.sample(window))?;
+        recorder.sampled(metrics.timestamp);
+        samples += 1;
+    }
+
+    recorder.finish(window).save(out)?;
+    println!("Recorded {} samples to {}", samples, out.display());
+    Ok(())
+}
@@ -0,0 +1,230 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+
+use crate::rapl::PowerMetrics;
+use crate::stats::percentile;
+
+// Full resolution samples of the last [history] minutes, for the API's
+// percentile queries. Set once at startup.
+pub static RING: OnceCell<Ring> = OnceCell::new();
+
+// Power readings kept per sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    Package,
+    CoreSum,
+    Dram,
+    Soc,
+}
+
+const METRICS: usize = 4;
+
+impl Metric {
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "package" => Ok(Metric::Package),
+            "core_sum" => Ok(Metric::CoreSum),
+            "dram" => Ok(Metric::Dram),
+            "soc" => Ok(Metric::Soc),
+            _ => Err(format!("unknown metric {:?}, expected package, core_sum, dram or soc", name)),
+        }
+    }
+}
+
+struct Slot {
+    // 2 * (n + 1) once sample n is complete in the slot, odd while it is
+    // being written
+    sequence: AtomicU64,
+    timestamp_ms: AtomicU64,
+    // f64 bits by Metric, NaN when the sample has no such reading
+    values: [AtomicU64; METRICS],
+}
+
+// A fixed number of samples in a ring of atomics, so the sampler never waits
+// for a query and a query never waits for the sampler. Readers skip slots
+// that are overwritten while they read them, seqlock style.
+pub struct Ring {
+    slots: Box<[Slot]>,
+    // Samples pushed so far
+    pushed: AtomicU64,
+}
+
+// Min, max, mean and the requested percentiles of one metric over a range.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RangeStats {
+    pub samples: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    // e.g. "p95", nearest rank
+    pub percentiles: BTreeMap<String, f64>,
+}
+
+impl Ring {
+    pub fn new(capacity: usize) -> Self {
+        let slots = (0..capacity.max(1))
+            .map(|_| Slot {
+                sequence: AtomicU64::new(0),
+                timestamp_ms: AtomicU64::new(0),
+                values: std::array::from_fn(|_| AtomicU64::new(f64::NAN.to_bits())),
+            })
+            .collect();
+        Ring {
+            slots,
+            pushed: AtomicU64::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    // Overwrites the oldest sample once full.
+    pub fn push(&self, metrics: &PowerMetrics) {
+        let n = self.pushed.fetch_add(1, Ordering::Relaxed);
+        let slot = &self.slots[(n % self.slots.len() as u64) as usize];
+        slot.sequence.store(2 * n + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        let ms = metrics.timestamp.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+        slot.timestamp_ms.store(ms, Ordering::Relaxed);
+        for (metric, value) in [
+            (Metric::Package, Some(metrics.package_watts)),
+            (Metric::CoreSum, Some(metrics.core_sum)),
+            (Metric::Dram, metrics.dram_watts),
+            (Metric::Soc, metrics.soc_watts),
+        ] {
+            slot.values[metric.index()].store(value.unwrap_or(f64::NAN).to_bits(), Ordering::Relaxed);
+        }
+        slot.sequence.store(2 * n + 2, Ordering::Release);
+    }
+
+    // Timestamp and value of `metric` for every sample taken in [from, to],
+    // oldest first.
+    pub fn readings(&self, metric: Metric, from: SystemTime, to: SystemTime) -> Vec<(SystemTime, f64)> {
+        let pushed = self.pushed.load(Ordering::Acquire);
+        let first = pushed.saturating_sub(self.slots.len() as u64);
+        let mut readings = Vec::new();
+        for n in first..pushed {
+            let slot = &self.slots[(n % self.slots.len() as u64) as usize];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            let ms = slot.timestamp_ms.load(Ordering::Relaxed);
+            let value = f64::from_bits(slot.values[metric.index()].load(Ordering::Relaxed));
+            fence(Ordering::Acquire);
+            // Not yet written, or overwritten by a newer sample meanwhile
+            if sequence != 2 * n + 2 || slot.sequence.load(Ordering::Relaxed) != sequence {
+                continue;
+            }
+            let at = UNIX_EPOCH + Duration::from_millis(ms);
+            if at >= from && at <= to && !value.is_nan() {
+                readings.push((at, value));
+            }
+        }
+        readings
+    }
+
+    // None when no sample in [from, to] has the metric. `percentiles` are
+    // between 0 and 100.
+    pub fn stats(&self, metric: Metric, from: SystemTime, to: SystemTime, percentiles: &[f64]) -> Option<RangeStats> {
+        let mut values: Vec<f64> = self.readings(metric, from, to).into_iter().map(|(_, value)| value).collect();
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(f64::total_cmp);
+        Some(RangeStats {
+            samples: values.len(),
+            min: values[0],
+            max: values[values.len() - 1],
+            mean: values.iter().sum::<f64>() / values.len() as f64,
+            percentiles: percentiles.iter().map(|&p| (format!("p{}", p), percentile(&values, p))).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::platform::fixture_sample;
+
+    #[test]
+    fn keeps_the_latest_samples() {
+        let ring = Ring::new(4);
+        let mut metrics = fixture_sample();
+        for secs in 1..=6 {
+            metrics.timestamp = UNIX_EPOCH + Duration::from_secs(secs);
+            metrics.package_watts = secs as f64 * 10.0;
+            ring.push(&metrics);
+        }
+        let all = ring.readings(Metric::Package, UNIX_EPOCH, SystemTime::now());
+        assert_eq!(all.iter().map(|&(_, watts)| watts).collect::<Vec<_>>(), vec![30.0, 40.0, 50.0, 60.0]);
+
+        let range = ring.readings(Metric::Package, UNIX_EPOCH + Duration::from_secs(4), UNIX_EPOCH + Duration::from_secs(5));
+        assert_eq!(range.len(), 2);
+        assert_eq!(range[0], (UNIX_EPOCH + Duration::from_secs(4), 40.0));
+    }
+
+    #[test]
+    fn computes_percentiles_over_a_range() {
+        let ring = Ring::new(100);
+        let mut metrics = fixture_sample();
+        metrics.dram_watts = None;
+        for secs in 1..=20 {
+            metrics.timestamp = UNIX_EPOCH + Duration::from_secs(secs);
+            metrics.package_watts = secs as f64;
+            ring.push(&metrics);
+        }
+        let stats = ring.stats(Metric::Package, UNIX_EPOCH, SystemTime::now(), &[50.0, 95.0, 99.9]).unwrap();
+        assert_eq!(stats.samples, 20);
+        assert_eq!(stats.min, 1.0);
+        assert_eq!(stats.max, 20.0);
+        assert_eq!(stats.mean, 10.5);
+        assert_eq!(stats.percentiles["p50"], 10.0);
+        assert_eq!(stats.percentiles["p95"], 19.0);
+        assert_eq!(stats.percentiles["p99.9"], 20.0);
+
+        assert!(ring.stats(Metric::Dram, UNIX_EPOCH, SystemTime::now(), &[50.0]).is_none());
+        assert!("uncore".parse::<Metric>().is_err());
+    }
+
+    #[test]
+    fn reads_while_another_thread_writes() {
+        let ring = Arc::new(Ring::new(64));
+        let writer = {
+            let ring = ring.clone();
+            std::thread::spawn(move || {
+                let mut metrics = fixture_sample();
+                for n in 1..=10_000u64 {
+                    metrics.timestamp = UNIX_EPOCH + Duration::from_millis(n);
+                    // Every reading of a sample is the same, so a torn one shows.
+                    metrics.package_watts = n as f64;
+                    metrics.core_sum = n as f64;
+                    ring.push(&metrics);
+                }
+            })
+        };
+        while !writer.is_finished() {
+            let package = ring.readings(Metric::Package, UNIX_EPOCH, SystemTime::now());
+            assert!(package.len() <= 64);
+            for (at, watts) in package {
+                assert_eq!(at, UNIX_EPOCH + Duration::from_millis(watts as u64));
+            }
+        }
+        writer.join().unwrap();
+        assert_eq!(ring.readings(Metric::CoreSum, UNIX_EPOCH, SystemTime::now()).len(), 64);
+    }
+}
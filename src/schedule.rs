@@ -0,0 +1,257 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use chrono::{Datelike, Timelike};
+
+use crate::config::{AdaptiveConfig, ScheduleConfig};
+use crate::error::{RyzenmonError, Result};
+
+// Samples are taken on a grid of `period` counted from the Unix epoch, so
+// timestamps land on the same wall clock times (:00, :10, ...) across hosts
+// and restarts.
+
+// Time from `now` until the next multiple of `period`.
+pub fn until_next(now: SystemTime, period: Duration) -> Duration {
+    let period_ns = period.as_nanos().max(1);
+    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let remaining = period_ns - since_epoch % period_ns;
+    Duration::from_nanos(remaining as u64)
+}
+
+// `time` rounded to the nearest multiple of `period`.
+pub fn align(time: SystemTime, period: Duration) -> SystemTime {
+    let period_ns = period.as_nanos().max(1);
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let slots = (since_epoch + period_ns / 2) / period_ns;
+    UNIX_EPOCH + Duration::from_nanos((slots * period_ns) as u64)
+}
+
+// sampling.adaptive: samples every fast_interval_ms from a sample at or above
+// a threshold until hold_secs after the last such sample.
+#[derive(Debug, Default)]
+pub struct Adaptive {
+    busy_until: Option<Instant>,
+}
+
+impl Adaptive {
+    // Account a sample with `package_watts` and system `utilization`.
+    pub fn observe(&mut self, config: &AdaptiveConfig, package_watts: f64, utilization: Option<f64>, now: Instant) {
+        let busy = config.watts.is_some_and(|watts| package_watts >= watts)
+            || config.utilization.zip(utilization).is_some_and(|(threshold, busy)| busy >= threshold);
+        if busy {
+            self.busy_until = Some(now + Duration::from_secs(config.hold_secs));
+        }
+    }
+
+    // Time until the next sample, `slow` when not busy.
+    pub fn period(&self, config: &AdaptiveConfig, slow: Duration, now: Instant) -> Duration {
+        match self.busy_until {
+            Some(until) if now <= until => Duration::from_millis(config.fast_interval_ms),
+            _ => slow,
+        }
+    }
+}
+
+// Name, lowest and highest value of each cron field.
+const CRON_FIELDS: [(&str, u32, u32); 5] = [
+    ("minute", 0, 59),
+    ("hour", 0, 23),
+    ("day of month", 1, 31),
+    ("month", 1, 12),
+    ("day of week", 0, 7),
+];
+
+// `<minute> <hour> <day of month> <month> <day of week>`, each `*`, a number,
+// a range `a-b`, either with a step `/n`, or a list of those, e.g.
+// "*/15 7-22 * * 1-5". Sunday is 0 or 7. As in cron, when both day fields are
+// restricted, a day matching either one matches.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    // Bit n set for each matching value n of the field
+    fields: [u64; 5],
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Self> {
+        let invalid = |reason: String| RyzenmonError::Config(format!("invalid schedule {:?}: {}", expression, reason));
+        let parts: Vec<&str> = expression.split_whitespace().collect();
+        if parts.len() != CRON_FIELDS.len() {
+            return Err(invalid("expected <minute> <hour> <day of month> <month> <day of week>".to_string()));
+        }
+        let mut fields = [0; 5];
+        for ((bits, part), (name, min, max)) in fields.iter_mut().zip(&parts).zip(CRON_FIELDS) {
+            *bits = parse_cron_field(part, min, max).map_err(|reason| invalid(format!("{} {:?}: {}", name, part, reason)))?;
+        }
+        // Sunday as 7 is Sunday as 0.
+        if fields[4] & 1 << 7 != 0 {
+            fields[4] |= 1;
+        }
+        Ok(Cron {
+            fields,
+            any_day: parts[2].starts_with('*'),
+            any_weekday: parts[4].starts_with('*'),
+        })
+    }
+
+    pub fn matches(&self, time: &(impl Datelike + Timelike)) -> bool {
+        let is_set = |field: usize, value: u32| self.fields[field] & 1 << value != 0;
+        let day = is_set(2, time.day());
+        let weekday = is_set(4, time.weekday().num_days_from_sunday());
+        let day = if self.any_day || self.any_weekday { day && weekday } else { day || weekday };
+        is_set(0, time.minute()) && is_set(1, time.hour()) && day && is_set(3, time.month())
+    }
+}
+
+fn parse_cron_field(field: &str, min: u32, max: u32) -> std::result::Result<u64, String> {
+    let number = |value: &str| value.parse::<u32>().map_err(|_| format!("{:?} is not a number", value));
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(number(step)?).filter(|step| *step > 0).ok_or("a step must be at least 1")?),
+            None => (part, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (number(start)?, number(end)?),
+            // `5/15` steps from 5 to the end of the range.
+            None if part.contains('/') => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("must be within {}-{}", min, max));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+// [[schedule]]: settings by the local time of a sample. Each rule applies in
+// the minutes its cron expression matches.
+#[derive(Debug, Default)]
+pub struct Schedule {
+    config: Vec<ScheduleConfig>,
+    rules: Vec<Cron>,
+}
+
+impl Schedule {
+    pub fn new(config: &[ScheduleConfig]) -> Result<Self> {
+        Ok(Schedule {
+            config: config.to_vec(),
+            rules: config.iter().map(|rule| Cron::parse(&rule.when)).collect::<Result<_>>()?,
+        })
+    }
+
+    pub fn config(&self) -> &[ScheduleConfig] {
+        &self.config
+    }
+
+    fn matching<'a>(&'a self, time: &'a (impl Datelike + Timelike)) -> impl Iterator<Item = &'a ScheduleConfig> {
+        self.rules.iter().zip(&self.config).filter(|(cron, _)| cron.matches(time)).map(|(_, rule)| rule)
+    }
+
+    // sampling.interval_secs at `time`, from the first matching rule that has one.
+    pub fn interval_secs(&self, time: &(impl Datelike + Timelike), default: u64) -> u64 {
+        self.matching(time).find_map(|rule| rule.interval_secs).unwrap_or(default)
+    }
+
+    // The longest interval any rule sets, for when a sample counts as overdue.
+    pub fn longest_interval_secs(&self, default: u64) -> u64 {
+        self.config.iter().filter_map(|rule| rule.interval_secs).fold(default, u64::max)
+    }
+
+    // Whether samples at `time` go to the sink called `sink`.
+    pub fn uploads_to(&self, time: &(impl Datelike + Timelike), sink: &str) -> bool {
+        !self
+            .matching(time)
+            .any(|rule| !rule.upload && (rule.sinks.is_empty() || rule.sinks.iter().any(|name| name == sink)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn aligns_to_multiples_of_the_period() {
+        let period = Duration::from_secs(10);
+        let at = |ms: u64| UNIX_EPOCH + Duration::from_millis(ms);
+
+        assert_eq!(until_next(at(1_000_003_000), period), Duration::from_secs(7));
+        assert_eq!(until_next(at(1_000_000_000), period), period);
+
+        assert_eq!(align(at(1_000_000_040), period), at(1_000_000_000));
+        assert_eq!(align(at(1_000_009_990), period), at(1_000_010_000));
+        assert_eq!(align(at(1_000_000_250), Duration::from_millis(100)), at(1_000_000_300));
+    }
+
+    #[test]
+    fn samples_fast_while_busy_and_for_the_hold_time() {
+        let config = AdaptiveConfig {
+            fast_interval_ms: 1000,
+            watts: Some(60.0),
+            utilization: Some(50.0),
+            hold_secs: 30,
+        };
+        let slow = Duration::from_secs(10);
+        let fast = Duration::from_secs(1);
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        let mut adaptive = Adaptive::default();
+        adaptive.observe(&config, 20.0, Some(5.0), at(0));
+        assert_eq!(adaptive.period(&config, slow, at(0)), slow);
+
+        adaptive.observe(&config, 80.0, Some(5.0), at(10));
+        assert_eq!(adaptive.period(&config, slow, at(10)), fast);
+        adaptive.observe(&config, 20.0, Some(5.0), at(11));
+        assert_eq!(adaptive.period(&config, slow, at(39)), fast);
+        assert_eq!(adaptive.period(&config, slow, at(41)), slow);
+
+        adaptive.observe(&config, 20.0, Some(90.0), at(50));
+        assert_eq!(adaptive.period(&config, slow, at(50)), fast);
+    }
+
+    #[test]
+    fn applies_rules_in_the_minutes_they_match() {
+        let at = |day: u32, hour: u32, minute: u32| {
+            NaiveDate::from_ymd_opt(2024, 6, day).unwrap().and_hms_opt(hour, minute, 0).unwrap()
+        };
+        // 2024-06-01 is a Saturday.
+        let cron = Cron::parse("*/15 7-22 * * 1-5").unwrap();
+        assert!(cron.matches(&at(3, 7, 0)));
+        assert!(cron.matches(&at(3, 22, 45)));
+        assert!(!cron.matches(&at(3, 23, 0)));
+        assert!(!cron.matches(&at(3, 7, 5)));
+        assert!(!cron.matches(&at(1, 12, 0)));
+        assert!(Cron::parse("0 12 * * 7").unwrap().matches(&at(2, 12, 0)));
+        // Either day field, when both are restricted.
+        let cron = Cron::parse("0 12 15 * 6").unwrap();
+        assert!(cron.matches(&at(1, 12, 0)) && cron.matches(&at(15, 12, 0)) && !cron.matches(&at(3, 12, 0)));
+        for invalid in ["* * * *", "60 * * * *", "* 5-3 * * *", "*/0 * * * *", "* * 0 * *", "x * * * *"] {
+            assert!(Cron::parse(invalid).is_err(), "{}", invalid);
+        }
+
+        let rule = |when: &str, interval_secs: Option<u64>, upload: bool, sinks: &[&str]| ScheduleConfig {
+            when: when.to_string(),
+            interval_secs,
+            upload,
+            sinks: sinks.iter().map(|sink| sink.to_string()).collect(),
+        };
+        let schedule = Schedule::new(&[
+            rule("* 0-6,23 * * *", None, false, &["influxdb"]),
+            rule("* 0-6 * * *", Some(60), true, &[]),
+            rule("* 3 * * *", Some(300), true, &[]),
+        ])
+        .unwrap();
+        assert_eq!(schedule.interval_secs(&at(3, 3, 0), 10), 60);
+        assert_eq!(schedule.interval_secs(&at(3, 12, 0), 10), 10);
+        assert_eq!(schedule.longest_interval_secs(10), 300);
+        assert!(!schedule.uploads_to(&at(3, 23, 30), "influxdb"));
+        assert!(schedule.uploads_to(&at(3, 23, 30), "api"));
+        assert!(schedule.uploads_to(&at(3, 7, 0), "influxdb"));
+    }
+}
@@ -0,0 +1,75 @@
+use std::io;
+
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+#[cfg(windows)]
+use tokio::signal::windows::{ctrl_break, ctrl_c, ctrl_shutdown};
+
+// What the daemon is asked to do from outside. Only Stop on Windows.
+#[cfg_attr(windows, allow(dead_code))]
+pub enum Signal {
+    // SIGTERM or SIGINT, or on Windows Ctrl+C, Ctrl+Break or a shutdown;
+    // named for the log
+    Stop(&'static str),
+    // SIGHUP
+    Reload,
+    // SIGUSR1
+    Flush,
+}
+
+// Windows has nothing like SIGHUP or SIGUSR1, so there the daemon can only be
+// stopped.
+#[cfg(unix)]
+pub struct Signals {
+    terminate: tokio::signal::unix::Signal,
+    interrupt: tokio::signal::unix::Signal,
+    hangup: tokio::signal::unix::Signal,
+    user_defined1: tokio::signal::unix::Signal,
+}
+
+#[cfg(unix)]
+impl Signals {
+    pub fn new() -> io::Result<Self> {
+        Ok(Signals {
+            terminate: signal(SignalKind::terminate())?,
+            interrupt: signal(SignalKind::interrupt())?,
+            hangup: signal(SignalKind::hangup())?,
+            user_defined1: signal(SignalKind::user_defined1())?,
+        })
+    }
+
+    pub async fn recv(&mut self) -> Signal {
+        tokio::select! {
+            _ = self.terminate.recv() => Signal::Stop("SIGTERM"),
+            _ = self.interrupt.recv() => Signal::Stop("SIGINT"),
+            _ = self.hangup.recv() => Signal::Reload,
+            _ = self.user_defined1.recv() => Signal::Flush,
+        }
+    }
+}
+
+#[cfg(windows)]
+pub struct Signals {
+    ctrl_c: tokio::signal::windows::CtrlC,
+    ctrl_break: tokio::signal::windows::CtrlBreak,
+    shutdown: tokio::signal::windows::CtrlShutdown,
+}
+
+#[cfg(windows)]
+impl Signals {
+    pub fn new() -> io::Result<Self> {
+        Ok(Signals {
+            ctrl_c: ctrl_c()?,
+            ctrl_break: ctrl_break()?,
+            shutdown: ctrl_shutdown()?,
+        })
+    }
+
+    pub async fn recv(&mut self) -> Signal {
+        tokio::select! {
+            _ = self.ctrl_c.recv() => Signal::Stop("Ctrl+C"),
+            _ = self.ctrl_break.recv() => Signal::Stop("Ctrl+Break"),
+            _ = self.shutdown.recv() => Signal::Stop("a shutdown"),
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use futures::channel::mpsc;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tonic::transport::Endpoint;
+use tracing::{info, warn};
+
+use crate::config::AgentConfig;
+use crate::proto::aggregator_client::AggregatorClient;
+use crate::proto::{self, Ack, Sample};
+use crate::rapl::PowerMetrics;
+use crate::sink::{MetricSink, SinkError};
+use crate::telemetry;
+
+// How long shutdown waits for the aggregator to acknowledge what is left.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Detects an aggregator that went away without closing the connection.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// `--agent`: streams every sample, tagged with this host's tags, to an
+// aggregator over one long-lived gRPC stream instead of uploading it. Samples
+// stay buffered until the aggregator acknowledges them; a background task
+// reconnects with backoff and resends whatever was not acknowledged.
+pub struct AgentSink {
+    shared: Arc<Shared>,
+    tags: BTreeMap<String, String>,
+    task: JoinHandle<()>,
+}
+
+struct Shared {
+    pending: Mutex<Pending>,
+    // Woken for every new sample
+    notify: Notify,
+}
+
+struct Pending {
+    samples: VecDeque<Sample>,
+    next_sequence: u64,
+    capacity: usize,
+    // Why the last connection failed, until the next one is up
+    error: Option<String>,
+}
+
+impl AgentSink {
+    pub fn new(address: &str, config: AgentConfig, tags: BTreeMap<String, String>) -> Result<Self, SinkError> {
+        let address = match address.contains("://") {
+            true => address.to_string(),
+            false => format!("http://{}", address),
+        };
+        let endpoint = Endpoint::from_shared(address)?
+            .http2_keep_alive_interval(KEEPALIVE_INTERVAL)
+            .keep_alive_timeout(KEEPALIVE_TIMEOUT)
+            .keep_alive_while_idle(true);
+        let shared = Arc::new(Shared {
+            pending: Mutex::new(Pending {
+                samples: VecDeque::new(),
+                next_sequence: 0,
+                capacity: config.max_buffered_points,
+                error: None,
+            }),
+            notify: Notify::new(),
+        });
+        let task = tokio::spawn(run(
+            endpoint,
+            config.token,
+            shared.clone(),
+            Duration::from_secs(config.max_retry_secs),
+        ));
+        Ok(AgentSink { shared, tags, task })
+    }
+}
+
+#[async_trait]
+impl MetricSink for AgentSink {
+    fn name(&self) -> &str {
+        "agent"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        let mut metrics = proto::PowerMetrics::from(metrics);
+        metrics.tags = self.tags.clone().into_iter().collect();
+
+        let mut pending = self.shared.pending.lock().unwrap();
+        let sequence = pending.next_sequence;
+        pending.next_sequence += 1;
+        pending.samples.push_back(Sample {
+            sequence,
+            metrics: Some(metrics),
+        });
+        if pending.samples.len() > pending.capacity {
+            pending.samples.pop_front();
+            telemetry::record_dropped_points(1);
+        }
+        self.shared.notify.notify_one();
+
+        match &pending.error {
+            Some(e) => Err(format!("{} ({} samples buffered)", e, pending.samples.len()).into()),
+            None => Ok(()),
+        }
+    }
+
+    // Wait a little for the aggregator to acknowledge the last samples.
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        let deadline = Instant::now() + FLUSH_TIMEOUT;
+        loop {
+            let buffered = self.buffered();
+            if buffered == 0 {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(format!("{} samples were not acknowledged", buffered).into());
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    async fn close(&mut self) {
+        self.task.abort();
+    }
+
+    fn buffered(&self) -> usize {
+        self.shared.pending.lock().unwrap().samples.len()
+    }
+}
+
+impl Drop for AgentSink {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// Keep a stream to the aggregator open, reconnecting with exponential backoff
+// up to `max_backoff`.
+async fn run(endpoint: Endpoint, token: Option<String>, shared: Arc<Shared>, max_backoff: Duration) {
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        let result = stream(&endpoint, token.as_deref(), &shared, &mut backoff).await;
+        let e = match result {
+            Ok(()) => "the aggregator closed the stream".to_string(),
+            Err(e) => describe(e.as_ref()),
+        };
+        warn!("Agent stream to {} failed, reconnecting in {:?}: {}", endpoint.uri(), backoff, e);
+        shared.pending.lock().unwrap().error = Some(e);
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(max_backoff);
+    }
+}
+
+// tonic's transport errors only say "transport error", the cause is in the
+// source chain; statuses print their metadata too.
+fn describe(e: &(dyn std::error::Error + 'static)) -> String {
+    if let Some(status) = e.downcast_ref::<tonic::Status>() {
+        return format!("{:?}: {}", status.code(), status.message());
+    }
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        let cause = e.to_string();
+        if !message.ends_with(&cause) {
+            message = format!("{}: {}", message, cause);
+        }
+        source = e.source();
+    }
+    message
+}
+
+// Send everything pending, then every new sample, until the stream breaks.
+// Acknowledged samples are dropped from the buffer.
+async fn stream(
+    endpoint: &Endpoint,
+    token: Option<&str>,
+    shared: &Shared,
+    backoff: &mut Duration,
+) -> Result<(), SinkError> {
+    let mut client = AggregatorClient::new(endpoint.connect().await?);
+    let (sender, receiver) = mpsc::unbounded::<Sample>();
+    let mut request = tonic::Request::new(receiver);
+    if let Some(token) = token {
+        request.metadata_mut().insert("authorization", format!("Bearer {}", token).parse()?);
+    }
+    let mut acks = client.stream(request).await?.into_inner();
+    info!("Streaming samples to {}", endpoint.uri());
+    shared.pending.lock().unwrap().error = None;
+
+    // Samples from this sequence on have not been sent on this stream yet,
+    // which starts with everything still unacknowledged.
+    let mut unsent = 0;
+    loop {
+        {
+            let pending = shared.pending.lock().unwrap();
+            for sample in pending.samples.iter().filter(|sample| sample.sequence >= unsent) {
+                sender.unbounded_send(sample.clone())?;
+            }
+            unsent = pending.next_sequence;
+        }
+        tokio::select! {
+            _ = shared.notify.notified() => {}
+            ack = acks.message() => match ack? {
+                Some(Ack { sequence }) => {
+                    shared.pending.lock().unwrap().samples.retain(|sample| sample.sequence > sequence);
+                    *backoff = Duration::from_secs(1);
+                }
+                None => return Ok(()),
+            },
+        }
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::aggregator::authorized;
+use crate::config::{ApiConfig, ControlConfig};
+use crate::health::HEALTH;
+use crate::rapl::PowerMetrics;
+use crate::ring::{Metric, RING};
+use crate::sink::{MetricSink, SinkError};
+
+// Samples of the last `history` kept for the API, oldest first.
+pub struct History {
+    samples: VecDeque<PowerMetrics>,
+    history: Duration,
+}
+
+impl History {
+    pub fn new(history: Duration) -> Self {
+        History {
+            samples: VecDeque::new(),
+            history,
+        }
+    }
+
+    pub fn push(&mut self, metrics: PowerMetrics) {
+        let cutoff = metrics.timestamp.checked_sub(self.history);
+        self.samples.push_back(metrics);
+        if let Some(cutoff) = cutoff {
+            while self.samples.front().is_some_and(|m| m.timestamp < cutoff) {
+                self.samples.pop_front();
+            }
+        }
+    }
+
+    pub fn latest(&self) -> Option<&PowerMetrics> {
+        self.samples.back()
+    }
+
+    // Samples taken within `secs` of `now`.
+    pub fn since(&self, secs: u64, now: SystemTime) -> Vec<&PowerMetrics> {
+        let cutoff = now.checked_sub(Duration::from_secs(secs)).unwrap_or(SystemTime::UNIX_EPOCH);
+        self.samples.iter().filter(|m| m.timestamp >= cutoff).collect()
+    }
+}
+
+// Serves recent samples as JSON on /v1/metrics/current and
+// /v1/metrics/history?secs=<n> for local tools, and /healthz and /readyz for
+// supervisors. With [history], /v1/metrics/percentiles summarizes any part of
+// it. With [control], PUT /v1/limits/ppt sets the power limit.
+pub struct ApiServer {
+    history: Arc<RwLock<History>>,
+    server: Option<JoinHandle<()>>,
+}
+
+impl ApiServer {
+    pub fn bind(config: &ApiConfig, control: Option<ControlConfig>) -> Result<Self, SinkError> {
+        let addr: SocketAddr = config.bind.parse()?;
+        let history_secs = config.history_secs;
+        let mut api = ApiServer {
+            history: Arc::new(RwLock::new(History::new(Duration::from_secs(history_secs)))),
+            server: None,
+        };
+
+        let history = api.history.clone();
+        let control = Arc::new(control);
+        let make_svc = make_service_fn(move |conn: &AddrStream| {
+            let history = history.clone();
+            let control = control.clone();
+            let peer = conn.remote_addr();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let history = history.clone();
+                    let control = control.clone();
+                    async move {
+                        if req.uri().path() == "/v1/limits/ppt" {
+                            return Ok::<_, Infallible>(set_ppt(req, control.as_ref().as_ref(), peer).await);
+                        }
+                        Ok(handle(req, &history, history_secs))
+                    }
+                }))
+            }
+        });
+
+        let server = Server::try_bind(&addr)?.serve(make_svc);
+        info!("Serving the metrics API on http://{}/v1/metrics/current", addr);
+        api.server = Some(tokio::spawn(async move {
+            if let Err(e) = server.await {
+                error!("Metrics API failed: {}", e);
+            }
+        }));
+
+        Ok(api)
+    }
+}
+
+#[async_trait]
+impl MetricSink for ApiServer {
+    fn name(&self) -> &str {
+        "api"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        self.history.write().unwrap().push(metrics.clone());
+        Ok(())
+    }
+
+    // Stop the server and wait for it, so the address can be bound again.
+    async fn close(&mut self) {
+        if let Some(server) = self.server.take() {
+            server.abort();
+            let _ = server.await;
+        }
+    }
+
+    fn listens(&self) -> bool {
+        true
+    }
+}
+
+impl Drop for ApiServer {
+    fn drop(&mut self) {
+        if let Some(server) = &self.server {
+            server.abort();
+        }
+    }
+}
+
+fn handle(req: Request<Body>, history: &RwLock<History>, history_secs: u64) -> Response<Body> {
+    if req.method() != Method::GET {
+        return error_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+    }
+
+    let path = req.uri().path();
+    if path == "/healthz" || path == "/readyz" {
+        let health = HEALTH.lock().unwrap().clone();
+        let now = SystemTime::now();
+        let ok = if path == "/healthz" { health.healthy(now) } else { health.ready(now) };
+        let status = if ok { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+        return Response::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_string(&health).unwrap_or_default()))
+            .unwrap();
+    }
+
+    if path == "/v1/metrics/percentiles" {
+        return percentiles(req.uri().query());
+    }
+
+    let history = history.read().unwrap();
+    let body = match path {
+        "/v1/metrics/current" => match history.latest() {
+            Some(metrics) => serde_json::to_string(metrics),
+            None => return error_response(StatusCode::SERVICE_UNAVAILABLE, "no sample yet"),
+        },
+        "/v1/metrics/history" => {
+            let secs = match query_secs(req.uri().query()) {
+                Ok(secs) => secs.unwrap_or(history_secs),
+                Err(message) => return error_response(StatusCode::BAD_REQUEST, &message),
+            };
+            serde_json::to_string(&history.since(secs, SystemTime::now()))
+        }
+        _ => return error_response(StatusCode::NOT_FOUND, "not found"),
+    };
+
+    match body {
+        Ok(body) => Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(body))
+            .unwrap(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// PUT {"watts": <n>} sets the package power limit. Needs control.token as a
+// bearer token when one is set, and a loopback client when not.
+async fn set_ppt(req: Request<Body>, control: Option<&ControlConfig>, peer: SocketAddr) -> Response<Body> {
+    if req.method() != Method::PUT {
+        return error_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+    }
+    let Some(control) = control else {
+        return error_response(StatusCode::FORBIDDEN, "setting the power limit needs [control] in the config");
+    };
+    let authorization = req.headers().get(hyper::header::AUTHORIZATION).and_then(|value| value.to_str().ok());
+    if control.token.is_none() && !peer.ip().is_loopback() {
+        return error_response(StatusCode::FORBIDDEN, "setting the power limit from another host needs control.token");
+    }
+    if !authorized(control.token.as_deref(), authorization) {
+        return error_response(StatusCode::UNAUTHORIZED, "missing or wrong bearer token");
+    }
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+    let watts = match serde_json::from_slice::<serde_json::Value>(&body).ok().and_then(|body| body["watts"].as_f64()) {
+        Some(watts) => watts,
+        None => return error_response(StatusCode::BAD_REQUEST, "expected {\"watts\": <number>}"),
+    };
+
+    #[cfg(feature = "control")]
+    {
+        use crate::error::RyzenmonError;
+        match tokio::task::spawn_blocking({
+            let control = control.clone();
+            move || crate::control::set_ppt(&control, watts)
+        })
+        .await
+        .expect("setting the power limit panicked")
+        {
+            Ok(interface) => Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::json!({ "watts": watts, "via": interface.to_string() }).to_string()))
+                .unwrap(),
+            Err(e @ RyzenmonError::Control(_)) => error_response(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()),
+            Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+        }
+    }
+    // Config validation rejects [control] in such builds.
+    #[cfg(not(feature = "control"))]
+    {
+        let _ = (control, watts);
+        error_response(StatusCode::NOT_IMPLEMENTED, "ryzenmon was built without the control feature")
+    }
+}
+
+// ?metric=package&from=<unix seconds>&to=<unix seconds>&p=50,95,99, or
+// secs=<n> instead of from for the last n seconds. Every parameter is optional.
+fn percentiles(query: Option<&str>) -> Response<Body> {
+    let Some(ring) = RING.get() else {
+        return error_response(StatusCode::NOT_FOUND, "percentiles need [history] in the config");
+    };
+    let metric = match query_param(query, "metric").unwrap_or("package").parse::<Metric>() {
+        Ok(metric) => metric,
+        Err(message) => return error_response(StatusCode::BAD_REQUEST, &message),
+    };
+    let now = SystemTime::now();
+    let range = (|| {
+        let to = query_time(query, "to")?.unwrap_or(now);
+        let from = match (query_time(query, "from")?, query_secs(query)?) {
+            (Some(_), Some(_)) => return Err("from and secs can't both be given".to_string()),
+            (Some(from), None) => from,
+            (None, Some(secs)) => to.checked_sub(Duration::from_secs(secs)).unwrap_or(UNIX_EPOCH),
+            (None, None) => UNIX_EPOCH,
+        };
+        let percentiles = query_param(query, "p")
+            .unwrap_or("50,90,95,99")
+            .split(',')
+            .map(|p| match p.parse::<f64>() {
+                Ok(p) if (0.0..=100.0).contains(&p) => Ok(p),
+                _ => Err(format!("percentiles must be between 0 and 100, got {:?}", p)),
+            })
+            .collect::<Result<Vec<f64>, String>>()?;
+        Ok((from, to, percentiles))
+    })();
+    let (from, to, percentiles) = match range {
+        Ok(range) => range,
+        Err(message) => return error_response(StatusCode::BAD_REQUEST, &message),
+    };
+    let Some(stats) = ring.stats(metric, from, to, &percentiles) else {
+        return error_response(StatusCode::NOT_FOUND, "no samples in the range");
+    };
+    Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_string(&stats).unwrap_or_default()))
+        .unwrap()
+}
+
+fn query_param<'a>(query: Option<&'a str>, name: &str) -> Option<&'a str> {
+    query
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+}
+
+// The `secs` query parameter, None when absent.
+fn query_secs(query: Option<&str>) -> Result<Option<u64>, String> {
+    let Some(value) = query_param(query, "secs") else {
+        return Ok(None);
+    };
+    value
+        .parse()
+        .map(Some)
+        .map_err(|_| format!("secs must be a whole number of seconds, got {:?}", value))
+}
+
+// A Unix timestamp in seconds, fractions allowed.
+fn query_time(query: Option<&str>, name: &str) -> Result<Option<SystemTime>, String> {
+    let Some(value) = query_param(query, name) else {
+        return Ok(None);
+    };
+    value
+        .parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .and_then(|since_epoch| UNIX_EPOCH.checked_add(since_epoch))
+        .map(Some)
+        .ok_or_else(|| format!("{} must be a Unix timestamp in seconds, got {:?}", name, value))
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::json!({ "error": message }).to_string()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn control(token: Option<&str>) -> ControlConfig {
+        ControlConfig {
+            min_ppt_watts: 15.0,
+            max_ppt_watts: 142.0,
+            token: token.map(str::to_string),
+            policy: None,
+        }
+    }
+
+    fn put_ppt(authorization: Option<&str>) -> Request<Body> {
+        let mut req = Request::put("/v1/limits/ppt");
+        if let Some(authorization) = authorization {
+            req = req.header("Authorization", authorization);
+        }
+        req.body(Body::from(r#"{"watts": 1000}"#)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn refuses_unauthenticated_limit_changes() {
+        let local: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let remote: SocketAddr = "192.0.2.1:50000".parse().unwrap();
+        let with_token = control(Some("secret"));
+        let without_token = control(None);
+
+        for authorization in [None, Some("Bearer wrong"), Some("secret")] {
+            let response = set_ppt(put_ppt(authorization), Some(&with_token), local).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{:?}", authorization);
+        }
+        let response = set_ppt(put_ppt(None), Some(&without_token), remote).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // Past the checks, 1000 W is out of bounds (or there is no control
+        // feature)
+        for (control, peer) in [(&with_token, remote), (&without_token, local)] {
+            let authorization = control.token.as_ref().map(|token| format!("Bearer {}", token));
+            let response = set_ppt(put_ppt(authorization.as_deref()), Some(control), peer).await;
+            assert!(
+                [StatusCode::UNPROCESSABLE_ENTITY, StatusCode::NOT_IMPLEMENTED].contains(&response.status()),
+                "{}",
+                response.status()
+            );
+        }
+    }
+
+    #[test]
+    fn parses_query_times() {
+        assert_eq!(
+            query_time(Some("from=1.5&to=2"), "from").unwrap(),
+            Some(UNIX_EPOCH + Duration::from_millis(1500))
+        );
+        assert_eq!(query_time(Some("secs=60"), "to").unwrap(), None);
+        for to in ["1e300", "-1", "inf", "NaN", "soon"] {
+            assert!(query_time(Some(&format!("to={}", to)), "to").is_err(), "{}", to);
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_range_percentile_queries() {
+        let _ = RING.set(crate::ring::Ring::new(8));
+        assert_eq!(percentiles(Some("to=1e300")).status(), StatusCode::BAD_REQUEST);
+        assert_eq!(percentiles(Some("from=1e300")).status(), StatusCode::BAD_REQUEST);
+    }
+}
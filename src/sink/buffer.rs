@@ -0,0 +1,238 @@
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use tracing::{error, info, warn};
+
+use crate::telemetry;
+
+const RETRY_BASE: Duration = Duration::from_secs(5);
+// Failures in a row after which the circuit opens
+const BREAKER_THRESHOLD: u32 = 5;
+
+// Line protocol that failed to upload, kept until the sink is reachable again.
+// Retries back off exponentially between `RETRY_BASE` and `max_backoff`, with
+// jitter so hosts that lost the same endpoint don't retry in lockstep. After
+// `BREAKER_THRESHOLD` failures in a row the circuit opens: the sink is only
+// probed once per `max_backoff` until an upload succeeds. The oldest lines are
+// dropped once `capacity` is exceeded.
+pub struct RetryBuffer {
+    // The sink, for the breaker's log lines
+    name: String,
+    lines: VecDeque<String>,
+    capacity: usize,
+    path: Option<PathBuf>,
+    failures: u32,
+    retry_at: Option<Instant>,
+    max_backoff: Duration,
+    // When the circuit opened, None while closed
+    opened_at: Option<Instant>,
+}
+
+impl RetryBuffer {
+    pub fn new(name: &str, capacity: usize, path: Option<PathBuf>, max_backoff: Duration) -> Self {
+        let mut buffer = RetryBuffer {
+            name: name.to_string(),
+            lines: VecDeque::new(),
+            capacity,
+            path,
+            failures: 0,
+            retry_at: None,
+            max_backoff,
+            opened_at: None,
+        };
+
+        if let Some(path) = &buffer.path {
+            if let Ok(contents) = fs::read_to_string(path) {
+                let restored: Vec<String> = contents.lines().map(|l| l.to_string()).collect();
+                if !restored.is_empty() {
+                    info!("Restored {} buffered points from {}", restored.len(), path.display());
+                }
+                buffer.push(restored);
+            }
+        }
+
+        buffer
+    }
+
+    pub fn push(&mut self, lines: impl IntoIterator<Item = String>) {
+        self.lines.extend(lines);
+        let overflow = self.lines.len().saturating_sub(self.capacity);
+        if overflow > 0 {
+            warn!("Upload buffer full, dropping {} oldest points", overflow);
+            telemetry::record_dropped_points(overflow);
+            self.lines.drain(..overflow);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    // Whether the backoff from the last failure has elapsed.
+    pub fn ready(&self) -> bool {
+        self.retry_at.is_none_or(|at| Instant::now() >= at)
+    }
+
+    pub fn body(&self) -> String {
+        let mut body = String::new();
+        for line in &self.lines {
+            body.push_str(line);
+            body.push('\n');
+        }
+        body
+    }
+
+    // Whether uploads are paused after too many failures in a row.
+    pub fn is_open(&self) -> bool {
+        self.opened_at.is_some()
+    }
+
+    pub fn succeeded(&mut self) {
+        if let Some(opened_at) = self.opened_at.take() {
+            telemetry::record_circuit_closed();
+            info!(
+                "{}: upload succeeded after {} failures, closing the circuit after {:?}",
+                self.name,
+                self.failures,
+                opened_at.elapsed()
+            );
+        }
+        self.lines.clear();
+        self.failures = 0;
+        self.retry_at = None;
+        self.persist();
+    }
+
+    // The time until the next attempt.
+    pub fn failed(&mut self) -> Duration {
+        telemetry::record_upload_retry();
+        self.failures = self.failures.saturating_add(1);
+        if self.failures >= BREAKER_THRESHOLD && self.opened_at.is_none() {
+            self.opened_at = Some(Instant::now());
+            telemetry::record_circuit_opened();
+            warn!(
+                "{}: {} uploads failed in a row, opening the circuit and trying once every {:?}",
+                self.name, self.failures, self.max_backoff
+            );
+        }
+        let backoff = match self.opened_at {
+            Some(_) => self.max_backoff,
+            None => RETRY_BASE
+                .saturating_mul(2u32.saturating_pow(self.failures - 1))
+                .min(self.max_backoff),
+        };
+        let backoff = jitter(backoff);
+        self.retry_at = Some(Instant::now() + backoff);
+        self.persist();
+        backoff
+    }
+
+    // Take over the lines `previous` still holds, when a reload replaces its
+    // sink. With the same path, they stand in for what was restored from it.
+    pub fn adopt(&mut self, previous: &mut RetryBuffer) {
+        if self.path.is_some() && self.path == previous.path {
+            self.lines.clear();
+        }
+        let lines = std::mem::take(&mut previous.lines);
+        self.push(lines);
+        self.persist();
+    }
+
+    fn persist(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = fs::File::create(path).and_then(|mut file| {
+            for line in &self.lines {
+                writeln!(file, "{}", line)?;
+            }
+            Ok::<_, io::Error>(())
+        });
+        if let Err(e) = result {
+            error!("Failed to persist upload buffer to {}: {}", path.display(), e);
+        }
+    }
+}
+
+impl Drop for RetryBuffer {
+    // A sink replaced on reload no longer counts as open.
+    fn drop(&mut self) {
+        if self.opened_at.is_some() {
+            telemetry::record_circuit_closed();
+        }
+    }
+}
+
+// Somewhere between half of `backoff` and all of it.
+fn jitter(backoff: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    backoff / 2 + backoff.mul_f64(random as f64 / u64::MAX as f64 / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_oldest_lines_over_capacity() {
+        let mut buffer = RetryBuffer::new("test", 2, None, Duration::from_secs(60));
+        buffer.push(["a".to_string(), "b".to_string(), "c".to_string()]);
+        assert_eq!(buffer.body(), "b\nc\n");
+    }
+
+    #[test]
+    fn adopts_the_lines_of_a_replaced_buffer() {
+        let path = std::env::temp_dir().join(format!("ryzenmon-buffer-{}", std::process::id()));
+        let mut previous = RetryBuffer::new("test", 10, Some(path.clone()), Duration::from_secs(60));
+        previous.push(["a".to_string()]);
+        previous.failed();
+        previous.push(["b".to_string()]);
+
+        // Restores "a" from the file, which "a" and "b" then replace.
+        let mut buffer = RetryBuffer::new("test", 10, Some(path.clone()), Duration::from_secs(60));
+        buffer.adopt(&mut previous);
+        assert_eq!(buffer.body(), "a\nb\n");
+        assert!(previous.is_empty());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\nb\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max_with_jitter() {
+        let mut buffer = RetryBuffer::new("test", 10, None, Duration::from_secs(12));
+        let within = |backoff: Duration, secs: f64| backoff.as_secs_f64() >= secs / 2.0 && backoff.as_secs_f64() <= secs;
+        assert!(within(buffer.failed(), 5.0));
+        assert!(within(buffer.failed(), 10.0));
+        assert!(within(buffer.failed(), 12.0));
+        assert!(!buffer.ready());
+
+        buffer.succeeded();
+        assert!(buffer.ready());
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn opens_the_circuit_after_repeated_failures() {
+        let mut buffer = RetryBuffer::new("test", 10, None, Duration::from_secs(300));
+        for _ in 1..BREAKER_THRESHOLD {
+            assert!(buffer.failed() < Duration::from_secs(80));
+            assert!(!buffer.is_open());
+        }
+        // Open: only probed once per max_backoff.
+        assert!(buffer.failed() >= Duration::from_secs(150));
+        assert!(buffer.is_open());
+
+        buffer.succeeded();
+        assert!(!buffer.is_open());
+        assert!(buffer.failed() <= Duration::from_secs(5));
+    }
+}
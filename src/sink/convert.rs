@@ -0,0 +1,60 @@
+use async_trait::async_trait;
+
+use crate::rapl::PowerMetrics;
+use crate::sink::{MetricSink, RetryBuffer, SinkError};
+use crate::system_info::SystemInfo;
+use crate::units::Units;
+
+// Hands a sink its samples in the units of [units]. Inside a [downsample]
+// wrapper, so aggregates are taken over the readings and rounded once.
+pub struct ConvertedSink {
+    inner: Box<dyn MetricSink>,
+    units: Units,
+}
+
+impl ConvertedSink {
+    pub fn new(inner: Box<dyn MetricSink>, units: Units) -> Self {
+        ConvertedSink { inner, units }
+    }
+}
+
+#[async_trait]
+impl MetricSink for ConvertedSink {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        let mut converted = metrics.clone();
+        self.units.apply(&mut converted);
+        self.inner.write(&converted).await
+    }
+
+    async fn prepare(&mut self) -> Result<(), SinkError> {
+        self.inner.prepare().await
+    }
+
+    async fn write_info(&mut self, info: &SystemInfo) -> Result<(), SinkError> {
+        self.inner.write_info(info).await
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        self.inner.flush().await
+    }
+
+    async fn close(&mut self) {
+        self.inner.close().await
+    }
+
+    fn buffered(&self) -> usize {
+        self.inner.buffered()
+    }
+
+    fn retry_buffer(&mut self) -> Option<&mut RetryBuffer> {
+        self.inner.retry_buffer()
+    }
+
+    fn listens(&self) -> bool {
+        self.inner.listens()
+    }
+}
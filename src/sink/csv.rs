@@ -0,0 +1,232 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Local};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tracing::{info, warn};
+
+use crate::config::CsvConfig;
+use crate::rapl::PowerMetrics;
+use crate::sink::filter::{filtered_gauges, MetricFilter};
+use crate::sink::{MetricSink, SinkError};
+use crate::units::Units;
+
+// Appends one row per sample to a CSV file with a `timestamp` column and one
+// column per metric, named like the StatsD gauges. The file is rotated to
+// `<stem>-<local time>.<extension>` once it reaches max_size_mb or
+// max_age_secs, and whenever the set of metrics changes, e.g. when a core
+// goes offline, so every file has a single header.
+pub struct CsvSink {
+    path: PathBuf,
+    max_bytes: Option<u64>,
+    max_age: Option<Duration>,
+    gzip: bool,
+    file: File,
+    size: u64,
+    created: SystemTime,
+    // Header of the current file, empty until the first row
+    columns: Vec<String>,
+    filter: Option<MetricFilter>,
+}
+
+impl CsvSink {
+    pub fn open(config: &CsvConfig) -> Result<Self, SinkError> {
+        let path = PathBuf::from(&config.path);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let metadata = file.metadata()?;
+        // Carry on with the file a previous run left behind.
+        let columns = match BufReader::new(File::open(&path)?).lines().next() {
+            Some(header) => header?.split(',').skip(1).map(str::to_string).collect(),
+            None => Vec::new(),
+        };
+        Ok(CsvSink {
+            max_bytes: config.max_size_mb.map(|mb| mb * 1024 * 1024),
+            max_age: config.max_age_secs.map(Duration::from_secs),
+            gzip: config.gzip,
+            size: metadata.len(),
+            created: metadata.created().or_else(|_| metadata.modified()).unwrap_or_else(|_| SystemTime::now()),
+            columns,
+            file,
+            path,
+            filter: None,
+        })
+    }
+
+    fn needs_rotation(&self, columns: &[String]) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        let age = SystemTime::now().duration_since(self.created).unwrap_or_default();
+        self.columns != columns
+            || self.max_bytes.is_some_and(|max| self.size >= max)
+            || self.max_age.is_some_and(|max| age >= max)
+    }
+
+    // Move the current file aside and start a new one.
+    fn rotate(&mut self) -> Result<(), SinkError> {
+        self.file.sync_data()?;
+        let rotated = rotated_path(&self.path, SystemTime::now());
+        fs::rename(&self.path, &rotated)?;
+        info!("Rotated {} to {}", self.path.display(), rotated.display());
+        if self.gzip {
+            compress(rotated);
+        }
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        self.created = SystemTime::now();
+        self.columns.clear();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MetricSink for CsvSink {
+    fn name(&self) -> &str {
+        "csv"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        let (columns, values): (Vec<String>, Vec<f64>) = filtered_gauges(self.filter.as_ref(), metrics).into_iter().unzip();
+        if self.needs_rotation(&columns) {
+            self.rotate()?;
+        }
+
+        let mut out = String::new();
+        if self.size == 0 {
+            out.push_str("timestamp");
+            for column in &columns {
+                out.push(',');
+                out.push_str(column);
+            }
+            out.push('\n');
+            self.columns = columns;
+        }
+        let timestamp = metrics.timestamp.duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+        out.push_str(&format!("{:.3}", timestamp));
+        for value in values {
+            out.push_str(&format!(",{}", value));
+        }
+        out.push('\n');
+
+        self.file.write_all(out.as_bytes())?;
+        self.size += out.len() as u64;
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    fn set_filter(&mut self, filter: MetricFilter) -> Result<(), SinkError> {
+        self.filter = Some(filter);
+        Ok(())
+    }
+
+    fn set_units(&mut self, _units: &Units) -> bool {
+        true
+    }
+}
+
+// `/var/log/ryzenmon.csv` -> `/var/log/ryzenmon-20240131T120000.csv`, with a
+// counter appended if that name is taken.
+fn rotated_path(path: &Path, now: SystemTime) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let time = DateTime::<Local>::from(now).format("%Y%m%dT%H%M%S");
+    let mut candidate = path.with_file_name(format!("{}-{}{}", stem, time, extension));
+    let mut counter = 1;
+    while candidate.exists() || gzipped(&candidate).exists() {
+        candidate = path.with_file_name(format!("{}-{}-{}{}", stem, time, counter, extension));
+        counter += 1;
+    }
+    candidate
+}
+
+fn gzipped(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".gz");
+    PathBuf::from(name)
+}
+
+// Compress in the background so a large file doesn't hold up sampling.
+fn compress(path: PathBuf) {
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = gzip(&path) {
+            warn!("Could not gzip {}: {}", path.display(), e);
+        }
+    });
+}
+
+// Replace `path` with `<path>.gz`. The original stays until the compressed
+// file is complete, and an existing .gz is never overwritten.
+fn gzip(path: &Path) -> io::Result<()> {
+    let target = gzipped(path);
+    let compressed = OpenOptions::new().write(true).create_new(true).open(&target)?;
+    let result = (|| {
+        let mut encoder = GzEncoder::new(compressed, Compression::default());
+        io::copy(&mut File::open(path)?, &mut encoder)?;
+        encoder.finish()?.sync_all()
+    })();
+    match result {
+        Ok(()) => fs::remove_file(path),
+        Err(e) => {
+            let _ = fs::remove_file(&target);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ryzenmon-csv-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn rotated_names_skip_taken_and_gzipped_ones() {
+        let dir = test_dir("names");
+        let path = dir.join("power.csv");
+        let now = SystemTime::now();
+        let time = DateTime::<Local>::from(now).format("%Y%m%dT%H%M%S").to_string();
+
+        assert_eq!(rotated_path(&path, now), dir.join(format!("power-{}.csv", time)));
+        fs::write(dir.join(format!("power-{}.csv", time)), "").unwrap();
+        fs::write(dir.join(format!("power-{}-1.csv.gz", time)), "").unwrap();
+        assert_eq!(rotated_path(&path, now), dir.join(format!("power-{}-2.csv", time)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn gzipped_files_decode() {
+        let dir = test_dir("gzip");
+        let path = dir.join("power-20240131T120000.csv");
+        let contents = "timestamp,package\n1706702400.000,42.5\n".repeat(100);
+        fs::write(&path, &contents).unwrap();
+
+        gzip(&path).unwrap();
+        assert!(!path.exists());
+        let mut decoded = String::new();
+        GzDecoder::new(File::open(gzipped(&path)).unwrap()).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, contents);
+
+        // An existing .gz is left alone, and so is the file.
+        fs::write(&path, "again").unwrap();
+        assert!(gzip(&path).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "again");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
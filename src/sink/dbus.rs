@@ -0,0 +1,194 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::time::UNIX_EPOCH;
+
+use async_trait::async_trait;
+use tracing::info;
+use zbus::fdo::Properties;
+use zbus::names::InterfaceName;
+use zbus::object_server::InterfaceRef;
+use zbus::zvariant::Value;
+use zbus::{connection, interface, Connection};
+
+use crate::config::{DbusBus, DbusConfig};
+use crate::rapl::PowerMetrics;
+use crate::sink::{MetricSink, SinkError};
+
+const INTERFACE: &str = "org.ryzenmon.Monitor";
+
+// The latest sample, 0 or empty until the first one.
+#[derive(Default, PartialEq)]
+struct Monitor {
+    package_watts: f64,
+    core_sum: f64,
+    core_watts: Vec<f64>,
+    temperatures: HashMap<String, f64>,
+    timestamp: f64,
+}
+
+#[interface(name = "org.ryzenmon.Monitor")]
+impl Monitor {
+    #[zbus(property)]
+    fn package_watts(&self) -> f64 {
+        self.package_watts
+    }
+
+    #[zbus(property)]
+    fn core_sum(&self) -> f64 {
+        self.core_sum
+    }
+
+    #[zbus(property)]
+    fn core_watts(&self) -> Vec<f64> {
+        self.core_watts.clone()
+    }
+
+    #[zbus(property)]
+    fn temperatures(&self) -> HashMap<String, f64> {
+        self.temperatures.clone()
+    }
+
+    // Seconds since the epoch
+    #[zbus(property)]
+    fn timestamp(&self) -> f64 {
+        self.timestamp
+    }
+}
+
+impl Monitor {
+    fn new(metrics: &PowerMetrics) -> Self {
+        Monitor {
+            package_watts: metrics.package_watts,
+            core_sum: metrics.core_sum,
+            core_watts: metrics.core_watts.clone(),
+            temperatures: metrics.temperatures.iter().map(|t| (t.label.clone(), t.celsius)).collect(),
+            timestamp: metrics.timestamp.duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0),
+        }
+    }
+
+    // The properties that differ from `previous`, for PropertiesChanged.
+    fn changed(&self, previous: &Monitor) -> HashMap<&'static str, Value<'static>> {
+        let mut changed = HashMap::new();
+        if self.package_watts != previous.package_watts {
+            changed.insert("PackageWatts", Value::from(self.package_watts));
+        }
+        if self.core_sum != previous.core_sum {
+            changed.insert("CoreSum", Value::from(self.core_sum));
+        }
+        if self.core_watts != previous.core_watts {
+            changed.insert("CoreWatts", Value::new(self.core_watts.clone()));
+        }
+        if self.temperatures != previous.temperatures {
+            changed.insert("Temperatures", Value::new(self.temperatures.clone()));
+        }
+        if self.timestamp != previous.timestamp {
+            changed.insert("Timestamp", Value::from(self.timestamp));
+        }
+        changed
+    }
+}
+
+// Publishes the latest sample as properties of INTERFACE on a path derived
+// from `name`, owned as `name` on the system or session bus, and emits
+// PropertiesChanged for every sample. The bus is connected lazily and
+// reconnected after it restarts.
+pub struct DbusSink {
+    config: DbusConfig,
+    path: String,
+    connection: Option<(Connection, InterfaceRef<Monitor>)>,
+}
+
+impl DbusSink {
+    pub fn new(config: DbusConfig) -> Self {
+        let path = format!("/{}", config.name.replace('.', "/"));
+        DbusSink {
+            config,
+            path,
+            connection: None,
+        }
+    }
+
+    async fn connect(&mut self) -> Result<InterfaceRef<Monitor>, SinkError> {
+        if let Some((_, monitor)) = &self.connection {
+            return Ok(monitor.clone());
+        }
+        let builder = match self.config.bus {
+            DbusBus::System => connection::Builder::system()?,
+            DbusBus::Session => connection::Builder::session()?,
+        };
+        let connection = builder
+            .name(self.config.name.as_str())?
+            .serve_at(self.path.as_str(), Monitor::default())?
+            .build()
+            .await?;
+        let monitor = connection.object_server().interface::<_, Monitor>(self.path.as_str()).await?;
+        info!("Publishing samples on D-Bus as {} {}", self.config.name, self.path);
+        self.connection = Some((connection, monitor.clone()));
+        Ok(monitor)
+    }
+}
+
+#[async_trait]
+impl MetricSink for DbusSink {
+    fn name(&self) -> &str {
+        "dbus"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        let updated = Monitor::new(metrics);
+        let monitor = self.connect().await?;
+        let changed = {
+            let mut current = monitor.get_mut().await;
+            let changed = updated.changed(&current);
+            *current = updated;
+            changed
+        };
+        if changed.is_empty() {
+            return Ok(());
+        }
+        let interface = InterfaceName::from_static_str_unchecked(INTERFACE);
+        if let Err(e) = Properties::properties_changed(monitor.signal_emitter(), interface, changed, Cow::Borrowed(&[])).await {
+            // Connects again on the next sample, once the bus is back.
+            self.close().await;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self) {
+        if let Some((connection, _)) = self.connection.take() {
+            let _ = connection.close().await;
+        }
+    }
+
+    fn listens(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signals_only_changed_properties() {
+        let previous = Monitor {
+            package_watts: 42.0,
+            core_sum: 30.0,
+            core_watts: vec![15.0, 15.0],
+            temperatures: HashMap::from([("Tctl".to_string(), 60.0)]),
+            timestamp: 1.0,
+        };
+        let updated = Monitor {
+            package_watts: 45.0,
+            core_sum: 0.0,
+            core_watts: previous.core_watts.clone(),
+            temperatures: previous.temperatures.clone(),
+            timestamp: 2.0,
+        };
+        let mut changed: Vec<_> = updated.changed(&previous).into_keys().collect();
+        changed.sort();
+        assert_eq!(changed, ["CoreSum", "PackageWatts", "Timestamp"]);
+        assert!(updated.changed(&updated).is_empty());
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use crate::config::DeadbandConfig;
+use crate::sink::influxdb::{split_line, split_once_unescaped};
+
+const NANOS_PER_SEC: i64 = 1_000_000_000;
+
+// Leaves fields out of line protocol while they stay within a deadband of the
+// value last written for the same series, so an idle machine writes little
+// more than what changes. Every field is written again once max_age_secs have
+// passed since it last was, so gaps in a query stay bounded. Ages go by the
+// points' own timestamps. Non-numeric fields are always written.
+pub struct Deadband {
+    config: DeadbandConfig,
+    // Last written value and timestamp by series and field
+    last: HashMap<(String, String), (f64, i64)>,
+}
+
+impl Deadband {
+    pub fn new(config: DeadbandConfig) -> Self {
+        Deadband {
+            config,
+            last: HashMap::new(),
+        }
+    }
+
+    // The lines with the fields that moved, or are due, and without the
+    // lines that have none left.
+    pub fn filter(&mut self, lines: Vec<String>) -> Vec<String> {
+        let max_age = self.config.max_age_secs as i64 * NANOS_PER_SEC;
+        let filtered: Vec<String> = lines.into_iter().filter_map(|line| self.filter_line(line, max_age)).collect();
+        // Series that are gone, e.g. processes that exited, would be written
+        // in full when they come back anyway.
+        if let Some(newest) = self.last.values().map(|(_, timestamp)| *timestamp).max() {
+            self.last.retain(|_, (_, timestamp)| newest - *timestamp < max_age);
+        }
+        filtered
+    }
+
+    fn filter_line(&mut self, line: String, max_age: i64) -> Option<String> {
+        let Some((series, fields, timestamp)) = split_line(&line) else {
+            return Some(line);
+        };
+        let Ok(timestamp) = timestamp.parse::<i64>() else {
+            return Some(line);
+        };
+        let kept: Vec<&str> = fields
+            .into_iter()
+            .filter(|field| {
+                let Some((name, value)) = split_once_unescaped(field, '=', false) else {
+                    return true;
+                };
+                let Some(value) = numeric(value) else {
+                    return true;
+                };
+                let key = (series.to_string(), name.to_string());
+                let band = self.config.fields.get(name).copied().unwrap_or(self.config.absolute);
+                let within = self.last.get(&key).is_some_and(|(last, at)| {
+                    timestamp - at < max_age && (value - last).abs() <= band.max(self.config.relative * last.abs())
+                });
+                if !within {
+                    self.last.insert(key, (value, timestamp));
+                }
+                !within
+            })
+            .collect();
+        if kept.is_empty() {
+            return None;
+        }
+        Some(format!("{} {} {}", series, kept.join(","), timestamp))
+    }
+}
+
+// A float, or an integer with its `i` or `u` suffix.
+fn numeric(value: &str) -> Option<f64> {
+    let number = value.strip_suffix(['i', 'u']).unwrap_or(value);
+    number.parse().ok().filter(|_| !value.starts_with('"'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn leaves_out_fields_within_the_deadband() {
+        let mut deadband = Deadband::new(DeadbandConfig {
+            absolute: 0.5,
+            relative: 0.0,
+            fields: BTreeMap::from([("core-power".to_string(), 1.0)]),
+            max_age_secs: 60,
+        });
+        let line = |package: f64, core: f64, secs: i64| {
+            format!("power,host=a\\ b package-power={},core-power={},note=\"x, y\" {}", package, core, secs * NANOS_PER_SEC)
+        };
+        let filter = |deadband: &mut Deadband, lines: &[String]| deadband.filter(lines.to_vec());
+
+        assert_eq!(filter(&mut deadband, &[line(40.0, 10.0, 0)]), vec![line(40.0, 10.0, 0)]);
+        assert_eq!(
+            filter(&mut deadband, &[line(40.4, 10.8, 10)]),
+            vec![format!("power,host=a\\ b note=\"x, y\" {}", 10 * NANOS_PER_SEC)]
+        );
+        // Compared with the value last written, not the last one seen.
+        assert_eq!(
+            filter(&mut deadband, &[line(40.6, 11.2, 20)]),
+            vec![format!("power,host=a\\ b package-power=40.6,core-power=11.2,note=\"x, y\" {}", 20 * NANOS_PER_SEC)]
+        );
+        assert!(filter(&mut deadband, &["cpu,core=1 mhz=3000i 30000000000".to_string()]).len() == 1);
+        assert!(filter(&mut deadband, &["cpu,core=1 mhz=3000i 40000000000".to_string()]).is_empty());
+        // Written again once max_age_secs have passed.
+        assert_eq!(filter(&mut deadband, &[line(40.6, 11.2, 80)]), vec![line(40.6, 11.2, 80)]);
+    }
+}
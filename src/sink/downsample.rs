@@ -0,0 +1,66 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::rapl::PowerMetrics;
+use crate::sink::{MetricSink, RetryBuffer, SinkError};
+use crate::stats::Downsampler;
+use crate::system_info::SystemInfo;
+
+// Passes one aggregate per resolution on to a sink named in [downsample],
+// while the other sinks keep getting every sample.
+pub struct DownsampledSink {
+    inner: Box<dyn MetricSink>,
+    downsampler: Downsampler,
+}
+
+impl DownsampledSink {
+    pub fn new(inner: Box<dyn MetricSink>, resolution: Duration) -> Self {
+        DownsampledSink {
+            inner,
+            downsampler: Downsampler::new(resolution),
+        }
+    }
+}
+
+#[async_trait]
+impl MetricSink for DownsampledSink {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        for aggregate in self.downsampler.push(metrics.clone()) {
+            self.inner.write(&aggregate).await?;
+        }
+        Ok(())
+    }
+
+    async fn write_info(&mut self, info: &SystemInfo) -> Result<(), SinkError> {
+        self.inner.write_info(info).await
+    }
+
+    // The unfinished period goes out too, rather than being lost on shutdown.
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        if let Some(aggregate) = self.downsampler.take() {
+            self.inner.write(&aggregate).await?;
+        }
+        self.inner.flush().await
+    }
+
+    async fn close(&mut self) {
+        self.inner.close().await
+    }
+
+    fn buffered(&self) -> usize {
+        self.inner.buffered()
+    }
+
+    fn retry_buffer(&mut self) -> Option<&mut RetryBuffer> {
+        self.inner.retry_buffer()
+    }
+
+    fn listens(&self) -> bool {
+        self.inner.listens()
+    }
+}
@@ -0,0 +1,36 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+use async_trait::async_trait;
+
+use crate::rapl::PowerMetrics;
+use crate::sink::stdout::format_line;
+use crate::sink::{MetricSink, SinkError};
+
+pub struct FileSink {
+    file: File,
+}
+
+impl FileSink {
+    pub fn open(path: &str) -> Result<Self, SinkError> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileSink { file })
+    }
+}
+
+#[async_trait]
+impl MetricSink for FileSink {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        writeln!(self.file, "{}", format_line(metrics))?;
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
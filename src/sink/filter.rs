@@ -0,0 +1,156 @@
+use std::collections::BTreeSet;
+
+use crate::config::FilterConfig;
+use crate::hwmon::glob_match;
+use crate::rapl::PowerMetrics;
+use crate::sink::influxdb::{split_line, split_once_unescaped, split_unescaped};
+use crate::sink::prometheus::parse_series;
+use crate::sink::statsd::gauges;
+
+// Which metrics a sink writes, for [filter.<sink>]. Metrics go by the names
+// the sink writes: `<measurement>.<field>` with the point's tags for
+// InfluxDB, series names with their labels for Prometheus, and StatsD gauge
+// names such as `core3.power`, which have no tags, for the others.
+#[derive(Debug, Clone)]
+pub struct MetricFilter {
+    config: FilterConfig,
+}
+
+impl MetricFilter {
+    pub fn new(config: FilterConfig) -> Self {
+        MetricFilter { config }
+    }
+
+    pub fn allows(&self, name: &str, tags: &[(&str, &str)]) -> bool {
+        let config = &self.config;
+        let matches_any = |patterns: &[String]| patterns.iter().any(|pattern| glob_match(pattern, name));
+        if !(config.include.is_empty() || matches_any(&config.include)) || matches_any(&config.exclude) {
+            return false;
+        }
+        tags.iter().all(|(key, value)| {
+            config.tags.get(*key).is_none_or(|pattern| glob_match(pattern, value))
+                && !config.exclude_tags.get(*key).is_some_and(|pattern| glob_match(pattern, value))
+        })
+    }
+
+    // Line protocol with only the fields allowed, and without the points
+    // that have none left.
+    pub fn filter_lines(&self, lines: Vec<String>) -> Vec<String> {
+        lines.into_iter().filter_map(|line| self.filter_line(line)).collect()
+    }
+
+    fn filter_line(&self, line: String) -> Option<String> {
+        let Some((series, fields, timestamp)) = split_line(&line) else {
+            return Some(line);
+        };
+        let parts = split_unescaped(series, ',', false);
+        let measurement = parts[0];
+        let tags: Vec<(&str, &str)> = parts[1..].iter().filter_map(|tag| split_once_unescaped(tag, '=', false)).collect();
+        let kept: Vec<&str> = fields
+            .iter()
+            .copied()
+            .filter(|field| {
+                split_once_unescaped(field, '=', false)
+                    .is_none_or(|(name, _)| self.allows(&format!("{}.{}", measurement, name), &tags))
+            })
+            .collect();
+        if kept.is_empty() {
+            return None;
+        }
+        if kept.len() == fields.len() {
+            return Some(line);
+        }
+        Some(format!("{} {} {}", series, kept.join(","), timestamp))
+    }
+
+    // Prometheus text with only the series allowed. HELP and TYPE lines of
+    // metrics with no series left go too.
+    pub fn filter_exposition(&self, text: &str) -> String {
+        let mut names = BTreeSet::new();
+        let allowed: Vec<bool> = text
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| {
+                let Some((labels, _)) = parse_series(line) else {
+                    return true;
+                };
+                let name = &labels.iter().find(|(key, _)| key == "__name__").expect("every series has a name").1;
+                let tags: Vec<(&str, &str)> = labels
+                    .iter()
+                    .filter(|(key, _)| key != "__name__")
+                    .map(|(key, value)| (key.as_str(), value.as_str()))
+                    .collect();
+                let allowed = self.allows(name, &tags);
+                if allowed {
+                    names.insert(name.clone());
+                }
+                allowed
+            })
+            .collect();
+        // Histograms describe their _bucket, _sum and _count series.
+        let described = |metric: &str| {
+            names.iter().any(|name| {
+                name.strip_prefix(metric).is_some_and(|suffix| ["", "_bucket", "_sum", "_count"].contains(&suffix))
+            })
+        };
+        let mut allowed = allowed.into_iter();
+        let mut filtered = String::new();
+        for line in text.lines() {
+            let keep = match line.strip_prefix('#') {
+                Some(comment) => comment.split_whitespace().nth(1).is_none_or(described),
+                None => allowed.next().unwrap_or(true),
+            };
+            if keep {
+                filtered.push_str(line);
+                filtered.push('\n');
+            }
+        }
+        filtered
+    }
+}
+
+// The gauges of `metrics` that `filter` allows, or all of them without one.
+pub fn filtered_gauges(filter: Option<&MetricFilter>, metrics: &PowerMetrics) -> Vec<(String, f64)> {
+    match filter {
+        Some(filter) => gauges(metrics).into_iter().filter(|(name, _)| filter.allows(name, &[])).collect(),
+        None => gauges(metrics),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn writes_only_the_metrics_allowed() {
+        let filter = MetricFilter::new(FilterConfig {
+            include: vec!["power.*".to_string(), "ryzenmon_*".to_string()],
+            exclude: vec!["*-p95".to_string()],
+            tags: BTreeMap::new(),
+            exclude_tags: BTreeMap::from([("core".to_string(), "*".to_string())]),
+        });
+        let lines = vec![
+            "power,host=a\\ b package-power=40,package-power-p95=45 1".to_string(),
+            "power,host=a\\ b,core=3 core-power=4.5,utilization=0.2 1".to_string(),
+            "frequency,host=a\\ b package-frequency=3800 1".to_string(),
+        ];
+        assert_eq!(filter.filter_lines(lines), vec!["power,host=a\\ b package-power=40 1".to_string()]);
+
+        let text = "# HELP ryzenmon_package_watts Package power\n\
+                    # TYPE ryzenmon_package_watts gauge\n\
+                    ryzenmon_package_watts{host=\"a\"} 40\n\
+                    # HELP ryzenmon_core_watts Core power\n\
+                    # TYPE ryzenmon_core_watts gauge\n\
+                    ryzenmon_core_watts{host=\"a\",core=\"3\"} 4.5\n\
+                    # HELP cpu_mhz Frequency\n\
+                    cpu_mhz 3800\n";
+        assert_eq!(
+            filter.filter_exposition(text),
+            "# HELP ryzenmon_package_watts Package power\n\
+             # TYPE ryzenmon_package_watts gauge\n\
+             ryzenmon_package_watts{host=\"a\"} 40\n"
+        );
+        assert!(filter.allows("power.package-power", &[]) && !filter.allows("core3.power", &[]));
+    }
+}
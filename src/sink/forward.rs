@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::config::ForwardConfig;
+use crate::rapl::PowerMetrics;
+use crate::sink::buffer::RetryBuffer;
+use crate::sink::http;
+use crate::sink::{MetricSink, SinkError};
+use crate::telemetry;
+
+// Sends every sample, tagged with this host's tags, to an aggregator, which
+// uploads samples from all its senders. Samples are buffered as one JSON
+// object per line and POSTed as a JSON array to /v1/samples.
+pub struct ForwardSink {
+    client: reqwest::Client,
+    url: reqwest::Url,
+    token: Option<String>,
+    tags: BTreeMap<String, String>,
+    buffer: RetryBuffer,
+}
+
+impl ForwardSink {
+    pub fn new(config: ForwardConfig, tags: BTreeMap<String, String>) -> Result<Self, SinkError> {
+        Ok(ForwardSink {
+            client: http::client_builder(&config.tls, config.proxy.as_deref())?
+                .timeout(Duration::from_secs(config.timeout_secs))
+                .build()?,
+            url: http::endpoint(&config.url, "v1/samples")?,
+            token: config.token,
+            tags,
+            buffer: RetryBuffer::new("forward", config.max_buffered_points, None, Duration::from_secs(config.max_retry_secs)),
+        })
+    }
+
+    async fn send(&mut self) -> Result<(), SinkError> {
+        let body = format!("[{}]", self.buffer.body().lines().collect::<Vec<_>>().join(","));
+        let mut post = self
+            .client
+            .post(self.url.clone())
+            .header("Content-Type", "application/json")
+            .body(body);
+        if let Some(token) = &self.token {
+            post = post.bearer_auth(token);
+        }
+
+        let buffered = self.buffer.len();
+        let result = match post.send().await {
+            Ok(response) if response.status().is_success() => Ok(()),
+            // A bad token or a sample the aggregator can't read fails the
+            // same way every time.
+            Ok(response) if response.status().is_client_error() && response.status().as_u16() != 429 => {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                telemetry::record_dropped_points(buffered);
+                self.buffer.succeeded();
+                return Err(format!("{}: {} ({} samples dropped)", status, body.trim(), buffered).into());
+            }
+            Ok(response) => {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                Err(format!("{}: {}", status, body.trim()))
+            }
+            Err(e) => Err(e.to_string()),
+        };
+
+        match result {
+            Ok(()) => {
+                self.buffer.succeeded();
+                Ok(())
+            }
+            Err(e) => {
+                let backoff = self.buffer.failed();
+                Err(format!("{} ({} samples buffered, retrying in {:.1?})", e, buffered, backoff).into())
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl MetricSink for ForwardSink {
+    fn name(&self) -> &str {
+        "forward"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        let mut metrics = metrics.clone();
+        metrics.tags = self.tags.clone();
+        self.buffer.push([serde_json::to_string(&metrics)?]);
+
+        if !self.buffer.ready() {
+            return Ok(());
+        }
+        self.send().await
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.send().await
+    }
+
+    fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn retry_buffer(&mut self) -> Option<&mut RetryBuffer> {
+        Some(&mut self.buffer)
+    }
+}
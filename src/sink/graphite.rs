@@ -0,0 +1,248 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::UNIX_EPOCH;
+
+use async_trait::async_trait;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+
+use crate::config::{hostname, GraphiteConfig, GraphiteProtocol};
+use crate::rapl::PowerMetrics;
+use crate::sink::{MetricSink, SinkError};
+use crate::units::Units;
+
+// Sends the Carbon plaintext protocol, one `<path> <value> <timestamp>` line
+// per metric, e.g. `hosts.myhost.power.core3 4.2 1700000000`.
+pub struct GraphiteSink {
+    address: String,
+    protocol: GraphiteProtocol,
+    prefix: String,
+    tcp: Option<TcpStream>,
+    udp: Option<UdpSocket>,
+}
+
+impl GraphiteSink {
+    pub fn new(config: GraphiteConfig, tags: &BTreeMap<String, String>) -> Self {
+        let host = tags.get("host").cloned().unwrap_or_else(hostname);
+        let mut prefix = config.prefix.trim_end_matches('.').to_string();
+        if !prefix.is_empty() {
+            prefix.push('.');
+        }
+        prefix.push_str(&sanitize(&host));
+
+        GraphiteSink {
+            address: config.address,
+            protocol: config.protocol,
+            prefix,
+            tcp: None,
+            udp: None,
+        }
+    }
+
+    async fn send(&mut self, payload: &str) -> Result<(), SinkError> {
+        match self.protocol {
+            GraphiteProtocol::Tcp => {
+                if self.tcp.is_none() {
+                    self.tcp = Some(TcpStream::connect(&self.address).await?);
+                }
+                if let Some(stream) = self.tcp.as_mut() {
+                    // Reconnect on the next sample if Carbon went away.
+                    if let Err(e) = stream.write_all(payload.as_bytes()).await {
+                        self.tcp = None;
+                        return Err(e.into());
+                    }
+                }
+            }
+            GraphiteProtocol::Udp => {
+                if self.udp.is_none() {
+                    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+                    socket.connect(&self.address).await?;
+                    self.udp = Some(socket);
+                }
+                if let Some(socket) = &self.udp {
+                    socket.send(payload.as_bytes()).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MetricSink for GraphiteSink {
+    fn name(&self) -> &str {
+        "graphite"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        let payload = build_lines(&self.prefix, metrics);
+        self.send(&payload).await
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        if let Some(stream) = self.tcp.as_mut() {
+            stream.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self) {
+        if let Some(mut stream) = self.tcp.take() {
+            let _ = stream.shutdown().await;
+        }
+        self.udp = None;
+    }
+
+    fn set_units(&mut self, _units: &Units) -> bool {
+        true
+    }
+}
+
+pub fn build_lines(prefix: &str, metrics: &PowerMetrics) -> String {
+    let timestamp = metrics
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let mut out = String::new();
+    let mut line = |path: &str, value: f64| {
+        let _ = writeln!(out, "{}.{} {} {}", prefix, path, value, timestamp);
+    };
+
+    line("power.package", metrics.package_watts);
+    line("power.cores", metrics.core_sum);
+    line("power.uncore", metrics.package_watts - metrics.core_sum);
+    if metrics.packages.len() > 1 {
+        for package in &metrics.packages {
+            line(&format!("power.package{}", package.package), package.watts);
+        }
+    }
+    if let Some(dram_watts) = metrics.dram_watts {
+        line("power.dram", dram_watts);
+    }
+    if let Some(soc_watts) = metrics.soc_watts {
+        line("power.soc", soc_watts);
+    }
+    if let Some(idle_floor_watts) = metrics.idle_floor_watts {
+        line("power.idle_floor", idle_floor_watts);
+    }
+    for (core, watts) in metrics.per_core(&metrics.core_watts) {
+        line(&format!("power.core{}", core), *watts);
+    }
+    if let Some(stats) = &metrics.stats {
+        for (metric, core, summary) in stats.iter() {
+            let name = match core {
+                Some(core) => format!("power.core{}", metrics.core_id(core)),
+                None if metric == "core_sum" => "power.cores".to_string(),
+                None => format!("power.{}", metric),
+            };
+            for (stat, value) in summary.iter() {
+                line(&format!("{}.{}", name, stat), value);
+            }
+        }
+    }
+    if let Some(utilization) = metrics.utilization {
+        line("utilization.package", utilization);
+    }
+    for (core, utilization) in metrics.per_core(&metrics.core_utilization) {
+        line(&format!("utilization.core{}", core), *utilization);
+    }
+    if !metrics.skipped_cores.is_empty() {
+        line("cores.skipped", metrics.skipped_cores.len() as f64);
+    }
+    for process in &metrics.processes {
+        line(&format!("power.process.{}", sanitize(&process.name)), process.watts);
+    }
+    for cgroup in &metrics.cgroups {
+        line(&format!("power.cgroup.{}", sanitize(&cgroup.cgroup)), cgroup.watts);
+    }
+    if let Some(mhz) = metrics.average_mhz {
+        line("frequency.package", mhz);
+    }
+    for (core, mhz) in metrics.per_core(&metrics.core_mhz) {
+        line(&format!("frequency.core{}", core), *mhz);
+    }
+    if let Some(activity) = metrics.mean_activity() {
+        line("frequency.effective", activity.effective_mhz);
+        line("busy.package", activity.busy_percent);
+    }
+    for (core, activity) in metrics.per_core(&metrics.core_activity) {
+        line(&format!("frequency.effective.core{}", core), activity.effective_mhz);
+        line(&format!("busy.core{}", core), activity.busy_percent);
+    }
+    for residency in &metrics.cstates {
+        let state = sanitize(&residency.state);
+        line(&format!("cstate.{}.package", state), residency.percent);
+        for (core, percent) in metrics.per_core(&residency.cores) {
+            line(&format!("cstate.{}.core{}", state, core), *percent);
+        }
+    }
+    for ccd in &metrics.ccds {
+        line(&format!("power.ccd{}", ccd.ccd), ccd.watts);
+        if let Some(celsius) = ccd.celsius {
+            line(&format!("temperature.ccd{}", ccd.ccd), celsius);
+        }
+    }
+    for temperature in &metrics.temperatures {
+        line(&format!("temperature.{}", sanitize(&temperature.label)), temperature.celsius);
+    }
+    for gpu in &metrics.gpus {
+        if let Some(watts) = gpu.watts {
+            line(&format!("gpu{}.power", gpu.gpu), watts);
+        }
+        for temperature in &gpu.temperatures {
+            line(&format!("gpu{}.temperature.{}", gpu.gpu, sanitize(&temperature.label)), temperature.celsius);
+        }
+        if let Some(rpm) = gpu.fan_rpm {
+            line(&format!("gpu{}.fan", gpu.gpu), rpm);
+        }
+    }
+    for sensor in &metrics.sensors {
+        line(&format!("hwmon.{}", sanitize(&sensor.label)), sensor.value);
+    }
+    if let Some(limits) = &metrics.limits {
+        for (name, limit, _) in limits.iter() {
+            line(&format!("limit.{}.value", name), limit.value);
+            line(&format!("limit.{}.limit", name), limit.limit);
+            line(&format!("limit.{}.usage", name), limit.usage());
+        }
+    }
+    if let Some(throttle) = &metrics.throttle {
+        line("throttle.any", f64::from(u8::from(throttle.is_throttled())));
+        for (reason, active) in throttle.iter() {
+            line(&format!("throttle.{}.active", reason), f64::from(u8::from(active)));
+            if let Some(residency) = throttle.residency.get(&reason) {
+                line(&format!("throttle.{}.residency", reason), *residency);
+            }
+        }
+    }
+    for rail in &metrics.rails {
+        if let Some(volts) = rail.volts {
+            line(&format!("rail.{}.voltage", rail.rail), volts);
+        }
+        if let Some(amps) = rail.amps {
+            line(&format!("rail.{}.current", rail.rail), amps);
+        }
+    }
+    if let Some(energy) = &metrics.energy {
+        line("energy.joules", energy.joules);
+        line("energy.kwh", energy.kwh);
+        if let Some(cost) = energy.cost {
+            line("energy.cost", cost);
+        }
+    }
+    if let Some(telemetry) = &metrics.self_telemetry {
+        for (name, value, _) in telemetry.iter() {
+            line(&format!("ryzenmon.{}", name), value);
+        }
+    }
+    out
+}
+
+// Dots separate path components in Graphite, so they can't appear in a name.
+// Also used for StatsD, where ':' and '|' are part of the syntax.
+pub fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
@@ -0,0 +1,83 @@
+use std::fs;
+
+use reqwest::{Certificate, ClientBuilder, Identity, Proxy, Url};
+
+use crate::config::TlsConfig;
+use crate::sink::SinkError;
+
+// HTTP client settings shared by the sinks that talk HTTP(S). Without an
+// explicit `proxy`, HTTPS_PROXY, HTTP_PROXY and NO_PROXY from the environment
+// are honored.
+pub fn client_builder(tls: &TlsConfig, proxy: Option<&str>) -> Result<ClientBuilder, SinkError> {
+    let mut builder = ClientBuilder::new();
+
+    if let Some(proxy) = proxy {
+        if !proxy.starts_with("http://") && !proxy.starts_with("https://") {
+            return Err(format!("unsupported proxy {:?}, only http:// and https:// proxies are supported", proxy).into());
+        }
+        builder = builder.proxy(Proxy::all(proxy).map_err(|e| format!("invalid proxy {:?}: {}", proxy, e))?);
+    }
+
+    if let Some(ca_file) = &tls.ca_file {
+        // A bundle may hold several certificates; each is trusted on top of
+        // the system roots.
+        for pem in split_pem(&read(ca_file)?) {
+            builder = builder.add_root_certificate(
+                Certificate::from_pem(pem.as_bytes()).map_err(|e| format!("invalid certificate in {}: {}", ca_file, e))?,
+            );
+        }
+    }
+
+    match (&tls.cert_file, &tls.key_file) {
+        (Some(cert_file), Some(key_file)) => {
+            let identity = Identity::from_pkcs8_pem(read(cert_file)?.as_bytes(), read(key_file)?.as_bytes())
+                .map_err(|e| format!("invalid client certificate {} or key {}: {}", cert_file, key_file, e))?;
+            builder = builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => return Err("tls.cert_file and tls.key_file must be set together".into()),
+    }
+
+    if tls.insecure_skip_verify {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    Ok(builder)
+}
+
+// `path` under `base`, keeping a path `base` already has: a proxy at
+// http://proxy/influx writes to http://proxy/influx/write.
+pub fn endpoint(base: &str, path: &str) -> Result<Url, SinkError> {
+    let mut url = Url::parse(base)?;
+    if !url.path().ends_with('/') {
+        let with_slash = format!("{}/", url.path());
+        url.set_path(&with_slash);
+    }
+    Ok(url.join(path)?)
+}
+
+fn read(path: &str) -> Result<String, SinkError> {
+    fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e).into())
+}
+
+// Every certificate block in a PEM bundle, in order.
+fn split_pem(bundle: &str) -> Vec<String> {
+    const END: &str = "-----END CERTIFICATE-----";
+    bundle
+        .split_inclusive(END)
+        .filter(|block| block.contains("-----BEGIN CERTIFICATE-----"))
+        .map(|block| block.trim().to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_base_path() {
+        let write = |base: &str| endpoint(base, "write").unwrap().to_string();
+        assert_eq!(write("http://localhost:8086"), "http://localhost:8086/write");
+        assert_eq!(write("http://proxy/influx"), "http://proxy/influx/write");
+        assert_eq!(write("http://proxy/influx/"), "http://proxy/influx/write");
+    }
+}
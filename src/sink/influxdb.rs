@@ -0,0 +1,867 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use influxdb2::api::buckets::ListBucketsRequest;
+use influxdb2::api::organization::ListOrganizationRequest;
+use influxdb2::models::data_point::DataPointBuilder;
+use influxdb2::models::health::Status;
+use influxdb2::models::{DataPoint, FieldValue, PostBucketRequest, Query, WriteDataPoint};
+use influxdb2::{Client, ClientBuilder, RequestError};
+use reqwest::StatusCode;
+use tracing::{debug, error, info, warn};
+
+use crate::config::{hostname, InfluxDBConfig, InfluxSchema, TlsConfig};
+use crate::histogram::Histogram;
+use crate::instance::{Claim, Instance};
+use crate::rapl::PowerMetrics;
+use crate::stats::Summary;
+use crate::system_info::SystemInfo;
+use crate::sink::buffer::RetryBuffer;
+use crate::sink::deadband::Deadband;
+use crate::sink::filter::MetricFilter;
+use crate::sink::http;
+use crate::sink::{MetricSink, SinkError};
+use crate::units::Units;
+
+// How long startup waits for InfluxDB to confirm the bucket.
+const PREPARE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Where the token comes from, kept so a file or command can be read again.
+enum TokenSource {
+    Inline(String),
+    File(String),
+    Command(String),
+}
+
+impl TokenSource {
+    fn from_config(config: &InfluxDBConfig) -> Self {
+        match (&config.token_file, &config.token_command) {
+            (Some(path), _) => TokenSource::File(path.clone()),
+            (None, Some(command)) => TokenSource::Command(command.clone()),
+            (None, None) => TokenSource::Inline(config.token.clone()),
+        }
+    }
+
+    fn read(&self) -> Result<String, SinkError> {
+        let token = match self {
+            TokenSource::Inline(token) => token.clone(),
+            TokenSource::File(path) => {
+                fs::read_to_string(path).map_err(|e| format!("failed to read token_file {}: {}", path, e))?
+            }
+            TokenSource::Command(command) => {
+                let output = Command::new("sh")
+                    .args(["-c", command])
+                    .output()
+                    .map_err(|e| format!("failed to run token_command: {}", e))?;
+                if !output.status.success() {
+                    return Err(format!("token_command failed with {}", output.status).into());
+                }
+                String::from_utf8(output.stdout)?
+            }
+        };
+        let token = token.trim().to_string();
+        if token.is_empty() {
+            return Err("the InfluxDB token is empty".into());
+        }
+        Ok(token)
+    }
+}
+
+// The client is built once so its HTTP connection pool is kept alive across writes.
+pub struct InfluxDbSink {
+    name: String,
+    client: Client,
+    host: String,
+    tls: TlsConfig,
+    proxy: Option<String>,
+    token_source: TokenSource,
+    token: String,
+    org: String,
+    bucket: String,
+    check_bucket: bool,
+    create_bucket: bool,
+    per_core: bool,
+    tags: BTreeMap<String, String>,
+    schema: InfluxSchema,
+    deadband: Option<Deadband>,
+    buffer: RetryBuffer,
+    batch_size: usize,
+    flush_interval: Duration,
+    last_flush: Instant,
+    filter: Option<MetricFilter>,
+    claims: Option<ClaimSchedule>,
+}
+
+// When this installation next claims its host tag, with [instance].
+struct ClaimSchedule {
+    instance: &'static Instance,
+    interval: Duration,
+    due: Instant,
+}
+
+impl InfluxDbSink {
+    pub fn new(config: InfluxDBConfig, tags: BTreeMap<String, String>) -> Result<Self, SinkError> {
+        let token_source = TokenSource::from_config(&config);
+        let token = token_source.read()?;
+        let name = config.name.map_or_else(|| "influxdb".to_string(), |name| format!("influxdb:{}", name));
+        let buffer = RetryBuffer::new(
+            &name,
+            config.max_buffered_points,
+            config.buffer_path.map(PathBuf::from),
+            Duration::from_secs(config.max_retry_secs),
+        );
+        Ok(InfluxDbSink {
+            name,
+            client: connect(&config.host, &config.org, &token, &config.tls, config.proxy.as_deref())?,
+            host: config.host,
+            tls: config.tls,
+            proxy: config.proxy,
+            token_source,
+            token,
+            org: config.org,
+            bucket: config.bucket,
+            check_bucket: config.check_bucket,
+            create_bucket: config.create_bucket,
+            per_core: config.per_core,
+            tags,
+            schema: config.schema,
+            deadband: config.deadband.map(Deadband::new),
+            buffer,
+            batch_size: config.batch_size,
+            flush_interval: Duration::from_secs(config.flush_interval_secs),
+            last_flush: Instant::now(),
+            filter: None,
+            claims: None,
+        })
+    }
+
+    // Claim the host tag every `interval`, warning about other machines
+    // that claim it too.
+    pub fn claim_host(&mut self, instance: &'static Instance, interval: Duration) {
+        self.claims = Some(ClaimSchedule {
+            instance,
+            interval,
+            due: Instant::now(),
+        });
+    }
+
+    fn batch_due(&self) -> bool {
+        self.buffer.len() >= self.batch_size || self.last_flush.elapsed() >= self.flush_interval
+    }
+
+    // For `check-config`: the server is healthy and the token can see the
+    // bucket. Returns the server version.
+    pub async fn check(&self) -> Result<String, SinkError> {
+        let health = self.client.health().await?;
+        if health.status != Status::Pass {
+            return Err(format!("unhealthy: {}", health.message.unwrap_or_default()).into());
+        }
+        let version = health.version.unwrap_or_else(|| "unknown version".to_string());
+        self.org_id().await?;
+        if !self.bucket_exists().await? {
+            if self.create_bucket {
+                return Ok(format!("{}, bucket {:?} will be created at startup", version, self.bucket));
+            }
+            return Err(self.missing_bucket().into());
+        }
+        Ok(version)
+    }
+
+    async fn org_id(&self) -> Result<String, SinkError> {
+        let request = ListOrganizationRequest {
+            org: Some(self.org.clone()),
+            ..Default::default()
+        };
+        let orgs = self.client.list_organizations(request).await?.orgs;
+        match orgs.into_iter().find(|org| org.name == self.org).and_then(|org| org.id) {
+            Some(id) => Ok(id),
+            None => Err(format!(
+                "org {:?} not found or not visible to the token; set check_bucket = false for a write-only token",
+                self.org
+            )
+            .into()),
+        }
+    }
+
+    async fn bucket_exists(&self) -> Result<bool, RequestError> {
+        let request = ListBucketsRequest {
+            name: Some(self.bucket.clone()),
+            org: Some(self.org.clone()),
+            ..Default::default()
+        };
+        Ok(!self.client.list_buckets(Some(request)).await?.buckets.is_empty())
+    }
+
+    fn missing_bucket(&self) -> String {
+        format!(
+            "bucket {:?} not found in org {:?} or not visible to the token; create it, set create_bucket = true, or set check_bucket = false for a write-only token",
+            self.bucket, self.org
+        )
+    }
+
+    // The org and bucket exist, creating the bucket with create_bucket.
+    async fn ensure_bucket(&self) -> Result<(), SinkError> {
+        let org_id = self.org_id().await?;
+        if self.bucket_exists().await? {
+            return Ok(());
+        }
+        if !self.create_bucket {
+            return Err(self.missing_bucket().into());
+        }
+        match self.client.create_bucket(Some(PostBucketRequest::new(org_id, self.bucket.clone()))).await {
+            Ok(()) => {
+                info!("{}: created bucket {:?} in org {:?}", self.name, self.bucket, self.org);
+                Ok(())
+            }
+            // It exists after all, just not where the token can read it.
+            Err(RequestError::Http { status, .. }) if status == StatusCode::UNPROCESSABLE_ENTITY => Ok(()),
+            Err(RequestError::Http { status, .. }) if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN => {
+                Err(format!("bucket {:?} not found and the token may not create buckets", self.bucket).into())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Look for claims on the host tag by other machines over the last two
+    // intervals, then queue this one's. The claim is a ryzenmon_claim point
+    // outside the schema, tagged with the host tag, the instance id and the
+    // real hostname, with the boot id as its only field.
+    async fn claim(&mut self) {
+        let Some(claims) = &mut self.claims else {
+            return;
+        };
+        if Instant::now() < claims.due {
+            return;
+        }
+        claims.due = Instant::now() + claims.interval;
+        let (instance, window) = (claims.instance, claims.interval * 2);
+        let host = self.tags.get("host").cloned().unwrap_or_default();
+
+        match tokio::time::timeout(PREPARE_TIMEOUT, self.read_claims(&host, window)).await {
+            Ok(Ok(found)) => {
+                for claim in found.iter().filter(|claim| instance.conflicts_with(claim)) {
+                    error!(
+                        "{}: another machine writes as host {:?}: instance {} on {} (boot {}), this is instance {} on {}. \
+                         Their series are interleaved; give each machine its own host tag",
+                        self.name,
+                        host,
+                        claim.instance,
+                        claim.hostname,
+                        claim.boot_id,
+                        instance.id,
+                        hostname()
+                    );
+                }
+            }
+            Ok(Err(e)) if e.downcast_ref::<RequestError>().is_some_and(|e| {
+                matches!(e, RequestError::Http { status, .. } if *status == StatusCode::UNAUTHORIZED || *status == StatusCode::FORBIDDEN)
+            }) => {
+                warn!(
+                    "{}: the token may not read bucket {:?}, not claiming host {:?}; set instance.claim = false for a write-only token",
+                    self.name, self.bucket, host
+                );
+                self.claims = None;
+                return;
+            }
+            Ok(Err(e)) => debug!("{}: could not read claims on host {:?}: {}", self.name, host, e),
+            Err(_) => debug!("{}: could not read claims on host {:?}, no answer within {:?}", self.name, host, PREPARE_TIMEOUT),
+        }
+
+        match build_claim_point(&host, instance).and_then(|point| to_line_protocol(&[point])) {
+            Ok(lines) => self.buffer.push(lines),
+            Err(e) => warn!("{}: {}", self.name, e),
+        }
+    }
+
+    async fn read_claims(&self, host: &str, window: Duration) -> Result<Vec<Claim>, SinkError> {
+        let flux = format!(
+            "from(bucket: \"{}\")\n\
+             |> range(start: -{}s)\n\
+             |> filter(fn: (r) => r._measurement == \"ryzenmon_claim\" and r._field == \"boot_id\" and r.host == \"{}\")\n\
+             |> last()\n\
+             |> map(fn: (r) => ({{r with seen: int(v: r._time)}}))",
+            flux_string(&self.bucket),
+            window.as_secs(),
+            flux_string(host)
+        );
+        let records = self.client.query_raw(Some(Query::new(flux))).await?;
+        let text = |record: &influxdb2::api::query::FluxRecord, key: &str| {
+            record.values.get(key).and_then(|value| value.string()).unwrap_or_default()
+        };
+        Ok(records
+            .iter()
+            .map(|record| Claim {
+                instance: text(record, "instance"),
+                boot_id: text(record, "_value"),
+                hostname: text(record, "hostname"),
+                seen: UNIX_EPOCH
+                    + Duration::from_nanos(record.values.get("seen").and_then(|value| value.i64()).unwrap_or(0).max(0) as u64),
+            })
+            .collect())
+    }
+
+    // Read the token again after InfluxDB rejected it and reconnect if it
+    // changed. False when there is nothing new to try.
+    fn refresh_token(&mut self) -> bool {
+        if matches!(self.token_source, TokenSource::Inline(_)) {
+            return false;
+        }
+        let token = match self.token_source.read() {
+            Ok(token) if token != self.token => token,
+            Ok(_) => return false,
+            Err(e) => {
+                warn!("{}: {}", self.name, e);
+                return false;
+            }
+        };
+        match connect(&self.host, &self.org, &token, &self.tls, self.proxy.as_deref()) {
+            Ok(client) => {
+                info!("{}: token was rejected, retrying with the token read again", self.name);
+                self.client = client;
+                self.token = token;
+                true
+            }
+            Err(e) => {
+                warn!("{}: {}", self.name, e);
+                false
+            }
+        }
+    }
+}
+
+fn connect(host: &str, org: &str, token: &str, tls: &TlsConfig, proxy: Option<&str>) -> Result<Client, SinkError> {
+    Ok(ClientBuilder::with_builder(http::client_builder(tls, proxy)?, host, org, token).build()?)
+}
+
+#[async_trait]
+impl MetricSink for InfluxDbSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    // New points join the pending buffer, which is written as a whole once a
+    // batch is due and any backoff from an earlier failure has elapsed.
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        self.claim().await;
+        let points = build_points(metrics, self.per_core, &self.tags, &self.schema)?;
+        let mut lines = to_line_protocol(&points)?;
+        if let Some(filter) = &self.filter {
+            lines = filter.filter_lines(lines);
+        }
+        if let Some(deadband) = &mut self.deadband {
+            lines = deadband.filter(lines);
+        }
+        self.buffer.push(lines);
+
+        if self.buffer.is_empty() || !self.batch_due() || !self.buffer.ready() {
+            return Ok(());
+        }
+        self.send().await
+    }
+
+    // Stops startup when InfluxDB answers that the org or bucket is missing.
+    // A server that doesn't answer is left to the retry buffer.
+    async fn prepare(&mut self) -> Result<(), SinkError> {
+        if !self.check_bucket {
+            return Ok(());
+        }
+        match tokio::time::timeout(PREPARE_TIMEOUT, self.ensure_bucket()).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) if e.downcast_ref::<RequestError>().is_some_and(|e| matches!(e, RequestError::ReqwestProcessing { .. })) => {
+                warn!("{}: could not check bucket {:?}: {}", self.name, self.bucket, e);
+                Ok(())
+            }
+            Ok(Err(e)) => Err(e),
+            Err(_) => {
+                warn!("{}: could not check bucket {:?}, no answer within {:?}", self.name, self.bucket, PREPARE_TIMEOUT);
+                Ok(())
+            }
+        }
+    }
+
+    // Goes out with the first batch.
+    async fn write_info(&mut self, info: &SystemInfo) -> Result<(), SinkError> {
+        let point = build_info_point(info, &self.tags, &self.schema)?;
+        self.buffer.push(to_line_protocol(&[point])?);
+        Ok(())
+    }
+
+    // Ignores batching and backoff, so pending points get one last chance.
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.send().await
+    }
+    fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn retry_buffer(&mut self) -> Option<&mut RetryBuffer> {
+        Some(&mut self.buffer)
+    }
+
+    fn set_filter(&mut self, filter: MetricFilter) -> Result<(), SinkError> {
+        self.filter = Some(filter);
+        Ok(())
+    }
+
+    fn set_units(&mut self, _units: &Units) -> bool {
+        true
+    }
+}
+
+impl InfluxDbSink {
+    async fn send(&mut self) -> Result<(), SinkError> {
+        self.last_flush = Instant::now();
+
+        let buffered = self.buffer.len();
+        let mut result = self.client.write_line_protocol(&self.org, &self.bucket, self.buffer.body()).await;
+        let rejected = matches!(
+            &result,
+            Err(RequestError::Http { status, .. }) if *status == StatusCode::UNAUTHORIZED || *status == StatusCode::FORBIDDEN
+        );
+        if rejected && self.refresh_token() {
+            result = self.client.write_line_protocol(&self.org, &self.bucket, self.buffer.body()).await;
+        }
+        match result {
+            Ok(()) => {
+                self.buffer.succeeded();
+                Ok(())
+            }
+            Err(e) => {
+                let backoff = self.buffer.failed();
+                Err(format!("{} ({} points buffered, retrying in {:.1?})", e, buffered, backoff).into())
+            }
+        }
+    }
+}
+
+// A data point under the measurement and field names from the schema, which
+// maps the default names to the ones an existing setup expects.
+struct Point<'a> {
+    builder: DataPointBuilder,
+    fields: Option<&'a BTreeMap<String, String>>,
+}
+
+impl<'a> Point<'a> {
+    fn new(measurement: &str, schema: &'a InfluxSchema, tags: &BTreeMap<String, String>, timestamp: i64) -> Self {
+        let overrides = schema.get(measurement);
+        let measurement = overrides.and_then(|o| o.measurement.as_deref()).unwrap_or(measurement);
+        Point {
+            builder: tags
+                .iter()
+                .fold(DataPoint::builder(measurement), |point, (key, value)| point.tag(key, value))
+                .timestamp(timestamp),
+            fields: overrides.map(|o| &o.fields),
+        }
+    }
+
+    fn tag(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.builder = self.builder.tag(key, value);
+        self
+    }
+
+    fn field_name<'b>(&self, name: &'b str) -> &'b str
+    where
+        'a: 'b,
+    {
+        self.fields.and_then(|fields| fields.get(name)).map_or(name, String::as_str)
+    }
+
+    fn field(mut self, name: &str, value: impl Into<FieldValue>) -> Self {
+        let name = self.field_name(name).to_string();
+        self.builder = self.builder.field(name, value);
+        self
+    }
+
+    fn build(self) -> Result<DataPoint, SinkError> {
+        Ok(self.builder.build()?)
+    }
+}
+
+// `<field>-min`, `-max` and `-p95` next to the field, which holds the mean.
+// They follow a renamed field.
+fn with_stats<'a>(point: Point<'a>, field: &str, summary: Option<&Summary>) -> Point<'a> {
+    let Some(summary) = summary else {
+        return point;
+    };
+    let field = point.field_name(field).to_string();
+    summary
+        .iter()
+        .into_iter()
+        .fold(point, |point, (stat, value)| point.field(&format!("{}-{}", field, stat), value))
+}
+
+// `core` with its NUMA node and L3 where known, so queries can group cores
+// by locality.
+fn with_core<'a>(point: Point<'a>, metrics: &PowerMetrics, core: usize) -> Point<'a> {
+    metrics.core_tags(core).into_iter().fold(point, |point, (key, value)| point.tag(key, value))
+}
+
+// `le-<bound>` with the samples of the interval at or below each bound,
+// `le-inf` with all of them, then `sum` and `count`, as Prometheus has it.
+fn with_buckets<'a>(point: Point<'a>, histogram: &Histogram) -> Point<'a> {
+    let bounds = histogram.bounds.iter().map(|bound| format!("le-{}", bound)).chain(std::iter::once("le-inf".to_string()));
+    bounds
+        .zip(histogram.interval.cumulative())
+        .fold(point, |point, (field, count)| point.field(&field, count as i64))
+        .field("sum", histogram.interval.sum)
+        .field("count", histogram.interval.count() as i64)
+}
+
+pub fn build_points(
+    metrics: &PowerMetrics,
+    per_core: bool,
+    tags: &BTreeMap<String, String>,
+    schema: &InfluxSchema,
+) -> Result<Vec<DataPoint>, SinkError> {
+    // Points may be batched or retried, so they carry the time they were sampled at.
+    let timestamp = metrics
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as i64)
+        .unwrap_or(0);
+    // Samples relayed by an aggregator keep the tags of the host that took them.
+    let tags: BTreeMap<String, String> = tags.clone().into_iter().chain(metrics.tags.clone()).collect();
+    let builder = |measurement: &str| Point::new(measurement, schema, &tags, timestamp);
+    let power = || builder("power");
+
+    let stats = metrics.stats.as_ref();
+    let mut points = vec![
+        with_stats(power().field("core-power", metrics.core_sum), "core-power", stats.map(|s| &s.core_sum)).build()?,
+        with_stats(power().field("package-power", metrics.package_watts), "package-power", stats.map(|s| &s.package))
+            .build()?,
+    ];
+
+    if metrics.packages.len() > 1 {
+        for package in &metrics.packages {
+            points.push(
+                power()
+                    .tag("package", package.package.to_string())
+                    .field("package-power", package.watts)
+                    .build()?,
+            );
+        }
+    }
+
+    if let Some(dram_watts) = metrics.dram_watts {
+        let point = power().field("dram-power", dram_watts);
+        points.push(with_stats(point, "dram-power", stats.and_then(|s| s.dram.as_ref())).build()?);
+    }
+    if let Some(soc_watts) = metrics.soc_watts {
+        points.push(power().field("soc-power", soc_watts).build()?);
+    }
+    if let Some(idle_floor_watts) = metrics.idle_floor_watts {
+        points.push(power().field("idle-floor-power", idle_floor_watts).build()?);
+    }
+
+    if let Some(utilization) = metrics.utilization {
+        points.push(power().field("utilization", utilization).build()?);
+    }
+    if !metrics.skipped_cores.is_empty() {
+        points.push(power().field("skipped-cores", metrics.skipped_cores.len() as i64).build()?);
+    }
+
+    for process in &metrics.processes {
+        points.push(
+            builder("process")
+                .tag("process", &process.name)
+                .field("power", process.watts)
+                .build()?,
+        );
+    }
+    for cgroup in &metrics.cgroups {
+        points.push(
+            builder("cgroup")
+                .tag("cgroup", &cgroup.cgroup)
+                .field("power", cgroup.watts)
+                .build()?,
+        );
+    }
+
+    if per_core {
+        for (core, watts) in metrics.core_watts.iter().enumerate() {
+            // Utilization goes on the same point so load and watts line up per core.
+            let mut point = with_core(power(), metrics, core);
+            if let Some(cpus) = metrics.core_cpu_list(core) {
+                point = point.tag("cpus", cpus);
+            }
+            point = point.field("core-power", *watts);
+            point = with_stats(point, "core-power", stats.and_then(|s| s.cores.get(core)));
+            if let Some(utilization) = metrics.core_utilization.get(core) {
+                point = point.field("utilization", *utilization);
+            }
+            points.push(point.build()?);
+        }
+    }
+
+    let frequency = || builder("frequency");
+    if let Some(mhz) = metrics.average_mhz {
+        points.push(frequency().field("package-frequency", mhz).build()?);
+    }
+    if let Some(activity) = metrics.mean_activity() {
+        points.push(
+            frequency()
+                .field("effective-frequency", activity.effective_mhz)
+                .field("busy", activity.busy_percent)
+                .build()?,
+        );
+    }
+    if per_core {
+        for (core, mhz) in metrics.core_mhz.iter().enumerate() {
+            points.push(
+                with_core(frequency(), metrics, core)
+                    .field("core-frequency", *mhz)
+                    .build()?,
+            );
+        }
+        for (core, activity) in metrics.core_activity.iter().enumerate() {
+            points.push(
+                with_core(frequency(), metrics, core)
+                    .field("effective-frequency", activity.effective_mhz)
+                    .field("busy", activity.busy_percent)
+                    .build()?,
+            );
+        }
+    }
+
+    let cstate = |state: &str| builder("cstate").tag("state", state);
+    for residency in &metrics.cstates {
+        points.push(cstate(&residency.state).field("residency", residency.percent).build()?);
+        if per_core {
+            for (core, percent) in residency.cores.iter().enumerate() {
+                points.push(
+                    with_core(cstate(&residency.state), metrics, core)
+                        .field("residency", *percent)
+                        .build()?,
+                );
+            }
+        }
+    }
+
+    for ccd in &metrics.ccds {
+        let mut point = builder("ccd")
+            .tag("ccd", ccd.ccd.to_string())
+            .field("power", ccd.watts);
+        if let Some(celsius) = ccd.celsius {
+            point = point.field("temperature", celsius);
+        }
+        points.push(point.build()?);
+    }
+
+    for temperature in &metrics.temperatures {
+        points.push(
+            builder("temperature")
+                .tag("sensor", &temperature.label)
+                .field("temperature", temperature.celsius)
+                .build()?,
+        );
+    }
+
+    for gpu in &metrics.gpus {
+        let gpu_point = |measurement: &str| builder(measurement).tag("gpu", gpu.gpu.to_string());
+        if gpu.watts.is_some() || gpu.fan_rpm.is_some() {
+            let mut point = gpu_point("gpu");
+            if let Some(watts) = gpu.watts {
+                point = point.field("power", watts);
+            }
+            if let Some(rpm) = gpu.fan_rpm {
+                point = point.field("fan-speed", rpm);
+            }
+            points.push(point.build()?);
+        }
+        for temperature in &gpu.temperatures {
+            points.push(
+                gpu_point("temperature")
+                    .tag("sensor", &temperature.label)
+                    .field("temperature", temperature.celsius)
+                    .build()?,
+            );
+        }
+    }
+
+    for sensor in &metrics.sensors {
+        points.push(
+            builder("hwmon")
+                .tag("sensor", &sensor.label)
+                .field("value", sensor.value)
+                .build()?,
+        );
+    }
+
+    for rail in &metrics.rails {
+        let mut point = builder("rail")
+            .tag("rail", &rail.rail);
+        if let Some(volts) = rail.volts {
+            point = point.field("voltage", volts);
+        }
+        if let Some(amps) = rail.amps {
+            point = point.field("current", amps);
+        }
+        // A point needs at least one field.
+        if rail.volts.is_some() || rail.amps.is_some() {
+            points.push(point.build()?);
+        }
+    }
+
+    if let Some(limits) = &metrics.limits {
+        for (name, limit, _) in limits.iter() {
+            points.push(
+                builder("limits")
+                    .tag("limit", name)
+                    .field("value", limit.value)
+                    .field("limit", limit.limit)
+                    .field("usage", limit.usage())
+                    .build()?,
+            );
+        }
+    }
+
+    if let Some(throttle) = &metrics.throttle {
+        for (reason, active) in throttle.iter() {
+            let mut point = builder("throttle").tag("reason", reason.name()).field("active", active);
+            if let Some(residency) = throttle.residency.get(&reason) {
+                point = point.field("residency", *residency);
+            }
+            points.push(point.build()?);
+        }
+        // Each at the time it started
+        for event in &throttle.events {
+            let started = event.started.duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as i64).unwrap_or(0);
+            points.push(
+                Point::new("throttle-event", schema, &tags, started)
+                    .tag("reason", event.reason.name())
+                    .field("duration", event.duration_secs)
+                    .build()?,
+            );
+        }
+    }
+
+    if let Some(histograms) = &metrics.histograms {
+        points.push(with_buckets(builder("power-histogram").tag("series", "package"), &histograms.package).build()?);
+        if per_core {
+            for (core, histogram) in histograms.cores.iter().enumerate() {
+                let point = with_core(builder("power-histogram").tag("series", "core"), metrics, core);
+                points.push(with_buckets(point, histogram).build()?);
+            }
+        }
+    }
+
+    // At the time of the resume, with how long the system was suspended
+    if let Some(resume) = &metrics.resume {
+        let at = resume.at.duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as i64).unwrap_or(0);
+        points.push(Point::new("resume", schema, &tags, at).field("suspended", resume.suspended_secs).build()?);
+    }
+
+    if let Some(energy) = &metrics.energy {
+        let point = builder("energy")
+            .field("joules", energy.joules)
+            .field("kwh", energy.kwh);
+        points.push(match energy.cost {
+            Some(cost) => point.field("cost", cost).build()?,
+            None => point.build()?,
+        });
+    }
+
+    if let Some(telemetry) = &metrics.self_telemetry {
+        let point = builder("ryzenmon");
+        points.push(
+            telemetry
+                .iter()
+                .into_iter()
+                .fold(point, |point, (name, value, _)| point.field(name, value))
+                .build()?,
+        );
+    }
+
+    Ok(points)
+}
+
+// One ryzenmon_info point with every fact that could be read as a field,
+// stamped with the current time.
+pub fn build_info_point(
+    info: &SystemInfo,
+    tags: &BTreeMap<String, String>,
+    schema: &InfluxSchema,
+) -> Result<DataPoint, SinkError> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as i64).unwrap_or(0);
+    let mut point = Point::new("ryzenmon_info", schema, tags, timestamp).field("version", info.version.clone());
+    for (field, value) in [("cpu_model", &info.cpu_model), ("microcode", &info.microcode), ("kernel", &info.kernel)] {
+        if let Some(value) = value {
+            point = point.field(field, value.clone());
+        }
+    }
+    for (field, count) in [("cores", info.cores), ("threads", info.threads)] {
+        if let Some(count) = count {
+            point = point.field(field, count as i64);
+        }
+    }
+    point.build()
+}
+
+// This installation's claim on `host`, stamped with the current time.
+pub fn build_claim_point(host: &str, instance: &Instance) -> Result<DataPoint, SinkError> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as i64).unwrap_or(0);
+    Ok(DataPoint::builder("ryzenmon_claim")
+        .tag("host", host)
+        .tag("instance", instance.id.clone())
+        .tag("hostname", hostname())
+        .field("boot_id", instance.boot_id.clone())
+        .timestamp(timestamp)
+        .build()?)
+}
+
+// `value` inside a Flux string literal.
+fn flux_string(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+pub fn to_line_protocol(points: &[DataPoint]) -> Result<Vec<String>, SinkError> {
+    let mut lines = Vec::with_capacity(points.len());
+    for point in points {
+        let mut line = Vec::new();
+        point.write_data_point_to(&mut line)?;
+        lines.push(String::from_utf8(line)?.trim_end().to_string());
+    }
+    Ok(lines)
+}
+
+// `<series> <fields> <timestamp>` of a line of line protocol, with the fields
+// split apart.
+pub(crate) fn split_line(line: &str) -> Option<(&str, Vec<&str>, &str)> {
+    let (series, rest) = split_once_unescaped(line, ' ', false)?;
+    let (fields, timestamp) = rest.rsplit_once(' ')?;
+    Some((series, split_unescaped(fields, ',', true), timestamp))
+}
+
+pub(crate) fn split_once_unescaped(text: &str, separator: char, quoted: bool) -> Option<(&str, &str)> {
+    let parts = split_unescaped(text, separator, quoted);
+    let first = parts.first()?;
+    (parts.len() > 1).then(|| (*first, &text[first.len() + 1..]))
+}
+
+// Splits at `separator` where it is neither escaped with a backslash nor,
+// with `quoted`, inside a double-quoted string field.
+pub(crate) fn split_unescaped(text: &str, separator: char, quoted: bool) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut escaped, mut in_string) = (0, false, false);
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' if quoted => in_string = !in_string,
+            _ if c == separator && !in_string => {
+                parts.push(&text[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
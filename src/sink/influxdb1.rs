@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::config::{InfluxDB1Config, InfluxSchema};
+use crate::rapl::PowerMetrics;
+use crate::system_info::SystemInfo;
+use crate::sink::buffer::RetryBuffer;
+use crate::sink::deadband::Deadband;
+use crate::sink::filter::MetricFilter;
+use crate::sink::http;
+use crate::sink::influxdb::{build_info_point, build_points, to_line_protocol};
+use crate::sink::{MetricSink, SinkError};
+use crate::units::Units;
+
+// InfluxDB 1.x sink. The line protocol is the same as for 2.x, only the
+// endpoint and authentication differ.
+pub struct InfluxDb1Sink {
+    client: reqwest::Client,
+    url: reqwest::Url,
+    username: Option<String>,
+    password: Option<String>,
+    per_core: bool,
+    tags: BTreeMap<String, String>,
+    schema: InfluxSchema,
+    deadband: Option<Deadband>,
+    buffer: RetryBuffer,
+    filter: Option<MetricFilter>,
+}
+
+impl InfluxDb1Sink {
+    pub fn new(config: InfluxDB1Config, tags: BTreeMap<String, String>) -> Result<Self, SinkError> {
+        let mut url = http::endpoint(&config.host, "write")?;
+        url.query_pairs_mut()
+            .append_pair("db", &config.database)
+            .append_pair("precision", "ns");
+        if let Some(retention_policy) = &config.retention_policy {
+            url.query_pairs_mut().append_pair("rp", retention_policy);
+        }
+
+        Ok(InfluxDb1Sink {
+            client: http::client_builder(&config.tls, config.proxy.as_deref())?.build()?,
+            url,
+            username: config.username,
+            password: config.password,
+            per_core: config.per_core,
+            tags,
+            schema: config.schema,
+            deadband: config.deadband.map(Deadband::new),
+            buffer: RetryBuffer::new(
+                "influxdb1",
+                config.max_buffered_points,
+                None,
+                Duration::from_secs(config.max_retry_secs),
+            ),
+            filter: None,
+        })
+    }
+
+    // For `check-config`: the server answers /ping. Returns the server version.
+    pub async fn check(&self) -> Result<String, SinkError> {
+        let mut url = self.url.join("ping")?;
+        url.set_query(None);
+        let response = self.client.get(url).send().await?;
+        if !response.status().is_success() {
+            return Err(format!("ping returned {}", response.status()).into());
+        }
+        Ok(response
+            .headers()
+            .get("X-Influxdb-Version")
+            .and_then(|version| version.to_str().ok())
+            .unwrap_or("unknown version")
+            .to_string())
+    }
+
+    async fn send(&mut self) -> Result<(), SinkError> {
+        let mut request = self.client.post(self.url.clone()).body(self.buffer.body());
+        if let Some(username) = &self.username {
+            request = request.basic_auth(username, self.password.as_ref());
+        }
+
+        let buffered = self.buffer.len();
+        let result = match request.send().await {
+            Ok(response) if response.status().is_success() => Ok(()),
+            Ok(response) => {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                Err(format!("{}: {}", status, body.trim()))
+            }
+            Err(e) => Err(e.to_string()),
+        };
+
+        match result {
+            Ok(()) => {
+                self.buffer.succeeded();
+                Ok(())
+            }
+            Err(e) => {
+                let backoff = self.buffer.failed();
+                Err(format!("{} ({} points buffered, retrying in {:.1?})", e, buffered, backoff).into())
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl MetricSink for InfluxDb1Sink {
+    fn name(&self) -> &str {
+        "influxdb1"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        let points = build_points(metrics, self.per_core, &self.tags, &self.schema)?;
+        let mut lines = to_line_protocol(&points)?;
+        if let Some(filter) = &self.filter {
+            lines = filter.filter_lines(lines);
+        }
+        if let Some(deadband) = &mut self.deadband {
+            lines = deadband.filter(lines);
+        }
+        self.buffer.push(lines);
+
+        if self.buffer.is_empty() || !self.buffer.ready() {
+            return Ok(());
+        }
+        self.send().await
+    }
+
+    // Goes out with the first sample.
+    async fn write_info(&mut self, info: &SystemInfo) -> Result<(), SinkError> {
+        let point = build_info_point(info, &self.tags, &self.schema)?;
+        self.buffer.push(to_line_protocol(&[point])?);
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.send().await
+    }
+    fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn retry_buffer(&mut self) -> Option<&mut RetryBuffer> {
+        Some(&mut self.buffer)
+    }
+
+    fn set_filter(&mut self, filter: MetricFilter) -> Result<(), SinkError> {
+        self.filter = Some(filter);
+        Ok(())
+    }
+
+    fn set_units(&mut self, _units: &Units) -> bool {
+        true
+    }
+}
@@ -0,0 +1,138 @@
+use std::time::UNIX_EPOCH;
+
+use async_trait::async_trait;
+use tokio::net::UnixDatagram;
+
+use crate::config::JournaldConfig;
+use crate::rapl::PowerMetrics;
+use crate::sink::filter::{filtered_gauges, MetricFilter};
+use crate::sink::{MetricSink, SinkError};
+use crate::units::Units;
+
+// Marks every sample, so `journalctl MESSAGE_ID=...` finds them among log lines.
+pub const SAMPLE_MESSAGE_ID: &str = "8d3f6a1c52e04b7e9a0c4f2b7d61e935";
+
+// Journal field names are at most 64 characters.
+const MAX_FIELD_NAME: usize = 64;
+
+// Writes every sample to the journal as one entry of structured fields,
+// `RYZENMON_PACKAGE_POWER=54.2`, `RYZENMON_CORE3_POWER=2.1` and so on, named
+// like the StatsD gauges. Sent straight to journald's socket with its native
+// protocol, so a machine with nothing but systemd keeps a metrics history:
+// `journalctl -o json MESSAGE_ID=<SAMPLE_MESSAGE_ID>`.
+pub struct JournaldSink {
+    socket_path: String,
+    identifier: String,
+    priority: u8,
+    socket: Option<UnixDatagram>,
+    filter: Option<MetricFilter>,
+}
+
+impl JournaldSink {
+    pub fn new(config: JournaldConfig) -> Self {
+        JournaldSink {
+            socket_path: config.socket,
+            identifier: config.identifier,
+            priority: config.priority,
+            socket: None,
+            filter: None,
+        }
+    }
+}
+
+#[async_trait]
+impl MetricSink for JournaldSink {
+    fn name(&self) -> &str {
+        "journald"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        let mut entry = Vec::new();
+        append_field(
+            &mut entry,
+            "MESSAGE",
+            &format!("Package {:.1} W, cores {:.1} W", metrics.package_watts, metrics.core_sum),
+        );
+        append_field(&mut entry, "MESSAGE_ID", SAMPLE_MESSAGE_ID);
+        append_field(&mut entry, "PRIORITY", &self.priority.to_string());
+        append_field(&mut entry, "SYSLOG_IDENTIFIER", &self.identifier);
+        // journald stamps entries when they arrive; an aggregate is stamped
+        // with the end of its interval instead.
+        let timestamp = metrics.timestamp.duration_since(UNIX_EPOCH)?;
+        append_field(&mut entry, "RYZENMON_TIMESTAMP_USEC", &timestamp.as_micros().to_string());
+        for (metric, value) in filtered_gauges(self.filter.as_ref(), metrics) {
+            append_field(&mut entry, &field_name(&metric), &value.to_string());
+        }
+
+        if self.socket.is_none() {
+            self.socket = Some(UnixDatagram::unbound()?);
+        }
+        if let Some(socket) = &self.socket {
+            socket
+                .send_to(&entry, &self.socket_path)
+                .await
+                .map_err(|e| format!("cannot write to the journal at {}: {}", self.socket_path, e))?;
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self) {
+        self.socket = None;
+    }
+
+    fn set_filter(&mut self, filter: MetricFilter) -> Result<(), SinkError> {
+        self.filter = Some(filter);
+        Ok(())
+    }
+
+    fn set_units(&mut self, _units: &Units) -> bool {
+        true
+    }
+}
+
+// `core3.power` as `RYZENMON_CORE3_POWER`: journal fields are upper case
+// letters, digits and underscores. Self-telemetry such as
+// `ryzenmon_resumes` keeps a single prefix.
+pub fn field_name(metric: &str) -> String {
+    let metric = metric.strip_prefix("ryzenmon_").unwrap_or(metric);
+    let name: String = format!("RYZENMON_{}", metric)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    name.chars().take(MAX_FIELD_NAME).collect()
+}
+
+// `NAME=value` and a newline, or for values with a newline in them, the name,
+// a newline, the value's length as a little endian u64, the value and a newline.
+fn append_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_journal_fields() {
+        assert_eq!(field_name("core3.power"), "RYZENMON_CORE3_POWER");
+        assert_eq!(field_name("gpu0.vram-temp"), "RYZENMON_GPU0_VRAM_TEMP");
+        assert_eq!(field_name("ryzenmon_resumes"), "RYZENMON_RESUMES");
+        assert_eq!(field_name(&"x".repeat(100)).len(), MAX_FIELD_NAME);
+
+        let mut entry = Vec::new();
+        append_field(&mut entry, "RYZENMON_PACKAGE_POWER", "54.2");
+        append_field(&mut entry, "MESSAGE", "two\nlines");
+        let mut expected = b"RYZENMON_PACKAGE_POWER=54.2\nMESSAGE\n".to_vec();
+        expected.extend_from_slice(&9u64.to_le_bytes());
+        expected.extend_from_slice(b"two\nlines\n");
+        assert_eq!(entry, expected);
+    }
+}
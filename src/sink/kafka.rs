@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use futures::future::join_all;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::ClientConfig;
+
+use crate::config::{hostname, KafkaConfig};
+use crate::rapl::PowerMetrics;
+use crate::sink::buffer::RetryBuffer;
+use crate::sink::{MetricSink, SinkError};
+
+// Publishes every sample as a JSON record, keyed by host, to one topic with
+// librdkafka. The partition is picked from the key the way the Java client
+// does, so all samples of a host land in order on one partition.
+pub struct KafkaSink {
+    producer: FutureProducer,
+    topic: String,
+    key: String,
+    tags: BTreeMap<String, String>,
+    timeout: Duration,
+    buffer: RetryBuffer,
+}
+
+impl KafkaSink {
+    pub fn new(config: KafkaConfig, tags: &BTreeMap<String, String>) -> Result<Self, SinkError> {
+        if config.brokers.is_empty() {
+            return Err("kafka.brokers must not be empty".into());
+        }
+        if !["all", "-1", "0", "1"].contains(&config.acks.as_str()) {
+            return Err(format!("kafka.acks must be 0, 1 or all, got {:?}", config.acks).into());
+        }
+        let timeout = Duration::from_secs(config.timeout_secs);
+        // librdkafka connects in the background, so an unreachable broker
+        // only shows up as failed deliveries.
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", config.brokers.join(","))
+            .set("client.id", "ryzenmon")
+            .set("acks", &config.acks)
+            .set("partitioner", "murmur2_random")
+            .set("message.timeout.ms", timeout.as_millis().to_string())
+            .create()?;
+        Ok(KafkaSink {
+            producer,
+            topic: config.topic,
+            key: tags.get("host").cloned().unwrap_or_else(hostname),
+            tags: tags.clone(),
+            timeout,
+            buffer: RetryBuffer::new("kafka", config.max_buffered_points, None, Duration::from_secs(config.max_retry_secs)),
+        })
+    }
+
+    // Every buffered record, queued in order. Records delivered before one
+    // fails are sent again with the rest on the next attempt.
+    async fn produce(&self) -> Result<(), SinkError> {
+        let body = self.buffer.body();
+        let deliveries = body.lines().map(|line| {
+            let record = FutureRecord::to(&self.topic)
+                .key(&self.key)
+                .payload(line)
+                .timestamp(record_timestamp(line));
+            self.producer.send(record, self.timeout)
+        });
+        for delivery in join_all(deliveries).await {
+            delivery.map_err(|(e, _)| e)?;
+        }
+        Ok(())
+    }
+
+    async fn send(&mut self) -> Result<(), SinkError> {
+        let buffered = self.buffer.len();
+        match self.produce().await {
+            Ok(()) => {
+                self.buffer.succeeded();
+                Ok(())
+            }
+            Err(e) => {
+                let backoff = self.buffer.failed();
+                Err(format!("{} ({} points buffered, retrying in {:.1?})", e, buffered, backoff).into())
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl MetricSink for KafkaSink {
+    fn name(&self) -> &str {
+        "kafka"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        let mut record = serde_json::to_value(metrics)?;
+        record["tags"] = serde_json::to_value(&self.tags)?;
+        self.buffer.push([record.to_string()]);
+
+        if !self.buffer.ready() {
+            return Ok(());
+        }
+        self.send().await
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.send().await
+    }
+
+    fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn retry_buffer(&mut self) -> Option<&mut RetryBuffer> {
+        Some(&mut self.buffer)
+    }
+}
+
+// The sample's own time in milliseconds, so a retried record keeps it.
+fn record_timestamp(record: &str) -> i64 {
+    serde_json::from_str::<serde_json::Value>(record)
+        .ok()
+        .and_then(|record| record["timestamp"].as_f64())
+        .map(|secs| (secs * 1000.0) as i64)
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stamps_records_with_the_sample_time() {
+        assert_eq!(record_timestamp(r#"{"timestamp":1700000000.25}"#), 1_700_000_000_250);
+        assert!(record_timestamp("not json") > 1_700_000_000_000);
+    }
+
+    #[test]
+    fn rejects_unknown_acks() {
+        let config = |acks: &str| KafkaConfig {
+            brokers: vec!["localhost:9092".to_string()],
+            topic: "ryzenmon".to_string(),
+            acks: acks.to_string(),
+            timeout_secs: 10,
+            max_buffered_points: 10,
+            max_retry_secs: 60,
+        };
+        assert!(KafkaSink::new(config("all"), &BTreeMap::new()).is_ok());
+        assert!(KafkaSink::new(config("2"), &BTreeMap::new()).is_err());
+    }
+}
@@ -0,0 +1,391 @@
+pub mod agent;
+pub mod api;
+pub mod buffer;
+pub mod convert;
+pub mod csv;
+pub mod dbus;
+pub mod deadband;
+pub mod downsample;
+pub mod file;
+pub mod filter;
+pub mod forward;
+pub mod graphite;
+pub mod http;
+pub mod influxdb;
+pub mod influxdb1;
+#[cfg(unix)]
+pub mod journald;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod mqtt;
+pub mod otlp;
+pub mod postgres;
+pub mod prometheus;
+pub mod remote_write;
+#[cfg(unix)]
+pub mod socket;
+pub mod sqlite;
+pub mod statsd;
+pub mod stdout;
+pub mod victoriametrics;
+#[cfg(target_os = "linux")]
+pub mod vsock;
+pub mod zabbix;
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::future::join_all;
+use tracing::{debug_span, error, warn, Instrument};
+
+use crate::config::{Config, UnitsConfig};
+use crate::error::RyzenmonError;
+use crate::health;
+use crate::instance::INSTANCE;
+use crate::rapl::PowerMetrics;
+use crate::system_info::SystemInfo;
+use crate::units::Units;
+
+pub use agent::AgentSink;
+pub use api::ApiServer;
+pub use buffer::RetryBuffer;
+pub use convert::ConvertedSink;
+pub use csv::CsvSink;
+pub use dbus::DbusSink;
+pub use downsample::DownsampledSink;
+pub use file::FileSink;
+pub use filter::MetricFilter;
+pub use forward::ForwardSink;
+pub use graphite::GraphiteSink;
+pub use influxdb::{build_points, to_line_protocol, InfluxDbSink};
+pub use influxdb1::InfluxDb1Sink;
+#[cfg(unix)]
+pub use journald::JournaldSink;
+#[cfg(feature = "kafka")]
+pub use kafka::KafkaSink;
+pub use mqtt::MqttSink;
+pub use otlp::OtlpSink;
+pub use postgres::PostgresSink;
+pub use prometheus::PrometheusExporter;
+pub use remote_write::RemoteWriteSink;
+#[cfg(unix)]
+pub use socket::SocketServer;
+pub use sqlite::SqliteSink;
+pub use statsd::StatsdSink;
+pub use stdout::StdoutSink;
+pub use victoriametrics::VictoriaMetricsSink;
+#[cfg(target_os = "linux")]
+pub use vsock::VsockServer;
+pub use zabbix::ZabbixSink;
+
+pub type SinkError = Box<dyn std::error::Error + Send + Sync>;
+
+// Well inside systemd's default 90 s stop timeout.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+// An output backend that receives every sample.
+#[async_trait]
+pub trait MetricSink: Send {
+    fn name(&self) -> &str;
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError>;
+
+    // Make sure the destination can take writes, once before the first one.
+    // An error stops startup, so only return one for a setup that would fail
+    // every write, not for a server that is merely down.
+    async fn prepare(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    // The ryzenmon_info point written once at startup, for sinks that have
+    // a place for it.
+    async fn write_info(&mut self, _info: &SystemInfo) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    // Push out anything still pending, called before shutdown.
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    // Release listeners and other resources before the sink is replaced.
+    async fn close(&mut self) {}
+
+    // Points held for retry, reported on /readyz.
+    fn buffered(&self) -> usize {
+        0
+    }
+
+    // Where those points are held, for the sink that replaces this one on
+    // reload to take them over.
+    fn retry_buffer(&mut self) -> Option<&mut RetryBuffer> {
+        None
+    }
+
+    // Whether the sink holds an address or bus name, which a reload has to
+    // release before the replacement can take it.
+    fn listens(&self) -> bool {
+        false
+    }
+
+    // Write only what `filter` allows from now on, for [filter.<sink>].
+    // Sinks that don't write named metrics refuse.
+    fn set_filter(&mut self, _filter: MetricFilter) -> Result<(), SinkError> {
+        Err(format!("{} can't filter metrics", self.name()).into())
+    }
+
+    // Whether the sink takes its values in `units`, for [units]. Sinks whose
+    // names or readers fix the unit, such as Prometheus' `_watts` series or
+    // the JSON of [api], keep W, J, °C and MHz.
+    fn set_units(&mut self, _units: &Units) -> bool {
+        false
+    }
+}
+
+#[derive(Default)]
+pub struct SinkRegistry {
+    sinks: Vec<Box<dyn MetricSink>>,
+}
+
+impl SinkRegistry {
+    pub fn from_config(config: &Config) -> Result<Self, SinkError> {
+        let mut registry = SinkRegistry::default();
+        let tags = config.resolved_tags();
+
+        let claims = config.instance.as_ref().filter(|instance| instance.claim).zip(INSTANCE.get());
+        for influxdb in &config.influxdb {
+            let mut sink = InfluxDbSink::new(influxdb.clone(), tags.clone())?;
+            if let Some((config, instance)) = claims {
+                sink.claim_host(instance, Duration::from_secs(config.claim_interval_secs));
+            }
+            registry.register(Box::new(sink));
+        }
+        if let Some(influxdb1) = &config.influxdb1 {
+            registry.register(Box::new(InfluxDb1Sink::new(influxdb1.clone(), tags.clone())?));
+        }
+        if let Some(prometheus) = &config.prometheus {
+            registry.register(Box::new(PrometheusExporter::bind(&prometheus.bind, &tags)?));
+        }
+        if let Some(remote_write) = &config.remote_write {
+            registry.register(Box::new(RemoteWriteSink::new(remote_write.clone(), &tags)?));
+        }
+        if let Some(victoriametrics) = &config.victoriametrics {
+            registry.register(Box::new(VictoriaMetricsSink::new(victoriametrics.clone(), &tags)?));
+        }
+        if let Some(mqtt) = &config.mqtt {
+            registry.register(Box::new(MqttSink::connect(mqtt.clone(), &tags)?));
+        }
+        if let Some(kafka) = &config.kafka {
+            #[cfg(feature = "kafka")]
+            registry.register(Box::new(KafkaSink::new(kafka.clone(), &tags)?));
+            #[cfg(not(feature = "kafka"))]
+            return Err(format!(
+                "[kafka] with brokers {:?} is configured, but ryzenmon was built without the kafka feature",
+                kafka.brokers
+            )
+            .into());
+        }
+        if let Some(otlp) = &config.otlp {
+            registry.register(Box::new(OtlpSink::connect(otlp, &tags)?));
+        }
+        if let Some(graphite) = &config.graphite {
+            registry.register(Box::new(GraphiteSink::new(graphite.clone(), &tags)));
+        }
+        if let Some(statsd) = &config.statsd {
+            registry.register(Box::new(StatsdSink::new(statsd.clone())));
+        }
+        if let Some(zabbix) = &config.zabbix {
+            registry.register(Box::new(ZabbixSink::new(zabbix.clone(), &tags)));
+        }
+        if let Some(stdout) = &config.stdout {
+            registry.register(Box::new(StdoutSink { format: stdout.format }));
+        }
+        if let Some(file) = &config.file {
+            registry.register(Box::new(FileSink::open(&file.path)?));
+        }
+        if let Some(csv) = &config.csv {
+            registry.register(Box::new(CsvSink::open(csv)?));
+        }
+        if let Some(sqlite) = &config.sqlite {
+            registry.register(Box::new(SqliteSink::open(sqlite)?));
+        }
+        if let Some(postgres) = &config.postgres {
+            registry.register(Box::new(PostgresSink::new(postgres.clone(), &tags)));
+        }
+        if let Some(api) = &config.api {
+            registry.register(Box::new(ApiServer::bind(api, config.control.clone())?));
+        }
+        if let Some(socket) = &config.socket {
+            #[cfg(unix)]
+            registry.register(Box::new(SocketServer::bind(socket)?));
+            #[cfg(not(unix))]
+            return Err(format!("[socket] at {} is configured, but Unix sockets are only available on Unix", socket.path).into());
+        }
+        if let Some(vsock) = &config.vsock {
+            #[cfg(target_os = "linux")]
+            registry.register(Box::new(VsockServer::bind(vsock)?));
+            #[cfg(not(target_os = "linux"))]
+            return Err(format!("[vsock] on port {} is configured, but vsock is only available on Linux", vsock.port).into());
+        }
+        if let Some(dbus) = &config.dbus {
+            registry.register(Box::new(DbusSink::new(dbus.clone())));
+        }
+        if let Some(journald) = &config.journald {
+            #[cfg(unix)]
+            registry.register(Box::new(JournaldSink::new(journald.clone())));
+            #[cfg(not(unix))]
+            {
+                let _ = journald;
+                return Err("[journald] is configured, but journald is only available on Linux".into());
+            }
+        }
+        if let Some(forward) = &config.forward {
+            registry.register(Box::new(ForwardSink::new(forward.clone(), tags.clone())?));
+        }
+
+        for (name, filter) in &config.filter {
+            let Some(sink) = registry.sinks.iter_mut().find(|sink| sink.name() == name) else {
+                return Err(format!("[filter] has {}, which is not an enabled sink", name).into());
+            };
+            sink.set_filter(MetricFilter::new(filter.clone())).map_err(|e| format!("[filter.{}]: {}", name, e))?;
+        }
+
+        if config.units != UnitsConfig::default() {
+            let units = Units::new(config.units.clone());
+            registry.sinks = std::mem::take(&mut registry.sinks)
+                .into_iter()
+                .map(|mut sink| -> Box<dyn MetricSink> {
+                    if sink.set_units(&units) {
+                        Box::new(ConvertedSink::new(sink, units.clone()))
+                    } else {
+                        sink
+                    }
+                })
+                .collect();
+        }
+
+        for (name, secs) in &config.downsample {
+            let Some(index) = registry.sinks.iter().position(|sink| sink.name() == name) else {
+                return Err(format!("[downsample] has {}, which is not an enabled sink", name).into());
+            };
+            let sink = registry.sinks.remove(index);
+            let downsampled = DownsampledSink::new(sink, Duration::from_secs(*secs));
+            registry.sinks.insert(index, Box::new(downsampled));
+        }
+        for name in config.schedule.iter().flat_map(|rule| &rule.sinks) {
+            if !registry.sinks.iter().any(|sink| sink.name() == name) {
+                return Err(format!("[[schedule]] holds back {}, which is not an enabled sink", name).into());
+            }
+        }
+
+        Ok(registry)
+    }
+
+    pub fn register(&mut self, sink: Box<dyn MetricSink>) {
+        self.sinks.push(sink);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.sinks.iter().map(|s| s.name()).collect()
+    }
+
+    // Write to every sink concurrently; a failing or slow sink does not stop
+    // or delay the others.
+    pub async fn write_all(&mut self, metrics: &PowerMetrics) {
+        self.write_where(metrics, |_| true).await;
+    }
+
+    // Write to the sinks whose name `uploads` accepts, as write_all does.
+    pub async fn write_where(&mut self, metrics: &PowerMetrics, uploads: impl Fn(&str) -> bool) {
+        let sinks = self.sinks.iter_mut().filter(|sink| uploads(sink.name())).collect::<Vec<_>>();
+        let uploading = !sinks.is_empty();
+        let results = join_all(sinks.into_iter().map(|sink| async move {
+            let span = debug_span!("write", sink = sink.name());
+            let result = sink.write(metrics).instrument(span).await;
+            result.map_err(|source| upload_error(sink.as_ref(), source))
+        }))
+        .await;
+        let mut succeeded = true;
+        for e in results.into_iter().filter_map(Result::err) {
+            warn!("{}", e);
+            succeeded = false;
+        }
+        health::record_upload(uploading, succeeded, self.sinks.iter().map(|s| s.buffered()).sum());
+    }
+
+    pub async fn prepare_all(&mut self) -> Result<(), SinkError> {
+        for sink in self.sinks.iter_mut() {
+            sink.prepare().await.map_err(|e| format!("{}: {}", sink.name(), e))?;
+        }
+        Ok(())
+    }
+
+    pub async fn write_info_all(&mut self, info: &SystemInfo) {
+        for sink in self.sinks.iter_mut() {
+            if let Err(e) = sink.write_info(info).await {
+                warn!("{}", upload_error(sink.as_ref(), e));
+            }
+        }
+    }
+
+    // Flush every sink concurrently, giving up on those that take longer than
+    // FLUSH_TIMEOUT so an unreachable server can't hold up shutdown.
+    pub async fn flush_all(&mut self) {
+        let results = join_all(self.sinks.iter_mut().map(|sink| async move {
+            let result = tokio::time::timeout(FLUSH_TIMEOUT, sink.flush()).await;
+            (sink.name().to_string(), result)
+        }))
+        .await;
+        for (name, result) in results {
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => error!("Flushing {} failed: {}", name, e),
+                Err(_) => error!("Flushing {} timed out after {:?}", name, FLUSH_TIMEOUT),
+            }
+        }
+    }
+
+    pub async fn close_all(&mut self) {
+        for sink in self.sinks.iter_mut() {
+            sink.close().await;
+        }
+    }
+
+    // Flush and close every sink.
+    pub async fn shutdown(&mut self) {
+        self.flush_all().await;
+        self.close_all().await;
+    }
+
+    // Close the sinks that listen and drop them, freeing their addresses for
+    // the sinks of a reloaded config.
+    pub async fn close_listeners(&mut self) {
+        for sink in self.sinks.iter_mut().filter(|sink| sink.listens()) {
+            sink.close().await;
+        }
+        self.sinks.retain(|sink| !sink.listens());
+    }
+
+    // Move the points `previous` holds for retry to the sinks of the same name.
+    pub fn adopt_buffers(&mut self, previous: &mut SinkRegistry) {
+        for sink in self.sinks.iter_mut() {
+            let Some(old) = previous.sinks.iter_mut().find(|old| old.name() == sink.name()) else {
+                continue;
+            };
+            if let (Some(buffer), Some(old)) = (sink.retry_buffer(), old.retry_buffer()) {
+                buffer.adopt(old);
+            }
+        }
+    }
+}
+
+fn upload_error(sink: &dyn MetricSink, source: SinkError) -> RyzenmonError {
+    RyzenmonError::Upload {
+        sink: sink.name().to_string(),
+        source,
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::{Duration, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use rumqttc::{AsyncClient, Event, MqttOptions, Outgoing, QoS, Transport};
+use serde_json::json;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+use crate::config::{hostname, MqttConfig, MqttFormat};
+use crate::rapl::PowerMetrics;
+use crate::sink::graphite::sanitize;
+use crate::sink::{MetricSink, SinkError};
+use crate::units::{Unit, UnitKind, Units};
+
+// Publishes every metric on its own topic, which is what Home Assistant and
+// most other MQTT consumers expect.
+pub struct MqttSink {
+    client: AsyncClient,
+    topic: String,
+    qos: QoS,
+    retain: bool,
+    format: MqttFormat,
+    // Only names the unit in JSON payloads; the values come converted
+    units: Units,
+    event_loop: Option<JoinHandle<()>>,
+}
+
+impl MqttSink {
+    pub fn connect(config: MqttConfig, tags: &BTreeMap<String, String>) -> Result<Self, SinkError> {
+        let host = tags.get("host").cloned().unwrap_or_else(hostname);
+        let client_id = config.client_id.unwrap_or_else(|| format!("ryzenmon-{}", host));
+        let qos = match config.qos {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            2 => QoS::ExactlyOnce,
+            qos => return Err(format!("mqtt.qos must be 0, 1 or 2, got {}", qos).into()),
+        };
+
+        let mut options = MqttOptions::new(client_id, config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if let Some(username) = config.username {
+            options.set_credentials(username, config.password.unwrap_or_default());
+        }
+        if config.tls {
+            options.set_transport(match config.ca_path {
+                Some(ca_path) => Transport::tls(fs::read(ca_path)?, None, None),
+                None => Transport::tls_with_default_config(),
+            });
+        }
+
+        let (client, mut event_loop) = AsyncClient::new(options, 1000);
+        // rumqttc only makes progress while its event loop is polled; it
+        // reconnects on the next poll after an error.
+        let event_loop = tokio::spawn(async move {
+            loop {
+                match event_loop.poll().await {
+                    Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("MQTT connection failed: {}", e);
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                    }
+                }
+            }
+        });
+
+        Ok(MqttSink {
+            client,
+            topic: format!("{}/{}", config.topic_prefix.trim_end_matches('/'), host),
+            qos,
+            retain: config.retain,
+            format: config.format,
+            units: Units::default(),
+            event_loop: Some(event_loop),
+        })
+    }
+
+    fn publish(&self, metric: &str, value: f64, unit: &str, timestamp: f64) -> Result<(), SinkError> {
+        let payload = match self.format {
+            MqttFormat::Raw => value.to_string(),
+            MqttFormat::Json => json!({ "value": value, "unit": unit, "timestamp": timestamp }).to_string(),
+        };
+        // try_publish so an unreachable broker fills the queue instead of blocking sampling.
+        self.client
+            .try_publish(format!("{}/{}", self.topic, metric), self.qos, self.retain, payload)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MetricSink for MqttSink {
+    fn name(&self) -> &str {
+        "mqtt"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        let timestamp = metrics
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+        let power_unit = self.units.symbol(UnitKind::Power);
+        let core_power_unit = self.units.symbol(UnitKind::CorePower);
+        let temperature_unit = self.units.symbol(UnitKind::Temperature);
+        let frequency_unit = self.units.symbol(UnitKind::Frequency);
+
+        self.publish("package_power", metrics.package_watts, power_unit, timestamp)?;
+        self.publish("core_power", metrics.core_sum, power_unit, timestamp)?;
+        self.publish("uncore_power", metrics.package_watts - metrics.core_sum, power_unit, timestamp)?;
+        if metrics.packages.len() > 1 {
+            for package in &metrics.packages {
+                self.publish(&format!("package/{}/power", package.package), package.watts, power_unit, timestamp)?;
+            }
+        }
+        if let Some(dram_watts) = metrics.dram_watts {
+            self.publish("dram_power", dram_watts, power_unit, timestamp)?;
+        }
+        if let Some(soc_watts) = metrics.soc_watts {
+            self.publish("soc_power", soc_watts, power_unit, timestamp)?;
+        }
+        if let Some(idle_floor_watts) = metrics.idle_floor_watts {
+            self.publish("idle_floor_power", idle_floor_watts, power_unit, timestamp)?;
+        }
+        for (core, watts) in metrics.per_core(&metrics.core_watts) {
+            self.publish(&format!("core/{}/power", core), *watts, core_power_unit, timestamp)?;
+        }
+        if let Some(stats) = &metrics.stats {
+            for (metric, core, summary) in stats.iter() {
+                let topic = match core {
+                    Some(core) => format!("core/{}/power", metrics.core_id(core)),
+                    None if metric == "core_sum" => "core_power".to_string(),
+                    None => format!("{}_power", metric),
+                };
+                let unit = if core.is_some() { core_power_unit } else { power_unit };
+                for (stat, value) in summary.iter() {
+                    self.publish(&format!("{}/{}", topic, stat), value, unit, timestamp)?;
+                }
+            }
+        }
+        if let Some(utilization) = metrics.utilization {
+            self.publish("utilization", utilization, "%", timestamp)?;
+        }
+        for (core, utilization) in metrics.per_core(&metrics.core_utilization) {
+            self.publish(&format!("core/{}/utilization", core), *utilization, "%", timestamp)?;
+        }
+        if !metrics.skipped_cores.is_empty() {
+            self.publish("skipped_cores", metrics.skipped_cores.len() as f64, "", timestamp)?;
+        }
+        for process in &metrics.processes {
+            // Process names may contain topic wildcards and separators.
+            self.publish(&format!("process/{}/power", sanitize(&process.name)), process.watts, power_unit, timestamp)?;
+        }
+        for cgroup in &metrics.cgroups {
+            self.publish(&format!("cgroup/{}/power", sanitize(&cgroup.cgroup)), cgroup.watts, power_unit, timestamp)?;
+        }
+        if let Some(mhz) = metrics.average_mhz {
+            self.publish("frequency", mhz, frequency_unit, timestamp)?;
+        }
+        for (core, mhz) in metrics.per_core(&metrics.core_mhz) {
+            self.publish(&format!("core/{}/frequency", core), *mhz, frequency_unit, timestamp)?;
+        }
+        if let Some(activity) = metrics.mean_activity() {
+            self.publish("effective_frequency", activity.effective_mhz, frequency_unit, timestamp)?;
+            self.publish("busy", activity.busy_percent, "%", timestamp)?;
+        }
+        for (core, activity) in metrics.per_core(&metrics.core_activity) {
+            self.publish(&format!("core/{}/effective_frequency", core), activity.effective_mhz, frequency_unit, timestamp)?;
+            self.publish(&format!("core/{}/busy", core), activity.busy_percent, "%", timestamp)?;
+        }
+        for residency in &metrics.cstates {
+            self.publish(&format!("cstate/{}", residency.state), residency.percent, "%", timestamp)?;
+            for (core, percent) in metrics.per_core(&residency.cores) {
+                self.publish(&format!("core/{}/cstate/{}", core, residency.state), *percent, "%", timestamp)?;
+            }
+        }
+        for ccd in &metrics.ccds {
+            self.publish(&format!("ccd/{}/power", ccd.ccd), ccd.watts, power_unit, timestamp)?;
+            if let Some(celsius) = ccd.celsius {
+                self.publish(&format!("ccd/{}/temperature", ccd.ccd), celsius, temperature_unit, timestamp)?;
+            }
+        }
+        for temperature in &metrics.temperatures {
+            self.publish(&format!("temperature/{}", sanitize(&temperature.label)), temperature.celsius, temperature_unit, timestamp)?;
+        }
+        for gpu in &metrics.gpus {
+            if let Some(watts) = gpu.watts {
+                self.publish(&format!("gpu/{}/power", gpu.gpu), watts, power_unit, timestamp)?;
+            }
+            for temperature in &gpu.temperatures {
+                let topic = format!("gpu/{}/temperature/{}", gpu.gpu, sanitize(&temperature.label));
+                self.publish(&topic, temperature.celsius, temperature_unit, timestamp)?;
+            }
+            if let Some(rpm) = gpu.fan_rpm {
+                self.publish(&format!("gpu/{}/fan", gpu.gpu), rpm, "RPM", timestamp)?;
+            }
+        }
+        for sensor in &metrics.sensors {
+            self.publish(&format!("hwmon/{}", sanitize(&sensor.label)), sensor.value, &sensor.unit, timestamp)?;
+        }
+        if let Some(limits) = &metrics.limits {
+            for (name, limit, unit) in limits.iter() {
+                self.publish(&format!("limit/{}/value", name), limit.value, unit, timestamp)?;
+                self.publish(&format!("limit/{}/limit", name), limit.limit, unit, timestamp)?;
+                self.publish(&format!("limit/{}/usage", name), limit.usage(), "", timestamp)?;
+            }
+        }
+        if let Some(throttle) = &metrics.throttle {
+            self.publish("throttled", f64::from(u8::from(throttle.is_throttled())), "", timestamp)?;
+            for (reason, active) in throttle.iter() {
+                self.publish(&format!("throttle/{}/active", reason), f64::from(u8::from(active)), "", timestamp)?;
+                if let Some(residency) = throttle.residency.get(&reason) {
+                    self.publish(&format!("throttle/{}/residency", reason), *residency, "", timestamp)?;
+                }
+            }
+        }
+        for rail in &metrics.rails {
+            if let Some(volts) = rail.volts {
+                self.publish(&format!("rail/{}/voltage", rail.rail), volts, "V", timestamp)?;
+            }
+            if let Some(amps) = rail.amps {
+                self.publish(&format!("rail/{}/current", rail.rail), amps, "A", timestamp)?;
+            }
+        }
+        if let Some(energy) = &metrics.energy {
+            let (value, unit) = self.units.energy(energy.joules, Unit::KilowattHours);
+            self.publish("energy", value, unit.symbol(), timestamp)?;
+            if let Some(cost) = energy.cost {
+                self.publish("energy/cost", cost, &energy.currency, timestamp)?;
+            }
+        }
+        if let Some(telemetry) = &metrics.self_telemetry {
+            for (name, value, unit) in telemetry.iter() {
+                self.publish(&format!("ryzenmon/{}", name), value, unit, timestamp)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_units(&mut self, units: &Units) -> bool {
+        self.units = units.clone();
+        true
+    }
+
+    // Disconnect cleanly, giving queued messages a moment to go out first.
+    async fn close(&mut self) {
+        let _ = self.client.try_disconnect();
+        if let Some(mut event_loop) = self.event_loop.take() {
+            if tokio::time::timeout(Duration::from_secs(2), &mut event_loop).await.is_err() {
+                event_loop.abort();
+            }
+        }
+    }
+}
+
+impl Drop for MqttSink {
+    fn drop(&mut self) {
+        if let Some(event_loop) = &self.event_loop {
+            event_loop.abort();
+        }
+    }
+}
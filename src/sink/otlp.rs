@@ -0,0 +1,479 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use opentelemetry_proto::tonic::collector::metrics::v1::metrics_service_client::MetricsServiceClient;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, InstrumentationScope, KeyValue};
+use opentelemetry_proto::tonic::metrics::v1::{
+    metric, number_data_point, Gauge, Metric, NumberDataPoint, ResourceMetrics, ScopeMetrics,
+};
+use opentelemetry_proto::tonic::resource::v1::Resource;
+use tonic::transport::{Channel, Endpoint};
+
+use crate::config::OtlpConfig;
+use crate::msr::cpu_model;
+use crate::rapl::PowerMetrics;
+use crate::sink::{MetricSink, SinkError};
+use crate::units::{Unit, UnitKind, Units};
+
+// Exports every sample as OTLP gauges. The tags become resource attributes,
+// package/core/sensor are data point attributes.
+pub struct OtlpSink {
+    client: MetricsServiceClient<Channel>,
+    resource: Resource,
+    units: Units,
+}
+
+impl OtlpSink {
+    pub fn connect(config: &OtlpConfig, tags: &BTreeMap<String, String>) -> Result<Self, SinkError> {
+        // Connect lazily so a collector that is down at startup is not fatal.
+        let channel = Endpoint::from_shared(config.endpoint.clone())?
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .connect_lazy();
+
+        let mut attributes: Vec<KeyValue> = tags.iter().map(|(key, value)| attribute(key, value)).collect();
+        if let Some(host) = tags.get("host") {
+            attributes.push(attribute("host.name", host));
+        }
+        if let Some(service) = tags.get("service") {
+            attributes.push(attribute("service.name", service));
+        }
+        if let Some(model) = cpu_model() {
+            attributes.push(attribute("host.cpu.model.name", &model));
+        }
+
+        Ok(OtlpSink {
+            client: MetricsServiceClient::new(channel),
+            resource: Resource {
+                attributes,
+                dropped_attributes_count: 0,
+            },
+            units: Units::default(),
+        })
+    }
+}
+
+#[async_trait]
+impl MetricSink for OtlpSink {
+    fn name(&self) -> &str {
+        "otlp"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        let request = ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: Some(self.resource.clone()),
+                scope_metrics: vec![ScopeMetrics {
+                    scope: Some(InstrumentationScope {
+                        name: env!("CARGO_PKG_NAME").to_string(),
+                        version: env!("CARGO_PKG_VERSION").to_string(),
+                        ..Default::default()
+                    }),
+                    metrics: build_metrics(metrics, &self.units),
+                    schema_url: String::new(),
+                }],
+                schema_url: String::new(),
+            }],
+        };
+        self.client.export(request).await?;
+        Ok(())
+    }
+
+    fn set_units(&mut self, units: &Units) -> bool {
+        self.units = units.clone();
+        true
+    }
+}
+
+// Values in `units`, which only label them: the sink is handed converted samples.
+pub fn build_metrics(metrics: &PowerMetrics, units: &Units) -> Vec<Metric> {
+    let watts = units.unit(UnitKind::Power).ucum();
+    let core_watts = units.unit(UnitKind::CorePower).ucum();
+    let celsius = units.unit(UnitKind::Temperature).ucum();
+    let frequency = units.unit(UnitKind::Frequency).ucum();
+    let time = metrics
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    let point = |value: f64, attributes: Vec<KeyValue>| NumberDataPoint {
+        attributes,
+        time_unix_nano: time,
+        value: Some(number_data_point::Value::AsDouble(value)),
+        ..Default::default()
+    };
+
+    let mut out = vec![
+        gauge(
+            "ryzenmon.package.power",
+            "Package power",
+            watts,
+            metrics
+                .packages
+                .iter()
+                .map(|p| point(p.watts, vec![attribute("package", &p.package.to_string())]))
+                .collect(),
+        ),
+        gauge("ryzenmon.cores.power", "Sum of all core power", watts, vec![point(metrics.core_sum, vec![])]),
+        gauge(
+            "ryzenmon.uncore.power",
+            "Package power not attributed to cores",
+            watts,
+            vec![point(metrics.package_watts - metrics.core_sum, vec![])],
+        ),
+    ];
+    if let Some(dram_watts) = metrics.dram_watts {
+        out.push(gauge("ryzenmon.dram.power", "DRAM power", watts, vec![point(dram_watts, vec![])]));
+    }
+    if let Some(soc_watts) = metrics.soc_watts {
+        out.push(gauge("ryzenmon.soc.power", "SoC power", watts, vec![point(soc_watts, vec![])]));
+    }
+    if let Some(idle_floor_watts) = metrics.idle_floor_watts {
+        out.push(gauge(
+            "ryzenmon.idle_floor.power",
+            "Lowest package power over the idle floor window",
+            watts,
+            vec![point(idle_floor_watts, vec![])],
+        ));
+    }
+    if !metrics.core_watts.is_empty() {
+        out.push(gauge(
+            "ryzenmon.core.power",
+            "Per-core power",
+            core_watts,
+            metrics
+                .core_watts
+                .iter()
+                .enumerate()
+                .map(|(core, watts)| point(*watts, core_attributes(metrics, core)))
+                .collect(),
+        ));
+    }
+    if let Some(stats) = &metrics.stats {
+        let (mut points, mut core_points) = (Vec::new(), Vec::new());
+        for (metric, core, summary) in stats.iter() {
+            for (stat, value) in summary.iter() {
+                let mut attributes = vec![attribute("metric", metric), attribute("stat", stat)];
+                attributes.extend(core.into_iter().flat_map(|core| core_attributes(metrics, core)));
+                match core {
+                    Some(_) => core_points.push(point(value, attributes)),
+                    None => points.push(point(value, attributes)),
+                }
+            }
+        }
+        // A gauge has one unit, so per-core power in a unit of its own gets
+        // a gauge of its own.
+        if core_watts == watts {
+            points.append(&mut core_points);
+        } else {
+            out.push(gauge("ryzenmon.core.power.stats", "Per-core power over the upload interval", core_watts, core_points));
+        }
+        out.push(gauge("ryzenmon.power.stats", "Power over the upload interval", watts, points));
+    }
+    if let Some(utilization) = metrics.utilization {
+        out.push(gauge(
+            "ryzenmon.utilization",
+            "Busy share of all CPUs from /proc/stat",
+            "%",
+            vec![point(utilization, vec![])],
+        ));
+    }
+    if !metrics.core_utilization.is_empty() {
+        out.push(gauge(
+            "ryzenmon.core.utilization",
+            "Per-core busy share from /proc/stat",
+            "%",
+            metrics
+                .core_utilization
+                .iter()
+                .enumerate()
+                .map(|(core, utilization)| point(*utilization, core_attributes(metrics, core)))
+                .collect(),
+        ));
+    }
+    if !metrics.skipped_cores.is_empty() {
+        out.push(gauge(
+            "ryzenmon.cores.skipped",
+            "Cores left out because their MSR can't be read",
+            "",
+            vec![point(metrics.skipped_cores.len() as f64, vec![])],
+        ));
+    }
+    if !metrics.processes.is_empty() {
+        out.push(gauge(
+            "ryzenmon.process.power",
+            "Package power attributed to a process by CPU time",
+            watts,
+            metrics
+                .processes
+                .iter()
+                .map(|p| point(p.watts, vec![attribute("process", &p.name)]))
+                .collect(),
+        ));
+    }
+    if !metrics.cgroups.is_empty() {
+        out.push(gauge(
+            "ryzenmon.cgroup.power",
+            "Package power attributed to a cgroup by CPU time",
+            watts,
+            metrics
+                .cgroups
+                .iter()
+                .map(|c| point(c.watts, vec![attribute("cgroup", &c.cgroup)]))
+                .collect(),
+        ));
+    }
+    if let Some(mhz) = metrics.average_mhz {
+        out.push(gauge("ryzenmon.package.frequency", "Mean core frequency", frequency, vec![point(mhz, vec![])]));
+    }
+    if !metrics.core_mhz.is_empty() {
+        out.push(gauge(
+            "ryzenmon.core.frequency",
+            "Per-core frequency",
+            frequency,
+            metrics
+                .core_mhz
+                .iter()
+                .enumerate()
+                .map(|(core, mhz)| point(*mhz, core_attributes(metrics, core)))
+                .collect(),
+        ));
+    }
+    if !metrics.core_activity.is_empty() {
+        let core_point = |core: usize, value: f64| point(value, core_attributes(metrics, core));
+        out.push(gauge(
+            "ryzenmon.core.effective_frequency",
+            "Per-core clock from APERF, idle time included",
+            frequency,
+            metrics
+                .core_activity
+                .iter()
+                .enumerate()
+                .map(|(core, a)| core_point(core, a.effective_mhz))
+                .collect(),
+        ));
+        out.push(gauge(
+            "ryzenmon.core.busy",
+            "Per-core C0 residency from MPERF",
+            "%",
+            metrics
+                .core_activity
+                .iter()
+                .enumerate()
+                .map(|(core, a)| core_point(core, a.busy_percent))
+                .collect(),
+        ));
+    }
+    if !metrics.cstates.is_empty() {
+        out.push(gauge(
+            "ryzenmon.cstate.residency",
+            "Share of the window spent in an idle state",
+            "%",
+            metrics
+                .cstates
+                .iter()
+                .flat_map(|r| {
+                    r.cores.iter().enumerate().map(move |(core, percent)| {
+                        let mut attributes = core_attributes(metrics, core);
+                        attributes.push(attribute("state", &r.state));
+                        point(*percent, attributes)
+                    })
+                })
+                .collect(),
+        ));
+    }
+    if !metrics.ccds.is_empty() {
+        out.push(gauge(
+            "ryzenmon.ccd.power",
+            "Core power per CCD",
+            watts,
+            metrics
+                .ccds
+                .iter()
+                .map(|c| point(c.watts, vec![attribute("ccd", &c.ccd.to_string())]))
+                .collect(),
+        ));
+        out.push(gauge(
+            "ryzenmon.ccd.temperature",
+            "CCD temperature",
+            celsius,
+            metrics
+                .ccds
+                .iter()
+                .filter_map(|c| Some(point(c.celsius?, vec![attribute("ccd", &c.ccd.to_string())])))
+                .collect(),
+        ));
+    }
+    if !metrics.temperatures.is_empty() {
+        out.push(gauge(
+            "ryzenmon.temperature",
+            "CPU temperature",
+            celsius,
+            metrics
+                .temperatures
+                .iter()
+                .map(|t| point(t.celsius, vec![attribute("sensor", &t.label)]))
+                .collect(),
+        ));
+    }
+    if !metrics.gpus.is_empty() {
+        let gpu_attribute = |gpu: usize| attribute("gpu", &gpu.to_string());
+        out.push(gauge(
+            "ryzenmon.gpu.power",
+            "amdgpu power",
+            watts,
+            metrics
+                .gpus
+                .iter()
+                .filter_map(|g| Some(point(g.watts?, vec![gpu_attribute(g.gpu)])))
+                .collect(),
+        ));
+        out.push(gauge(
+            "ryzenmon.gpu.temperature",
+            "amdgpu temperature",
+            celsius,
+            metrics
+                .gpus
+                .iter()
+                .flat_map(|g| {
+                    g.temperatures
+                        .iter()
+                        .map(move |t| point(t.celsius, vec![gpu_attribute(g.gpu), attribute("sensor", &t.label)]))
+                })
+                .collect(),
+        ));
+        out.push(gauge(
+            "ryzenmon.gpu.fan.speed",
+            "amdgpu fan speed",
+            "{rpm}",
+            metrics
+                .gpus
+                .iter()
+                .filter_map(|g| Some(point(g.fan_rpm?, vec![gpu_attribute(g.gpu)])))
+                .collect(),
+        ));
+    }
+    for sensor in &metrics.sensors {
+        out.push(gauge(
+            &format!("ryzenmon.hwmon.{}", sensor.label),
+            "hwmon sensor from the config",
+            &sensor.unit,
+            vec![point(sensor.value, vec![])],
+        ));
+    }
+    let volts: Vec<NumberDataPoint> = metrics
+        .rails
+        .iter()
+        .filter_map(|r| Some(point(r.volts?, vec![attribute("rail", &r.rail)])))
+        .collect();
+    if !volts.is_empty() {
+        out.push(gauge("ryzenmon.rail.voltage", "SVI rail voltage", "V", volts));
+    }
+    let amps: Vec<NumberDataPoint> = metrics
+        .rails
+        .iter()
+        .filter_map(|r| Some(point(r.amps?, vec![attribute("rail", &r.rail)])))
+        .collect();
+    if !amps.is_empty() {
+        out.push(gauge("ryzenmon.rail.current", "SVI rail current", "A", amps));
+    }
+    if let Some(limits) = &metrics.limits {
+        for (name, limit, unit) in limits.iter() {
+            let upper = name.to_ascii_uppercase();
+            out.push(gauge(&format!("ryzenmon.{}", name), &upper, unit, vec![point(limit.value, vec![])]));
+            out.push(gauge(
+                &format!("ryzenmon.{}.limit", name),
+                &format!("{} limit", upper),
+                unit,
+                vec![point(limit.limit, vec![])],
+            ));
+        }
+        out.push(gauge(
+            "ryzenmon.limit.usage",
+            "Fraction of the PPT/TDC/EDC limit in use",
+            "1",
+            limits
+                .iter()
+                .into_iter()
+                .map(|(name, limit, _)| point(limit.usage(), vec![attribute("limit", name)]))
+                .collect(),
+        ));
+    }
+    if let Some(throttle) = &metrics.throttle {
+        out.push(gauge(
+            "ryzenmon.throttled",
+            "1 while the CPU is throttled for any reason",
+            "1",
+            vec![point(f64::from(u8::from(throttle.is_throttled())), vec![])],
+        ));
+        out.push(gauge(
+            "ryzenmon.throttle.active",
+            "1 while the CPU is throttled for this reason",
+            "1",
+            throttle
+                .iter()
+                .map(|(reason, active)| point(f64::from(u8::from(active)), vec![attribute("reason", reason.name())]))
+                .collect(),
+        ));
+        if !throttle.residency.is_empty() {
+            out.push(gauge(
+                "ryzenmon.throttle.residency",
+                "Share of the samples of the last interval throttled for this reason",
+                "1",
+                throttle
+                    .residency
+                    .iter()
+                    .map(|(reason, residency)| point(*residency, vec![attribute("reason", reason.name())]))
+                    .collect(),
+            ));
+        }
+    }
+    if let Some(energy) = &metrics.energy {
+        let (value, unit) = units.energy(energy.joules, Unit::Joules);
+        out.push(gauge(
+            "ryzenmon.package.energy",
+            "Package energy since the counter started",
+            unit.ucum(),
+            vec![point(value, vec![])],
+        ));
+        if let Some(cost) = energy.cost {
+            out.push(gauge(
+                "ryzenmon.package.energy.cost",
+                "Estimated electricity cost of the package energy",
+                &energy.currency,
+                vec![point(cost, vec![])],
+            ));
+        }
+    }
+    if let Some(telemetry) = &metrics.self_telemetry {
+        for (name, value, unit) in telemetry.iter() {
+            out.push(gauge(&format!("ryzenmon.self.{}", name), "ryzenmon self-telemetry", unit, vec![point(value, vec![])]));
+        }
+    }
+    out
+}
+
+fn gauge(name: &str, description: &str, unit: &str, data_points: Vec<NumberDataPoint>) -> Metric {
+    Metric {
+        name: name.to_string(),
+        description: description.to_string(),
+        unit: unit.to_string(),
+        data: Some(metric::Data::Gauge(Gauge { data_points })),
+        ..Default::default()
+    }
+}
+
+// `core` with its NUMA node and L3 where known.
+fn core_attributes(metrics: &PowerMetrics, core: usize) -> Vec<KeyValue> {
+    metrics.core_tags(core).iter().map(|(key, value)| attribute(key, value)).collect()
+}
+
+fn attribute(key: &str, value: &str) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::StringValue(value.to_string())),
+        }),
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use postgres_native_tls::MakeTlsConnector;
+use tokio_postgres::Client;
+use tracing::{info, warn};
+
+use crate::config::{hostname, PostgresConfig};
+use crate::error::{RyzenmonError, Result};
+use crate::rapl::PowerMetrics;
+use crate::sink::buffer::RetryBuffer;
+use crate::sink::filter::{filtered_gauges, MetricFilter};
+use crate::sink::{MetricSink, SinkError};
+use crate::units::Units;
+
+// Inserts every metric as a `(time, host, metric, value)` row, named like the
+// StatsD gauges, into a table created on first connect, optionally as a
+// TimescaleDB hypertable. Rows are buffered as `<unix seconds>\t<metric>\t<value>`
+// lines and inserted in one statement per batch.
+pub struct PostgresSink {
+    url: String,
+    table: String,
+    timescaledb: bool,
+    host: String,
+    client: Option<Client>,
+    buffer: RetryBuffer,
+    batch_size: usize,
+    flush_interval: Duration,
+    last_flush: Instant,
+    filter: Option<MetricFilter>,
+}
+
+impl PostgresSink {
+    pub fn new(config: PostgresConfig, tags: &BTreeMap<String, String>) -> Self {
+        PostgresSink {
+            url: config.url,
+            table: config.table,
+            timescaledb: config.timescaledb,
+            host: tags.get("host").cloned().unwrap_or_else(hostname),
+            client: None,
+            buffer: RetryBuffer::new("postgres", config.max_buffered_points, None, Duration::from_secs(config.max_retry_secs)),
+            batch_size: config.batch_size,
+            flush_interval: Duration::from_secs(config.flush_interval_secs),
+            last_flush: Instant::now(),
+            filter: None,
+        }
+    }
+
+    fn batch_due(&self) -> bool {
+        self.buffer.len() >= self.batch_size || self.last_flush.elapsed() >= self.flush_interval
+    }
+
+    // Connect, or reconnect after the server went away, making sure the
+    // table exists. TLS is used as the URL's sslmode asks.
+    async fn client(&mut self) -> Result<&Client> {
+        if self.client.as_ref().is_some_and(Client::is_closed) {
+            info!("Reconnecting to Postgres");
+            self.client = None;
+        }
+        if self.client.is_none() {
+            let tls = native_tls::TlsConnector::new().map_err(|e| RyzenmonError::Postgres(e.to_string()))?;
+            let (client, connection) = tokio_postgres::connect(&self.url, MakeTlsConnector::new(tls)).await?;
+            tokio::spawn(async move {
+                if let Err(e) = connection.await {
+                    warn!("Postgres connection failed: {}", e);
+                }
+            });
+            create_schema(&client, &self.table, self.timescaledb).await?;
+            self.client = Some(client);
+        }
+        Ok(self.client.as_ref().expect("connected above"))
+    }
+
+    async fn insert(&mut self) -> Result<()> {
+        let (mut times, mut metrics, mut values) = (Vec::new(), Vec::new(), Vec::new());
+        for row in self.buffer.body().lines() {
+            let mut fields = row.splitn(3, '\t');
+            if let (Some(time), Some(metric), Some(value)) = (fields.next(), fields.next(), fields.next()) {
+                if let (Ok(time), Ok(value)) = (time.parse::<f64>(), value.parse::<f64>()) {
+                    times.push(time);
+                    metrics.push(metric.to_string());
+                    values.push(value);
+                }
+            }
+        }
+        let sql = format!(
+            "INSERT INTO {} (time, host, metric, value)
+             SELECT to_timestamp(t), $1, m, v FROM unnest($2::float8[], $3::text[], $4::float8[]) AS u(t, m, v)",
+            self.table
+        );
+        let host = self.host.clone();
+        self.client().await?.execute(&sql, &[&host, &times, &metrics, &values]).await?;
+        Ok(())
+    }
+
+    async fn send(&mut self) -> Result<(), SinkError> {
+        self.last_flush = Instant::now();
+        let buffered = self.buffer.len();
+        match self.insert().await {
+            Ok(()) => {
+                self.buffer.succeeded();
+                Ok(())
+            }
+            Err(e) => {
+                let backoff = self.buffer.failed();
+                Err(format!("{} ({} points buffered, retrying in {:.1?})", e, buffered, backoff).into())
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl MetricSink for PostgresSink {
+    fn name(&self) -> &str {
+        "postgres"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        let time = metrics.timestamp.duration_since(UNIX_EPOCH)?.as_secs_f64();
+        self.buffer.push(
+            filtered_gauges(self.filter.as_ref(), metrics)
+                .into_iter()
+                .map(|(metric, value)| format!("{:.6}\t{}\t{}", time, metric, value)),
+        );
+
+        if !self.batch_due() || !self.buffer.ready() {
+            return Ok(());
+        }
+        self.send().await
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.send().await
+    }
+
+    async fn close(&mut self) {
+        self.client = None;
+    }
+
+    fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn retry_buffer(&mut self) -> Option<&mut RetryBuffer> {
+        Some(&mut self.buffer)
+    }
+
+    fn set_filter(&mut self, filter: MetricFilter) -> Result<(), SinkError> {
+        self.filter = Some(filter);
+        Ok(())
+    }
+
+    fn set_units(&mut self, _units: &Units) -> bool {
+        true
+    }
+}
+
+async fn create_schema(client: &Client, table: &str, timescaledb: bool) -> Result<()> {
+    client
+        .batch_execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                time TIMESTAMPTZ NOT NULL,
+                host TEXT NOT NULL,
+                metric TEXT NOT NULL,
+                value DOUBLE PRECISION NOT NULL
+            )",
+            table
+        ))
+        .await?;
+    if timescaledb {
+        client.batch_execute("CREATE EXTENSION IF NOT EXISTS timescaledb").await?;
+        client
+            .execute("SELECT create_hypertable($1::text::regclass, 'time', if_not_exists => TRUE)", &[&table])
+            .await?;
+    }
+    let index = format!("{}_host_metric_time", table.rsplit('.').next().unwrap_or(table));
+    client
+        .batch_execute(&format!("CREATE INDEX IF NOT EXISTS {} ON {} (host, metric, time DESC)", index, table))
+        .await?;
+    Ok(())
+}
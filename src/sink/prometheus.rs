@@ -0,0 +1,631 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::histogram::Histogram;
+use crate::rapl::PowerMetrics;
+use crate::sink::filter::MetricFilter;
+use crate::sink::{MetricSink, SinkError};
+
+// Serves the most recent sample on /metrics in the Prometheus text format.
+pub struct PrometheusExporter {
+    // The latest sample, rendered when it is written
+    latest: Arc<RwLock<Option<String>>>,
+    labels: String,
+    filter: Option<MetricFilter>,
+    server: Option<JoinHandle<()>>,
+}
+
+impl PrometheusExporter {
+    pub fn bind(addr: &str, tags: &BTreeMap<String, String>) -> Result<Self, SinkError> {
+        let addr: SocketAddr = addr.parse()?;
+        let mut exporter = PrometheusExporter {
+            latest: Arc::new(RwLock::new(None)),
+            labels: format_labels(tags),
+            filter: None,
+            server: None,
+        };
+
+        let latest = exporter.latest.clone();
+        let make_svc = make_service_fn(move |_conn| {
+            let latest = latest.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let latest = latest.clone();
+                    async move { Ok::<_, Infallible>(handle(req, &latest)) }
+                }))
+            }
+        });
+
+        let server = Server::try_bind(&addr)?.serve(make_svc);
+        info!("Serving Prometheus metrics on http://{}/metrics", addr);
+        exporter.server = Some(tokio::spawn(async move {
+            if let Err(e) = server.await {
+                error!("Prometheus exporter failed: {}", e);
+            }
+        }));
+
+        Ok(exporter)
+    }
+
+    pub fn update(&self, metrics: &PowerMetrics) {
+        let mut text = render(metrics, &self.labels);
+        if let Some(filter) = &self.filter {
+            text = filter.filter_exposition(&text);
+        }
+        *self.latest.write().unwrap() = Some(text);
+    }
+}
+
+#[async_trait]
+impl MetricSink for PrometheusExporter {
+    fn name(&self) -> &str {
+        "prometheus"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        self.update(metrics);
+        Ok(())
+    }
+
+    // Stop the server and wait for it, so the address can be bound again.
+    async fn close(&mut self) {
+        if let Some(server) = self.server.take() {
+            server.abort();
+            let _ = server.await;
+        }
+    }
+
+    fn listens(&self) -> bool {
+        true
+    }
+
+    fn set_filter(&mut self, filter: MetricFilter) -> Result<(), SinkError> {
+        self.filter = Some(filter);
+        Ok(())
+    }
+}
+
+impl Drop for PrometheusExporter {
+    fn drop(&mut self) {
+        if let Some(server) = &self.server {
+            server.abort();
+        }
+    }
+}
+
+fn handle(req: Request<Body>, latest: &RwLock<Option<String>>) -> Response<Body> {
+    if req.method() != Method::GET || req.uri().path() != "/metrics" {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("not found\n"))
+            .unwrap();
+    }
+
+    let body = latest.read().unwrap().clone().unwrap_or_default();
+
+    Response::builder()
+        .header("Content-Type", "text/plain; version=0.0.4")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+// `labels` is a pre-rendered, comma separated label list without braces.
+pub fn render(metrics: &PowerMetrics, labels: &str) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(out, "# HELP ryzenmon_package_power_watts Package power in watts");
+    let _ = writeln!(out, "# TYPE ryzenmon_package_power_watts gauge");
+    for package in &metrics.packages {
+        let package_labels = join_labels(labels, &format!("package=\"{}\"", package.package));
+        let _ = writeln!(out, "ryzenmon_package_power_watts{{{}}} {}", package_labels, package.watts);
+    }
+    gauge(&mut out, "ryzenmon_core_power_sum_watts", "Sum of all core power in watts", labels, metrics.core_sum);
+    gauge(
+        &mut out,
+        "ryzenmon_uncore_power_watts",
+        "Package power not attributed to cores in watts",
+        labels,
+        metrics.package_watts - metrics.core_sum,
+    );
+
+    if let Some(dram_watts) = metrics.dram_watts {
+        gauge(&mut out, "ryzenmon_dram_power_watts", "DRAM power in watts", labels, dram_watts);
+    }
+    if let Some(soc_watts) = metrics.soc_watts {
+        gauge(&mut out, "ryzenmon_soc_power_watts", "SoC power in watts", labels, soc_watts);
+    }
+    if let Some(idle_floor_watts) = metrics.idle_floor_watts {
+        gauge(
+            &mut out,
+            "ryzenmon_idle_floor_watts",
+            "Lowest package power over the idle floor window in watts",
+            labels,
+            idle_floor_watts,
+        );
+    }
+
+    let _ = writeln!(out, "# HELP ryzenmon_core_power_watts Per-core power in watts");
+    let _ = writeln!(out, "# TYPE ryzenmon_core_power_watts gauge");
+    for (core, watts) in metrics.core_watts.iter().enumerate() {
+        let core_labels = join_labels(labels, &labels_for_core(metrics, core));
+        let _ = writeln!(out, "ryzenmon_core_power_watts{{{}}} {}", core_labels, watts);
+    }
+
+    if !metrics.core_cpus.is_empty() {
+        let _ = writeln!(out, "# HELP ryzenmon_core_info Logical CPUs of each core");
+        let _ = writeln!(out, "# TYPE ryzenmon_core_info gauge");
+        for core in 0..metrics.core_cpus.len() {
+            let cpus = metrics.core_cpu_list(core).unwrap_or_default();
+            let core_labels = join_labels(labels, &format!("{},cpus=\"{}\"", labels_for_core(metrics, core), cpus));
+            let _ = writeln!(out, "ryzenmon_core_info{{{}}} 1", core_labels);
+        }
+    }
+
+    if let Some(stats) = &metrics.stats {
+        let _ = writeln!(
+            out,
+            "# HELP ryzenmon_power_stats_watts Power over the upload interval in watts, the plain gauges hold the mean"
+        );
+        let _ = writeln!(out, "# TYPE ryzenmon_power_stats_watts gauge");
+        for (metric, core, summary) in stats.iter() {
+            let mut metric_labels = format!("metric=\"{}\"", metric);
+            if let Some(core) = core {
+                let _ = write!(metric_labels, ",{}", labels_for_core(metrics, core));
+            }
+            for (stat, value) in summary.iter() {
+                let stat_labels = join_labels(labels, &format!("{},stat=\"{}\"", metric_labels, stat));
+                let _ = writeln!(out, "ryzenmon_power_stats_watts{{{}}} {}", stat_labels, value);
+            }
+        }
+    }
+
+    if !metrics.skipped_cores.is_empty() {
+        gauge(
+            &mut out,
+            "ryzenmon_skipped_cores",
+            "Cores left out because their MSR can't be read",
+            labels,
+            metrics.skipped_cores.len() as f64,
+        );
+    }
+    if let Some(utilization) = metrics.utilization {
+        gauge(
+            &mut out,
+            "ryzenmon_utilization_ratio",
+            "Busy share of all CPUs from /proc/stat",
+            labels,
+            utilization / 100.0,
+        );
+    }
+    if !metrics.core_utilization.is_empty() {
+        let _ = writeln!(out, "# HELP ryzenmon_core_utilization_ratio Per-core busy share from /proc/stat");
+        let _ = writeln!(out, "# TYPE ryzenmon_core_utilization_ratio gauge");
+        for (core, utilization) in metrics.core_utilization.iter().enumerate() {
+            let core_labels = join_labels(labels, &labels_for_core(metrics, core));
+            let _ = writeln!(out, "ryzenmon_core_utilization_ratio{{{}}} {}", core_labels, utilization / 100.0);
+        }
+    }
+    if !metrics.processes.is_empty() {
+        let _ = writeln!(
+            out,
+            "# HELP ryzenmon_process_power_watts Package power attributed to a process by CPU time in watts"
+        );
+        let _ = writeln!(out, "# TYPE ryzenmon_process_power_watts gauge");
+        for process in &metrics.processes {
+            let process_labels = join_labels(labels, &format!("process=\"{}\"", escape_label_value(&process.name)));
+            let _ = writeln!(out, "ryzenmon_process_power_watts{{{}}} {}", process_labels, process.watts);
+        }
+    }
+    if !metrics.cgroups.is_empty() {
+        let _ = writeln!(
+            out,
+            "# HELP ryzenmon_cgroup_power_watts Package power attributed to a cgroup by CPU time in watts"
+        );
+        let _ = writeln!(out, "# TYPE ryzenmon_cgroup_power_watts gauge");
+        for cgroup in &metrics.cgroups {
+            let cgroup_labels = join_labels(labels, &format!("cgroup=\"{}\"", escape_label_value(&cgroup.cgroup)));
+            let _ = writeln!(out, "ryzenmon_cgroup_power_watts{{{}}} {}", cgroup_labels, cgroup.watts);
+        }
+    }
+
+    if let Some(mhz) = metrics.average_mhz {
+        gauge(
+            &mut out,
+            "ryzenmon_package_frequency_hertz",
+            "Mean core frequency in hertz",
+            labels,
+            mhz * 1e6,
+        );
+    }
+    if !metrics.core_mhz.is_empty() {
+        let _ = writeln!(out, "# HELP ryzenmon_core_frequency_hertz Per-core frequency in hertz");
+        let _ = writeln!(out, "# TYPE ryzenmon_core_frequency_hertz gauge");
+        for (core, mhz) in metrics.core_mhz.iter().enumerate() {
+            let core_labels = join_labels(labels, &labels_for_core(metrics, core));
+            let _ = writeln!(out, "ryzenmon_core_frequency_hertz{{{}}} {}", core_labels, mhz * 1e6);
+        }
+    }
+
+    if !metrics.core_activity.is_empty() {
+        let _ = writeln!(
+            out,
+            "# HELP ryzenmon_core_effective_frequency_hertz Per-core clock from APERF, idle time included"
+        );
+        let _ = writeln!(out, "# TYPE ryzenmon_core_effective_frequency_hertz gauge");
+        for (core, activity) in metrics.core_activity.iter().enumerate() {
+            let core_labels = join_labels(labels, &labels_for_core(metrics, core));
+            let _ = writeln!(
+                out,
+                "ryzenmon_core_effective_frequency_hertz{{{}}} {}",
+                core_labels,
+                activity.effective_mhz * 1e6
+            );
+        }
+        let _ = writeln!(out, "# HELP ryzenmon_core_busy_ratio Per-core C0 residency from MPERF");
+        let _ = writeln!(out, "# TYPE ryzenmon_core_busy_ratio gauge");
+        for (core, activity) in metrics.core_activity.iter().enumerate() {
+            let core_labels = join_labels(labels, &labels_for_core(metrics, core));
+            let _ = writeln!(out, "ryzenmon_core_busy_ratio{{{}}} {}", core_labels, activity.busy_percent / 100.0);
+        }
+    }
+
+    if !metrics.cstates.is_empty() {
+        let _ = writeln!(out, "# HELP ryzenmon_cstate_residency_ratio Share of the window spent in an idle state");
+        let _ = writeln!(out, "# TYPE ryzenmon_cstate_residency_ratio gauge");
+        for residency in &metrics.cstates {
+            for (core, percent) in residency.cores.iter().enumerate() {
+                let state_labels = join_labels(labels, &format!("{},state=\"{}\"", labels_for_core(metrics, core), residency.state));
+                let _ = writeln!(out, "ryzenmon_cstate_residency_ratio{{{}}} {}", state_labels, percent / 100.0);
+            }
+        }
+    }
+
+    if !metrics.ccds.is_empty() {
+        let _ = writeln!(out, "# HELP ryzenmon_ccd_power_watts Core power per CCD in watts");
+        let _ = writeln!(out, "# TYPE ryzenmon_ccd_power_watts gauge");
+        for ccd in &metrics.ccds {
+            let ccd_labels = join_labels(labels, &format!("ccd=\"{}\"", ccd.ccd));
+            let _ = writeln!(out, "ryzenmon_ccd_power_watts{{{}}} {}", ccd_labels, ccd.watts);
+        }
+        let _ = writeln!(out, "# HELP ryzenmon_ccd_temperature_celsius CCD temperature from k10temp");
+        let _ = writeln!(out, "# TYPE ryzenmon_ccd_temperature_celsius gauge");
+        for ccd in &metrics.ccds {
+            if let Some(celsius) = ccd.celsius {
+                let ccd_labels = join_labels(labels, &format!("ccd=\"{}\"", ccd.ccd));
+                let _ = writeln!(out, "ryzenmon_ccd_temperature_celsius{{{}}} {}", ccd_labels, celsius);
+            }
+        }
+    }
+
+    if !metrics.temperatures.is_empty() {
+        let _ = writeln!(out, "# HELP ryzenmon_temperature_celsius CPU temperature from k10temp");
+        let _ = writeln!(out, "# TYPE ryzenmon_temperature_celsius gauge");
+        for temperature in &metrics.temperatures {
+            let sensor_labels = join_labels(labels, &format!("sensor=\"{}\"", escape_label_value(&temperature.label)));
+            let _ = writeln!(out, "ryzenmon_temperature_celsius{{{}}} {}", sensor_labels, temperature.celsius);
+        }
+    }
+
+    if !metrics.gpus.is_empty() {
+        let _ = writeln!(out, "# HELP ryzenmon_gpu_power_watts amdgpu power in watts");
+        let _ = writeln!(out, "# TYPE ryzenmon_gpu_power_watts gauge");
+        for gpu in &metrics.gpus {
+            if let Some(watts) = gpu.watts {
+                let gpu_labels = join_labels(labels, &format!("gpu=\"{}\"", gpu.gpu));
+                let _ = writeln!(out, "ryzenmon_gpu_power_watts{{{}}} {}", gpu_labels, watts);
+            }
+        }
+        let _ = writeln!(out, "# HELP ryzenmon_gpu_temperature_celsius amdgpu temperature");
+        let _ = writeln!(out, "# TYPE ryzenmon_gpu_temperature_celsius gauge");
+        for gpu in &metrics.gpus {
+            for temperature in &gpu.temperatures {
+                let sensor_labels = join_labels(
+                    labels,
+                    &format!("gpu=\"{}\",sensor=\"{}\"", gpu.gpu, escape_label_value(&temperature.label)),
+                );
+                let _ = writeln!(out, "ryzenmon_gpu_temperature_celsius{{{}}} {}", sensor_labels, temperature.celsius);
+            }
+        }
+        let _ = writeln!(out, "# HELP ryzenmon_gpu_fan_rpm amdgpu fan speed in RPM");
+        let _ = writeln!(out, "# TYPE ryzenmon_gpu_fan_rpm gauge");
+        for gpu in &metrics.gpus {
+            if let Some(rpm) = gpu.fan_rpm {
+                let gpu_labels = join_labels(labels, &format!("gpu=\"{}\"", gpu.gpu));
+                let _ = writeln!(out, "ryzenmon_gpu_fan_rpm{{{}}} {}", gpu_labels, rpm);
+            }
+        }
+    }
+
+    if !metrics.sensors.is_empty() {
+        let _ = writeln!(out, "# HELP ryzenmon_hwmon_value Sensors from [[hwmon]] in the config");
+        let _ = writeln!(out, "# TYPE ryzenmon_hwmon_value gauge");
+        for sensor in &metrics.sensors {
+            let sensor_labels = join_labels(labels, &format!("sensor=\"{}\"", escape_label_value(&sensor.label)));
+            let _ = writeln!(out, "ryzenmon_hwmon_value{{{}}} {}", sensor_labels, sensor.value);
+        }
+    }
+
+    let volts: Vec<_> = metrics.rails.iter().filter_map(|r| Some((&r.rail, r.volts?))).collect();
+    if !volts.is_empty() {
+        let _ = writeln!(out, "# HELP ryzenmon_rail_voltage_volts SVI rail voltage in volts");
+        let _ = writeln!(out, "# TYPE ryzenmon_rail_voltage_volts gauge");
+        for (rail, volts) in volts {
+            let rail_labels = join_labels(labels, &format!("rail=\"{}\"", rail));
+            let _ = writeln!(out, "ryzenmon_rail_voltage_volts{{{}}} {}", rail_labels, volts);
+        }
+    }
+    let amps: Vec<_> = metrics.rails.iter().filter_map(|r| Some((&r.rail, r.amps?))).collect();
+    if !amps.is_empty() {
+        let _ = writeln!(out, "# HELP ryzenmon_rail_current_amperes SVI rail current in amperes");
+        let _ = writeln!(out, "# TYPE ryzenmon_rail_current_amperes gauge");
+        for (rail, amps) in amps {
+            let rail_labels = join_labels(labels, &format!("rail=\"{}\"", rail));
+            let _ = writeln!(out, "ryzenmon_rail_current_amperes{{{}}} {}", rail_labels, amps);
+        }
+    }
+
+    if let Some(limits) = &metrics.limits {
+        for (name, limit, unit) in limits.iter() {
+            let unit = if unit == "W" { "watts" } else { "amperes" };
+            let upper = name.to_ascii_uppercase();
+            gauge(&mut out, &format!("ryzenmon_{}_{}", name, unit), &format!("{} in {}", upper, unit), labels, limit.value);
+            gauge(
+                &mut out,
+                &format!("ryzenmon_{}_limit_{}", name, unit),
+                &format!("{} limit in {}", upper, unit),
+                labels,
+                limit.limit,
+            );
+        }
+        let _ = writeln!(out, "# HELP ryzenmon_limit_usage_ratio Fraction of the PPT/TDC/EDC limit in use");
+        let _ = writeln!(out, "# TYPE ryzenmon_limit_usage_ratio gauge");
+        for (name, limit, _) in limits.iter() {
+            let limit_labels = join_labels(labels, &format!("limit=\"{}\"", name));
+            let _ = writeln!(out, "ryzenmon_limit_usage_ratio{{{}}} {}", limit_labels, limit.usage());
+        }
+    }
+
+    if let Some(throttle) = &metrics.throttle {
+        gauge(
+            &mut out,
+            "ryzenmon_throttled",
+            "1 while the CPU is throttled for any reason",
+            labels,
+            f64::from(u8::from(throttle.is_throttled())),
+        );
+        let _ = writeln!(out, "# HELP ryzenmon_throttle_active 1 while the CPU is throttled for this reason");
+        let _ = writeln!(out, "# TYPE ryzenmon_throttle_active gauge");
+        for (reason, active) in throttle.iter() {
+            let reason_labels = join_labels(labels, &format!("reason=\"{}\"", reason));
+            let _ = writeln!(out, "ryzenmon_throttle_active{{{}}} {}", reason_labels, u8::from(active));
+        }
+        if !throttle.residency.is_empty() {
+            let _ = writeln!(
+                out,
+                "# HELP ryzenmon_throttle_residency_ratio Share of the samples of the last interval throttled for this reason"
+            );
+            let _ = writeln!(out, "# TYPE ryzenmon_throttle_residency_ratio gauge");
+            for (reason, residency) in &throttle.residency {
+                let reason_labels = join_labels(labels, &format!("reason=\"{}\"", reason));
+                let _ = writeln!(out, "ryzenmon_throttle_residency_ratio{{{}}} {}", reason_labels, residency);
+            }
+        }
+    }
+
+    if let Some(histograms) = &metrics.histograms {
+        let _ = writeln!(
+            out,
+            "# HELP ryzenmon_package_power_distribution_watts Package power of every sample since the daemon started"
+        );
+        let _ = writeln!(out, "# TYPE ryzenmon_package_power_distribution_watts histogram");
+        histogram(&mut out, "ryzenmon_package_power_distribution_watts", labels, &histograms.package);
+        if !histograms.cores.is_empty() {
+            let _ = writeln!(
+                out,
+                "# HELP ryzenmon_core_power_distribution_watts Per-core power of every sample since the daemon started"
+            );
+            let _ = writeln!(out, "# TYPE ryzenmon_core_power_distribution_watts histogram");
+            for (core, core_histogram) in histograms.cores.iter().enumerate() {
+                let core_labels = join_labels(labels, &labels_for_core(metrics, core));
+                histogram(&mut out, "ryzenmon_core_power_distribution_watts", &core_labels, core_histogram);
+            }
+        }
+    }
+
+    if let Some(energy) = &metrics.energy {
+        let _ = writeln!(out, "# HELP ryzenmon_package_energy_joules_total Package energy since the counter started");
+        let _ = writeln!(out, "# TYPE ryzenmon_package_energy_joules_total counter");
+        if labels.is_empty() {
+            let _ = writeln!(out, "ryzenmon_package_energy_joules_total {}", energy.joules);
+        } else {
+            let _ = writeln!(out, "ryzenmon_package_energy_joules_total{{{}}} {}", labels, energy.joules);
+        }
+        if let Some(cost) = energy.cost {
+            let _ = writeln!(out, "# HELP ryzenmon_energy_cost_total Estimated electricity cost of the package energy");
+            let _ = writeln!(out, "# TYPE ryzenmon_energy_cost_total counter");
+            if labels.is_empty() {
+                let _ = writeln!(out, "ryzenmon_energy_cost_total {}", cost);
+            } else {
+                let _ = writeln!(out, "ryzenmon_energy_cost_total{{{}}} {}", labels, cost);
+            }
+        }
+    }
+
+    if let Some(telemetry) = &metrics.self_telemetry {
+        gauge(
+            &mut out,
+            "ryzenmon_sample_duration_seconds",
+            "Time the last sample took, window included",
+            labels,
+            telemetry.sample_duration_ms / 1000.0,
+        );
+        gauge(
+            &mut out,
+            "ryzenmon_upload_latency_seconds",
+            "Time the previous write to all sinks took",
+            labels,
+            telemetry.upload_latency_ms / 1000.0,
+        );
+        counter(&mut out, "ryzenmon_msr_read_errors_total", "Samples that failed to read an MSR", labels, telemetry.msr_read_errors);
+        counter(&mut out, "ryzenmon_upload_retries_total", "Uploads that failed and were retried", labels, telemetry.upload_retries);
+        counter(&mut out, "ryzenmon_dropped_points_total", "Points dropped from a full retry buffer", labels, telemetry.dropped_points);
+        counter(&mut out, "ryzenmon_rejected_values_total", "Readings outside the [validation] ranges", labels, telemetry.rejected_values);
+        counter(&mut out, "ryzenmon_resumes_total", "Resumes from suspend, each discarding a sample", labels, telemetry.resumes);
+        gauge(
+            &mut out,
+            "ryzenmon_open_circuits",
+            "Sinks whose uploads are paused after failing repeatedly",
+            labels,
+            telemetry.open_circuits as f64,
+        );
+    }
+
+    out
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, labels: &str, value: f64) {
+    sample(out, "gauge", name, help, labels, value);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, labels: &str, value: u64) {
+    sample(out, "counter", name, help, labels, value as f64);
+}
+
+fn sample(out: &mut String, kind: &str, name: &str, help: &str, labels: &str, value: f64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    if labels.is_empty() {
+        let _ = writeln!(out, "{} {}", name, value);
+    } else {
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+    }
+}
+
+// The _bucket, _sum and _count series of one histogram, from its totals so
+// they only ever grow.
+fn histogram(out: &mut String, name: &str, labels: &str, histogram: &Histogram) {
+    for (le, count) in histogram.labels().iter().zip(histogram.total.cumulative()) {
+        let bucket_labels = join_labels(labels, &format!("le=\"{}\"", le));
+        let _ = writeln!(out, "{}_bucket{{{}}} {}", name, bucket_labels, count);
+    }
+    if labels.is_empty() {
+        let _ = writeln!(out, "{}_sum {}", name, histogram.total.sum);
+        let _ = writeln!(out, "{}_count {}", name, histogram.total.count());
+    } else {
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, histogram.total.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, histogram.total.count());
+    }
+}
+
+// `core` with its NUMA node and L3 where known, so queries can sum cores by
+// locality, e.g. `sum by (numa_node) (ryzenmon_core_power_watts)`.
+fn labels_for_core(metrics: &PowerMetrics, core: usize) -> String {
+    metrics
+        .core_tags(core)
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, value))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn join_labels(labels: &str, extra: &str) -> String {
+    if labels.is_empty() {
+        extra.to_string()
+    } else {
+        format!("{},{}", labels, extra)
+    }
+}
+
+// Label names and values of one series.
+pub(crate) type Labels = Vec<(String, String)>;
+
+// Labels, sorted by name with the metric name as `__name__`, value and
+// timestamp of an exposition line with a timestamp,
+// `name{label="value"} 1.5 1700000000000`.
+pub(crate) fn parse_sample(line: &str) -> Option<(Labels, f64, i64)> {
+    let (labels, rest) = parse_series(line)?;
+    let mut fields = rest.split_whitespace();
+    let value = fields.next()?.parse().ok()?;
+    let timestamp = fields.next()?.parse().ok()?;
+    Some((labels, value, timestamp))
+}
+
+// The labels of an exposition line, as parse_sample has them, and the rest of
+// the line after them.
+pub(crate) fn parse_series(line: &str) -> Option<(Labels, &str)> {
+    let name_end = line.find(['{', ' '])?;
+    let mut labels = vec![("__name__".to_string(), line[..name_end].to_string())];
+    let mut rest = &line[name_end..];
+    if let Some(mut pairs) = rest.strip_prefix('{') {
+        while !pairs.starts_with('}') {
+            let (name, value) = pairs.split_once("=\"")?;
+            let mut unescaped = String::new();
+            let mut chars = value.char_indices();
+            let end = loop {
+                match chars.next()? {
+                    (i, '"') => break i,
+                    (_, '\\') => match chars.next()?.1 {
+                        'n' => unescaped.push('\n'),
+                        c => unescaped.push(c),
+                    },
+                    (_, c) => unescaped.push(c),
+                }
+            };
+            labels.push((name.to_string(), unescaped));
+            pairs = value[end + 1..].strip_prefix(',').unwrap_or(&value[end + 1..]);
+        }
+        rest = &pairs[1..];
+    }
+    labels.sort();
+    Some((labels, rest))
+}
+
+pub fn format_labels(tags: &BTreeMap<String, String>) -> String {
+    tags.iter()
+        .map(|(key, value)| {
+            let key: String = key
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            format!("{}=\"{}\"", key, escape_label_value(value))
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hwmon::{SensorReading, TemperatureReading};
+    use crate::platform::fixture_sample;
+
+    #[test]
+    fn escapes_sensor_labels() {
+        let mut metrics = fixture_sample();
+        let label = r#"Tdie "hot" \ 1"#.to_string();
+        metrics.temperatures = vec![TemperatureReading { label: label.clone(), celsius: 60.0 }];
+        metrics.sensors = vec![SensorReading { label: label.clone(), value: 1.0, unit: "V".to_string() }];
+
+        let rendered = render(&metrics, "");
+        let sensors: Vec<String> = rendered
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| parse_series(line).unwrap_or_else(|| panic!("unparsable {:?}", line)).0)
+            .filter_map(|labels| labels.into_iter().find(|(name, _)| name == "sensor").map(|(_, value)| value))
+            .collect();
+        assert_eq!(sensors, vec![label.clone(), label]);
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use prost::Message;
+
+use crate::config::RemoteWriteConfig;
+use crate::rapl::PowerMetrics;
+use crate::sink::buffer::RetryBuffer;
+use crate::sink::filter::MetricFilter;
+use crate::sink::http;
+use crate::sink::prometheus::{format_labels, parse_sample, render};
+use crate::sink::{MetricSink, SinkError};
+use crate::telemetry;
+
+// Pushes the same series as /metrics with the Prometheus remote_write 1.0
+// protocol, a snappy-compressed protobuf WriteRequest, for Mimir,
+// VictoriaMetrics, Thanos and Prometheus itself with its receiver enabled.
+// Samples are buffered as exposition lines with a millisecond timestamp and
+// sent in one request per batch.
+pub struct RemoteWriteSink {
+    client: reqwest::Client,
+    url: reqwest::Url,
+    username: Option<String>,
+    password: Option<String>,
+    bearer_token: Option<String>,
+    labels: String,
+    buffer: RetryBuffer,
+    filter: Option<MetricFilter>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TimeSeries {
+    #[prost(message, repeated, tag = "1")]
+    labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Message)]
+struct Label {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Sample {
+    #[prost(double, tag = "1")]
+    value: f64,
+    #[prost(int64, tag = "2")]
+    timestamp: i64,
+}
+
+impl RemoteWriteSink {
+    pub fn new(config: RemoteWriteConfig, tags: &BTreeMap<String, String>) -> Result<Self, SinkError> {
+        Ok(RemoteWriteSink {
+            client: http::client_builder(&config.tls, config.proxy.as_deref())?
+                .timeout(Duration::from_secs(config.timeout_secs))
+                .build()?,
+            url: reqwest::Url::parse(&config.url)?,
+            username: config.username,
+            password: config.password,
+            bearer_token: config.bearer_token,
+            labels: format_labels(tags),
+            buffer: RetryBuffer::new("remote_write", config.max_buffered_points, None, Duration::from_secs(config.max_retry_secs)),
+            filter: None,
+        })
+    }
+
+    async fn send(&mut self) -> Result<(), SinkError> {
+        let request = build_request(&self.buffer.body())?;
+        let mut post = self
+            .client
+            .post(self.url.clone())
+            .header("Content-Type", "application/x-protobuf")
+            .header("Content-Encoding", "snappy")
+            .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+            .body(snap::raw::Encoder::new().compress_vec(&request.encode_to_vec())?);
+        if let Some(username) = &self.username {
+            post = post.basic_auth(username, self.password.as_ref());
+        }
+        if let Some(token) = &self.bearer_token {
+            post = post.bearer_auth(token);
+        }
+
+        let buffered = self.buffer.len();
+        let result = match post.send().await {
+            Ok(response) if response.status().is_success() => Ok(()),
+            // The receiver will never take these samples, e.g. because they
+            // are out of order; retrying would only block newer ones.
+            Ok(response) if response.status().is_client_error() && response.status().as_u16() != 429 => {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                telemetry::record_dropped_points(buffered);
+                self.buffer.succeeded();
+                return Err(format!("{}: {} ({} points dropped)", status, body.trim(), buffered).into());
+            }
+            Ok(response) => {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                Err(format!("{}: {}", status, body.trim()))
+            }
+            Err(e) => Err(e.to_string()),
+        };
+
+        match result {
+            Ok(()) => {
+                self.buffer.succeeded();
+                Ok(())
+            }
+            Err(e) => {
+                let backoff = self.buffer.failed();
+                Err(format!("{} ({} points buffered, retrying in {:.1?})", e, buffered, backoff).into())
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl MetricSink for RemoteWriteSink {
+    fn name(&self) -> &str {
+        "remote_write"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        let timestamp = metrics.timestamp.duration_since(UNIX_EPOCH)?.as_millis();
+        let mut text = render(metrics, &self.labels);
+        if let Some(filter) = &self.filter {
+            text = filter.filter_exposition(&text);
+        }
+        let lines = text
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| format!("{} {}", line, timestamp))
+            .collect::<Vec<_>>();
+        self.buffer.push(lines);
+
+        if !self.buffer.ready() {
+            return Ok(());
+        }
+        self.send().await
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.send().await
+    }
+
+    fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn retry_buffer(&mut self) -> Option<&mut RetryBuffer> {
+        Some(&mut self.buffer)
+    }
+
+    fn set_filter(&mut self, filter: MetricFilter) -> Result<(), SinkError> {
+        self.filter = Some(filter);
+        Ok(())
+    }
+}
+
+// Group exposition lines with timestamps, `name{label="value"} 1.5 1700000000000`,
+// into one series per name and label set.
+fn build_request(body: &str) -> Result<WriteRequest, SinkError> {
+    let mut series: BTreeMap<Vec<Label>, Vec<Sample>> = BTreeMap::new();
+    for line in body.lines().filter(|line| !line.is_empty()) {
+        let (labels, sample) = parse_line(line).ok_or_else(|| format!("unparsable sample {:?}", line))?;
+        series.entry(labels).or_default().push(sample);
+    }
+    Ok(WriteRequest {
+        timeseries: series
+            .into_iter()
+            .map(|(labels, mut samples)| {
+                samples.sort_by_key(|sample| sample.timestamp);
+                TimeSeries { labels, samples }
+            })
+            .collect(),
+    })
+}
+
+// Labels sorted by name with the metric name as `__name__`, as remote_write requires.
+fn parse_line(line: &str) -> Option<(Vec<Label>, Sample)> {
+    let (labels, value, timestamp) = parse_sample(line)?;
+    let labels = labels.into_iter().map(|(name, value)| Label { name, value }).collect();
+    Some((labels, Sample { value, timestamp }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_a_request_receivers_can_decode() {
+        let metrics = crate::platform::fixture_sample();
+        let timestamp_ms = metrics.timestamp.duration_since(UNIX_EPOCH).unwrap().as_millis();
+        let tags = BTreeMap::from([("host".to_string(), "bench".to_string()), ("a".to_string(), "1".to_string())]);
+        let body = render(&metrics, &format_labels(&tags))
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| format!("{} {}\n", line, timestamp_ms))
+            .collect::<String>();
+
+        let encoded = build_request(&body).unwrap().encode_to_vec();
+        let request = WriteRequest::decode(encoded.as_slice()).unwrap();
+        assert!(!request.timeseries.is_empty());
+        for series in &request.timeseries {
+            assert!(series.labels.iter().any(|label| label.name == "__name__" && label.value.starts_with("ryzenmon_")));
+            assert!(series.labels.windows(2).all(|pair| pair[0].name < pair[1].name), "{:?}", series.labels);
+            assert!(series.labels.iter().any(|label| label.name == "host" && label.value == "bench"));
+            assert_eq!(series.samples.len(), 1);
+            assert_eq!(series.samples[0].timestamp as u128, timestamp_ms);
+        }
+        let package = request
+            .timeseries
+            .iter()
+            .find(|series| series.labels.iter().any(|label| label.value == "ryzenmon_package_power_watts"))
+            .unwrap();
+        assert_eq!(package.samples[0].value, metrics.packages[0].watts);
+    }
+
+    #[test]
+    fn parses_escaped_label_values() {
+        let (labels, sample) =
+            parse_line(r#"ryzenmon_info{path="C:\\dir",quote="say \"hi\"",note="a\nb, c}"} 1.5 1700000000123"#).unwrap();
+        let label = |name: &str, value: &str| Label {
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+        assert_eq!(
+            labels,
+            vec![
+                label("__name__", "ryzenmon_info"),
+                label("note", "a\nb, c}"),
+                label("path", r"C:\dir"),
+                label("quote", r#"say "hi""#),
+            ]
+        );
+        assert_eq!(sample, Sample { value: 1.5, timestamp: 1_700_000_000_123 });
+        assert!(parse_line(r#"ryzenmon_info{path="unterminated} 1 2"#).is_none());
+        assert!(parse_line("ryzenmon_info 1").is_none());
+    }
+}
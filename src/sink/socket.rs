@@ -0,0 +1,104 @@
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use tokio::io::AsyncWriteExt;
+use tokio::net::UnixListener;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info};
+
+use crate::config::SocketConfig;
+use crate::rapl::PowerMetrics;
+use crate::sink::{MetricSink, SinkError};
+
+// Writes the latest sample as one line of JSON to every client that connects
+// to a Unix socket, then hangs up, for shell prompts and status bars:
+// `socat - UNIX-CONNECT:/run/ryzenmon.sock | jq .package_watts`.
+pub struct SocketServer {
+    path: PathBuf,
+    latest: Arc<RwLock<Option<String>>>,
+    server: Option<JoinHandle<()>>,
+}
+
+impl SocketServer {
+    pub fn bind(config: &SocketConfig) -> Result<Self, SinkError> {
+        let path = PathBuf::from(&config.path);
+        // Left behind by a previous run that did not shut down cleanly.
+        if std::fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_socket()) {
+            std::fs::remove_file(&path)?;
+        }
+        let listener =
+            UnixListener::bind(&path).map_err(|e| format!("cannot listen on {}: {}", path.display(), e))?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(config.mode))?;
+        info!("Serving the latest sample on {}", path.display());
+
+        let latest = Arc::new(RwLock::new(None));
+        let server = tokio::spawn(serve(listener, latest.clone()));
+        Ok(SocketServer {
+            path,
+            latest,
+            server: Some(server),
+        })
+    }
+}
+
+async fn serve(listener: UnixListener, latest: Arc<RwLock<Option<String>>>) {
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                error!("Sample socket failed: {}", e);
+                return;
+            }
+        };
+        let line = latest
+            .read()
+            .unwrap()
+            .clone()
+            .unwrap_or_else(|| serde_json::json!({ "error": "no sample yet" }).to_string());
+        // A slow client must not hold up the next one.
+        tokio::spawn(async move {
+            if let Err(e) = stream.write_all(format!("{}\n", line).as_bytes()).await {
+                debug!("Writing to a sample socket client failed: {}", e);
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl MetricSink for SocketServer {
+    fn name(&self) -> &str {
+        "socket"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        *self.latest.write().unwrap() = Some(serde_json::to_string(metrics)?);
+        Ok(())
+    }
+
+    // Stop the server and remove the socket, so the path can be bound again.
+    async fn close(&mut self) {
+        if let Some(server) = self.server.take() {
+            server.abort();
+            let _ = server.await;
+            // Fails after dropping privileges in a root-owned directory; the
+            // next bind removes it then.
+            if let Err(e) = std::fs::remove_file(&self.path) {
+                debug!("Removing {} failed: {}", self.path.display(), e);
+            }
+        }
+    }
+
+    fn listens(&self) -> bool {
+        true
+    }
+}
+
+impl Drop for SocketServer {
+    fn drop(&mut self) {
+        if let Some(server) = &self.server {
+            server.abort();
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OpenFlags};
+use tracing::debug;
+
+use crate::config::SqliteConfig;
+use crate::error::{RyzenmonError, Result};
+use crate::rapl::PowerMetrics;
+use crate::sink::filter::{filtered_gauges, MetricFilter};
+use crate::sink::{MetricSink, SinkError};
+use crate::units::Units;
+
+// One row per metric and sample, named like the StatsD gauges. Narrow rather
+// than a column per metric, so cores, sensors and GPUs coming and going need
+// no schema changes.
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    CREATE TABLE IF NOT EXISTS samples (
+        time REAL NOT NULL,
+        metric TEXT NOT NULL,
+        value REAL NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS samples_metric_time ON samples (metric, time);
+    CREATE INDEX IF NOT EXISTS samples_time ON samples (time);
+";
+
+// How often rows older than retention_days are deleted.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+// How long to wait for another process, e.g. the daemon while querying,
+// to release a lock.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Writes every sample into a local SQLite database, for `ryzenmon query`
+// and anything else that reads SQLite.
+pub struct SqliteSink {
+    db: Connection,
+    retention: Option<Duration>,
+    pruned: Option<Instant>,
+    filter: Option<MetricFilter>,
+}
+
+impl SqliteSink {
+    pub fn open(config: &SqliteConfig) -> Result<Self> {
+        let db = open(Path::new(&config.path), OpenFlags::default())?;
+        db.execute_batch(SCHEMA)?;
+        Ok(SqliteSink {
+            db,
+            retention: config.retention_days.map(|days| Duration::from_secs(days * 86400)),
+            pruned: None,
+            filter: None,
+        })
+    }
+
+    fn prune(&mut self) -> Result<()> {
+        let Some(retention) = self.retention else {
+            return Ok(());
+        };
+        if self.pruned.is_some_and(|pruned| pruned.elapsed() < PRUNE_INTERVAL) {
+            return Ok(());
+        }
+        self.db.execute("DELETE FROM samples WHERE time < ?1", params![unix_seconds(SystemTime::now() - retention)])?;
+        debug!("Deleted samples older than {:?}", retention);
+        self.pruned = Some(Instant::now());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MetricSink for SqliteSink {
+    fn name(&self) -> &str {
+        "sqlite"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        self.prune()?;
+        // One transaction per sample instead of one per row; dropped without
+        // a commit, it rolls back.
+        let transaction = self.db.transaction()?;
+        {
+            let mut insert =
+                transaction.prepare_cached("INSERT INTO samples (time, metric, value) VALUES (?1, ?2, ?3)")?;
+            let time = unix_seconds(metrics.timestamp);
+            for (metric, value) in filtered_gauges(self.filter.as_ref(), metrics) {
+                insert.execute(params![time, metric, value])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn set_filter(&mut self, filter: MetricFilter) -> Result<(), SinkError> {
+        self.filter = Some(filter);
+        Ok(())
+    }
+
+    fn set_units(&mut self, _units: &Units) -> bool {
+        true
+    }
+}
+
+// Count, mean, minimum and maximum of one metric over a time range.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub metric: String,
+    pub samples: u64,
+    pub mean: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+// The database at `path`, read-only for `ryzenmon query`.
+pub fn open_read_only(path: &Path) -> Result<Connection> {
+    open(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+}
+
+fn open(path: &Path, flags: OpenFlags) -> Result<Connection> {
+    let db = Connection::open_with_flags(path, flags)
+        .map_err(|e| RyzenmonError::Sqlite(format!("cannot open {}: {}", path.display(), e)))?;
+    db.busy_timeout(BUSY_TIMEOUT)?;
+    Ok(db)
+}
+
+// Summaries of every metric matching the GLOB `pattern`, e.g. `core*.power`,
+// over samples taken between `from` and `to`.
+pub fn summarize(
+    db: &Connection,
+    pattern: &str,
+    from: Option<SystemTime>,
+    to: Option<SystemTime>,
+) -> Result<Vec<Summary>> {
+    let mut select = db.prepare(
+        "SELECT metric, COUNT(*), AVG(value), MIN(value), MAX(value) FROM samples
+         WHERE metric GLOB ?1 AND time >= ?2 AND time <= ?3
+         GROUP BY metric ORDER BY metric",
+    )?;
+    let from = from.map_or(f64::MIN, unix_seconds);
+    let to = to.map_or(f64::MAX, unix_seconds);
+    let summaries = select.query_map(params![pattern, from, to], |row| {
+        Ok(Summary {
+            metric: row.get(0)?,
+            samples: row.get::<_, i64>(1)? as u64,
+            mean: row.get::<_, Option<f64>>(2)?.unwrap_or(f64::NAN),
+            min: row.get::<_, Option<f64>>(3)?.unwrap_or(f64::NAN),
+            max: row.get::<_, Option<f64>>(4)?.unwrap_or(f64::NAN),
+        })
+    })?;
+    Ok(summaries.collect::<Result<_, _>>()?)
+}
+
+fn unix_seconds(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarizes_matching_metrics() {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(SCHEMA).unwrap();
+        for (time, metric, value) in [(1.0, "core0.power", 2.0), (2.0, "core0.power", 4.0), (2.0, "package_power", 40.0)] {
+            db.execute("INSERT INTO samples VALUES (?1, ?2, ?3)", params![time, metric, value]).unwrap();
+        }
+        let summaries = summarize(&db, "core*", None, None).unwrap();
+        assert_eq!(
+            summaries,
+            vec![Summary {
+                metric: "core0.power".to_string(),
+                samples: 2,
+                mean: 3.0,
+                min: 2.0,
+                max: 4.0,
+            }]
+        );
+        let since = UNIX_EPOCH + Duration::from_secs_f64(1.5);
+        assert_eq!(summarize(&db, "*", Some(since), None).unwrap().len(), 2);
+        assert!(summarize(&db, "nothing", None, None).unwrap().is_empty());
+    }
+}
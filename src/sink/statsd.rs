@@ -0,0 +1,231 @@
+use async_trait::async_trait;
+use tokio::net::UdpSocket;
+
+use crate::config::StatsdConfig;
+use crate::rapl::PowerMetrics;
+use crate::sink::filter::{filtered_gauges, MetricFilter};
+use crate::sink::graphite::sanitize;
+use crate::sink::{MetricSink, SinkError};
+use crate::units::Units;
+
+// Keeps datagrams under the usual Ethernet MTU so they are not fragmented.
+const MAX_DATAGRAM: usize = 1432;
+
+// Sends every metric as a StatsD gauge, `<prefix>.package_power:42.5|g`.
+// StatsD has no timestamps, the listener stamps gauges on arrival.
+pub struct StatsdSink {
+    address: String,
+    prefix: String,
+    socket: Option<UdpSocket>,
+    filter: Option<MetricFilter>,
+}
+
+impl StatsdSink {
+    pub fn new(config: StatsdConfig) -> Self {
+        StatsdSink {
+            address: config.address,
+            prefix: config.prefix.trim_end_matches('.').to_string(),
+            socket: None,
+            filter: None,
+        }
+    }
+}
+
+#[async_trait]
+impl MetricSink for StatsdSink {
+    fn name(&self) -> &str {
+        "statsd"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        if self.socket.is_none() {
+            let socket = UdpSocket::bind("0.0.0.0:0").await?;
+            socket.connect(&self.address).await?;
+            self.socket = Some(socket);
+        }
+        if let Some(socket) = &self.socket {
+            let gauges = filtered_gauges(self.filter.as_ref(), metrics);
+            for datagram in pack_datagrams(build_gauges(&self.prefix, gauges)) {
+                socket.send(datagram.as_bytes()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self) {
+        self.socket = None;
+    }
+
+    fn set_filter(&mut self, filter: MetricFilter) -> Result<(), SinkError> {
+        self.filter = Some(filter);
+        Ok(())
+    }
+
+    fn set_units(&mut self, _units: &Units) -> bool {
+        true
+    }
+}
+
+pub fn build_gauges(prefix: &str, gauges: Vec<(String, f64)>) -> Vec<String> {
+    gauges
+        .into_iter()
+        .map(|(name, value)| {
+            if prefix.is_empty() {
+                format!("{}:{}|g", name, value)
+            } else {
+                format!("{}.{}:{}|g", prefix, name, value)
+            }
+        })
+        .collect()
+}
+
+// Every metric of a sample as a flat name and value, e.g. `core3.power`,
+// shared by the StatsD and CSV sinks.
+pub fn gauges(metrics: &PowerMetrics) -> Vec<(String, f64)> {
+    let mut out = Vec::new();
+    let mut gauge = |name: &str, value: f64| out.push((name.to_string(), value));
+
+    gauge("package_power", metrics.package_watts);
+    gauge("core_power", metrics.core_sum);
+    gauge("uncore_power", metrics.package_watts - metrics.core_sum);
+    if metrics.packages.len() > 1 {
+        for package in &metrics.packages {
+            gauge(&format!("package{}.power", package.package), package.watts);
+        }
+    }
+    if let Some(dram_watts) = metrics.dram_watts {
+        gauge("dram_power", dram_watts);
+    }
+    if let Some(soc_watts) = metrics.soc_watts {
+        gauge("soc_power", soc_watts);
+    }
+    if let Some(idle_floor_watts) = metrics.idle_floor_watts {
+        gauge("idle_floor_power", idle_floor_watts);
+    }
+    for (core, watts) in metrics.per_core(&metrics.core_watts) {
+        gauge(&format!("core{}.power", core), *watts);
+    }
+    if let Some(stats) = &metrics.stats {
+        for (metric, core, summary) in stats.iter() {
+            let name = match core {
+                Some(core) => format!("core{}.power", metrics.core_id(core)),
+                None if metric == "core_sum" => "core_power".to_string(),
+                None => format!("{}_power", metric),
+            };
+            for (stat, value) in summary.iter() {
+                gauge(&format!("{}.{}", name, stat), value);
+            }
+        }
+    }
+    if let Some(utilization) = metrics.utilization {
+        gauge("utilization", utilization);
+    }
+    for (core, utilization) in metrics.per_core(&metrics.core_utilization) {
+        gauge(&format!("core{}.utilization", core), *utilization);
+    }
+    if !metrics.skipped_cores.is_empty() {
+        gauge("cores.skipped", metrics.skipped_cores.len() as f64);
+    }
+    for process in &metrics.processes {
+        gauge(&format!("process.{}.power", sanitize(&process.name)), process.watts);
+    }
+    for cgroup in &metrics.cgroups {
+        gauge(&format!("cgroup.{}.power", sanitize(&cgroup.cgroup)), cgroup.watts);
+    }
+    if let Some(mhz) = metrics.average_mhz {
+        gauge("frequency", mhz);
+    }
+    for (core, mhz) in metrics.per_core(&metrics.core_mhz) {
+        gauge(&format!("core{}.frequency", core), *mhz);
+    }
+    if let Some(activity) = metrics.mean_activity() {
+        gauge("effective_frequency", activity.effective_mhz);
+        gauge("busy", activity.busy_percent);
+    }
+    for (core, activity) in metrics.per_core(&metrics.core_activity) {
+        gauge(&format!("core{}.effective_frequency", core), activity.effective_mhz);
+        gauge(&format!("core{}.busy", core), activity.busy_percent);
+    }
+    for residency in &metrics.cstates {
+        let state = sanitize(&residency.state);
+        gauge(&format!("cstate.{}", state), residency.percent);
+        for (core, percent) in metrics.per_core(&residency.cores) {
+            gauge(&format!("core{}.cstate.{}", core, state), *percent);
+        }
+    }
+    for ccd in &metrics.ccds {
+        gauge(&format!("ccd{}.power", ccd.ccd), ccd.watts);
+        if let Some(celsius) = ccd.celsius {
+            gauge(&format!("ccd{}.temperature", ccd.ccd), celsius);
+        }
+    }
+    for temperature in &metrics.temperatures {
+        gauge(&format!("temperature.{}", sanitize(&temperature.label)), temperature.celsius);
+    }
+    for gpu in &metrics.gpus {
+        if let Some(watts) = gpu.watts {
+            gauge(&format!("gpu{}.power", gpu.gpu), watts);
+        }
+        for temperature in &gpu.temperatures {
+            gauge(&format!("gpu{}.temperature.{}", gpu.gpu, sanitize(&temperature.label)), temperature.celsius);
+        }
+        if let Some(rpm) = gpu.fan_rpm {
+            gauge(&format!("gpu{}.fan", gpu.gpu), rpm);
+        }
+    }
+    for sensor in &metrics.sensors {
+        gauge(&format!("hwmon.{}", sanitize(&sensor.label)), sensor.value);
+    }
+    if let Some(limits) = &metrics.limits {
+        for (name, limit, _) in limits.iter() {
+            gauge(&format!("limit.{}.value", name), limit.value);
+            gauge(&format!("limit.{}.limit", name), limit.limit);
+            gauge(&format!("limit.{}.usage", name), limit.usage());
+        }
+    }
+    if let Some(throttle) = &metrics.throttle {
+        gauge("throttled", f64::from(u8::from(throttle.is_throttled())));
+        for (reason, active) in throttle.iter() {
+            gauge(&format!("throttle.{}", reason), f64::from(u8::from(active)));
+            if let Some(residency) = throttle.residency.get(&reason) {
+                gauge(&format!("throttle.{}.residency", reason), *residency);
+            }
+        }
+    }
+    for rail in &metrics.rails {
+        if let Some(volts) = rail.volts {
+            gauge(&format!("rail.{}.voltage", rail.rail), volts);
+        }
+        if let Some(amps) = rail.amps {
+            gauge(&format!("rail.{}.current", rail.rail), amps);
+        }
+    }
+    if let Some(energy) = &metrics.energy {
+        gauge("energy.joules", energy.joules);
+        gauge("energy.kwh", energy.kwh);
+        if let Some(cost) = energy.cost {
+            gauge("energy.cost", cost);
+        }
+    }
+    if let Some(telemetry) = &metrics.self_telemetry {
+        for (name, value, _) in telemetry.iter() {
+            gauge(&format!("ryzenmon.{}", name), value);
+        }
+    }
+    out
+}
+
+// Joins gauges with newlines into as few datagrams as fit.
+fn pack_datagrams(gauges: Vec<String>) -> Vec<String> {
+    let mut datagrams: Vec<String> = Vec::new();
+    for gauge in gauges {
+        match datagrams.last_mut() {
+            Some(last) if last.len() + 1 + gauge.len() <= MAX_DATAGRAM => {
+                last.push('\n');
+                last.push_str(&gauge);
+            }
+            _ => datagrams.push(gauge),
+        }
+    }
+    datagrams
+}
@@ -0,0 +1,118 @@
+use std::fmt::Write;
+use std::time::UNIX_EPOCH;
+
+use async_trait::async_trait;
+
+use crate::config::OutputFormat;
+use crate::rapl::PowerMetrics;
+use crate::sink::{MetricSink, SinkError};
+
+pub struct StdoutSink {
+    pub format: OutputFormat,
+}
+
+#[async_trait]
+impl MetricSink for StdoutSink {
+    fn name(&self) -> &str {
+        "stdout"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        match self.format {
+            OutputFormat::Text => println!("{}", format_line(metrics)),
+            OutputFormat::Json => println!("{}", serde_json::to_string(metrics)?),
+        }
+        Ok(())
+    }
+}
+
+// One human-readable line per sample, shared by the stdout and file sinks.
+pub fn format_line(metrics: &PowerMetrics) -> String {
+    let timestamp = metrics
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let mut line = format!(
+        "{} package={:.3}W cores={:.3}W",
+        timestamp, metrics.package_watts, metrics.core_sum
+    );
+    if metrics.packages.len() > 1 {
+        for package in &metrics.packages {
+            let _ = write!(line, " package{}={:.3}W", package.package, package.watts);
+        }
+    }
+    if let Some(stats) = &metrics.stats {
+        let _ = write!(
+            line,
+            " package_max={:.3}W package_p95={:.3}W samples={}",
+            stats.package.max, stats.package.p95, stats.samples
+        );
+    }
+    if let Some(dram_watts) = metrics.dram_watts {
+        let _ = write!(line, " dram={:.3}W", dram_watts);
+    }
+    if let Some(soc_watts) = metrics.soc_watts {
+        let _ = write!(line, " soc={:.3}W", soc_watts);
+    }
+    for (core, watts) in metrics.per_core(&metrics.core_watts) {
+        let _ = write!(line, " core{}={:.3}W", core, watts);
+    }
+    if let Some(utilization) = metrics.utilization {
+        let _ = write!(line, " util={:.1}%", utilization);
+    }
+    if !metrics.skipped_cores.is_empty() {
+        let _ = write!(line, " skipped_cores={:?}", metrics.skipped_cores);
+    }
+    if let Some(energy) = &metrics.energy {
+        let _ = write!(line, " energy={:.4}kWh", energy.kwh);
+        if let Some(cost) = energy.cost {
+            let _ = write!(line, " cost={:.4}{}", cost, energy.currency);
+        }
+    }
+    if let Some(mhz) = metrics.average_mhz {
+        let _ = write!(line, " freq={:.0}MHz", mhz);
+    }
+    if let Some(activity) = metrics.mean_activity() {
+        let _ = write!(line, " effective={:.0}MHz busy={:.1}%", activity.effective_mhz, activity.busy_percent);
+    }
+    for residency in &metrics.cstates {
+        let _ = write!(line, " {}={:.1}%", residency.state, residency.percent);
+    }
+    for ccd in &metrics.ccds {
+        let _ = write!(line, " ccd{}={:.3}W", ccd.ccd, ccd.watts);
+    }
+    for temperature in &metrics.temperatures {
+        let _ = write!(line, " {}={:.1}C", temperature.label, temperature.celsius);
+    }
+    for gpu in &metrics.gpus {
+        if let Some(watts) = gpu.watts {
+            let _ = write!(line, " gpu{}={:.3}W", gpu.gpu, watts);
+        }
+        for temperature in &gpu.temperatures {
+            let _ = write!(line, " gpu{}_{}={:.1}C", gpu.gpu, temperature.label, temperature.celsius);
+        }
+    }
+    for sensor in &metrics.sensors {
+        let _ = write!(line, " {}={}{}", sensor.label, sensor.value, sensor.unit);
+    }
+    if let Some(limits) = &metrics.limits {
+        for (name, limit, unit) in limits.iter() {
+            let _ = write!(line, " {}={:.1}/{:.1}{}", name, limit.value, limit.limit, unit);
+        }
+    }
+    if let Some(throttle) = metrics.throttle.as_ref().filter(|throttle| throttle.is_throttled()) {
+        let reasons: Vec<&str> = throttle.active.iter().map(|reason| reason.name()).collect();
+        let _ = write!(line, " throttled={}", reasons.join(","));
+    }
+    for rail in &metrics.rails {
+        if let Some(volts) = rail.volts {
+            let _ = write!(line, " {}={:.3}V", rail.rail, volts);
+        }
+        if let Some(amps) = rail.amps {
+            let _ = write!(line, " {}={:.2}A", rail.rail, amps);
+        }
+    }
+    line
+}
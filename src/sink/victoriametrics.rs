@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use serde_json::json;
+
+use crate::config::VictoriaMetricsConfig;
+use crate::rapl::PowerMetrics;
+use crate::sink::buffer::RetryBuffer;
+use crate::sink::filter::MetricFilter;
+use crate::sink::http;
+use crate::sink::prometheus::{format_labels, parse_sample, render, Labels};
+use crate::sink::{MetricSink, SinkError};
+use crate::telemetry;
+
+// Imports the same series as /metrics into VictoriaMetrics with the JSON line
+// format of /api/v1/import, which it ingests with less work than line
+// protocol through its InfluxDB endpoint. Samples are buffered as exposition
+// lines with a millisecond timestamp, like remote_write, and each batch is
+// sent as one line per series.
+pub struct VictoriaMetricsSink {
+    client: reqwest::Client,
+    url: reqwest::Url,
+    username: Option<String>,
+    password: Option<String>,
+    bearer_token: Option<String>,
+    labels: String,
+    buffer: RetryBuffer,
+    filter: Option<MetricFilter>,
+}
+
+impl VictoriaMetricsSink {
+    pub fn new(config: VictoriaMetricsConfig, tags: &BTreeMap<String, String>) -> Result<Self, SinkError> {
+        let base = config.url.trim_end_matches('/');
+        // A cluster's vminsert takes the tenant in the path; a single node has none.
+        let url = match (config.account_id, config.project_id) {
+            (Some(account), Some(project)) => format!("{}/insert/{}:{}/prometheus/api/v1/import", base, account, project),
+            (Some(account), None) => format!("{}/insert/{}/prometheus/api/v1/import", base, account),
+            (None, _) => format!("{}/api/v1/import", base),
+        };
+        Ok(VictoriaMetricsSink {
+            client: http::client_builder(&config.tls, config.proxy.as_deref())?
+                .timeout(Duration::from_secs(config.timeout_secs))
+                .build()?,
+            url: reqwest::Url::parse(&url)?,
+            username: config.username,
+            password: config.password,
+            bearer_token: config.bearer_token,
+            labels: format_labels(tags),
+            buffer: RetryBuffer::new(
+                "victoriametrics",
+                config.max_buffered_points,
+                None,
+                Duration::from_secs(config.max_retry_secs),
+            ),
+            filter: None,
+        })
+    }
+
+    async fn send(&mut self) -> Result<(), SinkError> {
+        let body = build_import(&self.buffer.body())?;
+        let mut post = self.client.post(self.url.clone()).header("Content-Type", "application/json").body(body);
+        if let Some(username) = &self.username {
+            post = post.basic_auth(username, self.password.as_ref());
+        }
+        if let Some(token) = &self.bearer_token {
+            post = post.bearer_auth(token);
+        }
+
+        let buffered = self.buffer.len();
+        let result = match post.send().await {
+            Ok(response) if response.status().is_success() => Ok(()),
+            // Malformed or refused for good, e.g. an unknown tenant; retrying
+            // would only block newer samples.
+            Ok(response) if response.status().is_client_error() && response.status().as_u16() != 429 => {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                telemetry::record_dropped_points(buffered);
+                self.buffer.succeeded();
+                return Err(format!("{}: {} ({} points dropped)", status, body.trim(), buffered).into());
+            }
+            Ok(response) => {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                Err(format!("{}: {}", status, body.trim()))
+            }
+            Err(e) => Err(e.to_string()),
+        };
+
+        match result {
+            Ok(()) => {
+                self.buffer.succeeded();
+                Ok(())
+            }
+            Err(e) => {
+                let backoff = self.buffer.failed();
+                Err(format!("{} ({} points buffered, retrying in {:.1?})", e, buffered, backoff).into())
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl MetricSink for VictoriaMetricsSink {
+    fn name(&self) -> &str {
+        "victoriametrics"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        let timestamp = metrics.timestamp.duration_since(UNIX_EPOCH)?.as_millis();
+        let mut text = render(metrics, &self.labels);
+        if let Some(filter) = &self.filter {
+            text = filter.filter_exposition(&text);
+        }
+        let lines = text
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| format!("{} {}", line, timestamp))
+            .collect::<Vec<_>>();
+        self.buffer.push(lines);
+
+        if !self.buffer.ready() {
+            return Ok(());
+        }
+        self.send().await
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.send().await
+    }
+
+    fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn retry_buffer(&mut self) -> Option<&mut RetryBuffer> {
+        Some(&mut self.buffer)
+    }
+
+    fn set_filter(&mut self, filter: MetricFilter) -> Result<(), SinkError> {
+        self.filter = Some(filter);
+        Ok(())
+    }
+}
+
+// Group exposition lines with timestamps into one JSON line per series,
+// `{"metric":{"__name__":"...",...},"values":[...],"timestamps":[...]}`.
+// JSON has no NaN or infinity, so such samples are left out.
+fn build_import(body: &str) -> Result<String, SinkError> {
+    let mut series: BTreeMap<Labels, Vec<(i64, f64)>> = BTreeMap::new();
+    for line in body.lines().filter(|line| !line.is_empty()) {
+        let (labels, value, timestamp) = parse_sample(line).ok_or_else(|| format!("unparsable sample {:?}", line))?;
+        if value.is_finite() {
+            series.entry(labels).or_default().push((timestamp, value));
+        }
+    }
+    let mut import = String::new();
+    for (labels, mut samples) in series {
+        samples.sort_by_key(|(timestamp, _)| *timestamp);
+        let (timestamps, values): (Vec<i64>, Vec<f64>) = samples.into_iter().unzip();
+        let metric: serde_json::Map<String, serde_json::Value> =
+            labels.into_iter().map(|(name, value)| (name, value.into())).collect();
+        import.push_str(&json!({ "metric": metric, "values": values, "timestamps": timestamps }).to_string());
+        import.push('\n');
+    }
+    Ok(import)
+}
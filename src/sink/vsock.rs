@@ -0,0 +1,119 @@
+use std::fs::File;
+use std::io::Write;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use nix::sys::socket::{accept4, bind, listen, socket, AddressFamily, SockAddr, SockFlag, SockType};
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info};
+
+use crate::config::VsockConfig;
+use crate::rapl::PowerMetrics;
+use crate::sink::{MetricSink, SinkError};
+
+// Accept connections from every guest.
+const VMADDR_CID_ANY: u32 = u32::MAX;
+
+// Writes the configured fields of the latest sample as one line of JSON to
+// every virtual machine that connects to the vsock port, then hangs up, like
+// [socket]. Guests need no network route to the host, only a virtio-vsock
+// device: `ryzenmon-rust guest` or `socat - VSOCK-CONNECT:2:9630`.
+pub struct VsockServer {
+    fields: Vec<String>,
+    latest: Arc<RwLock<Option<String>>>,
+    server: Option<JoinHandle<()>>,
+}
+
+impl VsockServer {
+    pub fn bind(config: &VsockConfig) -> Result<Self, SinkError> {
+        let listener = listen_vsock(config.port)
+            .map_err(|e| format!("cannot listen on vsock port {} (is vhost_vsock loaded?): {}", config.port, e))?;
+        info!("Serving the latest sample to guests on vsock port {}", config.port);
+
+        let latest = Arc::new(RwLock::new(None));
+        let server = tokio::spawn(serve(AsyncFd::new(listener)?, latest.clone()));
+        Ok(VsockServer {
+            fields: config.fields.clone(),
+            latest,
+            server: Some(server),
+        })
+    }
+}
+
+fn listen_vsock(port: u32) -> nix::Result<OwnedFd> {
+    let fd = socket(
+        AddressFamily::Vsock,
+        SockType::Stream,
+        SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+        None,
+    )?;
+    // Owned right away, so it is closed if binding fails.
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    bind(fd.as_raw_fd(), &SockAddr::new_vsock(VMADDR_CID_ANY, port))?;
+    listen(fd.as_raw_fd(), 16)?;
+    Ok(fd)
+}
+
+async fn serve(listener: AsyncFd<OwnedFd>, latest: Arc<RwLock<Option<String>>>) {
+    loop {
+        let accepted = listener
+            .async_io(Interest::READABLE, |fd| Ok(accept4(fd.as_raw_fd(), SockFlag::SOCK_CLOEXEC)?))
+            .await;
+        let mut client = match accepted {
+            Ok(fd) => File::from(unsafe { OwnedFd::from_raw_fd(fd) }),
+            Err(e) => {
+                error!("Vsock server failed: {}", e);
+                return;
+            }
+        };
+        let line = latest
+            .read()
+            .unwrap()
+            .clone()
+            .unwrap_or_else(|| serde_json::json!({ "error": "no sample yet" }).to_string());
+        // The accepted socket blocks; a slow guest must not hold up the next one.
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = client.write_all(format!("{}\n", line).as_bytes()) {
+                debug!("Writing to a vsock client failed: {}", e);
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl MetricSink for VsockServer {
+    fn name(&self) -> &str {
+        "vsock"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        let mut sample = serde_json::to_value(metrics)?;
+        if let Some(sample) = sample.as_object_mut() {
+            sample.retain(|field, _| self.fields.contains(field));
+        }
+        *self.latest.write().unwrap() = Some(sample.to_string());
+        Ok(())
+    }
+
+    async fn close(&mut self) {
+        if let Some(server) = self.server.take() {
+            server.abort();
+            let _ = server.await;
+        }
+    }
+
+    fn listens(&self) -> bool {
+        true
+    }
+}
+
+impl Drop for VsockServer {
+    fn drop(&mut self) {
+        if let Some(server) = &self.server {
+            server.abort();
+        }
+    }
+}
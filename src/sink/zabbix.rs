@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::warn;
+
+use crate::config::{hostname, ZabbixConfig};
+use crate::rapl::PowerMetrics;
+use crate::sink::filter::{filtered_gauges, MetricFilter};
+use crate::sink::{MetricSink, SinkError};
+use crate::units::Units;
+
+// Responses are a short JSON summary.
+const MAX_RESPONSE: u64 = 1024 * 1024;
+
+// Sends metrics to Zabbix trapper items with the sender protocol, the same
+// as zabbix_sender. Metrics are named like the StatsD gauges; each becomes
+// the item `<key_prefix>.<metric>`, or only those in `keys` are sent, under
+// the item keys they map to.
+pub struct ZabbixSink {
+    server: String,
+    host: String,
+    key_prefix: String,
+    keys: BTreeMap<String, String>,
+    timeout: Duration,
+    // Rejected values are only warned about once, they usually mean items
+    // that were never created.
+    warned: bool,
+    filter: Option<MetricFilter>,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    response: String,
+    #[serde(default)]
+    info: String,
+}
+
+impl ZabbixSink {
+    pub fn new(config: ZabbixConfig, tags: &BTreeMap<String, String>) -> Self {
+        ZabbixSink {
+            server: config.server,
+            host: config
+                .host
+                .or_else(|| tags.get("host").cloned())
+                .unwrap_or_else(hostname),
+            key_prefix: config.key_prefix.trim_end_matches('.').to_string(),
+            keys: config.keys,
+            timeout: Duration::from_secs(config.timeout_secs),
+            warned: false,
+            filter: None,
+        }
+    }
+
+    fn item_key(&self, metric: &str) -> Option<String> {
+        if !self.keys.is_empty() {
+            return self.keys.get(metric).cloned();
+        }
+        Some(format!("{}.{}", self.key_prefix, metric))
+    }
+
+    async fn send(&self, request: &[u8]) -> Result<Response, SinkError> {
+        let mut stream = TcpStream::connect(&self.server).await?;
+        stream.write_all(&frame(request)).await?;
+
+        let mut header = [0u8; 13];
+        stream.read_exact(&mut header).await?;
+        if &header[..4] != b"ZBXD" {
+            return Err("not a Zabbix server response".into());
+        }
+        let length = u64::from_le_bytes(header[5..].try_into()?);
+        if length > MAX_RESPONSE {
+            return Err(format!("response of {} bytes is too large", length).into());
+        }
+        let mut body = vec![0; length as usize];
+        stream.read_exact(&mut body).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+#[async_trait]
+impl MetricSink for ZabbixSink {
+    fn name(&self) -> &str {
+        "zabbix"
+    }
+
+    async fn write(&mut self, metrics: &PowerMetrics) -> Result<(), SinkError> {
+        let since_epoch = metrics.timestamp.duration_since(UNIX_EPOCH)?;
+        let data: Vec<_> = filtered_gauges(self.filter.as_ref(), metrics)
+            .into_iter()
+            .filter_map(|(metric, value)| {
+                Some(json!({
+                    "host": self.host,
+                    "key": self.item_key(&metric)?,
+                    "value": value.to_string(),
+                    "clock": since_epoch.as_secs(),
+                    "ns": since_epoch.subsec_nanos(),
+                }))
+            })
+            .collect();
+        if data.is_empty() {
+            return Ok(());
+        }
+        let request = json!({ "request": "sender data", "data": data }).to_string();
+
+        let response = tokio::time::timeout(self.timeout, self.send(request.as_bytes()))
+            .await
+            .map_err(|_| format!("{} did not answer within {:?}", self.server, self.timeout))??;
+        if response.response != "success" {
+            return Err(format!("{} refused the values: {}", self.server, response.info).into());
+        }
+        let failed = failed(&response.info);
+        if failed > 0 && !self.warned {
+            warn!(
+                "Zabbix rejected {} of {} values for host {:?}: {}. Create trapper items for them or list the ones to send in [zabbix.keys]",
+                failed,
+                data.len(),
+                self.host,
+                response.info
+            );
+            self.warned = true;
+        }
+        Ok(())
+    }
+
+    fn set_filter(&mut self, filter: MetricFilter) -> Result<(), SinkError> {
+        self.filter = Some(filter);
+        Ok(())
+    }
+
+    fn set_units(&mut self, _units: &Units) -> bool {
+        true
+    }
+}
+
+// "ZBXD", flags (0x01, uncompressed), and the length as eight little-endian
+// bytes, which Zabbix 4.0 and later read as a 4-byte length and 4 reserved
+// bytes.
+fn frame(data: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(data.len() + 13);
+    frame.extend_from_slice(b"ZBXD\x01");
+    frame.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    frame.extend_from_slice(data);
+    frame
+}
+
+// The number of rejected values in the info of a response, e.g. 1 for
+// "processed: 3; failed: 1; total: 4; seconds spent: 0.000055".
+fn failed(info: &str) -> usize {
+    info.split(';')
+        .find_map(|field| field.trim().strip_prefix("failed:"))
+        .and_then(|failed| failed.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use tokio::net::TcpListener;
+
+    use crate::platform::fixture_sample;
+
+    fn config(server: String) -> ZabbixConfig {
+        ZabbixConfig {
+            server,
+            host: Some("desktop".to_string()),
+            key_prefix: "ryzenmon.".to_string(),
+            keys: BTreeMap::new(),
+            timeout_secs: 5,
+        }
+    }
+
+    #[test]
+    fn frames_data_with_header_and_length() {
+        let framed = frame(b"{\"request\":\"sender data\"}");
+        assert_eq!(&framed[..5], b"ZBXD\x01");
+        assert_eq!(framed[5..13], 25u64.to_le_bytes());
+        assert_eq!(&framed[13..], b"{\"request\":\"sender data\"}");
+        assert_eq!(frame(b"").len(), 13);
+    }
+
+    #[test]
+    fn parses_responses() {
+        let response: Response =
+            serde_json::from_str(r#"{"response":"success","info":"processed: 3; failed: 1; total: 4; seconds spent: 0.000055"}"#)
+                .unwrap();
+        assert_eq!(response.response, "success");
+        assert_eq!(failed(&response.info), 1);
+        assert_eq!(failed("processed: 4; failed: 0; total: 4; seconds spent: 0.000041"), 0);
+
+        let response: Response = serde_json::from_str(r#"{"response":"failed"}"#).unwrap();
+        assert_eq!(response.response, "failed");
+        assert_eq!(failed(&response.info), 0);
+    }
+
+    #[test]
+    fn maps_item_keys() {
+        let mut sink = ZabbixSink::new(config("localhost:10051".to_string()), &BTreeMap::new());
+        assert_eq!(sink.item_key("package_power").as_deref(), Some("ryzenmon.package_power"));
+
+        sink.keys = BTreeMap::from([("package_power".to_string(), "cpu.power".to_string())]);
+        assert_eq!(sink.item_key("package_power").as_deref(), Some("cpu.power"));
+        assert_eq!(sink.item_key("core_power"), None);
+    }
+
+    // A trapper that answers one request with `info` and returns the request.
+    async fn trapper(listener: TcpListener, info: &'static str) -> Value {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut header = [0u8; 13];
+        stream.read_exact(&mut header).await.unwrap();
+        assert_eq!(&header[..5], b"ZBXD\x01");
+        let mut body = vec![0; u64::from_le_bytes(header[5..].try_into().unwrap()) as usize];
+        stream.read_exact(&mut body).await.unwrap();
+        let response = json!({ "response": "success", "info": info }).to_string();
+        stream.write_all(&frame(response.as_bytes())).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn sends_values_to_a_trapper() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let trapper = tokio::spawn(trapper(listener, "processed: 2; failed: 1; total: 3; seconds spent: 0.000050"));
+
+        let mut sink = ZabbixSink::new(config(server), &BTreeMap::new());
+        let metrics = fixture_sample();
+        sink.write(&metrics).await.unwrap();
+        assert!(sink.warned);
+
+        let request = trapper.await.unwrap();
+        assert_eq!(request["request"], "sender data");
+        let package = request["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|item| item["key"] == "ryzenmon.package_power")
+            .unwrap();
+        assert_eq!(package["host"], "desktop");
+        assert_eq!(package["value"], metrics.package_watts.to_string());
+        assert_eq!(package["clock"], metrics.timestamp.duration_since(UNIX_EPOCH).unwrap().as_secs());
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::config::{SmoothingConfig, SmoothingMethod};
+use crate::rapl::PowerMetrics;
+
+// Smooths power readings across samples before they are uploaded, either
+// with an exponential moving average or as the median of the last few
+// samples, per series: package, core sum, DRAM, each package, CCD and core.
+pub struct Smoother {
+    config: SmoothingConfig,
+    series: BTreeMap<String, Series>,
+    cores: usize,
+}
+
+enum Series {
+    Ema(f64),
+    Median(VecDeque<f64>),
+}
+
+impl Smoother {
+    pub fn new(config: SmoothingConfig) -> Self {
+        Smoother {
+            config,
+            series: BTreeMap::new(),
+            cores: 0,
+        }
+    }
+
+    pub fn config(&self) -> &SmoothingConfig {
+        &self.config
+    }
+
+    pub fn apply(&mut self, metrics: &mut PowerMetrics) {
+        // Core indices shift when cores go offline, so their history no
+        // longer belongs to them.
+        if metrics.core_watts.len() != self.cores {
+            self.series.retain(|key, _| !key.starts_with("core/"));
+            self.cores = metrics.core_watts.len();
+        }
+        metrics.package_watts = self.smooth("package", metrics.package_watts);
+        metrics.core_sum = self.smooth("core_sum", metrics.core_sum);
+        if let Some(dram_watts) = metrics.dram_watts {
+            metrics.dram_watts = Some(self.smooth("dram", dram_watts));
+        }
+        for package in &mut metrics.packages {
+            package.watts = self.smooth(&format!("package/{}", package.package), package.watts);
+        }
+        for ccd in &mut metrics.ccds {
+            ccd.watts = self.smooth(&format!("ccd/{}", ccd.ccd), ccd.watts);
+        }
+        for (core, watts) in metrics.core_watts.iter_mut().enumerate() {
+            *watts = self.smooth(&format!("core/{}", core), *watts);
+        }
+    }
+
+    fn smooth(&mut self, key: &str, value: f64) -> f64 {
+        if !value.is_finite() {
+            return value;
+        }
+        let series = self.series.entry(key.to_string()).or_insert_with(|| match self.config.method {
+            SmoothingMethod::Ema => Series::Ema(value),
+            SmoothingMethod::Median => Series::Median(VecDeque::new()),
+        });
+        match series {
+            Series::Ema(average) => {
+                *average += self.config.alpha * (value - *average);
+                *average
+            }
+            Series::Median(window) => {
+                window.push_back(value);
+                if window.len() > self.config.samples {
+                    window.pop_front();
+                }
+                let mut sorted: Vec<f64> = window.iter().copied().collect();
+                sorted.sort_by(f64::total_cmp);
+                let middle = sorted.len() / 2;
+                match sorted.len() % 2 {
+                    0 => (sorted[middle - 1] + sorted[middle]) / 2.0,
+                    _ => sorted[middle],
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn smoother(method: SmoothingMethod) -> Smoother {
+        Smoother::new(SmoothingConfig {
+            method,
+            alpha: 0.5,
+            samples: 3,
+        })
+    }
+
+    #[test]
+    fn ema_starts_at_the_first_value() {
+        let mut ema = smoother(SmoothingMethod::Ema);
+        assert_eq!(ema.smooth("package", 10.0), 10.0);
+        assert_eq!(ema.smooth("package", 20.0), 15.0);
+        assert_eq!(ema.smooth("package", 20.0), 17.5);
+        assert_eq!(ema.smooth("core/0", 4.0), 4.0);
+    }
+
+    #[test]
+    fn median_drops_spikes() {
+        let mut median = smoother(SmoothingMethod::Median);
+        assert_eq!(median.smooth("package", 10.0), 10.0);
+        assert_eq!(median.smooth("package", 12.0), 11.0);
+        assert_eq!(median.smooth("package", 90.0), 12.0);
+        assert_eq!(median.smooth("package", 11.0), 12.0);
+        assert_eq!(median.smooth("package", 13.0), 13.0);
+    }
+}
@@ -0,0 +1,135 @@
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::{Credentials, Mechanism};
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::transport::smtp::extension::ClientId;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+use crate::config::{EmailConfig, SmtpTls};
+use crate::error::{RyzenmonError, Result};
+
+// Send one message with `subject` and `body` to every address in config.to,
+// over implicit TLS, STARTTLS or plain SMTP, logging in with AUTH PLAIN when
+// a username is set.
+pub async fn send(config: &EmailConfig, host: &str, subject: &str, body: &str) -> Result<()> {
+    let message = message(config, subject, body)?;
+    let tls = match config.tls {
+        SmtpTls::Tls => Tls::Wrapper(TlsParameters::new(config.server.clone())?),
+        SmtpTls::Starttls => Tls::Required(TlsParameters::new(config.server.clone())?),
+        SmtpTls::None => Tls::None,
+    };
+    let mut transport = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.server)
+        .port(config.port())
+        .tls(tls)
+        .hello_name(ClientId::Domain(host.to_string()));
+    if let Some(username) = &config.username {
+        transport = transport
+            .credentials(Credentials::new(username.clone(), config.password()?))
+            .authentication(vec![Mechanism::Plain]);
+    }
+    transport.build().send(message).await?;
+    Ok(())
+}
+
+// A plain text message, with the subject and any names in the addresses
+// encoded for headers.
+fn message(config: &EmailConfig, subject: &str, body: &str) -> Result<Message> {
+    let mailbox = |address: &str| {
+        address
+            .parse::<Mailbox>()
+            .map_err(|e| RyzenmonError::Smtp(format!("invalid address {:?}: {}", address, e)))
+    };
+    let mut builder = Message::builder().from(mailbox(&config.from)?);
+    for to in &config.to {
+        builder = builder.to(mailbox(to)?);
+    }
+    builder
+        .subject(subject)
+        .date_now()
+        .header(ContentType::TEXT_PLAIN)
+        .body(body.to_string())
+        .map_err(|e| RyzenmonError::Smtp(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    fn config(port: u16) -> EmailConfig {
+        EmailConfig {
+            server: "127.0.0.1".to_string(),
+            port: Some(port),
+            tls: SmtpTls::None,
+            username: Some("user".to_string()),
+            password: Some("secret".to_string()),
+            password_file: None,
+            from: "Überwachung <ryzenmon@example.com>".to_string(),
+            to: vec!["ops@example.com".to_string(), "oncall@example.com".to_string()],
+        }
+    }
+
+    #[test]
+    fn encodes_headers() {
+        let formatted = message(&config(25), "[ryzenmon] hot firing on Jörg's desktop", "tctl = 96.0\n").unwrap().formatted();
+        let formatted = String::from_utf8(formatted).unwrap();
+        assert!(formatted.is_ascii(), "{}", formatted);
+        assert!(formatted.contains("Subject: [ryzenmon] hot firing on =?utf-8?b?"), "{}", formatted);
+        assert!(formatted.contains("From: =?utf-8?b?"), "{}", formatted);
+        assert!(formatted.contains("To: ops@example.com, oncall@example.com"), "{}", formatted);
+
+        let mut bad = config(25);
+        bad.to.push("not an address".to_string());
+        assert!(matches!(message(&bad, "hot", ""), Err(RyzenmonError::Smtp(_))));
+    }
+
+    #[tokio::test]
+    async fn sends_a_message() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            stream.get_mut().write_all(b"220 mail ready\r\n").await.unwrap();
+            let mut received = Vec::new();
+            let mut data = false;
+            loop {
+                let mut line = String::new();
+                if stream.read_line(&mut line).await.unwrap() == 0 {
+                    break;
+                }
+                let line = line.trim_end().to_string();
+                let reply: &[u8] = match line.as_str() {
+                    _ if data && line == "." => {
+                        data = false;
+                        b"250 queued\r\n"
+                    }
+                    _ if data => b"",
+                    "DATA" => {
+                        data = true;
+                        b"354 go ahead\r\n"
+                    }
+                    _ if line.starts_with("EHLO") => b"250-mail\r\n250 AUTH PLAIN\r\n",
+                    _ if line.starts_with("AUTH") => b"235 ok\r\n",
+                    "QUIT" => b"221 bye\r\n",
+                    _ => b"250 ok\r\n",
+                };
+                received.push(line);
+                stream.get_mut().write_all(reply).await.unwrap();
+            }
+            received
+        });
+
+        send(&config(port), "box", "hot firing", "tctl = 96.0\n.hidden").await.unwrap();
+
+        let received = server.await.unwrap();
+        assert_eq!(received[0], "EHLO box");
+        assert_eq!(received[1], "AUTH PLAIN AHVzZXIAc2VjcmV0");
+        assert_eq!(received[2], "MAIL FROM:<ryzenmon@example.com>");
+        assert_eq!(received[4], "RCPT TO:<oncall@example.com>");
+        assert!(received.contains(&"Subject: hot firing".to_string()));
+        assert!(received.contains(&"..hidden".to_string()));
+        assert_eq!(received.last().unwrap(), "QUIT");
+    }
+}
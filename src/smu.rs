@@ -0,0 +1,126 @@
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+pub const RYZEN_SMU_ROOT: &str = "/sys/kernel/ryzen_smu_drv";
+
+// PM table versions of Matisse, Vermeer and Raphael desktop parts. They all
+// start with the same limit/value pairs: PPT, TDC, THM, FIT, EDC.
+const KNOWN_PM_TABLE_VERSIONS: &[u32] = &[
+    0x240802, 0x240803, 0x240902, 0x240903, // Matisse
+    0x380804, 0x380805, 0x380904, 0x380905, // Vermeer
+    0x540100, 0x540101, 0x540102, 0x540103, 0x540104, 0x540105, 0x540108, // Raphael
+];
+
+// Float offsets into the PM table
+const PPT_LIMIT: usize = 0;
+const PPT_VALUE: usize = 1;
+const TDC_LIMIT: usize = 2;
+const TDC_VALUE: usize = 3;
+const THM_LIMIT: usize = 4;
+const THM_VALUE: usize = 5;
+const EDC_LIMIT: usize = 8;
+const EDC_VALUE: usize = 9;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Limit {
+    pub value: f64,
+    pub limit: f64,
+}
+
+impl Limit {
+    // How much of the limit is used, 0.0 to 1.0 and above when boosting past it.
+    pub fn usage(&self) -> f64 {
+        if self.limit > 0.0 {
+            self.value / self.limit
+        } else {
+            0.0
+        }
+    }
+}
+
+// Package power (PPT, watts) and VRM current (TDC sustained, EDC peak, amps)
+// against the limits the SMU enforces.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmuLimits {
+    pub ppt: Limit,
+    pub tdc: Limit,
+    pub edc: Limit,
+    // Temperature against the thermal limit in °C, only used to detect
+    // throttling
+    #[serde(default)]
+    pub thm: Option<Limit>,
+}
+
+impl SmuLimits {
+    // Name, reading and unit of every limit, for sinks that write them generically.
+    pub fn iter(&self) -> [(&'static str, Limit, &'static str); 3] {
+        [("ppt", self.ppt, "W"), ("tdc", self.tdc, "A"), ("edc", self.edc, "A")]
+    }
+}
+
+// PPT/TDC/EDC from the ryzen_smu PM table, None when the module is not loaded,
+// the table is not readable or its layout is unknown.
+pub fn read_limits() -> Option<SmuLimits> {
+    let version = pm_table_version()?;
+    if !KNOWN_PM_TABLE_VERSIONS.contains(&version) {
+        return None;
+    }
+    parse_limits(&fs::read(Path::new(RYZEN_SMU_ROOT).join("pm_table")).ok()?)
+}
+
+// PM table version of the loaded ryzen_smu driver, which identifies the CPU.
+pub fn pm_table_version() -> Option<u32> {
+    read_pm_table_version(&Path::new(RYZEN_SMU_ROOT).join("pm_table_version"))
+}
+
+// pm_table_version is a native endian u32.
+fn read_pm_table_version(path: &Path) -> Option<u32> {
+    let bytes = fs::read(path).ok()?;
+    Some(u32::from_ne_bytes(bytes.get(..4)?.try_into().ok()?))
+}
+
+pub fn parse_limits(table: &[u8]) -> Option<SmuLimits> {
+    let float = |index: usize| -> Option<f64> {
+        let bytes = table.get(index * 4..index * 4 + 4)?;
+        Some(f32::from_le_bytes(bytes.try_into().ok()?) as f64)
+    };
+    let limit = |value: usize, limit: usize| -> Option<Limit> {
+        Some(Limit {
+            value: float(value)?,
+            limit: float(limit)?,
+        })
+    };
+
+    Some(SmuLimits {
+        ppt: limit(PPT_VALUE, PPT_LIMIT)?,
+        tdc: limit(TDC_VALUE, TDC_LIMIT)?,
+        edc: limit(EDC_VALUE, EDC_LIMIT)?,
+        thm: limit(THM_VALUE, THM_LIMIT),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_limit_header() {
+        let floats = [142.0f32, 88.5, 95.0, 60.25, 90.0, 55.0, 1.0, 0.5, 140.0, 120.0];
+        let table: Vec<u8> = floats.iter().flat_map(|f| f.to_le_bytes()).collect();
+
+        let limits = parse_limits(&table).unwrap();
+        assert_eq!(limits.ppt.limit, 142.0);
+        assert_eq!(limits.ppt.value, 88.5);
+        assert_eq!(limits.tdc.value, 60.25);
+        assert_eq!(limits.edc.limit, 140.0);
+        assert_eq!(limits.thm.unwrap().value, 55.0);
+        assert!((limits.edc.usage() - 120.0 / 140.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rejects_short_table() {
+        assert!(parse_limits(&[0u8; 16]).is_none());
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+
+use serde_json::{json, Map, Value};
+
+use ryzenmon_rust::config::{is_secret_env, redact_secrets, ENV_PREFIX, REDACTED};
+use ryzenmon_rust::msr::{self, MsrAccess, MsrDevice, MsrMap, MSR_APERF, MSR_MPERF, MSR_TSC};
+use ryzenmon_rust::rapl::energy_unit_joules;
+use ryzenmon_rust::topology::Topology;
+use ryzenmon_rust::{hwmon, powercap, smu};
+
+use crate::cli::{Cli, SnapshotFormat};
+use crate::read_config;
+
+// `ryzenmon snapshot`: everything this machine lets ryzenmon read, for bug
+// reports. Every part is read on its own, so one that fails shows up as an
+// error instead of hiding the rest.
+pub fn run(cli: &Cli, format: SnapshotFormat) {
+    let snapshot = collect(cli);
+    match format {
+        SnapshotFormat::Json => println!("{}", serde_json::to_string_pretty(&snapshot).unwrap_or_default()),
+        SnapshotFormat::Yaml => print!("{}", to_yaml(&snapshot)),
+    }
+}
+
+fn collect(cli: &Cli) -> Value {
+    let access = MsrAccess::detect();
+    let cpu = msr::detect_cpu();
+    let map = cpu.as_ref().map_err(|e| e.to_string()).and_then(|cpu| MsrMap::for_cpu(cpu).map_err(|e| e.to_string()));
+    let topology = Topology::detect();
+    let sensors = read_config(cli).map(|config| config.hwmon).unwrap_or_default();
+
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "kernel": fs::read_to_string("/proc/sys/kernel/osrelease").ok().map(|release| release.trim().to_string()),
+        "cpu": {
+            "model": msr::cpu_model(),
+            "id": or_error(cpu),
+        },
+        "msr_access": {
+            "msr": format!("{:?}", access.msr),
+            "msr_safe": format!("{:?}", access.msr_safe),
+            "root": access.root,
+            "raw_io": access.raw_io,
+            "hint": access.hint(),
+        },
+        "topology": match &topology {
+            Ok(topology) => json!(topology),
+            Err(e) => error(e),
+        },
+        "msrs": match (&map, &topology) {
+            (Ok(map), Ok(topology)) => read_msrs(map, topology),
+            (Err(e), _) => error(e),
+            (_, Err(e)) => error(e),
+        },
+        "powercap": powercap::powercap_available(),
+        "smu": {
+            "pm_table_version": smu::pm_table_version().map(|version| format!("{:#x}", version)),
+            "limits": smu::read_limits(),
+        },
+        "hwmon": {
+            "k10temp": hwmon::read_k10temp(),
+            "svi_rails": hwmon::read_svi_rails(),
+            "amdgpu": hwmon::read_amdgpu(),
+            "sensors": hwmon::read_sensors(&sensors),
+        },
+        "config": config_summary(cli),
+    })
+}
+
+// The addresses of the RAPL registers, then their raw values and those of
+// the clock counters on every core, with the energy unit of each package.
+fn read_msrs(map: &MsrMap, topology: &Topology) -> Value {
+    let mut registers = vec![("power_unit", map.power_unit), ("core_energy", map.core_energy)];
+    registers.extend([("tsc", MSR_TSC), ("mperf", MSR_MPERF), ("aperf", MSR_APERF)]);
+    let mut package_registers = vec![("package_energy", map.package_energy)];
+    package_registers.extend(map.dram_energy.map(|dram| ("dram_energy", dram)));
+
+    let mut cpus: BTreeMap<usize, Vec<(&str, u64)>> = topology.cores.iter().map(|&cpu| (cpu, registers.clone())).collect();
+    for package in &topology.packages {
+        let cpu = cpus.entry(package.cpu).or_insert_with(|| registers.clone());
+        cpu.extend(package_registers.iter().copied());
+    }
+
+    let mut values = Map::new();
+    for (cpu, registers) in &cpus {
+        let read = match MsrDevice::open(*cpu) {
+            Ok(mut device) => Value::Object(
+                registers
+                    .iter()
+                    .map(|&(name, which)| {
+                        let value = device.read(which).map(|raw| format!("{:#x}", raw));
+                        (name.to_string(), or_error(value))
+                    })
+                    .collect(),
+            ),
+            Err(e) => error(e),
+        };
+        values.insert(cpu.to_string(), read);
+    }
+
+    let energy_units: Vec<Value> = topology
+        .packages
+        .iter()
+        .map(|package| {
+            let joules = MsrDevice::open(package.cpu)
+                .and_then(|mut device| device.read(map.power_unit))
+                .map(|raw| energy_unit_joules(raw, map.energy_unit_mask));
+            json!({ "package": package.id, "joules": or_error(joules) })
+        })
+        .collect();
+
+    let addresses: Map<String, Value> = registers
+        .iter()
+        .chain(&package_registers)
+        .map(|&(name, which)| (name.to_string(), json!(format!("{:#x}", which))))
+        .collect();
+
+    json!({
+        "registers": addresses,
+        "energy_units": energy_units,
+        "dram_energy_unit": map.dram_energy_unit,
+        "cpus": values,
+    })
+}
+
+// The config file as written, and the RYZENMON_ variables overriding it,
+// with credentials replaced by <redacted>.
+fn config_summary(cli: &Cli) -> Value {
+    let file = match fs::read_to_string(&cli.config) {
+        Ok(content) => match toml::from_str::<toml::Value>(&content) {
+            Ok(mut value) => {
+                redact_secrets(&mut value);
+                json!(value)
+            }
+            Err(e) => error(e),
+        },
+        Err(e) => error(e),
+    };
+    let environment: Map<String, Value> = std::env::vars()
+        .filter(|(key, _)| key.starts_with(ENV_PREFIX))
+        .map(|(key, value)| {
+            let value = if is_secret_env(&key) { REDACTED.to_string() } else { value };
+            (key, json!(value))
+        })
+        .collect();
+
+    json!({
+        "path": cli.config.display().to_string(),
+        "file": file,
+        "environment": environment,
+        "valid": read_config(cli).map(|_| json!(true)).unwrap_or_else(error),
+    })
+}
+
+fn error(e: impl Display) -> Value {
+    json!({ "error": e.to_string() })
+}
+
+fn or_error<T: serde::Serialize, E: Display>(result: Result<T, E>) -> Value {
+    match result {
+        Ok(value) => json!(value),
+        Err(e) => error(e),
+    }
+}
+
+// Block style YAML. Keys and strings are double quoted the way JSON quotes
+// them, which YAML reads back unchanged, so keys like `true`, `null`, `yes`
+// or `1e3` stay strings.
+fn to_yaml(value: &Value) -> String {
+    let mut yaml = String::new();
+    for line in yaml_lines(value) {
+        yaml.push_str(&line);
+        yaml.push('\n');
+    }
+    yaml
+}
+
+fn yaml_lines(value: &Value) -> Vec<String> {
+    let mut lines = Vec::new();
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                if is_block(value) {
+                    lines.push(format!("{}:", yaml_key(key)));
+                    lines.extend(yaml_lines(value).into_iter().map(|line| format!("  {}", line)));
+                } else {
+                    lines.push(format!("{}: {}", yaml_key(key), yaml_scalar(value)));
+                }
+            }
+        }
+        Value::Array(values) if !values.is_empty() => {
+            for value in values {
+                if is_block(value) {
+                    let mut item = yaml_lines(value).into_iter();
+                    lines.extend(item.next().map(|first| format!("- {}", first)));
+                    lines.extend(item.map(|line| format!("  {}", line)));
+                } else {
+                    lines.push(format!("- {}", yaml_scalar(value)));
+                }
+            }
+        }
+        value => lines.push(yaml_scalar(value)),
+    }
+    lines
+}
+
+// Non-empty maps and lists, which go on lines of their own.
+fn is_block(value: &Value) -> bool {
+    match value {
+        Value::Object(map) => !map.is_empty(),
+        Value::Array(values) => !values.is_empty(),
+        _ => false,
+    }
+}
+
+fn yaml_key(key: &str) -> String {
+    Value::String(key.to_string()).to_string()
+}
+
+fn yaml_scalar(value: &Value) -> String {
+    match value {
+        Value::Object(_) => "{}".to_string(),
+        Value::Array(_) => "[]".to_string(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yaml_reads_back_unchanged() {
+        let snapshot = json!({
+            "true": "yes",
+            "null": null,
+            "yes": ["no", "1e3", 1e3, true, "", {}],
+            "1e3": { "~": "- item", "key: value": "#comment", "multi\nline": [] },
+            "-": [{ "on": "off", "0x10": 16 }, [1, [2]]],
+        });
+        let parsed: Value = serde_yaml::from_str(&to_yaml(&snapshot)).unwrap();
+        assert_eq!(parsed, snapshot);
+    }
+}
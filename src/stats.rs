@@ -0,0 +1,275 @@
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::rapl::PowerMetrics;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub p95: f64,
+}
+
+impl Summary {
+    // None for an empty slice. p95 uses the nearest rank.
+    pub fn of(values: &[f64]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        Some(Summary {
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            p95: percentile(&sorted, 95.0),
+        })
+    }
+
+    // Suffix and value of every statistic but the mean, which replaces the
+    // reading itself.
+    pub fn iter(&self) -> [(&'static str, f64); 3] {
+        [("min", self.min), ("max", self.max), ("p95", self.p95)]
+    }
+}
+
+// The `p`th percentile, between 0 and 100, of non-empty sorted values by
+// nearest rank.
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (sorted.len() as f64 * (p / 100.0)).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+// Power over all samples taken during one upload interval.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PowerStats {
+    pub samples: usize,
+    pub core_sum: Summary,
+    pub package: Summary,
+    pub dram: Option<Summary>,
+    // In the same order as core_watts
+    pub cores: Vec<Summary>,
+}
+
+impl PowerStats {
+    // Metric name, core and summary of every entry, for sinks that write them generically.
+    pub fn iter(&self) -> Vec<(&'static str, Option<usize>, Summary)> {
+        let mut entries = vec![("package", None, self.package), ("core_sum", None, self.core_sum)];
+        entries.extend(self.dram.map(|dram| ("dram", None, dram)));
+        entries.extend(self.cores.iter().enumerate().map(|(core, summary)| ("core", Some(core), *summary)));
+        entries
+    }
+}
+
+// Fold the samples of one interval into a single reading: power becomes the
+// mean over the interval with min/max/p95 in `stats`, everything else is
+// taken from the last sample. A single sample is passed through unchanged.
+pub fn aggregate(mut samples: Vec<PowerMetrics>) -> Option<PowerMetrics> {
+    let mut metrics = samples.pop()?;
+    if samples.is_empty() {
+        return Some(metrics);
+    }
+    samples.push(metrics.clone());
+
+    let summary = |value: &dyn Fn(&PowerMetrics) -> Option<f64>| {
+        Summary::of(&samples.iter().filter_map(value).collect::<Vec<_>>())
+    };
+    let core_sum = summary(&|m| Some(m.core_sum))?;
+    let package = summary(&|m| Some(m.package_watts))?;
+    let dram = summary(&|m| m.dram_watts);
+    let cores: Vec<Summary> = (0..metrics.core_watts.len())
+        .filter_map(|core| summary(&|m| m.core_watts.get(core).copied()))
+        .collect();
+
+    metrics.core_sum = core_sum.mean;
+    metrics.package_watts = package.mean;
+    if let Some(dram) = &dram {
+        metrics.dram_watts = Some(dram.mean);
+    }
+    if let Some(soc) = summary(&|m| m.soc_watts) {
+        metrics.soc_watts = Some(soc.mean);
+    }
+    for (watts, core) in metrics.core_watts.iter_mut().zip(&cores) {
+        *watts = core.mean;
+    }
+    for (index, package) in metrics.packages.iter_mut().enumerate() {
+        if let Some(mean) = summary(&|m| m.packages.get(index).map(|p| p.watts)) {
+            package.watts = mean.mean;
+        }
+    }
+
+    // Uploads folded by [downsample] each carry the counts of their own interval.
+    if let Some(histograms) = &mut metrics.histograms {
+        for earlier in samples[..samples.len() - 1].iter().filter_map(|m| m.histograms.as_ref()) {
+            histograms.add_interval(earlier);
+        }
+    }
+
+    metrics.stats = Some(PowerStats {
+        samples: samples.len(),
+        core_sum,
+        package,
+        dram,
+        cores,
+    });
+    Some(metrics)
+}
+
+// Folds samples into one per `resolution` of wall clock time, e.g. one per
+// minute on the minute. A sample belongs to the period its window ends in,
+// so one taken at 12:01:00 completes the period from 12:00:00.
+pub struct Downsampler {
+    resolution_ms: u64,
+    // Number of the period `samples` belong to
+    period: Option<u64>,
+    samples: Vec<PowerMetrics>,
+}
+
+impl Downsampler {
+    pub fn new(resolution: Duration) -> Self {
+        Downsampler {
+            resolution_ms: (resolution.as_millis() as u64).max(1),
+            period: None,
+            samples: Vec::new(),
+        }
+    }
+
+    // Add a sample, returning the aggregate of every period it completes,
+    // each stamped with the end of its period.
+    pub fn push(&mut self, metrics: PowerMetrics) -> Vec<PowerMetrics> {
+        let ms = metrics.timestamp.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+        let period = ms.div_ceil(self.resolution_ms);
+        let mut complete = Vec::new();
+        if self.period.is_some_and(|pending| pending != period) {
+            complete.extend(self.complete());
+        }
+        self.period = Some(period);
+        self.samples.push(metrics);
+        // The last sample of a period completes it without waiting for the next.
+        if ms.is_multiple_of(self.resolution_ms) {
+            complete.extend(self.complete());
+        }
+        complete
+    }
+
+    // The aggregate of the samples of an unfinished period, e.g. at shutdown,
+    // with the time of its last sample.
+    pub fn take(&mut self) -> Option<PowerMetrics> {
+        self.period = None;
+        aggregate(std::mem::take(&mut self.samples))
+    }
+
+    fn complete(&mut self) -> Option<PowerMetrics> {
+        let end = self.period?.checked_mul(self.resolution_ms)?;
+        let mut metrics = self.take()?;
+        metrics.timestamp = UNIX_EPOCH + Duration::from_millis(end);
+        Some(metrics)
+    }
+}
+
+// Lowest package power over a trailing window, e.g. a day, so a BIOS or
+// kernel update that raises idle power stands out. Only readings that can
+// still become the minimum are kept, in increasing order.
+pub struct IdleFloor {
+    window: Duration,
+    readings: VecDeque<(SystemTime, f64)>,
+}
+
+impl IdleFloor {
+    pub fn new(window: Duration) -> Self {
+        IdleFloor {
+            window,
+            readings: VecDeque::new(),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    // Add the reading taken `at`, returning the floor over the window up to it.
+    pub fn push(&mut self, at: SystemTime, watts: f64) -> Option<f64> {
+        if watts.is_finite() {
+            while self.readings.back().is_some_and(|&(_, last)| last >= watts) {
+                self.readings.pop_back();
+            }
+            self.readings.push_back((at, watts));
+        }
+        while self
+            .readings
+            .front()
+            .is_some_and(|&(taken, _)| at.duration_since(taken).unwrap_or_default() > self.window)
+        {
+            self.readings.pop_front();
+        }
+        self.readings.front().map(|&(_, watts)| watts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::platform::fixture_sample;
+
+    #[test]
+    fn summarizes_with_nearest_rank_p95() {
+        let values: Vec<f64> = (1..=20).map(f64::from).collect();
+        let summary = Summary::of(&values).unwrap();
+        assert_eq!(summary.min, 1.0);
+        assert_eq!(summary.max, 20.0);
+        assert_eq!(summary.mean, 10.5);
+        assert_eq!(summary.p95, 19.0);
+
+        assert_eq!(Summary::of(&[42.0]).unwrap().p95, 42.0);
+        assert!(Summary::of(&[]).is_none());
+    }
+
+    #[test]
+    fn downsamples_to_one_aggregate_per_period() {
+        let sample = fixture_sample();
+        let at = |secs: u64, watts: f64| {
+            let mut metrics = sample.clone();
+            metrics.timestamp = UNIX_EPOCH + Duration::from_secs(secs);
+            metrics.package_watts = watts;
+            metrics
+        };
+
+        let mut downsampler = Downsampler::new(Duration::from_secs(60));
+        assert!(downsampler.push(at(1_700_000_010, 10.0)).is_empty());
+        assert!(downsampler.push(at(1_700_000_030, 20.0)).is_empty());
+        // 1_700_000_040 is a whole minute, which ends the period
+        let complete = downsampler.push(at(1_700_000_040, 30.0));
+        assert_eq!(complete.len(), 1);
+        assert_eq!(complete[0].package_watts, 20.0);
+        assert_eq!(complete[0].stats.as_ref().unwrap().samples, 3);
+        assert_eq!(complete[0].timestamp, UNIX_EPOCH + Duration::from_secs(1_700_000_040));
+
+        // A sample in a later period completes the pending one first
+        assert!(downsampler.push(at(1_700_000_050, 40.0)).is_empty());
+        let complete = downsampler.push(at(1_700_000_130, 50.0));
+        assert_eq!(complete.len(), 1);
+        assert_eq!(complete[0].package_watts, 40.0);
+        assert_eq!(complete[0].timestamp, UNIX_EPOCH + Duration::from_secs(1_700_000_100));
+
+        let rest = downsampler.take().unwrap();
+        assert_eq!(rest.timestamp, UNIX_EPOCH + Duration::from_secs(1_700_000_130));
+        assert!(downsampler.take().is_none());
+    }
+
+    #[test]
+    fn tracks_the_lowest_power_over_the_window() {
+        let mut floor = IdleFloor::new(Duration::from_secs(60));
+        let at = |secs: u64| UNIX_EPOCH + Duration::from_secs(secs);
+        assert_eq!(floor.push(at(0), 30.0), Some(30.0));
+        assert_eq!(floor.push(at(10), 20.0), Some(20.0));
+        assert_eq!(floor.push(at(20), 50.0), Some(20.0));
+        assert_eq!(floor.push(at(30), 25.0), Some(20.0));
+        // 20 W falls out of the window, 25 W is the lowest since.
+        assert_eq!(floor.push(at(75), 40.0), Some(25.0));
+        assert_eq!(floor.push(at(95), 45.0), Some(40.0));
+    }
+}
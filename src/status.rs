@@ -0,0 +1,68 @@
+use std::io::Read;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ryzenmon_rust::config::Config;
+use ryzenmon_rust::pidfile;
+
+// Exit codes as LSB init scripts have them for `status`.
+const RUNNING: i32 = 0;
+const NOT_RUNNING: i32 = 3;
+const UNKNOWN: i32 = 4;
+
+// `ryzenmon status`: whether a daemon holds the PID file and, when it serves
+// [socket], its latest sample. Returns the exit code.
+pub fn run(config: &Config) -> i32 {
+    if config.daemon.pid_file.is_empty() {
+        eprintln!("Error: daemon.pid_file is empty, so whether a daemon runs can't be told");
+        return UNKNOWN;
+    }
+    let path = Path::new(&config.daemon.pid_file);
+    let pid = match pidfile::holder(path) {
+        Ok(Some(pid)) => pid,
+        Ok(None) => {
+            println!("Not running ({} is not locked)", path.display());
+            return NOT_RUNNING;
+        }
+        Err(e) => {
+            eprintln!("Error: cannot read {}: {}", path.display(), e);
+            return UNKNOWN;
+        }
+    };
+    println!("Running as PID {}", pid);
+
+    let Some(socket) = &config.socket else {
+        println!("Latest sample: unknown, enable [socket] to see it here");
+        return RUNNING;
+    };
+    match latest_sample(Path::new(&socket.path)) {
+        Ok(Some(sample)) => {
+            let field = |name: &str| sample.get(name).and_then(serde_json::Value::as_f64).unwrap_or(f64::NAN);
+            let taken = UNIX_EPOCH + Duration::from_secs_f64(field("timestamp").max(0.0));
+            let age = SystemTime::now().duration_since(taken).unwrap_or_default();
+            println!(
+                "Latest sample: {} ago, package {:.1} W, cores {:.1} W",
+                humantime::format_duration(Duration::from_secs(age.as_secs())),
+                field("package_watts"),
+                field("core_sum")
+            );
+        }
+        Ok(None) => println!("Latest sample: none yet"),
+        Err(e) => println!("Latest sample: unknown, cannot read {}: {}", socket.path, e),
+    }
+    RUNNING
+}
+
+// The sample the daemon serves on its [socket], None before the first one.
+fn latest_sample(path: &Path) -> Result<Option<serde_json::Value>, Box<dyn std::error::Error>> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    let mut line = String::new();
+    stream.read_to_string(&mut line)?;
+    let sample: serde_json::Value = serde_json::from_str(&line)?;
+    if sample.get("error").is_some() {
+        return Ok(None);
+    }
+    Ok(Some(sample))
+}
@@ -0,0 +1,75 @@
+use std::time::{Duration, SystemTime};
+
+#[cfg(target_os = "linux")]
+use nix::time::{clock_gettime, ClockId};
+use serde::{Deserialize, Serialize};
+
+use crate::rapl::{deserialize_unix_seconds, serialize_unix_seconds};
+
+// Shorter gaps are the two clocks being read a moment apart rather than a
+// suspend.
+const MIN_SUSPEND: Duration = Duration::from_millis(500);
+
+// A suspend that ended during a sample, which is therefore discarded.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Resume {
+    // When the sample that noticed the resume ended
+    #[serde(serialize_with = "serialize_unix_seconds", deserialize_with = "deserialize_unix_seconds")]
+    pub at: SystemTime,
+    pub suspended_secs: f64,
+}
+
+// Notices suspends by CLOCK_BOOTTIME running ahead of CLOCK_MONOTONIC, which
+// stops while the system is suspended. Works without logind or D-Bus.
+#[derive(Debug, Default)]
+pub struct SuspendClock {
+    // CLOCK_BOOTTIME - CLOCK_MONOTONIC at the previous check
+    offset: Option<Duration>,
+}
+
+impl SuspendClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // How long the system was suspended since the previous check, None when
+    // it wasn't or the clocks can't be read.
+    pub fn check(&mut self) -> Option<Duration> {
+        self.observe(boot_offset()?)
+    }
+
+    fn observe(&mut self, offset: Duration) -> Option<Duration> {
+        let previous = self.offset.replace(offset)?;
+        offset.checked_sub(previous).filter(|suspended| *suspended >= MIN_SUSPEND)
+    }
+}
+
+// Monotonic first, so the offset can't come out negative on a machine that
+// never suspended.
+#[cfg(target_os = "linux")]
+fn boot_offset() -> Option<Duration> {
+    let monotonic = Duration::from(clock_gettime(ClockId::CLOCK_MONOTONIC).ok()?);
+    let boot = Duration::from(clock_gettime(ClockId::CLOCK_BOOTTIME).ok()?);
+    boot.checked_sub(monotonic)
+}
+
+// CLOCK_BOOTTIME is Linux only; elsewhere suspends go unnoticed.
+#[cfg(not(target_os = "linux"))]
+fn boot_offset() -> Option<Duration> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notices_the_clocks_diverging() {
+        let mut clock = SuspendClock::new();
+        assert_eq!(clock.observe(Duration::from_secs(100)), None);
+        assert_eq!(clock.observe(Duration::from_millis(100_001)), None);
+        assert_eq!(clock.observe(Duration::from_millis(3_700_001)), Some(Duration::from_secs(3600)));
+        assert_eq!(clock.observe(Duration::from_millis(3_700_001)), None);
+        assert!(boot_offset().is_some());
+    }
+}
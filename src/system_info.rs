@@ -0,0 +1,47 @@
+use std::fs;
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::config::SystemTag;
+use crate::msr;
+use crate::topology;
+
+// Read once, the first time tags are resolved.
+pub static SYSTEM_INFO: Lazy<SystemInfo> = Lazy::new(SystemInfo::detect);
+
+// What the machine is, for slicing dashboards across a fleet. Anything that
+// can't be read is left out.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SystemInfo {
+    pub cpu_model: Option<String>,
+    pub cores: Option<usize>,
+    pub threads: Option<usize>,
+    pub microcode: Option<String>,
+    pub kernel: Option<String>,
+    pub version: String,
+}
+
+impl SystemInfo {
+    pub fn detect() -> Self {
+        SystemInfo {
+            cpu_model: msr::cpu_model(),
+            cores: topology::physical_cores().ok().map(|cores| cores.len()),
+            threads: topology::logical_cpus().ok().map(|cpus| cpus.len()),
+            microcode: msr::cpu_microcode(),
+            kernel: fs::read_to_string("/proc/sys/kernel/osrelease").ok().map(|release| release.trim().to_string()),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    pub fn tag(&self, tag: SystemTag) -> Option<String> {
+        match tag {
+            SystemTag::CpuModel => self.cpu_model.clone(),
+            SystemTag::Cores => self.cores.map(|cores| cores.to_string()),
+            SystemTag::Threads => self.threads.map(|threads| threads.to_string()),
+            SystemTag::Microcode => self.microcode.clone(),
+            SystemTag::Kernel => self.kernel.clone(),
+            SystemTag::Version => Some(self.version.clone()),
+        }
+    }
+}
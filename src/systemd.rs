@@ -0,0 +1,57 @@
+use std::env;
+use std::io;
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+#[cfg(target_os = "linux")]
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+use tracing::warn;
+
+// Send a state string such as "READY=1" to systemd. Does nothing when not
+// started by systemd with Type=notify.
+#[cfg(target_os = "linux")]
+pub fn notify(state: &str) -> io::Result<()> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    let path = path.to_string_lossy();
+
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes())?,
+        None => SocketAddr::from_pathname(path.as_ref())?,
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+// There is no systemd to tell outside Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn notify(_state: &str) -> io::Result<()> {
+    Ok(())
+}
+
+pub fn notify_ready() {
+    if let Err(e) = notify("READY=1") {
+        warn!("Failed to notify systemd: {}", e);
+    }
+}
+
+pub fn notify_watchdog() {
+    if let Err(e) = notify("WATCHDOG=1") {
+        warn!("Failed to ping systemd watchdog: {}", e);
+    }
+}
+
+// WatchdogSec from the unit, if the watchdog is enabled for this process.
+pub fn watchdog_timeout() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec))
+}
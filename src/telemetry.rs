@@ -0,0 +1,102 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+// Counters about ryzenmon itself, written as their own measurement so the
+// monitor can be monitored.
+static SAMPLE_DURATION_US: AtomicU64 = AtomicU64::new(0);
+static UPLOAD_LATENCY_US: AtomicU64 = AtomicU64::new(0);
+static MSR_READ_ERRORS: AtomicU64 = AtomicU64::new(0);
+static UPLOAD_RETRIES: AtomicU64 = AtomicU64::new(0);
+static DROPPED_POINTS: AtomicU64 = AtomicU64::new(0);
+static REJECTED_VALUES: AtomicU64 = AtomicU64::new(0);
+static OPEN_CIRCUITS: AtomicU64 = AtomicU64::new(0);
+static RESUMES: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct SelfTelemetry {
+    // Time the last sample took, measurement window included
+    pub sample_duration_ms: f64,
+    // Time the previous write to all sinks took
+    pub upload_latency_ms: f64,
+    // Totals since startup
+    pub msr_read_errors: u64,
+    pub upload_retries: u64,
+    pub dropped_points: u64,
+    // Readings outside the [validation] ranges
+    #[serde(default)]
+    pub rejected_values: u64,
+    // Sinks whose uploads are paused after failing repeatedly, right now
+    #[serde(default)]
+    pub open_circuits: u64,
+    // Resumes from suspend, each discarding the sample it ended in
+    #[serde(default)]
+    pub resumes: u64,
+}
+
+impl SelfTelemetry {
+    // Name, value and unit of every field, for sinks that write them generically.
+    pub fn iter(&self) -> [(&'static str, f64, &'static str); 8] {
+        [
+            ("sample_duration", self.sample_duration_ms, "ms"),
+            ("upload_latency", self.upload_latency_ms, "ms"),
+            ("msr_read_errors", self.msr_read_errors as f64, ""),
+            ("upload_retries", self.upload_retries as f64, ""),
+            ("dropped_points", self.dropped_points as f64, ""),
+            ("rejected_values", self.rejected_values as f64, ""),
+            ("open_circuits", self.open_circuits as f64, ""),
+            ("resumes", self.resumes as f64, ""),
+        ]
+    }
+}
+
+pub fn snapshot() -> SelfTelemetry {
+    let ms = |us: &AtomicU64| us.load(Ordering::Relaxed) as f64 / 1000.0;
+    SelfTelemetry {
+        sample_duration_ms: ms(&SAMPLE_DURATION_US),
+        upload_latency_ms: ms(&UPLOAD_LATENCY_US),
+        msr_read_errors: MSR_READ_ERRORS.load(Ordering::Relaxed),
+        upload_retries: UPLOAD_RETRIES.load(Ordering::Relaxed),
+        dropped_points: DROPPED_POINTS.load(Ordering::Relaxed),
+        rejected_values: REJECTED_VALUES.load(Ordering::Relaxed),
+        open_circuits: OPEN_CIRCUITS.load(Ordering::Relaxed),
+        resumes: RESUMES.load(Ordering::Relaxed),
+    }
+}
+
+pub fn record_sample_duration(duration: Duration) {
+    SAMPLE_DURATION_US.store(duration.as_micros() as u64, Ordering::Relaxed);
+}
+
+pub fn record_upload_latency(duration: Duration) {
+    UPLOAD_LATENCY_US.store(duration.as_micros() as u64, Ordering::Relaxed);
+}
+
+pub fn record_msr_read_error() {
+    MSR_READ_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_upload_retry() {
+    UPLOAD_RETRIES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_dropped_points(count: usize) {
+    DROPPED_POINTS.fetch_add(count as u64, Ordering::Relaxed);
+}
+
+pub fn record_rejected_values(count: usize) {
+    REJECTED_VALUES.fetch_add(count as u64, Ordering::Relaxed);
+}
+
+pub fn record_circuit_opened() {
+    OPEN_CIRCUITS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_circuit_closed() {
+    OPEN_CIRCUITS.fetch_sub(1, Ordering::Relaxed);
+}
+
+pub fn record_resume() {
+    RESUMES.fetch_add(1, Ordering::Relaxed);
+}
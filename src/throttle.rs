@@ -0,0 +1,276 @@
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::rapl::{deserialize_unix_seconds, serialize_unix_seconds, PowerMetrics};
+use crate::smu::SmuLimits;
+
+// IA32_PACKAGE_THERM_STATUS bits
+const THERMAL_STATUS: u64 = 1 << 0;
+const PROCHOT_STATUS: u64 = 1 << 2;
+const POWER_LIMIT_STATUS: u64 = 1 << 10;
+
+// Share of an SMU limit from which the CPU counts as held at it; the SMU
+// regulates to just under the limit rather than exactly on it.
+const AT_LIMIT: f64 = 0.99;
+
+// Why the CPU is running below what it could.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThrottleReason {
+    // PROCHOT# asserted, e.g. by the VRM or the board (Intel)
+    Prochot,
+    // At the temperature limit (Intel thermal status, AMD THM)
+    Thermal,
+    // Held by a RAPL power limit (Intel)
+    PowerLimit,
+    // At the package power, sustained current or peak current limit (AMD SMU)
+    Ppt,
+    Tdc,
+    Edc,
+}
+
+impl ThrottleReason {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ThrottleReason::Prochot => "prochot",
+            ThrottleReason::Thermal => "thermal",
+            ThrottleReason::PowerLimit => "power_limit",
+            ThrottleReason::Ppt => "ppt",
+            ThrottleReason::Tdc => "tdc",
+            ThrottleReason::Edc => "edc",
+        }
+    }
+}
+
+impl fmt::Display for ThrottleReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// One stretch of throttling for one reason. It is only known to within a
+// sample interval, from the first sample with the reason to the first
+// without it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThrottleEvent {
+    pub reason: ThrottleReason,
+    #[serde(serialize_with = "serialize_unix_seconds", deserialize_with = "deserialize_unix_seconds")]
+    pub started: SystemTime,
+    pub duration_secs: f64,
+}
+
+// Throttle state of a sample, for whichever reasons this machine exposes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Throttle {
+    // Reasons that could be checked; one missing from `active` is not in effect
+    pub checked: Vec<ThrottleReason>,
+    pub active: Vec<ThrottleReason>,
+    // Share of the samples since the previous upload each checked reason was
+    // in effect in, 0.0 to 1.0, filled in by the daemon loop
+    #[serde(default)]
+    pub residency: BTreeMap<ThrottleReason, f64>,
+    // Stretches that ended since the previous upload, filled in by the daemon loop
+    #[serde(default)]
+    pub events: Vec<ThrottleEvent>,
+}
+
+impl Throttle {
+    // From IA32_PACKAGE_THERM_STATUS of every package; any package throttling counts.
+    pub fn from_package_therm_status(statuses: &[u64]) -> Option<Self> {
+        if statuses.is_empty() {
+            return None;
+        }
+        let status = statuses.iter().fold(0, |all, status| all | status);
+        let mut throttle = Throttle::default();
+        throttle.set(ThrottleReason::Prochot, status & PROCHOT_STATUS != 0);
+        throttle.set(ThrottleReason::Thermal, status & THERMAL_STATUS != 0);
+        throttle.set(ThrottleReason::PowerLimit, status & POWER_LIMIT_STATUS != 0);
+        Some(throttle)
+    }
+
+    // PPT, TDC, EDC and THM at their limits in the SMU PM table.
+    pub fn from_smu(limits: &SmuLimits) -> Self {
+        let mut throttle = Throttle::default();
+        for (reason, limit) in [
+            (ThrottleReason::Ppt, Some(limits.ppt)),
+            (ThrottleReason::Tdc, Some(limits.tdc)),
+            (ThrottleReason::Edc, Some(limits.edc)),
+            (ThrottleReason::Thermal, limits.thm),
+        ] {
+            if let Some(limit) = limit.filter(|limit| limit.limit > 0.0) {
+                throttle.set(reason, limit.usage() >= AT_LIMIT);
+            }
+        }
+        throttle
+    }
+
+    pub fn is_throttled(&self) -> bool {
+        !self.active.is_empty()
+    }
+
+    // Every checked reason with whether it is in effect.
+    pub fn iter(&self) -> impl Iterator<Item = (ThrottleReason, bool)> + '_ {
+        self.checked.iter().map(|reason| (*reason, self.active.contains(reason)))
+    }
+
+    fn set(&mut self, reason: ThrottleReason, active: bool) {
+        if !self.checked.contains(&reason) {
+            self.checked.push(reason);
+        }
+        if active && !self.active.contains(&reason) {
+            self.active.push(reason);
+        }
+    }
+}
+
+// Follows the throttle state of every sample, including those folded into
+// one upload with sampling.sample_interval_ms, to report residency and
+// discrete events.
+#[derive(Default)]
+pub struct ThrottleTracker {
+    // When each reason in effect was first seen
+    started: BTreeMap<ThrottleReason, SystemTime>,
+    // Samples since the last upload, and in how many each reason was in effect
+    samples: usize,
+    throttled: BTreeMap<ThrottleReason, usize>,
+    checked: Vec<ThrottleReason>,
+    events: Vec<ThrottleEvent>,
+}
+
+impl ThrottleTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn observe(&mut self, metrics: &PowerMetrics) {
+        let Some(throttle) = &metrics.throttle else {
+            return;
+        };
+        self.samples += 1;
+        for (reason, active) in throttle.iter() {
+            if !self.checked.contains(&reason) {
+                self.checked.push(reason);
+            }
+            if active {
+                *self.throttled.entry(reason).or_default() += 1;
+                if let Entry::Vacant(entry) = self.started.entry(reason) {
+                    info!("Throttling started: {}", reason);
+                    entry.insert(metrics.timestamp);
+                }
+            }
+        }
+        // Ended, or no longer checked at all
+        let ended: Vec<ThrottleReason> =
+            self.started.keys().copied().filter(|reason| !throttle.active.contains(reason)).collect();
+        for reason in ended {
+            let started = self.started.remove(&reason).expect("just listed");
+            let duration = metrics.timestamp.duration_since(started).unwrap_or_default();
+            info!("Throttling ended: {} after {:.1?}", reason, duration);
+            self.events.push(ThrottleEvent {
+                reason,
+                started,
+                duration_secs: duration.as_secs_f64(),
+            });
+        }
+    }
+
+    // Residency and events since the previous call into the sample about to
+    // be uploaded.
+    pub fn finish(&mut self, metrics: &mut PowerMetrics) {
+        let samples = std::mem::take(&mut self.samples);
+        let throttled = std::mem::take(&mut self.throttled);
+        let checked = std::mem::take(&mut self.checked);
+        let events = std::mem::take(&mut self.events);
+        let Some(throttle) = &mut metrics.throttle else {
+            return;
+        };
+        if samples > 0 {
+            throttle.residency = checked
+                .into_iter()
+                .map(|reason| (reason, throttled.get(&reason).copied().unwrap_or(0) as f64 / samples as f64))
+                .collect();
+        }
+        throttle.events = events;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::platform::fixture_sample;
+    use crate::smu::Limit;
+
+    #[test]
+    fn decodes_package_therm_status() {
+        let throttle = Throttle::from_package_therm_status(&[0x0000_0882, 0x0000_0004]).unwrap();
+        assert_eq!(throttle.checked.len(), 3);
+        assert_eq!(throttle.active, vec![ThrottleReason::Prochot]);
+        // Only the log bits set
+        assert!(!Throttle::from_package_therm_status(&[0x0000_0802]).unwrap().is_throttled());
+        assert!(Throttle::from_package_therm_status(&[]).is_none());
+    }
+
+    #[test]
+    fn detects_smu_limits() {
+        let limit = |value: f64, limit: f64| Limit { value, limit };
+        let limits = SmuLimits {
+            ppt: limit(141.5, 142.0),
+            tdc: limit(60.0, 95.0),
+            edc: limit(120.0, 140.0),
+            thm: Some(limit(90.0, 90.0)),
+        };
+        let throttle = Throttle::from_smu(&limits);
+        assert_eq!(throttle.checked.len(), 4);
+        assert_eq!(throttle.active, vec![ThrottleReason::Ppt, ThrottleReason::Thermal]);
+    }
+
+    #[test]
+    fn tracks_residency_and_events() {
+        let metrics = fixture_sample();
+        let at = |secs: u64, active: &[ThrottleReason]| {
+            let mut metrics = metrics.clone();
+            metrics.timestamp = UNIX_EPOCH + Duration::from_secs(secs);
+            metrics.throttle = Some(Throttle {
+                checked: vec![ThrottleReason::Ppt, ThrottleReason::Thermal],
+                active: active.to_vec(),
+                ..Throttle::default()
+            });
+            metrics
+        };
+
+        let mut tracker = ThrottleTracker::new();
+        tracker.observe(&at(10, &[]));
+        tracker.observe(&at(11, &[ThrottleReason::Ppt]));
+        tracker.observe(&at(12, &[ThrottleReason::Ppt]));
+        let mut upload = at(13, &[ThrottleReason::Thermal]);
+        tracker.observe(&upload);
+        tracker.finish(&mut upload);
+        let throttle = upload.throttle.unwrap();
+        assert_eq!(throttle.residency[&ThrottleReason::Ppt], 0.5);
+        assert_eq!(throttle.residency[&ThrottleReason::Thermal], 0.25);
+        assert_eq!(
+            throttle.events,
+            vec![ThrottleEvent {
+                reason: ThrottleReason::Ppt,
+                started: UNIX_EPOCH + Duration::from_secs(11),
+                duration_secs: 2.0,
+            }]
+        );
+
+        // Each upload only reports its own samples.
+        let mut upload = at(20, &[]);
+        tracker.observe(&upload);
+        tracker.finish(&mut upload);
+        let throttle = upload.throttle.unwrap();
+        assert_eq!(throttle.residency[&ThrottleReason::Ppt], 0.0);
+        assert_eq!(throttle.events[0].reason, ThrottleReason::Thermal);
+        assert_eq!(throttle.events[0].duration_secs, 7.0);
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{RyzenmonError, Result};
+use crate::msr::cpu_family;
+
+pub const CPU_SYSFS_ROOT: &str = "/sys/devices/system/cpu";
+
+// Parse a kernel cpu list such as "0-3,8,10-11".
+pub fn parse_cpu_list(list: &str) -> Vec<usize> {
+    let mut cpus = Vec::new();
+
+    for part in list.trim().split(',').filter(|p| !p.is_empty()) {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                if let (Ok(start), Ok(end)) = (start.parse::<usize>(), end.parse::<usize>()) {
+                    cpus.extend(start..=end);
+                }
+            }
+            None => {
+                if let Ok(cpu) = part.parse() {
+                    cpus.push(cpu);
+                }
+            }
+        }
+    }
+
+    cpus
+}
+
+// Logical CPUs that currently expose topology information, in ascending order.
+pub fn logical_cpus() -> io::Result<Vec<usize>> {
+    let mut cpus: Vec<usize> = fs::read_dir(CPU_SYSFS_ROOT)?
+        .flatten()
+        .filter_map(|e| e.file_name().to_str()?.strip_prefix("cpu")?.parse().ok())
+        .filter(|cpu| {
+            fs::metadata(format!("{}/cpu{}/topology/physical_package_id", CPU_SYSFS_ROOT, cpu)).is_ok()
+        })
+        .collect();
+    cpus.sort_unstable();
+    Ok(cpus)
+}
+
+// The kernel's list of online CPUs, which changes whenever one is onlined or
+// offlined; cheap enough to compare on every sample.
+pub fn online_cpus() -> Option<String> {
+    fs::read_to_string(format!("{}/online", CPU_SYSFS_ROOT)).ok()
+}
+
+// SMT siblings of `cpu`, including itself.
+pub fn thread_siblings(cpu: usize) -> io::Result<Vec<usize>> {
+    let topology = format!("{}/cpu{}/topology", CPU_SYSFS_ROOT, cpu);
+    let list = fs::read_to_string(format!("{}/core_cpus_list", topology))
+        .or_else(|_| fs::read_to_string(format!("{}/thread_siblings_list", topology)))?;
+    Ok(parse_cpu_list(&list))
+}
+
+// One logical CPU per physical core: the lowest numbered online sibling.
+pub fn physical_cores() -> io::Result<Vec<usize>> {
+    let cpus = logical_cpus()?;
+    let mut cores = Vec::new();
+
+    for &cpu in &cpus {
+        let siblings = thread_siblings(cpu).unwrap_or_else(|_| vec![cpu]);
+        let first_online = siblings.iter().copied().find(|s| cpus.contains(s)).unwrap_or(cpu);
+        if first_online == cpu {
+            cores.push(cpu);
+        }
+    }
+
+    Ok(cores)
+}
+
+// Online SMT siblings of `cpu`, just `cpu` when they are not known.
+#[cfg_attr(windows, allow(dead_code))]
+fn core_threads(cpu: usize, online: &[usize]) -> Vec<usize> {
+    let siblings = thread_siblings(cpu).unwrap_or_else(|_| vec![cpu]);
+    siblings.into_iter().filter(|s| online.contains(s)).collect()
+}
+
+pub fn package_id(cpu: usize) -> io::Result<usize> {
+    let filename = format!("{}/cpu{}/topology/physical_package_id", CPU_SYSFS_ROOT, cpu);
+    fs::read_to_string(filename)?
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// L3 cache id of `cpu`; every CCX has its own L3.
+pub fn l3_id(cpu: usize) -> io::Result<usize> {
+    let filename = format!("{}/cpu{}/cache/index3/id", CPU_SYSFS_ROOT, cpu);
+    fs::read_to_string(filename)?
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// NUMA node of `cpu`, from the node<N> link in its sysfs directory.
+pub fn numa_node(cpu: usize) -> io::Result<usize> {
+    fs::read_dir(format!("{}/cpu{}", CPU_SYSFS_ROOT, cpu))?
+        .flatten()
+        .find_map(|e| e.file_name().to_str()?.strip_prefix("node")?.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("cpu{} has no NUMA node", cpu)))
+}
+
+// L3 cache id of every core, empty when the cache topology is not exposed.
+pub fn detect_l3s(cores: &[usize]) -> Vec<usize> {
+    cores.iter().map(|&cpu| l3_id(cpu)).collect::<io::Result<_>>().unwrap_or_default()
+}
+
+// NUMA node of every core, empty without CONFIG_NUMA.
+pub fn detect_numa_nodes(cores: &[usize]) -> Vec<usize> {
+    cores.iter().map(|&cpu| numa_node(cpu)).collect::<io::Result<_>>().unwrap_or_default()
+}
+
+// Highest clock of `cpu` in kHz, as cpufreq reports it.
+pub fn max_freq_khz(cpu: usize) -> io::Result<u64> {
+    let filename = format!("{}/cpu{}/cpufreq/cpuinfo_max_freq", CPU_SYSFS_ROOT, cpu);
+    fs::read_to_string(filename)?
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Size of the L3 cache of `cpu` in KiB, from e.g. "98304K".
+pub fn l3_size_kb(cpu: usize) -> io::Result<u64> {
+    let filename = format!("{}/cpu{}/cache/index3/size", CPU_SYSFS_ROOT, cpu);
+    let size = fs::read_to_string(filename)?;
+    let size = size.trim();
+    let (number, scale) = match size.strip_suffix('K') {
+        Some(number) => (number, 1),
+        None => match size.strip_suffix('M') {
+            Some(number) => (number, 1024),
+            None => (size, 1),
+        },
+    };
+    number
+        .parse::<u64>()
+        .map(|number| number * scale)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Kind of core on parts that mix them. Comparing their power side by side
+// only makes sense within a class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CoreClass {
+    // Full-size cores that clock highest
+    Freq,
+    // Cores on a die with stacked L3, such as the first CCD of a 7950X3D
+    Cache,
+    // Compact Zen 4c and Zen 5c cores
+    Dense,
+}
+
+impl CoreClass {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CoreClass::Freq => "freq",
+            CoreClass::Cache => "cache",
+            CoreClass::Dense => "dense",
+        }
+    }
+}
+
+// Cores whose highest clock is below this share of the fastest core's are
+// dense ones. Preferred-core rankings leave a few percent between cores of
+// the same kind, and the V-Cache die of an X3D part clocks about 8% lower.
+const DENSE_MAX_FREQ_RATIO: f64 = 0.85;
+
+// Class of every core, empty when they are all alike or it can't be told.
+pub fn detect_core_classes(cores: &[usize]) -> Vec<CoreClass> {
+    let max_freqs: Vec<u64> = cores.iter().map(|&cpu| max_freq_khz(cpu)).collect::<io::Result<_>>().unwrap_or_default();
+    let l3_sizes: Vec<u64> = cores.iter().map(|&cpu| l3_size_kb(cpu)).collect::<io::Result<_>>().unwrap_or_default();
+    classify_cores(&max_freqs, &l3_sizes)
+}
+
+// Cores that clock well below the fastest are dense, then of the others
+// those with more L3 than the rest are cache cores. Dense cores go first as
+// Zen 5c shares a smaller L3, which would make the full-size cores next to
+// them look like cache cores. Either list may be empty when unknown.
+pub fn classify_cores(max_freqs: &[u64], l3_sizes: &[u64]) -> Vec<CoreClass> {
+    let cores = max_freqs.len().max(l3_sizes.len());
+    let fastest = max_freqs.iter().copied().max().unwrap_or(0) as f64;
+    let dense: Vec<bool> = (0..cores)
+        .map(|core| max_freqs.get(core).is_some_and(|&freq| (freq as f64) < fastest * DENSE_MAX_FREQ_RATIO))
+        .collect();
+    let smallest_l3 = (0..cores).filter(|&core| !dense[core]).filter_map(|core| l3_sizes.get(core)).min();
+    let classes: Vec<CoreClass> = (0..cores)
+        .map(|core| {
+            if dense[core] {
+                CoreClass::Dense
+            } else if l3_sizes.get(core).is_some_and(|size| Some(size) > smallest_l3) {
+                CoreClass::Cache
+            } else {
+                CoreClass::Freq
+            }
+        })
+        .collect();
+    if classes.iter().all(|&class| class == CoreClass::Freq) {
+        return Vec::new();
+    }
+    classes
+}
+
+// CCD of every core, numbered densely from 0 in L3 id order. Zen 2 has two
+// CCXs (and L3s) per CCD, from Zen 3 on a CCD has a single CCX. Empty when
+// the cache topology is not exposed.
+pub fn detect_ccds(l3_ids: &[usize]) -> Vec<usize> {
+    let ccxs_per_ccd = if cpu_family() == Some(0x17) { 2 } else { 1 };
+    assign_ccds(l3_ids, ccxs_per_ccd)
+}
+
+pub fn assign_ccds(l3_ids: &[usize], ccxs_per_ccd: usize) -> Vec<usize> {
+    let mut unique: Vec<usize> = l3_ids.to_vec();
+    unique.sort_unstable();
+    unique.dedup();
+    l3_ids
+        .iter()
+        .map(|id| unique.binary_search(id).unwrap_or(0) / ccxs_per_ccd)
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Package {
+    pub id: usize,
+    // Lowest numbered online CPU of the package, used for package-scope MSRs
+    pub cpu: usize,
+}
+
+// Physical packages (sockets) with the CPU their package counters are read from.
+pub fn detect_packages() -> io::Result<Vec<Package>> {
+    let mut package_map = BTreeMap::new();
+
+    for cpu in logical_cpus()? {
+        let id = package_id(cpu)?;
+        package_map.entry(id).or_insert(cpu);
+    }
+
+    Ok(package_map
+        .into_iter()
+        .map(|(id, cpu)| Package { id, cpu })
+        .collect())
+}
+
+// Drop the items whose flag in `keep` is false, for per-core vectors. Left
+// alone when the lengths differ, as `ccds` does when the cache topology is unknown.
+pub fn retain_by<T>(items: &mut Vec<T>, keep: &[bool]) {
+    if items.len() == keep.len() {
+        let mut flags = keep.iter();
+        items.retain(|_| *flags.next().unwrap());
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Topology {
+    // One logical CPU per physical core
+    pub cores: Vec<usize>,
+    pub packages: Vec<Package>,
+    #[serde(default)]
+    pub threads: usize,
+    // CCD of each entry in `cores`, empty when unknown
+    #[serde(default)]
+    pub ccds: Vec<usize>,
+    // Online SMT threads of each entry in `cores`
+    #[serde(default)]
+    pub core_threads: Vec<Vec<usize>>,
+    // Package id of each entry in `cores`, empty when unknown
+    #[serde(default)]
+    pub core_packages: Vec<usize>,
+    // Number of each entry in `cores` as lscpu's CORE column shows it, counting
+    // cores in order of their first CPU; kept when other cores are dropped
+    #[serde(default)]
+    pub core_ids: Vec<usize>,
+    // NUMA node of each entry in `cores`, empty when unknown
+    #[serde(default)]
+    pub core_nodes: Vec<usize>,
+    // L3 cache id, one per CCX on AMD, of each entry in `cores`, empty when unknown
+    #[serde(default)]
+    pub core_l3s: Vec<usize>,
+    // Class of each entry in `cores` on parts with more than one kind of
+    // core, empty otherwise
+    #[serde(default)]
+    pub core_classes: Vec<CoreClass>,
+}
+
+impl Topology {
+    // Keep the cores for which `keep` is true, together with their CCD,
+    // threads, package, NUMA node, L3 and class. Returns whether each former
+    // core was kept.
+    pub fn retain_cores(&mut self, keep: impl Fn(usize) -> bool) -> Vec<bool> {
+        let kept: Vec<bool> = self.cores.iter().map(|&cpu| keep(cpu)).collect();
+        retain_by(&mut self.cores, &kept);
+        retain_by(&mut self.ccds, &kept);
+        retain_by(&mut self.core_threads, &kept);
+        retain_by(&mut self.core_packages, &kept);
+        retain_by(&mut self.core_ids, &kept);
+        retain_by(&mut self.core_nodes, &kept);
+        retain_by(&mut self.core_l3s, &kept);
+        retain_by(&mut self.core_classes, &kept);
+        kept
+    }
+
+    #[cfg(not(windows))]
+    pub fn detect() -> Result<Self> {
+        let detect = || -> io::Result<Self> {
+            let cores = physical_cores()?;
+            let cpus = logical_cpus()?;
+            let l3s = detect_l3s(&cores);
+            Ok(Topology {
+                ccds: detect_ccds(&l3s),
+                core_nodes: detect_numa_nodes(&cores),
+                core_l3s: l3s,
+                core_classes: detect_core_classes(&cores),
+                core_threads: cores.iter().map(|&cpu| core_threads(cpu, &cpus)).collect(),
+                core_packages: cores.iter().map(|&cpu| package_id(cpu)).collect::<io::Result<_>>()?,
+                core_ids: (0..cores.len()).collect(),
+                cores,
+                packages: detect_packages()?,
+                threads: cpus.len(),
+            })
+        };
+        detect().map_err(RyzenmonError::Topology)
+    }
+
+    #[cfg(windows)]
+    pub fn detect() -> Result<Self> {
+        let ccxs_per_ccd = if crate::msr::detect_cpu()?.family == 0x17 { 2 } else { 1 };
+        crate::windows::processor_information()
+            .and_then(|buffer| crate::windows::parse_processor_information(&buffer, ccxs_per_ccd))
+            .map_err(RyzenmonError::Topology)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retains_cores_with_their_ccds_and_threads() {
+        let mut topology = Topology {
+            cores: vec![0, 1, 2],
+            ccds: vec![0, 0, 1],
+            core_threads: vec![vec![0, 3], vec![1, 4], vec![2, 5]],
+            core_packages: vec![0, 0, 1],
+            core_ids: vec![0, 1, 2],
+            core_nodes: vec![0, 0, 1],
+            core_l3s: vec![0, 8, 16],
+            core_classes: vec![CoreClass::Cache, CoreClass::Cache, CoreClass::Freq],
+            ..Topology::default()
+        };
+        assert_eq!(topology.retain_cores(|cpu| cpu != 1), vec![true, false, true]);
+        assert_eq!(topology.cores, vec![0, 2]);
+        assert_eq!(topology.ccds, vec![0, 1]);
+        assert_eq!(topology.core_threads, vec![vec![0, 3], vec![2, 5]]);
+        assert_eq!(topology.core_packages, vec![0, 1]);
+        assert_eq!(topology.core_ids, vec![0, 2]);
+        assert_eq!(topology.core_nodes, vec![0, 1]);
+        assert_eq!(topology.core_l3s, vec![0, 16]);
+        assert_eq!(topology.core_classes, vec![CoreClass::Cache, CoreClass::Freq]);
+    }
+
+    #[test]
+    fn classifies_heterogeneous_cores() {
+        use CoreClass::*;
+        // 7950X3D: a V-Cache CCD that clocks a little lower
+        assert_eq!(
+            classify_cores(&[5_250_000, 5_250_000, 5_700_000, 5_700_000], &[98304, 98304, 32768, 32768]),
+            vec![Cache, Cache, Freq, Freq]
+        );
+        // Ryzen AI 9 HX 370: Zen 5c cores with an L3 of their own, half the size
+        assert_eq!(
+            classify_cores(&[5_100_000, 5_100_000, 3_300_000, 3_300_000], &[16384, 16384, 8192, 8192]),
+            vec![Freq, Freq, Dense, Dense]
+        );
+        // Ryzen 5 7545U: Zen 4 and Zen 4c sharing one L3, sizes unknown
+        assert_eq!(classify_cores(&[4_900_000, 3_700_000], &[]), vec![Freq, Dense]);
+        // Preferred-core rankings alone don't make classes
+        assert!(classify_cores(&[5_700_000, 5_450_000], &[32768, 32768]).is_empty());
+        assert!(classify_cores(&[], &[]).is_empty());
+    }
+}
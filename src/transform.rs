@@ -0,0 +1,118 @@
+use std::fs;
+
+use rhai::serde::{from_dynamic, to_dynamic};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use tracing::warn;
+
+use crate::config::TransformConfig;
+use crate::error::{RyzenmonError, Result};
+use crate::rapl::PowerMetrics;
+
+// Runs every sample through a Rhai script before it reaches the sinks. The
+// script finds the sample as `sample`, a map shaped like the JSON of the
+// [api], and evaluates to the sample, changed as it likes, or to () to drop
+// it. A script that fails or runs longer than max_operations leaves the
+// sample unchanged.
+pub struct Transform {
+    engine: Engine,
+    ast: AST,
+}
+
+impl Transform {
+    // Compiles the script once, so a syntax error stops startup or a reload.
+    pub fn new(config: TransformConfig) -> Result<Self> {
+        let source = fs::read_to_string(&config.script)
+            .map_err(|e| RyzenmonError::Config(format!("failed to read transform.script {}: {}", config.script, e)))?;
+        let mut engine = Engine::new();
+        engine.set_max_operations(config.max_operations);
+        engine.on_print(|text| warn!("transform.script: {}", text));
+        let ast = engine
+            .compile(source)
+            .map_err(|e| RyzenmonError::Config(format!("transform.script {}: {}", config.script, e)))?;
+        Ok(Transform { engine, ast })
+    }
+
+    // The sample as the script returned it, None when it was dropped.
+    pub fn apply(&self, metrics: PowerMetrics) -> Option<PowerMetrics> {
+        match self.run(&metrics) {
+            Ok(transformed) => transformed,
+            Err(e) => {
+                warn!("transform.script failed, uploading the sample unchanged: {}", e);
+                Some(metrics)
+            }
+        }
+    }
+
+    fn run(&self, metrics: &PowerMetrics) -> std::result::Result<Option<PowerMetrics>, String> {
+        let mut scope = Scope::new();
+        let mut sample = to_dynamic(metrics).map_err(|e| e.to_string())?;
+        // Left out of the JSON when empty, but scripts should be able to add to it.
+        if let Some(mut map) = sample.write_lock::<Map>() {
+            map.entry("tags".into()).or_insert_with(|| Map::new().into());
+        }
+        scope.push_dynamic("sample", sample);
+        let returned: Dynamic = self.engine.eval_ast_with_scope(&mut scope, &self.ast).map_err(|e| e.to_string())?;
+        if returned.is_unit() {
+            return Ok(None);
+        }
+        from_dynamic(&returned)
+            .map(Some)
+            .map_err(|e| format!("invalid sample returned: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::fixture_sample;
+
+    fn transform(script: &str) -> Transform {
+        let path = std::env::temp_dir().join(format!("ryzenmon-transform-{}-{}.rhai", std::process::id(), script.len()));
+        fs::write(&path, script).unwrap();
+        let transform = Transform::new(TransformConfig {
+            script: path.to_string_lossy().into_owned(),
+            max_operations: 100_000,
+        });
+        fs::remove_file(&path).unwrap();
+        transform.unwrap()
+    }
+
+    #[test]
+    fn passes_samples_through_the_script() {
+        let metrics = fixture_sample();
+        let unchanged = transform("sample");
+        let returned = unchanged.apply(metrics.clone()).unwrap();
+        assert_eq!(returned.package_watts, metrics.package_watts);
+        assert_eq!(returned.core_watts, metrics.core_watts);
+        assert_eq!(returned.tags, metrics.tags);
+
+        let tagging = transform("sample.tags.rack = \"r12\";\nsample.package_watts *= 2.0;\nsample");
+        let returned = tagging.apply(metrics.clone()).unwrap();
+        assert_eq!(returned.tags.get("rack").map(String::as_str), Some("r12"));
+        assert_eq!(returned.package_watts, metrics.package_watts * 2.0);
+
+        let dropping = transform("if sample.package_watts > 0.0 { () } else { sample }");
+        assert!(dropping.apply(metrics).is_none());
+    }
+
+    #[test]
+    fn uploads_the_sample_unchanged_when_the_script_fails() {
+        let metrics = fixture_sample();
+        let failing = transform("sample.package_watts = \"hot\";\nsample");
+        assert_eq!(failing.apply(metrics.clone()).unwrap().package_watts, metrics.package_watts);
+        let endless = transform("loop {}");
+        assert_eq!(endless.apply(metrics.clone()).unwrap().package_watts, metrics.package_watts);
+    }
+
+    #[test]
+    fn refuses_scripts_that_dont_compile() {
+        let path = std::env::temp_dir().join(format!("ryzenmon-transform-{}-broken.rhai", std::process::id()));
+        fs::write(&path, "sample.tags.rack = ").unwrap();
+        let result = Transform::new(TransformConfig {
+            script: path.to_string_lossy().into_owned(),
+            max_operations: 100_000,
+        });
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(RyzenmonError::Config(_))));
+    }
+}
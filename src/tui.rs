@@ -0,0 +1,219 @@
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration, Instant, SystemTime};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Bar, BarChart, BarGroup, Block, Paragraph, Sparkline};
+use ratatui::{DefaultTerminal, Frame};
+
+use ryzenmon_rust::ring::{Metric, Ring};
+use ryzenmon_rust::{PowerMetrics, Sampler};
+
+// Samples kept for the sparklines and rolling averages.
+const HISTORY: usize = 300;
+
+// Ranges the percentiles can be shown over, as far as the history reaches.
+const RANGES: [Duration; 4] = [
+    Duration::from_secs(60),
+    Duration::from_secs(300),
+    Duration::from_secs(900),
+    Duration::from_secs(3600),
+];
+
+struct Dashboard {
+    latest: Option<PowerMetrics>,
+    package_history: VecDeque<f64>,
+    core_history: VecDeque<f64>,
+    // Every sample of the history, for percentiles over `ranges[range]`
+    ring: Ring,
+    ranges: Vec<Duration>,
+    range: usize,
+    error: Option<String>,
+}
+
+impl Dashboard {
+    fn record(&mut self, metrics: PowerMetrics) {
+        for (history, value) in [
+            (&mut self.package_history, metrics.package_watts),
+            (&mut self.core_history, metrics.core_sum),
+        ] {
+            if history.len() == HISTORY {
+                history.pop_front();
+            }
+            history.push_back(value);
+        }
+        self.ring.push(&metrics);
+        self.latest = Some(metrics);
+        self.error = None;
+    }
+}
+
+pub fn run(sampler: &mut Sampler, window: Duration, interval: Duration, history: Duration) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, sampler, window, interval, history);
+    ratatui::restore();
+    result
+}
+
+fn event_loop(
+    terminal: &mut DefaultTerminal,
+    sampler: &mut Sampler,
+    window: Duration,
+    interval: Duration,
+    history: Duration,
+) -> io::Result<()> {
+    let mut ranges: Vec<Duration> = RANGES.into_iter().filter(|&range| range < history).collect();
+    ranges.push(history);
+    let mut dashboard = Dashboard {
+        latest: None,
+        package_history: VecDeque::with_capacity(HISTORY),
+        core_history: VecDeque::with_capacity(HISTORY),
+        ring: Ring::new((history.as_millis() / interval.as_millis().max(1)) as usize),
+        ranges,
+        range: 0,
+        error: None,
+    };
+
+    loop {
+        let started = Instant::now();
+        match sampler.sample(window) {
+            Ok(metrics) => dashboard.record(metrics),
+            Err(e) => dashboard.error = Some(e.to_string()),
+        }
+        terminal.draw(|frame| draw(frame, &dashboard, interval))?;
+
+        // Wait out the rest of the interval while staying responsive to keys.
+        while let Some(remaining) = interval.checked_sub(started.elapsed()) {
+            if !event::poll(remaining)? {
+                break;
+            }
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => {
+                    match key.code {
+                        KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                        KeyCode::Char('r') => {
+                            dashboard.range = (dashboard.range + 1) % dashboard.ranges.len();
+                            terminal.draw(|frame| draw(frame, &dashboard, interval))?;
+                        }
+                        _ => {}
+                    }
+                }
+                Event::Resize(_, _) => {
+                    terminal.draw(|frame| draw(frame, &dashboard, interval))?;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn average(history: &VecDeque<f64>, last: usize) -> f64 {
+    let n = history.len().min(last);
+    if n == 0 {
+        return 0.0;
+    }
+    history.iter().rev().take(n).sum::<f64>() / n as f64
+}
+
+fn draw(frame: &mut Frame, dashboard: &Dashboard, interval: Duration) {
+    let [summary, sparklines, cores] = Layout::vertical([
+        Constraint::Length(7),
+        Constraint::Length(8),
+        Constraint::Min(4),
+    ])
+    .areas(frame.area());
+
+    let samples_per_minute = (60.0 / interval.as_secs_f64()).ceil().max(1.0) as usize;
+    let mut lines = Vec::new();
+    match &dashboard.latest {
+        Some(metrics) => {
+            lines.push(Line::from(format!(
+                "Package {:>7.2} W   Cores {:>7.2} W   Uncore {:>7.2} W",
+                metrics.package_watts,
+                metrics.core_sum,
+                metrics.package_watts - metrics.core_sum
+            )));
+            lines.push(Line::from(format!(
+                "1m avg  {:>7.2} W   1m avg {:>7.2} W   {}m avg {:>7.2} W",
+                average(&dashboard.package_history, samples_per_minute),
+                average(&dashboard.core_history, samples_per_minute),
+                (HISTORY as f64 * interval.as_secs_f64() / 60.0).round(),
+                average(&dashboard.package_history, HISTORY)
+            )));
+            let temperatures: Vec<String> = metrics
+                .temperatures
+                .iter()
+                .map(|t| format!("{} {:.1}°C", t.label, t.celsius))
+                .collect();
+            let range = dashboard.ranges[dashboard.range];
+            let now = SystemTime::now();
+            let from = now.checked_sub(range).unwrap_or(SystemTime::UNIX_EPOCH);
+            if let Some(stats) = dashboard.ring.stats(Metric::Package, from, now, &[50.0, 95.0, 99.0]) {
+                lines.push(Line::from(format!(
+                    "Package over {:<4} p50 {:>7.2} W   p95 {:>7.2} W   p99 {:>7.2} W   max {:>7.2} W",
+                    humantime::format_duration(range).to_string(),
+                    stats.percentiles["p50"],
+                    stats.percentiles["p95"],
+                    stats.percentiles["p99"],
+                    stats.max
+                )));
+            }
+            lines.push(Line::from(temperatures.join("   ")));
+        }
+        None => lines.push(Line::from("Waiting for the first sample...")),
+    }
+    if let Some(error) = &dashboard.error {
+        lines.push(Line::from(format!("Sampling failed: {}", error)).red());
+    }
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" ryzenmon (q to quit, r for the percentile range) ")),
+        summary,
+    );
+
+    let [package_area, core_area] =
+        Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(sparklines);
+    for (area, title, history, color) in [
+        (package_area, " Package W ", &dashboard.package_history, Color::Yellow),
+        (core_area, " Cores W ", &dashboard.core_history, Color::Cyan),
+    ] {
+        // Only the most recent samples that fit in the widget are shown.
+        let width = area.width.saturating_sub(2) as usize;
+        let data: Vec<u64> = history
+            .iter()
+            .skip(history.len().saturating_sub(width))
+            .map(|w| (w * 10.0).max(0.0) as u64)
+            .collect();
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(title))
+                .data(&data)
+                .style(Style::default().fg(color)),
+            area,
+        );
+    }
+
+    let bars: Vec<Bar> = dashboard
+        .latest
+        .iter()
+        .flat_map(|metrics| metrics.per_core(&metrics.core_watts))
+        .map(|(core, watts)| {
+            Bar::default()
+                .label(Line::from(format!("core{:<3}", core)))
+                .value((watts * 100.0).max(0.0) as u64)
+                .text_value(format!("{:.2} W", watts))
+        })
+        .collect();
+    frame.render_widget(
+        BarChart::default()
+            .block(Block::bordered().title(" Per-core power "))
+            .direction(Direction::Horizontal)
+            .bar_width(1)
+            .bar_gap(0)
+            .bar_style(Style::default().fg(Color::Green))
+            .data(BarGroup::default().bars(&bars)),
+        cores,
+    );
+}
@@ -0,0 +1,259 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::UnitsConfig;
+use crate::rapl::PowerMetrics;
+
+// What a unit measures. Readings are taken in the base unit of each: W, J,
+// °C and MHz.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantity {
+    Power,
+    Energy,
+    Temperature,
+    Frequency,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    #[serde(rename = "W")]
+    Watts,
+    #[serde(rename = "mW")]
+    Milliwatts,
+    #[serde(rename = "kW")]
+    Kilowatts,
+    #[serde(rename = "J")]
+    Joules,
+    #[serde(rename = "kJ")]
+    Kilojoules,
+    #[serde(rename = "Wh")]
+    WattHours,
+    #[serde(rename = "kWh")]
+    KilowattHours,
+    #[serde(rename = "C", alias = "°C")]
+    Celsius,
+    #[serde(rename = "F", alias = "°F")]
+    Fahrenheit,
+    #[serde(rename = "K")]
+    Kelvin,
+    #[serde(rename = "Hz")]
+    Hertz,
+    #[serde(rename = "MHz")]
+    Megahertz,
+    #[serde(rename = "GHz")]
+    Gigahertz,
+}
+
+impl Unit {
+    pub fn quantity(self) -> Quantity {
+        match self {
+            Unit::Watts | Unit::Milliwatts | Unit::Kilowatts => Quantity::Power,
+            Unit::Joules | Unit::Kilojoules | Unit::WattHours | Unit::KilowattHours => Quantity::Energy,
+            Unit::Celsius | Unit::Fahrenheit | Unit::Kelvin => Quantity::Temperature,
+            Unit::Hertz | Unit::Megahertz | Unit::Gigahertz => Quantity::Frequency,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Unit::Watts => "W",
+            Unit::Milliwatts => "mW",
+            Unit::Kilowatts => "kW",
+            Unit::Joules => "J",
+            Unit::Kilojoules => "kJ",
+            Unit::WattHours => "Wh",
+            Unit::KilowattHours => "kWh",
+            Unit::Celsius => "°C",
+            Unit::Fahrenheit => "°F",
+            Unit::Kelvin => "K",
+            Unit::Hertz => "Hz",
+            Unit::Megahertz => "MHz",
+            Unit::Gigahertz => "GHz",
+        }
+    }
+
+    // The UCUM code, as OpenTelemetry has units.
+    pub fn ucum(self) -> &'static str {
+        match self {
+            Unit::Celsius => "Cel",
+            Unit::Fahrenheit => "[degF]",
+            Unit::WattHours => "W.h",
+            Unit::KilowattHours => "kW.h",
+            unit => unit.symbol(),
+        }
+    }
+
+    // `value`, in the base unit of this unit's quantity, in this unit.
+    pub fn from_base(self, value: f64) -> f64 {
+        match self {
+            Unit::Watts | Unit::Joules | Unit::Celsius | Unit::Megahertz => value,
+            Unit::Milliwatts => value * 1000.0,
+            Unit::Kilowatts | Unit::Kilojoules | Unit::Gigahertz => value / 1000.0,
+            Unit::WattHours => value / 3600.0,
+            Unit::KilowattHours => value / 3_600_000.0,
+            Unit::Fahrenheit => value * 9.0 / 5.0 + 32.0,
+            Unit::Kelvin => value + 273.15,
+            Unit::Hertz => value * 1_000_000.0,
+        }
+    }
+}
+
+// The kinds of values [units] sets a unit and precision for.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum UnitKind {
+    // Package, core sum, DRAM, SoC, CCD, GPU, process and cgroup power
+    Power,
+    // Power of each core, which is often better read in mW
+    CorePower,
+    // The cumulative energy where a sink writes a single value of it
+    Energy,
+    Temperature,
+    Frequency,
+}
+
+impl UnitKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            UnitKind::Power => "power",
+            UnitKind::CorePower => "core_power",
+            UnitKind::Energy => "energy",
+            UnitKind::Temperature => "temperature",
+            UnitKind::Frequency => "frequency",
+        }
+    }
+
+    pub fn quantity(self) -> Quantity {
+        match self {
+            UnitKind::Power | UnitKind::CorePower => Quantity::Power,
+            UnitKind::Energy => Quantity::Energy,
+            UnitKind::Temperature => Quantity::Temperature,
+            UnitKind::Frequency => Quantity::Frequency,
+        }
+    }
+}
+
+// Converts readings to the units and decimal places of [units] on their way
+// to the sinks that take them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Units {
+    config: UnitsConfig,
+}
+
+impl Default for Units {
+    fn default() -> Self {
+        Units::new(UnitsConfig::default())
+    }
+}
+
+impl Units {
+    pub fn new(config: UnitsConfig) -> Self {
+        Units { config }
+    }
+
+    pub fn unit(&self, kind: UnitKind) -> Unit {
+        match kind {
+            UnitKind::Power => self.config.power,
+            UnitKind::CorePower => self.config.core_power.unwrap_or(self.config.power),
+            UnitKind::Energy => self.config.energy.unwrap_or(Unit::Joules),
+            UnitKind::Temperature => self.config.temperature,
+            UnitKind::Frequency => self.config.frequency,
+        }
+    }
+
+    pub fn symbol(&self, kind: UnitKind) -> &'static str {
+        self.unit(kind).symbol()
+    }
+
+    // `value` in the base unit, in the unit of `kind` and rounded to its
+    // precision when one is set.
+    pub fn convert(&self, kind: UnitKind, value: f64) -> f64 {
+        self.round(kind, self.unit(kind).from_base(value))
+    }
+
+    // The energy in `joules` in units.energy, or else in the sink's own
+    // `default`, and that unit.
+    pub fn energy(&self, joules: f64, default: Unit) -> (f64, Unit) {
+        let unit = self.config.energy.unwrap_or(default);
+        (self.round(UnitKind::Energy, unit.from_base(joules)), unit)
+    }
+
+    fn round(&self, kind: UnitKind, value: f64) -> f64 {
+        match self.config.precision.get(&kind) {
+            Some(&places) => {
+                let scale = 10f64.powi(places as i32);
+                (value * scale).round() / scale
+            }
+            None => value,
+        }
+    }
+
+    // Convert every power, temperature and frequency reading of `metrics`.
+    // The energy totals keep their joules and kWh, which are named by unit;
+    // histogram buckets, limits and [[hwmon]] sensors keep theirs too.
+    pub fn apply(&self, metrics: &mut PowerMetrics) {
+        let power = |value: &mut f64| *value = self.convert(UnitKind::Power, *value);
+        let core_power = |value: &mut f64| *value = self.convert(UnitKind::CorePower, *value);
+        let temperature = |value: &mut f64| *value = self.convert(UnitKind::Temperature, *value);
+        let frequency = |value: &mut f64| *value = self.convert(UnitKind::Frequency, *value);
+
+        power(&mut metrics.package_watts);
+        power(&mut metrics.core_sum);
+        metrics.packages.iter_mut().for_each(|package| power(&mut package.watts));
+        metrics.dram_watts.iter_mut().for_each(power);
+        metrics.soc_watts.iter_mut().for_each(power);
+        metrics.idle_floor_watts.iter_mut().for_each(power);
+        metrics.core_watts.iter_mut().for_each(core_power);
+        for ccd in &mut metrics.ccds {
+            power(&mut ccd.watts);
+            ccd.celsius.iter_mut().for_each(temperature);
+        }
+        for gpu in &mut metrics.gpus {
+            gpu.watts.iter_mut().for_each(power);
+            gpu.temperatures.iter_mut().for_each(|reading| temperature(&mut reading.celsius));
+        }
+        metrics.processes.iter_mut().for_each(|process| power(&mut process.watts));
+        metrics.cgroups.iter_mut().for_each(|cgroup| power(&mut cgroup.watts));
+        if let Some(stats) = &mut metrics.stats {
+            for summary in [&mut stats.package, &mut stats.core_sum].into_iter().chain(stats.dram.as_mut()) {
+                for value in [&mut summary.min, &mut summary.max, &mut summary.mean, &mut summary.p95] {
+                    power(value);
+                }
+            }
+            for summary in &mut stats.cores {
+                for value in [&mut summary.min, &mut summary.max, &mut summary.mean, &mut summary.p95] {
+                    core_power(value);
+                }
+            }
+        }
+        metrics.temperatures.iter_mut().for_each(|reading| temperature(&mut reading.celsius));
+        metrics.core_mhz.iter_mut().for_each(frequency);
+        metrics.average_mhz.iter_mut().for_each(frequency);
+        metrics.core_activity.iter_mut().for_each(|activity| frequency(&mut activity.effective_mhz));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_from_base_units() {
+        assert_eq!(Unit::Milliwatts.from_base(1.5), 1500.0);
+        assert_eq!(Unit::KilowattHours.from_base(7_200_000.0), 2.0);
+        assert_eq!(Unit::Fahrenheit.from_base(100.0), 212.0);
+        assert_eq!(Unit::Kelvin.from_base(0.0), 273.15);
+        assert_eq!(Unit::Gigahertz.from_base(3800.0), 3.8);
+    }
+
+    #[test]
+    fn rounds_to_the_precision_of_each_kind() {
+        let config: UnitsConfig = toml::from_str("core_power = \"mW\"\n[precision]\ncore_power = 0\npower = 1").unwrap();
+        let units = Units::new(config);
+        assert_eq!(units.convert(UnitKind::CorePower, 2.34567), 2346.0);
+        assert_eq!(units.convert(UnitKind::Power, 54.26), 54.3);
+        assert_eq!(units.convert(UnitKind::Frequency, 3812.25), 3812.25);
+        assert_eq!(units.symbol(UnitKind::CorePower), "mW");
+        assert_eq!(units.symbol(UnitKind::Temperature), "°C");
+        assert_eq!(units.energy(7_200_000.0, Unit::KilowattHours), (2.0, Unit::KilowattHours));
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+
+use tracing::warn;
+
+use crate::config::{ValidationAction, ValidationConfig};
+use crate::rapl::PowerMetrics;
+use crate::telemetry;
+
+// Checks every sample against the [validation] ranges before anything else
+// sees it, so one corrupted MSR read doesn't end up on a dashboard. Values
+// out of range are counted, and either left alone, cause the whole sample to
+// be dropped, or are replaced by the last good value of their series.
+pub struct Validator {
+    config: ValidationConfig,
+    // Last value in range per series: package, package/<n>, core/<n> and
+    // temperature/<label>
+    previous: BTreeMap<String, f64>,
+    cores: usize,
+}
+
+impl Validator {
+    pub fn new(config: ValidationConfig) -> Self {
+        Validator {
+            config,
+            previous: BTreeMap::new(),
+            cores: 0,
+        }
+    }
+
+    pub fn config(&self) -> &ValidationConfig {
+        &self.config
+    }
+
+    // False when the sample is to be dropped.
+    pub fn apply(&mut self, metrics: &mut PowerMetrics) -> bool {
+        // Core indices shift when cores go offline, so their last values no
+        // longer belong to them.
+        if metrics.core_watts.len() != self.cores {
+            self.previous.retain(|key, _| !key.starts_with("core/"));
+            self.cores = metrics.core_watts.len();
+        }
+        let max_package_watts = self.config.max_package_watts;
+        let max_core_watts = self.config.max_core_watts;
+        let max_celsius = self.config.max_celsius;
+
+        let mut rejected = Vec::new();
+        if let Some(max) = max_package_watts {
+            self.check("package", &mut metrics.package_watts, max, "W", &mut rejected);
+            for package in &mut metrics.packages {
+                self.check(&format!("package/{}", package.package), &mut package.watts, max, "W", &mut rejected);
+            }
+        }
+        for (core, watts) in metrics.core_watts.iter_mut().enumerate() {
+            self.check(&format!("core/{}", core), watts, max_core_watts, "W", &mut rejected);
+        }
+        for temperature in &mut metrics.temperatures {
+            let key = format!("temperature/{}", temperature.label);
+            self.check(&key, &mut temperature.celsius, max_celsius, "°C", &mut rejected);
+        }
+        if rejected.is_empty() {
+            return true;
+        }
+
+        telemetry::record_rejected_values(rejected.len());
+        match self.config.action {
+            ValidationAction::Flag => warn!("Out of range: {}", rejected.join(", ")),
+            ValidationAction::Drop => warn!("Dropping a sample, out of range: {}", rejected.join(", ")),
+            ValidationAction::Previous => warn!("Replaced with the previous value: {}", rejected.join(", ")),
+        }
+        if self.config.action == ValidationAction::Previous {
+            metrics.core_sum = metrics.core_watts.iter().sum();
+        }
+        self.config.action != ValidationAction::Drop
+    }
+
+    fn check(&mut self, key: &str, value: &mut f64, max: f64, unit: &str, rejected: &mut Vec<String>) {
+        if *value <= max || value.is_nan() {
+            self.previous.insert(key.to_string(), *value);
+            return;
+        }
+        rejected.push(format!("{} {:.1} {}", key, value, unit));
+        if self.config.action == ValidationAction::Previous {
+            // Nothing in range yet for this series, so the limit is the
+            // closest a value can be.
+            *value = self.previous.get(key).copied().unwrap_or(max);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hwmon::TemperatureReading;
+    use crate::platform::fixture_sample;
+
+    fn sample() -> PowerMetrics {
+        let mut metrics = fixture_sample();
+        metrics.temperatures = vec![TemperatureReading {
+            label: "Tctl".to_string(),
+            celsius: 60.0,
+        }];
+        metrics
+    }
+
+    fn validator(action: ValidationAction) -> Validator {
+        Validator::new(ValidationConfig {
+            max_package_watts: None,
+            max_core_watts: 100.0,
+            max_celsius: 120.0,
+            action,
+        })
+    }
+
+    #[test]
+    fn drops_samples_out_of_range() {
+        let mut validator = validator(ValidationAction::Drop);
+        let mut metrics = sample();
+        assert!(validator.apply(&mut metrics));
+
+        metrics.temperatures[0].celsius = 255.0;
+        assert!(!validator.apply(&mut metrics));
+    }
+
+    #[test]
+    fn replaces_values_out_of_range_with_the_previous_one() {
+        let mut previous = validator(ValidationAction::Previous);
+        let mut metrics = sample();
+        let good = metrics.core_watts[1];
+        assert!(previous.apply(&mut metrics));
+
+        metrics.core_watts[1] = 4000.0;
+        metrics.temperatures[0].celsius = 255.0;
+        assert!(previous.apply(&mut metrics));
+        assert_eq!(metrics.core_watts[1], good);
+        assert_eq!(metrics.core_sum, metrics.core_watts.iter().sum::<f64>());
+        assert_eq!(metrics.temperatures[0].celsius, 60.0);
+
+        let mut flagged = sample();
+        flagged.core_watts[0] = 4000.0;
+        assert!(validator(ValidationAction::Flag).apply(&mut flagged));
+        assert_eq!(flagged.core_watts[0], 4000.0);
+    }
+}
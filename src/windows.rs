@@ -0,0 +1,270 @@
+use std::io;
+
+use crate::topology::{assign_ccds, Package, Topology};
+
+#[cfg(windows)]
+pub use winring0::{processor_information, WinRing0};
+
+// LOGICAL_PROCESSOR_RELATIONSHIP values of the records we use
+const RELATION_PROCESSOR_CORE: i32 = 0;
+const RELATION_CACHE: i32 = 2;
+const RELATION_PROCESSOR_PACKAGE: i32 = 3;
+// Offsets into SYSTEM_LOGICAL_PROCESSOR_INFORMATION_EX records (winnt.h, x64).
+// Before Windows 10 20H2 the cache group count was reserved and zero, with
+// a single mask at the same offset.
+const PROCESSOR_GROUP_COUNT: usize = 30;
+const PROCESSOR_GROUP_MASKS: usize = 32;
+const CACHE_LEVEL: usize = 8;
+const CACHE_GROUP_COUNT: usize = 38;
+const CACHE_GROUP_MASKS: usize = 40;
+const GROUP_AFFINITY_SIZE: usize = 16;
+// Logical processors per processor group, numbered group * 64 + bit
+pub const GROUP_SIZE: usize = 64;
+
+// Cores, packages and L3 caches from the buffer that
+// GetLogicalProcessorInformationEx(RelationAll) fills, the same layout WMI's
+// Win32_Processor is built from.
+pub fn parse_processor_information(buffer: &[u8], ccxs_per_ccd: usize) -> io::Result<Topology> {
+    let mut core_threads = Vec::new();
+    let mut package_cpus = Vec::new();
+    let mut l3_cpus = Vec::new();
+
+    let mut offset = 0;
+    while offset < buffer.len() {
+        let relationship = u32_at(buffer, offset)? as i32;
+        let size = u32_at(buffer, offset + 4)? as usize;
+        let record = buffer
+            .get(offset..offset + size)
+            .filter(|_| size >= 8)
+            .ok_or_else(|| invalid("truncated processor information record"))?;
+        match relationship {
+            RELATION_PROCESSOR_CORE => core_threads.push(group_cpus(record, PROCESSOR_GROUP_COUNT, PROCESSOR_GROUP_MASKS)?),
+            RELATION_PROCESSOR_PACKAGE => package_cpus.push(group_cpus(record, PROCESSOR_GROUP_COUNT, PROCESSOR_GROUP_MASKS)?),
+            RELATION_CACHE if record.get(CACHE_LEVEL) == Some(&3) => {
+                l3_cpus.push(group_cpus(record, CACHE_GROUP_COUNT, CACHE_GROUP_MASKS)?)
+            }
+            _ => {}
+        }
+        offset += size;
+    }
+
+    core_threads.sort_unstable();
+    let cores: Vec<usize> = core_threads.iter().map(|threads| threads[0]).collect();
+    let position = |groups: &[Vec<usize>], cpu: &usize| groups.iter().position(|cpus| cpus.contains(cpu));
+    let l3_ids: Option<Vec<usize>> = cores.iter().map(|cpu| position(&l3_cpus, cpu)).collect();
+    Ok(Topology {
+        threads: core_threads.iter().map(Vec::len).sum(),
+        packages: package_cpus.iter().enumerate().map(|(id, cpus)| Package { id, cpu: cpus[0] }).collect(),
+        ccds: l3_ids.as_ref().map(|ids| assign_ccds(ids, ccxs_per_ccd)).unwrap_or_default(),
+        core_l3s: l3_ids.unwrap_or_default(),
+        core_nodes: Vec::new(),
+        core_classes: Vec::new(),
+        core_packages: cores.iter().map(|cpu| position(&package_cpus, cpu).unwrap_or(0)).collect(),
+        core_ids: (0..cores.len()).collect(),
+        core_threads,
+        cores,
+    })
+}
+
+// Logical CPUs in the GROUP_AFFINITY masks of a record, lowest first.
+fn group_cpus(record: &[u8], count_at: usize, masks_at: usize) -> io::Result<Vec<usize>> {
+    let count = u16::from_le_bytes(bytes(record, count_at)?).max(1) as usize;
+    let mut cpus = Vec::new();
+    for i in 0..count {
+        let at = masks_at + i * GROUP_AFFINITY_SIZE;
+        let mask = u64::from_le_bytes(bytes(record, at)?);
+        let group = u16::from_le_bytes(bytes(record, at + 8)?) as usize;
+        cpus.extend((0..GROUP_SIZE).filter(|bit| mask & (1 << bit) != 0).map(|bit| group * GROUP_SIZE + bit));
+    }
+    cpus.sort_unstable();
+    if cpus.is_empty() {
+        return Err(invalid("processor information record without CPUs"));
+    }
+    Ok(cpus)
+}
+
+fn u32_at(buffer: &[u8], at: usize) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(bytes(buffer, at)?))
+}
+
+fn bytes<const N: usize>(buffer: &[u8], at: usize) -> io::Result<[u8; N]> {
+    buffer
+        .get(at..at + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| invalid("truncated processor information record"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// MSRs through the WinRing0 driver (https://github.com/GermanAizek/WinRing0),
+// which is signed and shipped by several monitoring tools. WinRing0x64.dll
+// and WinRing0x64.sys must be next to the executable or on the PATH, and
+// loading the driver needs an administrator.
+#[cfg(windows)]
+mod winring0 {
+    use std::io;
+    use std::ptr;
+
+    use windows_sys::Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryW};
+    use windows_sys::Win32::System::SystemInformation::{GetLogicalProcessorInformationEx, RelationAll};
+
+    use super::GROUP_SIZE;
+    use crate::error::{RyzenmonError, Result};
+    use crate::msr::{detect_cpu, CpuId};
+    use crate::platform::{Msr, Platform};
+    use crate::topology::Topology;
+
+    const DLL: &str = "WinRing0x64.dll";
+
+    type InitializeOls = unsafe extern "system" fn() -> i32;
+    // index, eax, edx, thread affinity mask
+    type RdmsrTx = unsafe extern "system" fn(u32, *mut u32, *mut u32, usize) -> i32;
+
+    // The loaded driver, kept for the life of the process.
+    pub struct WinRing0 {
+        rdmsr_tx: RdmsrTx,
+    }
+
+    impl WinRing0 {
+        pub fn load() -> Result<Self> {
+            let unavailable = |why: String| RyzenmonError::NoEnergySource(why);
+            let name: Vec<u16> = DLL.encode_utf16().chain(Some(0)).collect();
+            // SAFETY: `name` is a NUL-terminated UTF-16 string.
+            let library = unsafe { LoadLibraryW(name.as_ptr()) };
+            if library.is_null() {
+                return Err(unavailable(format!("{} can't be loaded: {}", DLL, io::Error::last_os_error())));
+            }
+            let function = |symbol: &str| {
+                let name = format!("{}\0", symbol);
+                // SAFETY: `library` is loaded and never unloaded, `name` is NUL-terminated.
+                unsafe { GetProcAddress(library, name.as_ptr()) }
+                    .ok_or_else(|| unavailable(format!("{} has no {}", DLL, symbol)))
+            };
+            // SAFETY: the signatures are those of OlsApi.h.
+            let initialize: InitializeOls = unsafe { std::mem::transmute(function("InitializeOls")?) };
+            let rdmsr_tx: RdmsrTx = unsafe { std::mem::transmute(function("RdmsrTx")?) };
+            // SAFETY: InitializeOls takes no arguments and may be called more than once.
+            if unsafe { initialize() } == 0 {
+                return Err(unavailable(format!(
+                    "{} could not start the WinRing0 driver, run ryzenmon as administrator",
+                    DLL
+                )));
+            }
+            Ok(WinRing0 { rdmsr_tx })
+        }
+    }
+
+    impl Platform for WinRing0 {
+        fn cpu(&self) -> Result<CpuId> {
+            detect_cpu()
+        }
+
+        fn topology(&self) -> Result<Topology> {
+            Topology::detect()
+        }
+
+        fn online_cpus(&self) -> Option<String> {
+            None
+        }
+
+        // RdmsrTx pins the read with an affinity mask, which only reaches the
+        // first processor group.
+        fn open_msr(&self, cpu: usize) -> Result<Box<dyn Msr>> {
+            if cpu >= GROUP_SIZE {
+                let source = io::Error::new(io::ErrorKind::Unsupported, "WinRing0 only reaches the first 64 CPUs");
+                return Err(RyzenmonError::MsrAccess { cpu, source });
+            }
+            Ok(Box::new(WinRing0Msr {
+                cpu,
+                rdmsr_tx: self.rdmsr_tx,
+            }))
+        }
+    }
+
+    struct WinRing0Msr {
+        cpu: usize,
+        rdmsr_tx: RdmsrTx,
+    }
+
+    impl Msr for WinRing0Msr {
+        fn cpu(&self) -> usize {
+            self.cpu
+        }
+
+        fn read(&mut self, which: u64) -> Result<u64> {
+            let (mut eax, mut edx) = (0u32, 0u32);
+            // SAFETY: RdmsrTx writes the low and high halves of the MSR to eax and edx.
+            if unsafe { (self.rdmsr_tx)(which as u32, &mut eax, &mut edx, 1 << self.cpu) } == 0 {
+                let source = io::Error::other(format!("RdmsrTx of MSR {:#x} failed", which));
+                return Err(RyzenmonError::MsrAccess { cpu: self.cpu, source });
+            }
+            Ok(u64::from(edx) << 32 | u64::from(eax))
+        }
+    }
+
+    // Every SYSTEM_LOGICAL_PROCESSOR_INFORMATION_EX record, as bytes.
+    pub fn processor_information() -> io::Result<Vec<u8>> {
+        let mut length = 0;
+        // SAFETY: a null buffer asks for the length needed.
+        unsafe { GetLogicalProcessorInformationEx(RelationAll, ptr::null_mut(), &mut length) };
+        let mut buffer = vec![0u8; length as usize];
+        // SAFETY: `buffer` holds `length` bytes.
+        if unsafe { GetLogicalProcessorInformationEx(RelationAll, buffer.as_mut_ptr().cast(), &mut length) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        buffer.truncate(length as usize);
+        Ok(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(relationship: i32, size: usize, fields: &[(usize, &[u8])]) -> Vec<u8> {
+        let mut record = vec![0; size];
+        record[..4].copy_from_slice(&relationship.to_le_bytes());
+        record[4..8].copy_from_slice(&(size as u32).to_le_bytes());
+        for (at, bytes) in fields {
+            record[*at..*at + bytes.len()].copy_from_slice(bytes);
+        }
+        record
+    }
+
+    fn processor(relationship: i32, mask: u64) -> Vec<u8> {
+        record(relationship, 48, &[(PROCESSOR_GROUP_COUNT, &1u16.to_le_bytes()), (PROCESSOR_GROUP_MASKS, &mask.to_le_bytes())])
+    }
+
+    // The pre-20H2 layout, without a group count.
+    fn l3(mask: u64) -> Vec<u8> {
+        record(RELATION_CACHE, 56, &[(CACHE_LEVEL, &[3]), (CACHE_GROUP_MASKS, &mask.to_le_bytes())])
+    }
+
+    #[test]
+    fn parses_processor_information() {
+        // Two Zen 2 CCXs of two SMT cores each, enumerated out of order
+        let buffer = [
+            processor(RELATION_PROCESSOR_CORE, 0b0011_0000),
+            processor(RELATION_PROCESSOR_CORE, 0b0000_0011),
+            processor(RELATION_PROCESSOR_CORE, 0b0000_1100),
+            processor(RELATION_PROCESSOR_CORE, 0b1100_0000),
+            l3(0b0000_1111),
+            l3(0b1111_0000),
+            processor(RELATION_PROCESSOR_PACKAGE, 0b1111_1111),
+        ]
+        .concat();
+        let topology = parse_processor_information(&buffer, 2).unwrap();
+        assert_eq!(topology.cores, vec![0, 2, 4, 6]);
+        assert_eq!(topology.core_threads, vec![vec![0, 1], vec![2, 3], vec![4, 5], vec![6, 7]]);
+        assert_eq!(topology.threads, 8);
+        assert_eq!(topology.packages, vec![Package { id: 0, cpu: 0 }]);
+        assert_eq!(topology.core_packages, vec![0; 4]);
+        assert_eq!(topology.core_ids, vec![0, 1, 2, 3]);
+        assert_eq!(topology.ccds, vec![0; 4]);
+        assert_eq!(parse_processor_information(&buffer, 1).unwrap().ccds, vec![0, 0, 1, 1]);
+
+        assert!(parse_processor_information(&buffer[..buffer.len() - 1], 2).is_err());
+    }
+}